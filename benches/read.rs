@@ -0,0 +1,129 @@
+//! Parsing throughput on the test fixtures
+//!
+//! Run with `cargo bench`.
+
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use llvm_bitcode::arena::BitcodeArena;
+use llvm_bitcode::read::BlockItem;
+use llvm_bitcode::Bitcode;
+
+/// Run `f` repeatedly for about a second and print the mean time per run
+fn bench<F: FnMut()>(name: &str, bytes: usize, mut f: F) {
+    f();
+    let mut runs = 0u32;
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_secs(1) {
+        f();
+        runs += 1;
+    }
+    let per_run = start.elapsed() / runs;
+    let throughput = bytes as f64 / per_run.as_secs_f64() / (1024.0 * 1024.0);
+    println!("{:<32} {:>10.2?} {:>10.1} MiB/s", name, per_run, throughput);
+}
+
+/// Appends little-endian bit fields to a byte buffer
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit: usize,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, width: usize) {
+        for i in 0..width {
+            if self.bit.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if value >> i & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 1 << (self.bit % 8);
+            }
+            self.bit += 1;
+        }
+    }
+
+    fn write_vbr(&mut self, mut value: u64, width: usize) {
+        let threshold = 1 << (width - 1);
+        while value >= threshold {
+            self.write(value & (threshold - 1) | threshold, width);
+            value >>= width - 1;
+        }
+        self.write(value, width);
+    }
+
+    fn align32(&mut self) {
+        while !self.bit.is_multiple_of(32) {
+            self.write(0, 1);
+        }
+    }
+}
+
+/// A stream with a single block of unabbreviated records, whose fields are
+/// all VBR6 encoded
+fn vbr_stream(records: usize) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    writer.write(0xdec04342, 32);
+    // ENTER_SUBBLOCK with block id 8 and 3-bit abbreviation ids
+    writer.write(1, 2);
+    writer.write_vbr(8, 8);
+    writer.write_vbr(3, 4);
+    writer.align32();
+    let length_at = writer.bytes.len();
+    writer.write(0, 32);
+    for i in 0..records as u64 {
+        writer.write(3, 3);
+        writer.write_vbr(1, 6);
+        writer.write_vbr(8, 6);
+        for field in [i, i * 31, i << 20, 7, 100_000, u32::MAX as u64, 0, 42] {
+            writer.write_vbr(field, 6);
+        }
+    }
+    writer.write(0, 3);
+    writer.align32();
+    let length = (writer.bytes.len() - length_at - 4) / 4;
+    writer.bytes[length_at..length_at + 4].copy_from_slice(&(length as u32).to_le_bytes());
+    writer.bytes
+}
+
+fn main() {
+    let data = vbr_stream(100_000);
+    bench("Bitcode::new/vbr6", data.len(), || {
+        black_box(Bitcode::new(black_box(&data)).unwrap());
+    });
+    bench("BitcodeArena::new/vbr6", data.len(), || {
+        black_box(BitcodeArena::new(black_box(&data)).unwrap());
+    });
+    bench("BlockIter/vbr6", data.len(), || {
+        let (_, mut reader) = Bitcode::reader(black_box(&data)).unwrap();
+        let mut top_level = reader.iter_top_level();
+        let mut sum = 0u64;
+        while let Some(item) = top_level.next().unwrap() {
+            if let BlockItem::Block(mut block) = item {
+                while let Some(item) = block.next().unwrap() {
+                    if let BlockItem::Record(mut record) = item {
+                        while let Some(field) = record.next().unwrap() {
+                            sum = sum.wrapping_add(field);
+                        }
+                    }
+                }
+            }
+        }
+        black_box(sum);
+    });
+
+    for fixture in ["simple.bc", "serialized.dia"] {
+        let data = fs::read(format!("tests/fixtures/{}", fixture)).unwrap();
+        bench(&format!("Bitcode::new/{}", fixture), data.len(), || {
+            black_box(Bitcode::new(black_box(&data)).unwrap());
+        });
+        bench(
+            &format!("BitcodeArena::new/{}", fixture),
+            data.len(),
+            || {
+                black_box(BitcodeArena::new(black_box(&data)).unwrap());
+            },
+        );
+    }
+}
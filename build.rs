@@ -0,0 +1,40 @@
+use std::env;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    if env::var_os("CARGO_FEATURE_LLVM_VALIDATION").is_none() {
+        return;
+    }
+    // Link libLLVM for the `llvm-validation` feature, found with the
+    // `llvm-config` of `LLVM_CONFIG` or of the `PATH`
+    println!("cargo:rerun-if-env-changed=LLVM_CONFIG");
+    let llvm_config = env::var("LLVM_CONFIG").unwrap_or_else(|_| "llvm-config".to_string());
+    let run = |args: &[&str]| -> String {
+        let output = Command::new(&llvm_config)
+            .args(args)
+            .output()
+            .unwrap_or_else(|err| panic!("failed to run `{}`: {}", llvm_config, err));
+        assert!(
+            output.status.success(),
+            "`{} {}` failed: {}",
+            llvm_config,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).expect("llvm-config output is not UTF-8")
+    };
+    let lib_dir = run(&["--libdir"]);
+    let lib_dir = lib_dir.trim();
+    println!("cargo:rustc-link-search=native={}", lib_dir);
+    // Find the shared library at run time without setting the loader path
+    if env::var("CARGO_CFG_TARGET_OS").is_ok_and(|os| os != "windows") {
+        println!("cargo:rustc-link-arg=-Wl,-rpath,{}", lib_dir);
+    }
+    let libs = run(&["--libs", "--system-libs", "core", "bitreader"]);
+    for lib in libs.split_whitespace() {
+        if let Some(name) = lib.strip_prefix("-l") {
+            println!("cargo:rustc-link-lib={}", name);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Dump a bitcode file as JSON
+//!
+//! Usage: `cargo run --example bc2json -- [--offsets] [--blob-strings] FILE`
+use std::{env, fs, process};
+
+use llvm_bitcode::json::{to_json, BlobEncoding, JsonOptions};
+
+fn main() {
+    let mut options = JsonOptions::default();
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--offsets" => options.offsets = true,
+            "--blob-strings" => options.blobs = BlobEncoding::String,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => {
+                eprintln!("usage: bc2json [--offsets] [--blob-strings] FILE");
+                process::exit(2);
+            }
+        }
+    }
+    let path = path.unwrap_or_else(|| {
+        eprintln!("usage: bc2json [--offsets] [--blob-strings] FILE");
+        process::exit(2);
+    });
+    let data = fs::read(&path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    });
+    match to_json(&data, &options) {
+        Ok(json) => println!("{}", json),
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
+    }
+}
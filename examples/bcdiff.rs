@@ -0,0 +1,43 @@
+//! Compare two bitcode files, ignoring how their records are encoded
+//!
+//! Usage: `cargo run --example bcdiff -- LEFT RIGHT`
+//!
+//! Exits with status 1 when the files differ.
+use std::{env, fs, process};
+
+use llvm_bitcode::diff::diff;
+use llvm_bitcode::Bitcode;
+
+fn main() {
+    let paths: Vec<String> = env::args().skip(1).collect();
+    if paths.len() != 2 {
+        eprintln!("usage: bcdiff LEFT RIGHT");
+        process::exit(2);
+    }
+    let data: Vec<Vec<u8>> = paths
+        .iter()
+        .map(|path| {
+            fs::read(path).unwrap_or_else(|err| {
+                eprintln!("{}: {}", path, err);
+                process::exit(2);
+            })
+        })
+        .collect();
+    let parsed: Vec<Bitcode> = data
+        .iter()
+        .zip(&paths)
+        .map(|(data, path)| {
+            Bitcode::new(data).unwrap_or_else(|err| {
+                eprintln!("{}: {}", path, err);
+                process::exit(2);
+            })
+        })
+        .collect();
+    let differences = diff(&parsed[0], &parsed[1]);
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    if !differences.is_empty() {
+        process::exit(1);
+    }
+}
@@ -0,0 +1,57 @@
+//! List the symbols of a bitcode file like `llvm-nm`
+//!
+//! Usage: `cargo run --example bcnm -- [--all] FILE`
+use std::{env, fs, process};
+
+use llvm_bitcode::bitcode::BitcodeInfo;
+use llvm_bitcode::symbols::symbols;
+
+fn main() {
+    let mut all = false;
+    let mut path = None;
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--all" => all = true,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => {
+                eprintln!("usage: bcnm [--all] FILE");
+                process::exit(2);
+            }
+        }
+    }
+    let path = path.unwrap_or_else(|| {
+        eprintln!("usage: bcnm [--all] FILE");
+        process::exit(2);
+    });
+    let data = fs::read(&path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    });
+    let result = symbols(&data).and_then(|symbols| Ok((symbols, BitcodeInfo::peek(&data)?)));
+    let (mut symbols, info) = match result {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
+    };
+    // Constants of Darwin targets are listed in a section other than data
+    let darwin = info.triple.as_deref().is_some_and(|triple| {
+        ["apple", "darwin", "macos", "ios"]
+            .iter()
+            .any(|os| triple.contains(os))
+    });
+    symbols.retain(|symbol| all || !symbol.format_specific);
+    symbols.sort_by(|a, b| a.name.cmp(&b.name));
+    for symbol in symbols {
+        let mut kind = symbol.nm_type();
+        if darwin && symbol.constant && (kind == 'd' || kind == 'D') {
+            kind = if kind == 'd' { 's' } else { 'S' };
+        }
+        if symbol.defined || symbol.is_common() {
+            println!("{} {} {}", "-".repeat(16), kind, symbol.name);
+        } else {
+            println!("{:16} {} {}", "", kind, symbol.name);
+        }
+    }
+}
@@ -0,0 +1,27 @@
+//! Report which blocks, functions and metadata make up a bitcode file
+//!
+//! Usage: `cargo run --example bcsize -- FILE`
+use std::{env, fs, process};
+
+use llvm_bitcode::size::SizeReport;
+
+fn main() {
+    let path = match (env::args().nth(1), env::args().nth(2)) {
+        (Some(path), None) => path,
+        _ => {
+            eprintln!("usage: bcsize FILE");
+            process::exit(2);
+        }
+    };
+    let data = fs::read(&path).unwrap_or_else(|err| {
+        eprintln!("{}: {}", path, err);
+        process::exit(1);
+    });
+    match SizeReport::new(&data) {
+        Ok(report) => print!("{}", report),
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use llvm_bitcode::Bitcode;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Bitcode::new(data);
+    let _ = Bitcode::new_recovering(data);
+});
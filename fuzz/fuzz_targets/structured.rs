@@ -0,0 +1,10 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use llvm_bitcode::Bitcode;
+use llvm_bitcode_fuzz::Bitstream;
+
+fuzz_target!(|stream: Bitstream| {
+    // Generated streams are well-formed, so they must parse
+    let bitcode = Bitcode::new(&stream.bytes).unwrap();
+    assert_eq!(bitcode.elements.len(), stream.top_level_blocks);
+});
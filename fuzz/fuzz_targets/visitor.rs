@@ -0,0 +1,39 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use llvm_bitcode::bitcode::Record;
+use llvm_bitcode::{BitStreamVisitor, Bitcode};
+
+/// Reads every field and payload so that all of the decoding is exercised
+struct Visitor {
+    depth: usize,
+    fields: u64,
+}
+
+impl BitStreamVisitor for Visitor {
+    fn should_enter_block(&mut self, _id: u64) -> bool {
+        self.depth += 1;
+        true
+    }
+
+    fn did_exit_block(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn visit(&mut self, record: Record) {
+        self.fields = record
+            .fields
+            .iter()
+            .fold(self.fields, |acc, field| acc.wrapping_add(*field));
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let mut visitor = Visitor {
+        depth: 0,
+        fields: 0,
+    };
+    if Bitcode::read(data, &mut visitor).is_ok() {
+        assert_eq!(visitor.depth, 0);
+    }
+    let _ = Bitcode::read_reusing(data, &mut visitor);
+});
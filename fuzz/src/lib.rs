@@ -0,0 +1,299 @@
+//! Structured input for the fuzz targets
+//!
+//! Random bytes almost never get past the first few abbreviation ids of a
+//! bitstream. [`Bitstream`] instead uses the fuzzer input to make the
+//! choices of a bitstream writer, so every generated stream is well-formed
+//! and reading it reaches blocks, `BLOCKINFO` blocks, abbreviation
+//! definitions and abbreviated records of every operand kind.
+use std::collections::HashMap;
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+use llvm_bitcode::bitstream::Operand;
+
+/// Deepest block nesting generated
+const MAX_DEPTH: usize = 4;
+/// Most items generated in a single block
+const MAX_ITEMS: usize = 16;
+/// Most operands in a generated abbreviation, an array counting as two
+const MAX_OPERANDS: usize = 6;
+/// Magic number of the bitcode wrapper header, which the generated streams
+/// don't have
+const WRAPPER_MAGIC: u32 = 0x0B17_C0DE;
+/// Magic number of LLVM IR bitcode, `BC\xC0\xDE`
+const LLVM_IR_MAGIC: u32 = 0xDEC0_4342;
+
+/// A well-formed bitstream
+#[derive(Debug, Clone)]
+pub struct Bitstream {
+    /// The encoded stream, starting with the magic number
+    pub bytes: Vec<u8>,
+    /// Number of top level blocks other than `BLOCKINFO` blocks
+    pub top_level_blocks: usize,
+}
+
+impl<'a> Arbitrary<'a> for Bitstream {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut generator = Generator {
+            writer: Writer::default(),
+            global_abbrevs: HashMap::new(),
+            depth: 0,
+        };
+        // Any magic number but the wrapper's, which would make the reader
+        // look for a wrapper header
+        let magic = match u.arbitrary::<u32>()? {
+            WRAPPER_MAGIC => LLVM_IR_MAGIC,
+            magic => magic,
+        };
+        generator.writer.write(u64::from(magic), 32);
+        let mut top_level_blocks = 0;
+        for _ in 0..u.int_in_range(0..=4)? {
+            if u.ratio(1, 4)? {
+                generator.block_info_block(u, 2)?;
+            } else {
+                generator.block(u, 2)?;
+                top_level_blocks += 1;
+            }
+        }
+        Ok(Self {
+            bytes: generator.writer.bytes,
+            top_level_blocks,
+        })
+    }
+}
+
+struct Generator {
+    writer: Writer,
+    /// Abbreviations defined in `BLOCKINFO` blocks so far
+    global_abbrevs: HashMap<u64, Vec<Operands>>,
+    depth: usize,
+}
+
+/// The operands of an abbreviation definition
+type Operands = Vec<Operand>;
+
+impl Generator {
+    fn block(&mut self, u: &mut Unstructured<'_>, abbrev_width: usize) -> Result<()> {
+        let id = u.int_in_range(8..=40)?;
+        let width = u.int_in_range(2..=6)?;
+        let mut abbrevs = self.global_abbrevs.get(&id).cloned().unwrap_or_default();
+        self.writer.enter_block(abbrev_width, id, width);
+        self.depth += 1;
+        for _ in 0..u.int_in_range(0..=MAX_ITEMS)? {
+            match u.int_in_range(0..=4)? {
+                0 => self.unabbreviated_record(u, width)?,
+                1 => {
+                    let operands = self.define_abbrev(u, width)?;
+                    abbrevs.push(operands);
+                }
+                2 => {
+                    // Only the abbreviations whose id fits in the width are usable
+                    let usable = abbrevs.len().min((1 << width) - 4);
+                    if usable > 0 {
+                        let index = u.choose_index(usable)?;
+                        self.writer.write(index as u64 + 4, width);
+                        self.abbreviated_record(u, &abbrevs[index])?;
+                    }
+                }
+                3 if self.depth < MAX_DEPTH => self.block(u, width)?,
+                4 => self.block_info_block(u, width)?,
+                _ => {}
+            }
+        }
+        self.depth -= 1;
+        self.writer.end_block(width);
+        Ok(())
+    }
+
+    fn block_info_block(&mut self, u: &mut Unstructured<'_>, abbrev_width: usize) -> Result<()> {
+        let width = u.int_in_range(2..=6)?;
+        self.writer.enter_block(abbrev_width, 0, width);
+        for _ in 0..u.int_in_range(0..=4)? {
+            let block_id = u.int_in_range(8..=40)?;
+            self.writer.unabbreviated_record(width, 1, &[block_id]);
+            if u.arbitrary()? {
+                let name: &[u8] = u.arbitrary()?;
+                let name: Vec<u64> = name.iter().copied().map(u64::from).collect();
+                self.writer.unabbreviated_record(width, 2, &name);
+            }
+            for _ in 0..u.int_in_range(0..=3)? {
+                let operands = self.define_abbrev(u, width)?;
+                self.global_abbrevs
+                    .entry(block_id)
+                    .or_default()
+                    .push(operands);
+            }
+        }
+        self.writer.end_block(width);
+        Ok(())
+    }
+
+    fn unabbreviated_record(&mut self, u: &mut Unstructured<'_>, width: usize) -> Result<()> {
+        let code = u.arbitrary()?;
+        let fields: Vec<u64> = u.arbitrary()?;
+        self.writer.unabbreviated_record(width, code, &fields);
+        Ok(())
+    }
+
+    fn define_abbrev(&mut self, u: &mut Unstructured<'_>, width: usize) -> Result<Operands> {
+        let mut operands = vec![scalar_operand(u)?];
+        while operands.len() < MAX_OPERANDS - 1 && u.arbitrary()? {
+            operands.push(scalar_operand(u)?);
+        }
+        match u.int_in_range(0..=2)? {
+            0 => operands.push(Operand::Array(Box::new(element_operand(u)?))),
+            1 => operands.push(Operand::Blob),
+            _ => {}
+        }
+        let num_ops = operands.iter().map(|op| 1 + op.is_array() as u64).sum();
+        self.writer.write(2, width);
+        self.writer.write_vbr(num_ops, 5);
+        for op in &operands {
+            if let Operand::Array(element) = op {
+                self.writer.write(0, 1);
+                self.writer.write(3, 3);
+                self.writer.operand(element);
+            } else {
+                self.writer.operand(op);
+            }
+        }
+        Ok(operands)
+    }
+
+    fn abbreviated_record(&mut self, u: &mut Unstructured<'_>, operands: &[Operand]) -> Result<()> {
+        for op in operands {
+            match op {
+                Operand::Array(element) => {
+                    let len = u.int_in_range(0..=32)?;
+                    self.writer.write_vbr(len, 6);
+                    for _ in 0..len {
+                        self.writer.scalar(u, element)?;
+                    }
+                }
+                Operand::Blob => {
+                    let blob: &[u8] = u.arbitrary()?;
+                    self.writer.write_vbr(blob.len() as u64, 6);
+                    self.writer.align32();
+                    for byte in blob {
+                        self.writer.write(u64::from(*byte), 8);
+                    }
+                    self.writer.align32();
+                }
+                op => self.writer.scalar(u, op)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn scalar_operand(u: &mut Unstructured<'_>) -> Result<Operand> {
+    Ok(match u.int_in_range(0..=3)? {
+        0 => Operand::Literal(u.arbitrary()?),
+        1 => Operand::Fixed(u.int_in_range(0..=32)?),
+        2 => Operand::Vbr(u.int_in_range(2..=32)?),
+        _ => Operand::Char6,
+    })
+}
+
+fn element_operand(u: &mut Unstructured<'_>) -> Result<Operand> {
+    Ok(match u.int_in_range(0..=2)? {
+        0 => Operand::Fixed(u.int_in_range(0..=32)?),
+        1 => Operand::Vbr(u.int_in_range(2..=32)?),
+        _ => Operand::Char6,
+    })
+}
+
+/// A bit writer keeping track of the block lengths to fill in
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+    bit: usize,
+    /// Byte offsets of the length words of the open blocks
+    blocks: Vec<usize>,
+}
+
+impl Writer {
+    fn write(&mut self, value: u64, width: usize) {
+        for i in 0..width {
+            if self.bit.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 1 << (self.bit % 8);
+            }
+            self.bit += 1;
+        }
+    }
+
+    fn write_vbr(&mut self, mut value: u64, width: usize) {
+        let threshold = 1 << (width - 1);
+        while value >= threshold {
+            self.write((value & (threshold - 1)) | threshold, width);
+            value >>= width - 1;
+        }
+        self.write(value, width);
+    }
+
+    fn align32(&mut self) {
+        while !self.bit.is_multiple_of(32) {
+            self.write(0, 1);
+        }
+    }
+
+    fn enter_block(&mut self, abbrev_width: usize, id: u64, new_width: usize) {
+        self.write(1, abbrev_width);
+        self.write_vbr(id, 8);
+        self.write_vbr(new_width as u64, 4);
+        self.align32();
+        self.blocks.push(self.bytes.len());
+        self.write(0, 32);
+    }
+
+    fn end_block(&mut self, width: usize) {
+        self.write(0, width);
+        self.align32();
+        let start = self.blocks.pop().unwrap();
+        let words = ((self.bytes.len() - start - 4) / 4) as u32;
+        self.bytes[start..start + 4].copy_from_slice(&words.to_le_bytes());
+    }
+
+    fn unabbreviated_record(&mut self, width: usize, code: u64, fields: &[u64]) {
+        self.write(3, width);
+        self.write_vbr(code, 6);
+        self.write_vbr(fields.len() as u64, 6);
+        for field in fields {
+            self.write_vbr(*field, 6);
+        }
+    }
+
+    /// Write an abbreviation operand definition
+    fn operand(&mut self, op: &Operand) {
+        match op {
+            Operand::Literal(value) => {
+                self.write(1, 1);
+                self.write_vbr(*value, 8);
+            }
+            op => {
+                self.write(0, 1);
+                self.write(u64::from(op.encoded_kind()), 3);
+                match op {
+                    Operand::Fixed(width) | Operand::Vbr(width) => {
+                        self.write_vbr(u64::from(*width), 5)
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Write a random value for a scalar operand
+    fn scalar(&mut self, u: &mut Unstructured<'_>, op: &Operand) -> Result<()> {
+        match op {
+            Operand::Literal(_) => {}
+            Operand::Fixed(width) => self.write(u.arbitrary()?, usize::from(*width)),
+            Operand::Vbr(width) => self.write_vbr(u.arbitrary()?, usize::from(*width)),
+            Operand::Char6 => self.write(u.int_in_range(0..=63)?, 6),
+            Operand::Array(_) | Operand::Blob => unreachable!("not a scalar operand"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+/*
+ * C API of llvm-bitcode, built with the `capi` feature:
+ *
+ *     cargo rustc --release --features capi --crate-type cdylib
+ *
+ * Strings returned as `const char *` are owned by the bitcode handle and
+ * valid until it is freed. Strings returned as `char *` must be released
+ * with llvm_bitcode_string_free.
+ */
+#ifndef LLVM_BITCODE_H
+#define LLVM_BITCODE_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct LlvmBitcode LlvmBitcode;
+
+/* Linkage of a symbol */
+enum {
+  LLVM_BITCODE_LINKAGE_EXTERNAL = 0,
+  LLVM_BITCODE_LINKAGE_AVAILABLE_EXTERNALLY = 1,
+  LLVM_BITCODE_LINKAGE_LINKONCE_ANY = 2,
+  LLVM_BITCODE_LINKAGE_LINKONCE_ODR = 3,
+  LLVM_BITCODE_LINKAGE_WEAK_ANY = 4,
+  LLVM_BITCODE_LINKAGE_WEAK_ODR = 5,
+  LLVM_BITCODE_LINKAGE_APPENDING = 6,
+  LLVM_BITCODE_LINKAGE_INTERNAL = 7,
+  LLVM_BITCODE_LINKAGE_PRIVATE = 8,
+  LLVM_BITCODE_LINKAGE_EXTERNAL_WEAK = 9,
+  LLVM_BITCODE_LINKAGE_COMMON = 10
+};
+
+/* Kind of global value a symbol belongs to */
+enum {
+  LLVM_BITCODE_SYMBOL_FUNCTION = 0,
+  LLVM_BITCODE_SYMBOL_VARIABLE = 1,
+  LLVM_BITCODE_SYMBOL_ALIAS = 2,
+  LLVM_BITCODE_SYMBOL_IFUNC = 3,
+  /* A symbol of module-level inline assembly */
+  LLVM_BITCODE_SYMBOL_ASM = 4
+};
+
+/* Called for every record, returns zero to continue */
+typedef int (*LlvmBitcodeRecordCallback)(void *user_data, uint64_t block_id,
+                                         uint64_t record_id,
+                                         const uint64_t *fields,
+                                         size_t num_fields);
+
+/* Parse bitcode, wrapped or not. Returns NULL on failure, storing the error
+ * message in *error unless error is NULL. */
+LlvmBitcode *llvm_bitcode_parse(const uint8_t *data, size_t len, char **error);
+void llvm_bitcode_free(LlvmBitcode *bitcode);
+void llvm_bitcode_string_free(char *s);
+
+/* Magic number of the bitstream, 0xdec04342 for LLVM IR */
+uint32_t llvm_bitcode_signature(const LlvmBitcode *bitcode);
+/* Module information, NULL when absent */
+const char *llvm_bitcode_triple(const LlvmBitcode *bitcode);
+const char *llvm_bitcode_data_layout(const LlvmBitcode *bitcode);
+const char *llvm_bitcode_source_filename(const LlvmBitcode *bitcode);
+const char *llvm_bitcode_producer(const LlvmBitcode *bitcode);
+
+/* Dump the blocks and records, with their names where known */
+char *llvm_bitcode_dump(const LlvmBitcode *bitcode);
+/* Visit every record depth first, returning the first nonzero value
+ * returned by callback, or zero */
+int llvm_bitcode_visit_records(const LlvmBitcode *bitcode,
+                               LlvmBitcodeRecordCallback callback,
+                               void *user_data);
+
+/* Symbols of the module, like llvm-nm lists them. Accessors return NULL or
+ * -1 when index is out of range. */
+size_t llvm_bitcode_symbol_count(const LlvmBitcode *bitcode);
+const char *llvm_bitcode_symbol_name(const LlvmBitcode *bitcode, size_t index);
+/* One of LLVM_BITCODE_LINKAGE_* */
+int llvm_bitcode_symbol_linkage(const LlvmBitcode *bitcode, size_t index);
+/* One of LLVM_BITCODE_SYMBOL_* */
+int llvm_bitcode_symbol_kind(const LlvmBitcode *bitcode, size_t index);
+/* 1 if the symbol is defined for the linker, 0 otherwise */
+int llvm_bitcode_symbol_defined(const LlvmBitcode *bitcode, size_t index);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* LLVM_BITCODE_H */
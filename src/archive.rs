@@ -0,0 +1,202 @@
+//! `ar` archives, such as static libraries built for LTO
+//!
+//! GNU, BSD and thin archives are supported. Thin archives only store the
+//! paths of their members, relative to the archive, and not their contents.
+use std::{error, fmt, str};
+
+use crate::bitcode::Signature;
+
+const MAGIC: &[u8] = b"!<arch>\n";
+const THIN_MAGIC: &[u8] = b"!<thin>\n";
+const HEADER_SIZE: usize = 60;
+
+/// Errors reading an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The data does not start with an archive magic string
+    InvalidMagic,
+    /// The member header at this offset is malformed or out of bounds
+    InvalidHeader(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidMagic => write!(f, "invalid archive magic"),
+            Error::InvalidHeader(offset) => {
+                write!(f, "invalid archive member header at offset {}", offset)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A member of an archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveMember<'a> {
+    /// Name of the member, a path relative to the archive for thin archives
+    pub name: &'a str,
+    /// Contents of the member, `None` for members of thin archives
+    pub data: Option<&'a [u8]>,
+    /// Size in bytes of the member, also known for thin archives
+    pub size: usize,
+}
+
+impl<'a> ArchiveMember<'a> {
+    /// Whether the member is LLVM IR bitcode, wrapped or not
+    pub fn is_bitcode(&self) -> bool {
+        match self.data {
+            Some(data) => {
+                matches!(Signature::parse(data), Ok((signature, _)) if signature.is_llvm_ir())
+            }
+            None => false,
+        }
+    }
+}
+
+/// An `ar` archive
+#[derive(Debug, Clone, Copy)]
+pub struct Archive<'a> {
+    data: &'a [u8],
+    thin: bool,
+}
+
+impl<'a> Archive<'a> {
+    /// Check the magic string of an archive
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let thin = match data.get(..MAGIC.len()) {
+            Some(MAGIC) => false,
+            Some(THIN_MAGIC) => true,
+            _ => return Err(Error::InvalidMagic),
+        };
+        Ok(Self { data, thin })
+    }
+
+    /// Whether this is a thin archive, whose members are stored outside of it
+    pub fn is_thin(&self) -> bool {
+        self.thin
+    }
+
+    /// Iterate over the members, in archive order
+    ///
+    /// Symbol tables and the GNU long name table are skipped.
+    pub fn members(&self) -> Members<'a> {
+        Members {
+            data: self.data,
+            thin: self.thin,
+            offset: MAGIC.len(),
+            long_names: None,
+        }
+    }
+
+    /// Iterate over the bitcode members, see [`ArchiveMember::is_bitcode`]
+    pub fn bitcode_members(&self) -> impl Iterator<Item = Result<ArchiveMember<'a>, Error>> {
+        self.members()
+            .filter(|member| member.as_ref().map_or(true, ArchiveMember::is_bitcode))
+    }
+}
+
+/// Iterator over the members of an archive, see [`Archive::members`]
+///
+/// Iteration stops after the first error.
+#[derive(Debug, Clone)]
+pub struct Members<'a> {
+    data: &'a [u8],
+    thin: bool,
+    offset: usize,
+    /// GNU table of names longer than 15 bytes
+    long_names: Option<&'a [u8]>,
+}
+
+impl<'a> Members<'a> {
+    fn read_member(&mut self) -> Result<Option<ArchiveMember<'a>>, Error> {
+        loop {
+            if self.offset >= self.data.len() {
+                return Ok(None);
+            }
+            let start = self.offset;
+            let invalid = Error::InvalidHeader(start);
+            let header = self.data.get(start..start + HEADER_SIZE).ok_or(invalid)?;
+            if &header[58..] != b"`\n" {
+                return Err(invalid);
+            }
+            let size: usize = field(&header[48..58])
+                .and_then(|size| size.parse().ok())
+                .ok_or(invalid)?;
+            let raw_name = field(&header[..16]).ok_or(invalid)?;
+            let body = start + HEADER_SIZE;
+            let is_special = matches!(raw_name, "/" | "//" | "/SYM64/");
+            // Only symbol tables and long name tables are stored in thin
+            // archives
+            let stored = if self.thin && !is_special { 0 } else { size };
+            let end = body
+                .checked_add(stored)
+                .filter(|&end| end <= self.data.len())
+                .ok_or(invalid)?;
+            // Members are aligned to 2 bytes
+            self.offset = end + end % 2;
+            let contents = &self.data[body..end];
+
+            if raw_name == "//" {
+                self.long_names = Some(contents);
+                continue;
+            }
+            if is_special {
+                continue;
+            }
+            let (name, data) = if let Some(len) = raw_name.strip_prefix("#1/") {
+                // BSD names are stored at the start of the contents
+                let len: usize = len.parse().map_err(|_| invalid)?;
+                let name = contents.get(..len).ok_or(invalid)?;
+                let name = str::from_utf8(name).map_err(|_| invalid)?;
+                (name.trim_end_matches('\0'), &contents[len..])
+            } else if let Some(offset) = raw_name.strip_prefix('/') {
+                let offset: usize = offset.parse().map_err(|_| invalid)?;
+                let names = self.long_names.and_then(|names| names.get(offset..));
+                let name = names.ok_or(invalid)?;
+                let end = name
+                    .windows(2)
+                    .position(|window| window == b"/\n")
+                    .or_else(|| name.iter().position(|&byte| byte == b'\n'))
+                    .unwrap_or(name.len());
+                let name = str::from_utf8(&name[..end]).map_err(|_| invalid)?;
+                (name, contents)
+            } else {
+                // GNU terminates short names with a slash
+                (raw_name.strip_suffix('/').unwrap_or(raw_name), contents)
+            };
+            // BSD symbol tables
+            if name.starts_with("__.SYMDEF") {
+                continue;
+            }
+            let size = size - (contents.len() - data.len());
+            return Ok(Some(ArchiveMember {
+                name,
+                data: if self.thin { None } else { Some(data) },
+                size,
+            }));
+        }
+    }
+}
+
+impl<'a> Iterator for Members<'a> {
+    type Item = Result<ArchiveMember<'a>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_member() {
+            Ok(member) => member.map(Ok),
+            Err(err) => {
+                self.offset = self.data.len();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// A space padded header field
+fn field(bytes: &[u8]) -> Option<&str> {
+    str::from_utf8(bytes)
+        .ok()
+        .map(|field| field.trim_end_matches(' '))
+}
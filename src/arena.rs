@@ -0,0 +1,333 @@
+//! Bitcode read into flat storage shared by all of its records
+//!
+//! A [`BitcodeArena`] holds the same blocks and records as [`Bitcode`],
+//! without a vector per block and per record, for large modules that are
+//! read once and walked many times.
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::bitcode::{BitcodeElement, Block, BlockInfo, Payload, Record, Signature};
+use crate::bitstream::AbbrevInfo;
+use crate::read::{BitStreamReader, BlockContext, Error};
+use crate::visitor::TryBitStreamVisitor;
+use crate::Bitcode;
+
+/// The contents of a bitstream collected into shared storage
+///
+/// Unlike [`Bitcode`], blocks and records don't own a vector each: the
+/// fields of every record live in a single buffer, and blocks and records
+/// are stored flat in the order they appear in the stream. Only fields and
+/// element lists are pooled this way; an array or char6 payload is still
+/// kept in its own allocation, while blobs borrow from the input.
+#[derive(Debug, Clone)]
+pub struct BitcodeArena<'input> {
+    pub signature: Signature,
+    pub block_info: HashMap<u64, BlockInfo>,
+    fields: Vec<u64>,
+    nodes: Vec<Node<'input>>,
+}
+
+#[derive(Debug, Clone)]
+enum Node<'input> {
+    /// A block whose elements are the nodes up to `end`
+    Block {
+        id: u64,
+        end: usize,
+        bit_range: Option<Range<u64>>,
+    },
+    Record {
+        id: u64,
+        fields: Range<usize>,
+        payload: Option<Payload<'input>>,
+        abbrev: Option<AbbrevInfo>,
+        bit_range: Option<Range<u64>>,
+    },
+}
+
+impl<'input> BitcodeArena<'input> {
+    /// Parse bitcode from bytes
+    ///
+    /// Accepts both LLVM bitcode and bitcode wrapper formats
+    pub fn new(data: &'input [u8]) -> Result<Self, Error> {
+        let (signature, mut reader) = Bitcode::reader(data)?;
+        let mut visitor = ArenaVisitor {
+            fields: Vec::new(),
+            nodes: Vec::new(),
+            open: Vec::new(),
+        };
+        reader.read_block_reusing(BitStreamReader::TOP_LEVEL_BLOCK_ID, 2, &mut visitor)?;
+        Ok(Self {
+            signature,
+            block_info: reader.block_info,
+            fields: visitor.fields,
+            nodes: visitor.nodes,
+        })
+    }
+
+    /// Top level elements
+    pub fn elements(&self) -> ArenaElements<'_, 'input> {
+        ArenaElements {
+            arena: self,
+            next: 0,
+            end: self.nodes.len(),
+        }
+    }
+
+    /// Number of records stored in the arena
+    pub fn record_count(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| matches!(node, Node::Record { .. }))
+            .count()
+    }
+
+    /// Copy the contents into the tree representation of [`Bitcode`]
+    pub fn to_bitcode(&self) -> Bitcode<'input> {
+        Bitcode {
+            signature: self.signature,
+            elements: self
+                .elements()
+                .map(|element| element.to_element())
+                .collect(),
+            block_info: self.block_info.clone(),
+            abbrevs: Default::default(),
+        }
+    }
+}
+
+/// An element of a [`BitcodeArena`]
+#[derive(Debug, Clone, Copy)]
+pub enum ArenaElement<'a, 'input> {
+    Block(ArenaBlock<'a, 'input>),
+    Record(ArenaRecord<'a, 'input>),
+}
+
+impl<'a, 'input> ArenaElement<'a, 'input> {
+    /// If it is a `Block`, returns the associated block. Returns `None` otherwise.
+    pub fn as_block(&self) -> Option<ArenaBlock<'a, 'input>> {
+        match self {
+            ArenaElement::Block(block) => Some(*block),
+            ArenaElement::Record(_) => None,
+        }
+    }
+
+    /// If it is a `Record`, returns the associated record. Returns `None` otherwise.
+    pub fn as_record(&self) -> Option<ArenaRecord<'a, 'input>> {
+        match self {
+            ArenaElement::Block(_) => None,
+            ArenaElement::Record(record) => Some(*record),
+        }
+    }
+
+    /// Copy the element out of the arena
+    pub fn to_element(&self) -> BitcodeElement<'input> {
+        match self {
+            ArenaElement::Block(block) => BitcodeElement::Block(block.to_block()),
+            ArenaElement::Record(record) => BitcodeElement::Record(record.to_record()),
+        }
+    }
+}
+
+/// A block stored in a [`BitcodeArena`]
+#[derive(Debug, Clone, Copy)]
+pub struct ArenaBlock<'a, 'input> {
+    arena: &'a BitcodeArena<'input>,
+    index: usize,
+}
+
+impl<'a, 'input> ArenaBlock<'a, 'input> {
+    /// Block ID
+    pub fn id(&self) -> u64 {
+        match self.arena.nodes[self.index] {
+            Node::Block { id, .. } => id,
+            Node::Record { .. } => unreachable!(),
+        }
+    }
+
+    /// Block elements
+    pub fn elements(&self) -> ArenaElements<'a, 'input> {
+        match self.arena.nodes[self.index] {
+            Node::Block { end, .. } => ArenaElements {
+                arena: self.arena,
+                next: self.index + 1,
+                end,
+            },
+            Node::Record { .. } => unreachable!(),
+        }
+    }
+
+    /// Bits spanned by the block, see [`Block::bit_range`]
+    pub fn bit_range(&self) -> Option<Range<u64>> {
+        match &self.arena.nodes[self.index] {
+            Node::Block { bit_range, .. } => bit_range.clone(),
+            Node::Record { .. } => unreachable!(),
+        }
+    }
+
+    /// Copy the block out of the arena
+    pub fn to_block(&self) -> Block<'input> {
+        Block {
+            id: self.id(),
+            elements: self
+                .elements()
+                .map(|element| element.to_element())
+                .collect(),
+            bit_range: self.bit_range(),
+            unparsed: None,
+        }
+    }
+}
+
+/// A record stored in a [`BitcodeArena`]
+#[derive(Debug, Clone, Copy)]
+pub struct ArenaRecord<'a, 'input> {
+    arena: &'a BitcodeArena<'input>,
+    index: usize,
+}
+
+impl<'a, 'input> ArenaRecord<'a, 'input> {
+    /// Record code
+    pub fn id(&self) -> u64 {
+        match self.arena.nodes[self.index] {
+            Node::Record { id, .. } => id,
+            Node::Block { .. } => unreachable!(),
+        }
+    }
+
+    /// Record fields, see [`Record::fields`]
+    pub fn fields(&self) -> &'a [u64] {
+        match &self.arena.nodes[self.index] {
+            Node::Record { fields, .. } => &self.arena.fields[fields.clone()],
+            Node::Block { .. } => unreachable!(),
+        }
+    }
+
+    /// Array and Blob encoding has payload
+    pub fn payload(&self) -> Option<&'a Payload<'input>> {
+        match &self.arena.nodes[self.index] {
+            Node::Record { payload, .. } => payload.as_ref(),
+            Node::Block { .. } => unreachable!(),
+        }
+    }
+
+    /// The abbreviation the record was read with, `None` if it is unabbreviated
+    pub fn abbrev(&self) -> Option<&'a AbbrevInfo> {
+        match &self.arena.nodes[self.index] {
+            Node::Record { abbrev, .. } => abbrev.as_ref(),
+            Node::Block { .. } => unreachable!(),
+        }
+    }
+
+    /// Bits spanned by the record, see [`Record::bit_range`]
+    pub fn bit_range(&self) -> Option<Range<u64>> {
+        match &self.arena.nodes[self.index] {
+            Node::Record { bit_range, .. } => bit_range.clone(),
+            Node::Block { .. } => unreachable!(),
+        }
+    }
+
+    /// Copy the record out of the arena
+    pub fn to_record(&self) -> Record<'input> {
+        Record {
+            id: self.id(),
+            fields: self.fields().to_vec(),
+            payload: self.payload().cloned(),
+            abbrev: self.abbrev().cloned(),
+            bit_range: self.bit_range(),
+        }
+    }
+}
+
+/// An iterator over the elements of a block stored in a [`BitcodeArena`]
+#[derive(Debug, Clone)]
+pub struct ArenaElements<'a, 'input> {
+    arena: &'a BitcodeArena<'input>,
+    next: usize,
+    end: usize,
+}
+
+impl<'a, 'input> Iterator for ArenaElements<'a, 'input> {
+    type Item = ArenaElement<'a, 'input>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        let index = self.next;
+        match self.arena.nodes[index] {
+            Node::Block { end, .. } => {
+                self.next = end;
+                Some(ArenaElement::Block(ArenaBlock {
+                    arena: self.arena,
+                    index,
+                }))
+            }
+            Node::Record { .. } => {
+                self.next += 1;
+                Some(ArenaElement::Record(ArenaRecord {
+                    arena: self.arena,
+                    index,
+                }))
+            }
+        }
+    }
+}
+
+/// Collects a bitstream into arena storage
+struct ArenaVisitor<'input> {
+    fields: Vec<u64>,
+    nodes: Vec<Node<'input>>,
+    /// Indices of the blocks being read
+    open: Vec<usize>,
+}
+
+impl<'input> TryBitStreamVisitor<'input> for ArenaVisitor<'input> {
+    type Error = Error;
+
+    fn should_enter_block(&mut self, id: u64) -> Result<bool, Error> {
+        self.open.push(self.nodes.len());
+        self.nodes.push(Node::Block {
+            id,
+            end: 0,
+            bit_range: None,
+        });
+        Ok(true)
+    }
+
+    fn should_enter_block_with_context(&mut self, context: &BlockContext) -> Result<bool, Error> {
+        self.open.push(self.nodes.len());
+        self.nodes.push(Node::Block {
+            id: context.id,
+            end: 0,
+            bit_range: Some(context.bit_range()),
+        });
+        Ok(true)
+    }
+
+    fn did_exit_block(&mut self) -> Result<(), Error> {
+        if let Some(index) = self.open.pop() {
+            let len = self.nodes.len();
+            if let Node::Block { end, .. } = &mut self.nodes[index] {
+                *end = len;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit(&mut self, record: Record<'input>) -> Result<(), Error> {
+        self.visit_ref(&record)
+    }
+
+    fn visit_ref(&mut self, record: &Record<'input>) -> Result<(), Error> {
+        let start = self.fields.len();
+        self.fields.extend_from_slice(&record.fields);
+        self.nodes.push(Node::Record {
+            id: record.id,
+            fields: start..self.fields.len(),
+            payload: record.payload.clone(),
+            abbrev: record.abbrev.clone(),
+            bit_range: record.bit_range.clone(),
+        });
+        Ok(())
+    }
+}
@@ -1,18 +1,41 @@
-use std::collections::HashMap;
+use std::{borrow::Cow, collections::HashMap, fmt, ops::Range, sync::Arc};
 
-use crate::bits::Bits;
-use crate::read::{BitStreamReader, Error};
-use crate::visitor::{BitStreamVisitor, CollectingVisitor};
+use crate::bits;
+use crate::bitstream::{AbbrevInfo, Abbreviation};
+use crate::read::{BitStreamReader, BlockContext, BlockTable, Error, ParseOptions};
+use crate::schema::blocks::BlockId;
+use crate::schema::codes::{
+    IDENTIFICATION_CODE_STRING, MODULE_CODE_DATALAYOUT, MODULE_CODE_SOURCE_FILENAME,
+    MODULE_CODE_TRIPLE,
+};
+use crate::size::MemoryUsage;
+use crate::stream::Events;
+use crate::visitor::{CollectOptions, CollectingVisitor, LazyVisitor, TryBitStreamVisitor};
 
-const LLVM_BITCODE_WRAPPER_MAGIC: u32 = 0x0B17C0DE;
+pub(crate) const LLVM_BITCODE_WRAPPER_MAGIC: u32 = 0x0B17C0DE;
 
 /// Represents the contents of a file encoded using the
 /// [LLVM bitstream container format](https://llvm.org/docs/BitCodeFormat.html#bitstream-container-format)
-#[derive(Debug, Clone)]
-pub struct Bitcode {
+///
+/// Blob payloads borrow from the parsed buffer, use [`Bitcode::into_owned`]
+/// to detach the result from it.
+#[derive(Clone)]
+pub struct Bitcode<'input> {
     pub signature: Signature,
-    pub elements: Vec<BitcodeElement>,
+    pub elements: Vec<BitcodeElement<'input>>,
     pub block_info: HashMap<u64, BlockInfo>,
+    /// Abbreviations defined by `BLOCKINFO`, to parse unparsed blocks with
+    pub(crate) abbrevs: Arc<BlockTable<Vec<Arc<Abbreviation>>>>,
+}
+
+impl fmt::Debug for Bitcode<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bitcode")
+            .field("signature", &self.signature)
+            .field("elements", &self.elements)
+            .field("block_info", &self.block_info)
+            .finish()
+    }
 }
 
 /// Blocks in a bitstream denote nested regions of the stream,
@@ -22,50 +45,149 @@ pub struct Bitcode {
 /// whose meaning is defined by Bitcode;
 /// block IDs 8 and greater are application specific.
 #[derive(Debug, Clone)]
-pub struct Block {
+pub struct Block<'input> {
     /// Block ID
     pub id: u64,
     /// Block elements
-    pub elements: Vec<BitcodeElement>,
+    pub elements: Vec<BitcodeElement<'input>>,
+    /// Bits spanned by the block from the start of its header, relative to
+    /// the start of the reader's buffer. `None` if not read from a stream
+    pub bit_range: Option<Range<u64>>,
+    /// Header of the block if its body was left unparsed, `elements` is
+    /// empty then. See [`Bitcode::parse_block`]
+    pub unparsed: Option<BlockContext>,
 }
 
 #[derive(Debug, Clone)]
-pub enum Payload {
+pub enum Payload<'input> {
     Array(Vec<u64>),
     Char6String(String),
-    Blob(Vec<u8>),
+    /// Blob bytes, borrowed from the input unless made owned
+    Blob(Cow<'input, [u8]>),
 }
 
 /// Data records consist of a record code and a number of (up to) 64-bit integer values
 ///
 /// The interpretation of the code and values is application specific and may vary between different block types.
 #[derive(Debug, Clone)]
-pub struct Record {
+pub struct Record<'input> {
     /// Record code
     pub id: u64,
     /// An abbreviated record has a abbreviation id followed by a set of fields
     pub fields: Vec<u64>,
     /// Array and Blob encoding has payload
-    pub payload: Option<Payload>,
+    pub payload: Option<Payload<'input>>,
+    /// The abbreviation the record was read with, `None` if it is unabbreviated
+    pub abbrev: Option<AbbrevInfo>,
+    /// Bits spanned by the record from its abbreviation id, relative to the
+    /// start of the reader's buffer. `None` if not read from a stream
+    pub bit_range: Option<Range<u64>>,
 }
 
 /// Bitcode element
 #[derive(Debug, Clone)]
-pub enum BitcodeElement {
+pub enum BitcodeElement<'input> {
     /// Block
-    Block(Block),
+    Block(Block<'input>),
     /// Data record
-    Record(Record),
+    Record(Record<'input>),
+}
+
+impl<'input> Payload<'input> {
+    /// Returns a payload that owns its blob bytes
+    pub fn into_owned(self) -> Payload<'static> {
+        match self {
+            Payload::Array(array) => Payload::Array(array),
+            Payload::Char6String(s) => Payload::Char6String(s),
+            Payload::Blob(blob) => Payload::Blob(Cow::Owned(blob.into_owned())),
+        }
+    }
+}
+
+impl<'input> Record<'input> {
+    /// Returns a record that owns its payload
+    pub fn into_owned(self) -> Record<'static> {
+        Record {
+            id: self.id,
+            fields: self.fields,
+            payload: self.payload.map(Payload::into_owned),
+            abbrev: self.abbrev,
+            bit_range: self.bit_range,
+        }
+    }
+
+    /// Fields followed by the elements of the payload, whatever the
+    /// abbreviation the record was written with
+    pub fn operands(&self) -> Vec<u64> {
+        let mut operands = self.fields.clone();
+        match &self.payload {
+            Some(Payload::Array(elements)) => operands.extend(elements),
+            Some(Payload::Char6String(s)) => operands.extend(s.bytes().map(u64::from)),
+            Some(Payload::Blob(blob)) => operands.extend(blob.iter().copied().map(u64::from)),
+            None => {}
+        }
+        operands
+    }
+}
+
+impl<'input> Block<'input> {
+    /// Returns a block that owns all of its payloads
+    pub fn into_owned(self) -> Block<'static> {
+        Block {
+            id: self.id,
+            elements: self
+                .elements
+                .into_iter()
+                .map(BitcodeElement::into_owned)
+                .collect(),
+            bit_range: self.bit_range,
+            unparsed: self.unparsed,
+        }
+    }
+
+    /// Returns the direct child blocks with the given id
+    pub fn blocks(&self, id: impl Into<u64>) -> impl Iterator<Item = &Block<'input>> {
+        let id = id.into();
+        self.elements
+            .iter()
+            .filter_map(BitcodeElement::as_block)
+            .filter(move |block| block.id == id)
+    }
+
+    /// Returns the direct child records with the given record code
+    pub fn records(&self, id: u64) -> impl Iterator<Item = &Record<'input>> {
+        self.elements
+            .iter()
+            .filter_map(BitcodeElement::as_record)
+            .filter(move |record| record.id == id)
+    }
+
+    /// Returns the direct child records along with their code converted to
+    /// `K`, typically an enum of the record codes of the block
+    pub fn records_by<K: From<u64>>(&self) -> impl Iterator<Item = (K, &Record<'input>)> {
+        self.elements
+            .iter()
+            .filter_map(BitcodeElement::as_record)
+            .map(|record| (K::from(record.id), record))
+    }
 }
 
-impl BitcodeElement {
+impl<'input> BitcodeElement<'input> {
+    /// Returns an element that owns all of its payloads
+    pub fn into_owned(self) -> BitcodeElement<'static> {
+        match self {
+            BitcodeElement::Block(block) => BitcodeElement::Block(block.into_owned()),
+            BitcodeElement::Record(record) => BitcodeElement::Record(record.into_owned()),
+        }
+    }
+
     /// Returns true if it is a `Block`
     pub fn is_block(&self) -> bool {
         matches!(self, BitcodeElement::Block(_))
     }
 
     /// If it is a `Block`, returns the associated block. Returns `None` otherwise.
-    pub fn as_block(&self) -> Option<&Block> {
+    pub fn as_block(&self) -> Option<&Block<'input>> {
         match self {
             BitcodeElement::Block(block) => Some(block),
             BitcodeElement::Record(_) => None,
@@ -73,7 +195,7 @@ impl BitcodeElement {
     }
 
     /// If it is a `Block`, returns the associated mutable block. Returns `None` otherwise.
-    pub fn as_block_mut(&mut self) -> Option<&mut Block> {
+    pub fn as_block_mut(&mut self) -> Option<&mut Block<'input>> {
         match self {
             BitcodeElement::Block(block) => Some(block),
             BitcodeElement::Record(_) => None,
@@ -86,7 +208,7 @@ impl BitcodeElement {
     }
 
     /// If it is a `Record`, returns the associated record. Returns `None` otherwise.
-    pub fn as_record(&self) -> Option<&Record> {
+    pub fn as_record(&self) -> Option<&Record<'input>> {
         match self {
             BitcodeElement::Block(_) => None,
             BitcodeElement::Record(record) => Some(record),
@@ -94,7 +216,7 @@ impl BitcodeElement {
     }
 
     /// If it is a `Record`, returns the associated mutable record. Returns `None` otherwise.
-    pub fn as_record_mut(&mut self) -> Option<&mut Record> {
+    pub fn as_record_mut(&mut self) -> Option<&mut Record<'input>> {
         match self {
             BitcodeElement::Block(_) => None,
             BitcodeElement::Record(record) => Some(record),
@@ -112,63 +234,642 @@ pub struct BlockInfo {
 }
 
 /// aka. Magic number
-#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct Signature(u32);
 
 impl Signature {
+    /// Magic number of LLVM IR bitcode, `BC` followed by `0xC0DE`
+    pub const LLVM_IR: Signature = Signature(0xDEC0_4342);
+
     pub fn new(val: u32) -> Self {
         Self(val)
     }
 
+    /// Signature from the first four bytes of a stream
+    pub fn from_bytes(bytes: [u8; 4]) -> Self {
+        Self(u32::from_le_bytes(bytes))
+    }
+
     pub fn into_inner(self) -> u32 {
         self.0
     }
-}
 
-impl Bitcode {
-    fn clean(data: &[u8]) -> (Signature, &[u8]) {
-        assert!(data.len() > 4);
-        let signature = Bits::new(data).read_bits(0, 32) as u32;
+    /// The four bytes of the signature, in stream order
+    pub fn to_bytes(self) -> [u8; 4] {
+        self.0.to_le_bytes()
+    }
+
+    /// Whether this is the signature of LLVM IR bitcode
+    pub fn is_llvm_ir(self) -> bool {
+        self == Self::LLVM_IR
+    }
+
+    /// Read the signature at the start of `data`, returning it along with
+    /// the bitstream following it
+    ///
+    /// The signature is not checked, so streams of any format built on the
+    /// bitstream container are accepted. Bitcode wrapped in a wrapper header
+    /// is unwrapped first.
+    pub fn parse(data: &[u8]) -> Result<(Self, &[u8]), Error> {
+        let word = |index: usize| -> Result<u32, Error> {
+            match data.get(index..index + 4) {
+                Some(bytes) => Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+                None => Err(Error::ReadBits(bits::Error::BufferOverflow)),
+            }
+        };
+        let signature = word(0)?;
         if signature == LLVM_BITCODE_WRAPPER_MAGIC {
             // It is a LLVM Bitcode wrapper, remove wrapper header
-            assert!(data.len() > 20);
-            let offset = u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize;
-            let size = u32::from_le_bytes([data[12], data[13], data[14], data[15]]) as usize;
-            let data = &data[offset..offset + size];
-            let signature = Bits::new(data).read_bits(0, 32) as u32;
-            (Signature(signature), &data[4..])
+            let offset = word(8)? as usize;
+            let size = word(12)? as usize;
+            let data = offset
+                .checked_add(size)
+                .and_then(|end| data.get(offset..end))
+                .filter(|data| data.len() >= 4)
+                .ok_or(Error::InvalidWrapper)?;
+            let signature = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            Ok((Signature(signature), &data[4..]))
         } else {
-            (Signature(signature), &data[4..])
+            Ok((Signature(signature), &data[4..]))
         }
     }
+}
 
+impl<'input> Bitcode<'input> {
     /// Parse bitcode from bytes
     ///
     /// Accepts both LLVM bitcode and bitcode wrapper formats
-    pub fn new(data: &[u8]) -> Result<Self, Error> {
-        let (signature, stream) = Self::clean(data);
+    pub fn new(data: &'input [u8]) -> Result<Self, Error> {
+        Self::new_with_options(data, ParseOptions::default())
+    }
+
+    /// Parse bitcode from bytes, enforcing the limits of `options`
+    ///
+    /// Accepts both LLVM bitcode and bitcode wrapper formats
+    pub fn new_with_options(data: &'input [u8], options: ParseOptions) -> Result<Self, Error> {
+        let (signature, stream) = Signature::parse(data)?;
         let mut reader = BitStreamReader::new(stream);
+        reader.set_options(options);
         let mut visitor = CollectingVisitor::new();
         reader.read_block(BitStreamReader::TOP_LEVEL_BLOCK_ID, 2, &mut visitor)?;
         Ok(Self {
             signature,
             elements: visitor.finalize_top_level_elements(),
             block_info: reader.block_info,
+            abbrevs: Arc::new(reader.global_abbrevs),
+        })
+    }
+
+    /// Parse bitcode from bytes, collecting only what `options` allows
+    ///
+    /// Blocks left unparsed are decoded on demand with
+    /// [`Bitcode::parse_block`].
+    ///
+    /// Accepts both LLVM bitcode and bitcode wrapper formats
+    pub fn new_with_collect_options(
+        data: &'input [u8],
+        options: CollectOptions,
+    ) -> Result<Self, Error> {
+        let (signature, stream) = Signature::parse(data)?;
+        let mut reader = BitStreamReader::new(stream);
+        let mut visitor = CollectingVisitor::with_options(options);
+        reader.read_block(BitStreamReader::TOP_LEVEL_BLOCK_ID, 2, &mut visitor)?;
+        Ok(Self {
+            signature,
+            elements: visitor.finalize_top_level_elements(),
+            block_info: reader.block_info,
+            abbrevs: Arc::new(reader.global_abbrevs),
         })
     }
 
+    /// Parse the body of a block left [unparsed](Block::unparsed)
+    ///
+    /// `data` is the input this bitcode was parsed from. Nested blocks are
+    /// all parsed.
+    pub fn parse_block(
+        &self,
+        data: &'input [u8],
+        context: &BlockContext,
+    ) -> Result<Block<'input>, Error> {
+        let (_, stream) = Signature::parse(data)?;
+        let mut reader = BitStreamReader::new(stream);
+        reader.global_abbrevs = BlockTable::clone(&self.abbrevs);
+        reader.block_info = self.block_info.clone();
+        reader.seek_to(context.offset)?;
+        let mut visitor = CollectingVisitor::new();
+        reader.read_block(context.id, context.abbrev_width, &mut visitor)?;
+        Ok(Block {
+            id: context.id,
+            elements: visitor.finalize_top_level_elements(),
+            bit_range: Some(context.bit_range()),
+            unparsed: None,
+        })
+    }
+
+    /// Memory-map the file at `path` to parse it without reading it into
+    /// memory first
+    ///
+    /// Parse the result with [`MappedBitcode::parse`], the parsed bitcode
+    /// borrows from the map.
+    ///
+    /// [`MappedBitcode::parse`]: crate::mmap::MappedBitcode::parse
+    #[cfg(feature = "mmap")]
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<crate::mmap::MappedBitcode> {
+        crate::mmap::MappedBitcode::open(path)
+    }
+
+    /// Parse bitcode from bytes, recovering from malformed blocks
+    ///
+    /// Blocks that can't be read completely are kept with the elements read
+    /// before the error, and reading resumes after their end. Returns the
+    /// partial bitcode along with the errors encountered, see
+    /// [`BitStreamReader::read_block_recovering`].
+    ///
+    /// Accepts both LLVM bitcode and bitcode wrapper formats
+    pub fn new_recovering(data: &'input [u8]) -> Result<(Self, Vec<Error>), Error> {
+        let (signature, stream) = Signature::parse(data)?;
+        let mut reader = BitStreamReader::new(stream);
+        let mut visitor = CollectingVisitor::new();
+        let mut diagnostics = Vec::new();
+        reader.read_block_recovering(
+            BitStreamReader::TOP_LEVEL_BLOCK_ID,
+            2,
+            &mut visitor,
+            &mut diagnostics,
+        )?;
+        let bitcode = Self {
+            signature,
+            elements: visitor.finalize_top_level_elements(),
+            block_info: reader.block_info,
+            abbrevs: Arc::new(reader.global_abbrevs),
+        };
+        Ok((bitcode, diagnostics))
+    }
+
+    /// Create a pull-based reader positioned after the signature
+    ///
+    /// Accepts both LLVM bitcode and bitcode wrapper formats
+    pub fn reader(data: &'input [u8]) -> Result<(Signature, BitStreamReader<'input>), Error> {
+        let (signature, stream) = Signature::parse(data)?;
+        Ok((signature, BitStreamReader::new(stream)))
+    }
+
+    /// Create a pull-based reader positioned at the body of a block, see
+    /// [`BitStreamReader::resume`]
+    ///
+    /// `offset` is relative to the end of the signature, like the offsets
+    /// reported by readers created with [`Bitcode::reader`].
+    pub fn reader_at(
+        data: &'input [u8],
+        offset: u64,
+    ) -> Result<(Signature, BitStreamReader<'input>), Error> {
+        let (signature, stream) = Signature::parse(data)?;
+        Ok((signature, BitStreamReader::resume(stream, offset)?))
+    }
+
+    /// Read bitcode from bytes with a visitor receiving records before their
+    /// fields are decoded
+    ///
+    /// Accepts both LLVM bitcode and bitcode wrapper formats
+    pub fn read_lazy<V>(data: &'input [u8], visitor: &mut V) -> Result<(), V::Error>
+    where
+        V: LazyVisitor<'input>,
+    {
+        let (signature, stream) = Signature::parse(data)?;
+        if !visitor.validate(signature) {
+            return Err(Error::InvalidSignature(signature.into_inner()).into());
+        }
+        let mut reader = BitStreamReader::new(stream);
+        reader.read_block_lazy(BitStreamReader::TOP_LEVEL_BLOCK_ID, 2, visitor)
+    }
+
+    /// Iterate over the blocks and records of bitcode as events
+    ///
+    /// Accepts both LLVM bitcode and bitcode wrapper formats
+    pub fn events(data: &'input [u8]) -> Result<Events<'input>, Error> {
+        let (_, stream) = Signature::parse(data)?;
+        Ok(Events::new(BitStreamReader::new(stream)))
+    }
+
     /// Read bitcode from bytes with a visitor
     ///
     /// Accepts both LLVM bitcode and bitcode wrapper formats
-    pub fn read<V>(data: &[u8], visitor: &mut V) -> Result<(), Error>
+    pub fn read<V>(data: &'input [u8], visitor: &mut V) -> Result<(), V::Error>
     where
-        V: BitStreamVisitor,
+        V: TryBitStreamVisitor<'input>,
     {
-        let (signature, stream) = Self::clean(data);
+        let (signature, stream) = Signature::parse(data)?;
         if !visitor.validate(signature) {
-            return Err(Error::InvalidSignature(signature.into_inner()));
+            return Err(Error::InvalidSignature(signature.into_inner()).into());
         }
         let mut reader = BitStreamReader::new(stream);
         reader.read_block(BitStreamReader::TOP_LEVEL_BLOCK_ID, 2, visitor)
     }
+
+    /// Read bitcode from bytes with a visitor, passing records by reference
+    /// to [`TryBitStreamVisitor::visit_ref`]
+    ///
+    /// Every record is decoded into the same scratch record, so visitors
+    /// overriding `visit_ref` don't cause an allocation per record.
+    pub fn read_reusing<V>(data: &'input [u8], visitor: &mut V) -> Result<(), V::Error>
+    where
+        V: TryBitStreamVisitor<'input>,
+    {
+        let (signature, stream) = Signature::parse(data)?;
+        if !visitor.validate(signature) {
+            return Err(Error::InvalidSignature(signature.into_inner()).into());
+        }
+        let mut reader = BitStreamReader::new(stream);
+        reader.read_block_reusing(BitStreamReader::TOP_LEVEL_BLOCK_ID, 2, visitor)
+    }
+
+    /// Returns bitcode that owns all of its payloads
+    pub fn into_owned(self) -> Bitcode<'static> {
+        Bitcode {
+            signature: self.signature,
+            elements: self
+                .elements
+                .into_iter()
+                .map(BitcodeElement::into_owned)
+                .collect(),
+            block_info: self.block_info,
+            abbrevs: self.abbrevs,
+        }
+    }
+
+    /// Heap memory used by the parsed elements, broken down by block id
+    pub fn memory_usage(&self) -> MemoryUsage {
+        MemoryUsage::new(self)
+    }
+
+    /// Returns the name given to a block by the stream's `BLOCKINFO`
+    pub fn block_name(&self, block_id: u64) -> Option<&str> {
+        self.block_info
+            .get(&block_id)
+            .map(|info| info.name.as_str())
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Returns the name given to a record by the stream's `BLOCKINFO`
+    pub fn record_name(&self, block_id: u64, record_id: u64) -> Option<&str> {
+        self.block_info
+            .get(&block_id)?
+            .record_names
+            .get(&record_id)
+            .map(String::as_str)
+    }
+
+    /// Returns a wrapper whose `Debug` output includes the block and record
+    /// names from the stream's `BLOCKINFO`
+    pub fn annotated(&self) -> Annotated<'_> {
+        Annotated {
+            bitcode: self,
+            names: None,
+        }
+    }
+
+    /// Returns a wrapper whose `Debug` output includes the block and record
+    /// names from `names`, falling back to the stream's `BLOCKINFO`
+    ///
+    /// Most formats other than LLVM IR don't name their blocks and records
+    /// in `BLOCKINFO`, their names have to be supplied.
+    pub fn annotated_with<'a>(&'a self, names: &'a NameTable) -> Annotated<'a> {
+        Annotated {
+            bitcode: self,
+            names: Some(names),
+        }
+    }
+
+    /// Returns the first block with the given id, searching nested blocks
+    /// depth-first
+    pub fn find_block(&self, id: impl Into<u64>) -> Option<&Block<'input>> {
+        let id = id.into();
+        find_block(&self.elements, id)
+    }
+
+    /// Returns the blocks reached by following a path of block ids from the
+    /// top level, e.g. `&[BlockId::Module, BlockId::Function]` for every
+    /// function block
+    pub fn query<I>(&self, path: &[I]) -> impl Iterator<Item = &Block<'input>>
+    where
+        I: Copy + Into<u64>,
+    {
+        let mut blocks: Vec<&Block<'input>> = Vec::new();
+        if let Some((first, rest)) = path.split_first() {
+            let first = (*first).into();
+            blocks.extend(
+                self.elements
+                    .iter()
+                    .filter_map(BitcodeElement::as_block)
+                    .filter(|block| block.id == first),
+            );
+            for id in rest {
+                let id = (*id).into();
+                blocks = blocks
+                    .into_iter()
+                    .flat_map(|block| block.blocks(id))
+                    .collect();
+            }
+        }
+        blocks.into_iter()
+    }
+}
+
+fn find_block<'a, 'input>(
+    elements: &'a [BitcodeElement<'input>],
+    id: u64,
+) -> Option<&'a Block<'input>> {
+    elements
+        .iter()
+        .filter_map(BitcodeElement::as_block)
+        .find_map(|block| {
+            if block.id == id {
+                Some(block)
+            } else {
+                find_block(&block.elements, id)
+            }
+        })
+}
+
+/// Names of blocks and records, for formats that don't name them in
+/// `BLOCKINFO`
+///
+/// ```
+/// use llvm_bitcode::bitcode::NameTable;
+///
+/// let names = NameTable::new()
+///     .block(8u64, "CONTROL_BLOCK")
+///     .record(8u64, 1u64, "METADATA");
+/// assert_eq!(names.record_name(8, 1), Some("METADATA"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct NameTable {
+    blocks: HashMap<u64, BlockInfo>,
+}
+
+impl NameTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Name a block
+    pub fn block(mut self, block_id: impl Into<u64>, name: impl Into<String>) -> Self {
+        self.blocks.entry(block_id.into()).or_default().name = name.into();
+        self
+    }
+
+    /// Name a record of a block
+    pub fn record(
+        mut self,
+        block_id: impl Into<u64>,
+        record_id: impl Into<u64>,
+        name: impl Into<String>,
+    ) -> Self {
+        self.blocks
+            .entry(block_id.into())
+            .or_default()
+            .record_names
+            .insert(record_id.into(), name.into());
+        self
+    }
+
+    /// Returns the name of a block
+    pub fn block_name(&self, block_id: u64) -> Option<&str> {
+        self.blocks
+            .get(&block_id)
+            .map(|info| info.name.as_str())
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Returns the name of a record
+    pub fn record_name(&self, block_id: u64, record_id: u64) -> Option<&str> {
+        self.blocks
+            .get(&block_id)?
+            .record_names
+            .get(&record_id)
+            .map(String::as_str)
+    }
+}
+
+/// Debug formatter for [`Bitcode`] showing block and record names
+///
+/// Created with [`Bitcode::annotated`] or [`Bitcode::annotated_with`].
+#[derive(Clone, Copy)]
+pub struct Annotated<'a> {
+    bitcode: &'a Bitcode<'a>,
+    names: Option<&'a NameTable>,
+}
+
+impl<'a> Annotated<'a> {
+    fn block_name(&self, block_id: u64) -> Option<&'a str> {
+        self.names
+            .and_then(|names| names.block_name(block_id))
+            .or_else(|| self.bitcode.block_name(block_id))
+    }
+
+    fn record_name(&self, block_id: u64, record_id: u64) -> Option<&'a str> {
+        self.names
+            .and_then(|names| names.record_name(block_id, record_id))
+            .or_else(|| self.bitcode.record_name(block_id, record_id))
+    }
+}
+
+impl fmt::Debug for Annotated<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Bitcode")
+            .field("signature", &self.bitcode.signature)
+            .field(
+                "elements",
+                &AnnotatedElements {
+                    annotated: *self,
+                    block_id: BitStreamReader::TOP_LEVEL_BLOCK_ID,
+                    elements: &self.bitcode.elements,
+                },
+            )
+            .finish()
+    }
+}
+
+struct AnnotatedElements<'a> {
+    annotated: Annotated<'a>,
+    block_id: u64,
+    elements: &'a [BitcodeElement<'a>],
+}
+
+impl fmt::Debug for AnnotatedElements<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut list = f.debug_list();
+        for element in self.elements {
+            match element {
+                BitcodeElement::Block(block) => list.entry(&AnnotatedBlock {
+                    annotated: self.annotated,
+                    block,
+                }),
+                BitcodeElement::Record(record) => list.entry(&AnnotatedRecord {
+                    name: self.annotated.record_name(self.block_id, record.id),
+                    record,
+                }),
+            };
+        }
+        list.finish()
+    }
+}
+
+struct AnnotatedBlock<'a> {
+    annotated: Annotated<'a>,
+    block: &'a Block<'a>,
+}
+
+impl fmt::Debug for AnnotatedBlock<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Block");
+        s.field("id", &self.block.id);
+        if let Some(name) = self.annotated.block_name(self.block.id) {
+            s.field("name", &name);
+        }
+        s.field(
+            "elements",
+            &AnnotatedElements {
+                annotated: self.annotated,
+                block_id: self.block.id,
+                elements: &self.block.elements,
+            },
+        )
+        .finish()
+    }
+}
+
+struct AnnotatedRecord<'a> {
+    name: Option<&'a str>,
+    record: &'a Record<'a>,
+}
+
+impl fmt::Debug for AnnotatedRecord<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Record");
+        s.field("id", &self.record.id);
+        if let Some(name) = self.name {
+            s.field("name", &name);
+        }
+        s.field("fields", &self.record.fields)
+            .field("payload", &self.record.payload)
+            .finish()
+    }
+}
+
+/// Surface-level information about a bitcode module
+///
+/// Obtained with [`BitcodeInfo::peek`] without collecting the whole stream.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BitcodeInfo {
+    /// Target triple
+    pub triple: Option<String>,
+    /// Data layout string
+    pub data_layout: Option<String>,
+    /// Source file name
+    pub source_filename: Option<String>,
+    /// Producer string from the identification block
+    pub producer: Option<String>,
+    /// Whether the module contains a global value summary block
+    pub has_summary: bool,
+}
+
+impl BitcodeInfo {
+    /// Scan only the identification block and the records directly
+    /// inside the module block, skipping every nested block
+    ///
+    /// Accepts both LLVM bitcode and bitcode wrapper formats
+    pub fn peek(data: &[u8]) -> Result<Self, Error> {
+        let mut visitor = PeekVisitor::default();
+        match Bitcode::read(data, &mut visitor) {
+            Ok(()) | Err(PeekError::Done) => Ok(visitor.info),
+            Err(PeekError::Read(err)) => Err(err),
+        }
+    }
+}
+
+#[derive(Default)]
+struct PeekVisitor {
+    info: BitcodeInfo,
+    stack: Vec<BlockId>,
+}
+
+/// Stops the read once the module block has been scanned
+enum PeekError {
+    Read(Error),
+    Done,
+}
+
+impl From<Error> for PeekError {
+    fn from(err: Error) -> Self {
+        Self::Read(err)
+    }
+}
+
+impl TryBitStreamVisitor<'_> for PeekVisitor {
+    type Error = PeekError;
+
+    fn should_enter_block(&mut self, id: u64) -> Result<bool, PeekError> {
+        Ok(match (self.stack.last(), BlockId::from(id)) {
+            (None, id @ (BlockId::Module | BlockId::Identification)) => {
+                self.stack.push(id);
+                true
+            }
+            (
+                Some(BlockId::Module),
+                BlockId::GlobalValSummary | BlockId::FullLtoGlobalValSummary,
+            ) => {
+                self.info.has_summary = true;
+                false
+            }
+            _ => false,
+        })
+    }
+
+    fn should_visit_record(
+        &mut self,
+        block_id: u64,
+        record_id: u64,
+        _abbrev_id: u64,
+    ) -> Result<bool, PeekError> {
+        Ok(matches!(
+            (BlockId::from(block_id), record_id),
+            (
+                BlockId::Module,
+                MODULE_CODE_TRIPLE | MODULE_CODE_DATALAYOUT | MODULE_CODE_SOURCE_FILENAME
+            ) | (BlockId::Identification, IDENTIFICATION_CODE_STRING)
+        ))
+    }
+
+    fn did_exit_block(&mut self) -> Result<(), PeekError> {
+        match self.stack.pop() {
+            Some(BlockId::Module) => Err(PeekError::Done),
+            _ => Ok(()),
+        }
+    }
+
+    fn visit(&mut self, record: Record) -> Result<(), PeekError> {
+        let slot = match (self.stack.last(), record.id) {
+            (Some(BlockId::Module), MODULE_CODE_TRIPLE) => &mut self.info.triple,
+            (Some(BlockId::Module), MODULE_CODE_DATALAYOUT) => &mut self.info.data_layout,
+            (Some(BlockId::Module), MODULE_CODE_SOURCE_FILENAME) => &mut self.info.source_filename,
+            (Some(BlockId::Identification), IDENTIFICATION_CODE_STRING) => &mut self.info.producer,
+            _ => return Ok(()),
+        };
+        *slot = record_string(&record);
+        Ok(())
+    }
+}
+
+/// Decode a string record whose characters are stored either in its
+/// fields or in its array payload
+fn record_string(record: &Record) -> Option<String> {
+    let mut bytes: Vec<u8> = record.fields.iter().map(|x| *x as u8).collect();
+    match &record.payload {
+        Some(Payload::Array(elements)) => bytes.extend(elements.iter().map(|x| *x as u8)),
+        Some(Payload::Char6String(s)) => bytes.extend_from_slice(s.as_bytes()),
+        Some(Payload::Blob(blob)) => bytes.extend_from_slice(blob),
+        None => {}
+    }
+    String::from_utf8(bytes).ok()
 }
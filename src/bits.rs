@@ -1,3 +1,4 @@
+use std::convert::TryInto;
 use std::{error, fmt};
 
 #[derive(Debug, Clone)]
@@ -17,16 +18,48 @@ impl fmt::Display for Error {
 
 impl error::Error for Error {}
 
+/// Decode a 6-bit char6 value into the character it encodes
+///
+/// Char6 encodes `[a-zA-Z0-9._]`, `None` if `value` doesn't fit in 6 bits.
+pub fn char6_decode(value: u64) -> Option<char> {
+    let c = match value {
+        0..=25 => b'a' + value as u8,
+        26..=51 => b'A' + (value - 26) as u8,
+        52..=61 => b'0' + (value - 52) as u8,
+        62 => b'.',
+        63 => b'_',
+        _ => return None,
+    };
+    Some(char::from(c))
+}
+
+/// Encode a character as a char6 value, `None` if char6 can't encode it
+pub fn char6_encode(c: char) -> Option<u64> {
+    let value = match c {
+        'a'..='z' => c as u64 - 'a' as u64,
+        'A'..='Z' => c as u64 - 'A' as u64 + 26,
+        '0'..='9' => c as u64 - '0' as u64 + 52,
+        '.' => 62,
+        '_' => 63,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// A byte buffer addressed in bits
+///
+/// Bit offsets are `u64` whatever the pointer width, so that the offsets of
+/// buffers larger than `usize::MAX / 8` bytes don't overflow.
 #[derive(Debug, Clone)]
-pub struct Bits<'a> {
+pub(crate) struct Bits<'a> {
     buffer: &'a [u8],
-    start_index: usize,
-    end_index: usize,
+    start_index: u64,
+    end_index: u64,
 }
 
 impl<'a> Bits<'a> {
     pub fn new(buffer: &'a [u8]) -> Self {
-        let end_index = buffer.len() * 8;
+        let end_index = (buffer.len() as u64).saturating_mul(8);
         Self {
             buffer,
             start_index: 0,
@@ -34,39 +67,61 @@ impl<'a> Bits<'a> {
         }
     }
 
-    pub fn read_bits(&self, offset: usize, count: usize) -> u64 {
-        let upper_bound = offset.wrapping_add(count);
+    pub fn read_bits(&self, offset: u64, count: usize) -> u64 {
         assert!(count <= 64);
-        assert!(upper_bound >= offset);
-        assert!(upper_bound <= self.end_index);
-        let top_byte_index = upper_bound >> 3;
+        assert!(offset <= self.end_index && count as u64 <= self.end_index - offset);
+        // In bounds of the buffer, so the byte index fits in `usize`
+        let byte_index = (offset >> 3) as usize;
+        let shift = (offset & 7) as usize;
+        if count + shift <= 64 {
+            // Fast path: a single 64-bit load covers all the requested bits
+            if let Some(word) = self.buffer.get(byte_index..byte_index + 8) {
+                let word = u64::from_le_bytes(word.try_into().unwrap()) >> shift;
+                return if count == 64 {
+                    word
+                } else {
+                    word & ((1u64 << count) - 1)
+                };
+            }
+        }
+        self.read_bits_slow(offset, count)
+    }
+
+    /// Byte by byte read, used near the end of the buffer
+    fn read_bits_slow(&self, offset: u64, count: usize) -> u64 {
+        let upper_bound = offset + count as u64;
+        let top_byte_index = (upper_bound >> 3) as usize;
         let mut res = 0;
         if upper_bound & 7 != 0 {
             let mask = (1u8 << (upper_bound & 7) as u8).wrapping_sub(1);
             res = u64::from(self.buffer[top_byte_index] & mask);
         }
-        for i in ((offset >> 3)..(upper_bound >> 3)).rev() {
+        for i in (((offset >> 3) as usize)..top_byte_index).rev() {
             res <<= 8;
             res |= u64::from(self.buffer[i]);
         }
         if offset & 7 != 0 {
-            res >>= offset as u64 & 7;
+            res >>= offset & 7;
         }
         res
     }
 
-    pub fn len(&self) -> usize {
+    /// Length in bits
+    pub fn len(&self) -> u64 {
         self.end_index
     }
 }
 
 #[derive(Debug, Clone)]
-pub struct Cursor<'a> {
+pub(crate) struct Cursor<'a> {
     buffer: Bits<'a>,
-    offset: usize,
+    offset: u64,
 }
 
 impl<'a> Cursor<'a> {
+    /// Number of bits read at once by `read_vbr`
+    const VBR_WINDOW: usize = 57;
+
     pub fn new(buffer: Bits<'a>) -> Self {
         Self { buffer, offset: 0 }
     }
@@ -80,8 +135,29 @@ impl<'a> Cursor<'a> {
         self.offset == self.buffer.len()
     }
 
+    pub fn bit_len(&self) -> u64 {
+        self.buffer.len()
+    }
+
+    /// Number of bits left to read
+    pub fn remaining(&self) -> u64 {
+        self.buffer.len() - self.offset
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn seek(&mut self, offset: u64) -> Result<(), Error> {
+        if offset > self.buffer.len() {
+            return Err(Error::BufferOverflow);
+        }
+        self.offset = offset;
+        Ok(())
+    }
+
     pub fn peek(&self, count: usize) -> Result<u64, Error> {
-        if self.buffer.len() - self.offset < count {
+        if self.remaining() < count as u64 {
             return Err(Error::BufferOverflow);
         }
         Ok(self.buffer.read_bits(self.offset, count))
@@ -89,71 +165,103 @@ impl<'a> Cursor<'a> {
 
     pub fn read(&mut self, count: usize) -> Result<u64, Error> {
         let res = self.peek(count)?;
-        self.offset += count;
+        self.offset += count as u64;
         Ok(res)
     }
 
-    pub fn read_bytes(&mut self, count: usize) -> Result<Vec<u8>, Error> {
-        assert_eq!(self.offset & 0b111, 0);
-        let offset = self.offset.wrapping_add(count << 3);
-        assert!(offset >= self.offset);
-        if offset > self.buffer.len() {
-            return Err(Error::BufferOverflow);
-        }
-        let bytes: Vec<u8> = self
-            .buffer
-            .buffer
-            .iter()
-            .skip(self.offset >> 3)
-            .take((offset - self.offset) >> 3)
-            .cloned()
-            .collect();
+    /// Read `count` bytes starting at the next multiple of 32 bits, where
+    /// blob data starts
+    pub fn read_aligned_slice(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        self.advance(32)?;
+        let offset = self.bytes_end(count)?;
+        // Both offsets are in bounds of the buffer
+        let bytes = &self.buffer.buffer[(self.offset >> 3) as usize..(offset >> 3) as usize];
         self.offset = offset;
         Ok(bytes)
     }
 
-    pub fn skip_bytes(&mut self, count: usize) -> Result<(), Error> {
-        assert_eq!(self.offset & 0b111, 0);
-        let offset = self.offset.wrapping_add(count << 3);
-        assert!(offset >= self.offset);
-        if offset > self.buffer.len() {
+    /// Skip `count` bits
+    pub fn skip_bits(&mut self, count: u64) -> Result<(), Error> {
+        if count > self.remaining() {
             return Err(Error::BufferOverflow);
         }
-        self.offset = offset;
+        self.offset += count;
         Ok(())
     }
 
+    /// Skip `count` bytes starting at the next multiple of 32 bits, like
+    /// [`Cursor::read_aligned_slice`]
+    pub fn skip_aligned_bytes(&mut self, count: usize) -> Result<(), Error> {
+        self.advance(32)?;
+        self.offset = self.bytes_end(count)?;
+        Ok(())
+    }
+
+    /// Offset after `count` bytes from the current offset, which the callers
+    /// have aligned
+    fn bytes_end(&self, count: usize) -> Result<u64, Error> {
+        (count as u64)
+            .checked_mul(8)
+            .and_then(|bits| self.offset.checked_add(bits))
+            .filter(|&offset| offset <= self.buffer.len())
+            .ok_or(Error::BufferOverflow)
+    }
+
+    #[inline]
     pub fn read_vbr(&mut self, width: usize) -> Result<u64, Error> {
         assert!(width > 1);
-        let test_bit = (1 << width.wrapping_sub(1)) as u64;
-        let mask = test_bit.wrapping_sub(1);
+        if width <= 32 && self.remaining() >= Self::VBR_WINDOW as u64 {
+            // Fast path: decode the chunks from a single read, short enough
+            // to be served by one 64-bit load whatever the bit alignment
+            let word = self.buffer.read_bits(self.offset, Self::VBR_WINDOW);
+            let test_bit = 1u64 << (width - 1);
+            let chunk_mask = (1u64 << width) - 1;
+            let mut res = 0;
+            let mut shift = 0;
+            for i in 0..Self::VBR_WINDOW / width {
+                let chunk = (word >> (i * width)) & chunk_mask;
+                res |= (chunk & !test_bit) << shift;
+                if chunk & test_bit == 0 {
+                    self.offset += ((i + 1) * width) as u64;
+                    return Ok(res);
+                }
+                shift += width - 1;
+            }
+        }
+        self.read_vbr_slow(width)
+    }
+
+    /// Chunk by chunk read, used near the end of the buffer and for values
+    /// spanning more than 64 bits
+    fn read_vbr_slow(&mut self, width: usize) -> Result<u64, Error> {
+        let test_bit = 1u64 << (width - 1);
+        let mask = test_bit - 1;
         let mut res = 0;
         let mut offset = 0;
-        let mut next;
         loop {
-            next = self.read(width)?;
-            res |= (next & mask) << offset;
-            offset += width.wrapping_sub(1);
-            if offset > 64 {
+            let next = self.read(width)?;
+            if offset >= 64 {
                 return Err(Error::VbrOverflow);
             }
+            res |= (next & mask) << offset;
             if next & test_bit == 0 {
                 break;
             }
+            offset += width - 1;
         }
         Ok(res)
     }
 
     pub fn advance(&mut self, align: usize) -> Result<(), Error> {
-        assert!(self.offset.wrapping_add(align.wrapping_sub(1)) >= self.offset);
-        assert_eq!(align & align.wrapping_sub(1), 0);
-        if self.offset % align == 0 {
+        assert!(align.is_power_of_two());
+        let mask = align as u64 - 1;
+        if self.offset & mask == 0 {
             return Ok(());
         }
-        let offset = (self.offset.wrapping_add(align)) & !(align.wrapping_sub(1));
-        if offset > self.buffer.len() {
-            return Err(Error::BufferOverflow);
-        }
+        let offset = (self.offset | mask)
+            .checked_add(1)
+            .filter(|&offset| offset <= self.buffer.len())
+            .ok_or(Error::BufferOverflow)?;
         self.offset = offset;
         Ok(())
     }
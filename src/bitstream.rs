@@ -1,4 +1,6 @@
-use num_enum::TryFromPrimitive;
+use std::sync::Arc;
+
+use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
 
 /// An `Abbreviation` represents the encoding definition for a user-defined
 /// record. An `Abbreviation` is the primary form of compression available in
@@ -9,6 +11,15 @@ pub struct Abbreviation {
     pub operands: Vec<Operand>,
 }
 
+/// The abbreviation an abbreviated record was read with
+#[derive(Debug, Clone)]
+pub struct AbbrevInfo {
+    /// Abbreviation id, `4` or greater
+    pub id: u64,
+    /// Abbreviation definition
+    pub abbrev: Arc<Abbreviation>,
+}
+
 /// Abbreviation operand
 #[derive(Debug, Clone)]
 pub enum Operand {
@@ -57,7 +68,7 @@ impl Operand {
     /// The llvm::BitCodeAbbrevOp::Encoding value this
     /// enum case represents.
     /// - note: Must match the encoding in
-    ///         http://llvm.org/docs/BitCodeFormat.html#define-abbrev-encoding
+    ///   http://llvm.org/docs/BitCodeFormat.html#define-abbrev-encoding
     pub fn encoded_kind(&self) -> u8 {
         use Operand::*;
 
@@ -77,8 +88,8 @@ impl Operand {
 /// a name is given to a block or record with `BlockName` or
 /// `SetRecordName`, debugging tools like `llvm-bcanalyzer` can be used to
 /// introspect the structure of blocks and records in the bitstream file.
-#[derive(Debug, Clone, Copy, TryFromPrimitive)]
-#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
 pub enum BlockInfoCode {
     /// Indicates which block ID is being described.
     SetBid = 1,
@@ -88,6 +99,9 @@ pub enum BlockInfoCode {
     /// An optional element that records the record ID number and the bytes
     /// for the name of the corresponding record.
     SetRecordName = 3,
+    /// A record code unknown to this crate, ignored like LLVM does
+    #[num_enum(catch_all)]
+    Unknown(u64),
 }
 
 /// An abbreviation id is a fixed-width field that occurs at the start of
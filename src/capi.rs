@@ -0,0 +1,327 @@
+//! C API, declared in `include/llvm_bitcode.h`
+//!
+//! Build it as a shared library with
+//! `cargo rustc --release --features capi --crate-type cdylib`.
+//!
+//! Parsed bitcode is an opaque `LlvmBitcode` handle, the input can be freed
+//! once it is parsed. Strings returned as `const char *` are owned by the
+//! handle and valid until it is freed, strings returned as `char *` must be
+//! released with [`llvm_bitcode_string_free`].
+use std::ffi::CString;
+use std::os::raw::{c_char, c_int, c_void};
+use std::{ptr, slice};
+
+use crate::bitcode::BitcodeElement;
+use crate::symbols::{self, Linkage, Symbol, SymbolKind};
+use crate::{BitStreamReader, Bitcode, BitcodeInfo};
+
+/// Parsed bitcode, the module information of [`BitcodeInfo`] and the
+/// symbols of the module
+pub struct LlvmBitcode {
+    bitcode: Bitcode<'static>,
+    triple: Option<CString>,
+    data_layout: Option<CString>,
+    source_filename: Option<CString>,
+    producer: Option<CString>,
+    symbols: Vec<(Symbol, Option<CString>)>,
+}
+
+/// Called for every record by [`llvm_bitcode_visit_records`], returning
+/// zero to continue
+pub type LlvmBitcodeRecordCallback = unsafe extern "C" fn(
+    user_data: *mut c_void,
+    block_id: u64,
+    record_id: u64,
+    fields: *const u64,
+    num_fields: usize,
+) -> c_int;
+
+/// Convert to a C string, `None` if it contains a NUL byte
+fn c_string(s: Option<String>) -> Option<CString> {
+    s.and_then(|s| CString::new(s).ok())
+}
+
+fn as_ptr(s: &Option<CString>) -> *const c_char {
+    s.as_ref().map_or(ptr::null(), |s| s.as_ptr())
+}
+
+/// Parse `len` bytes of bitcode, wrapped or not
+///
+/// Returns NULL on failure, storing the error message in `*error` unless
+/// `error` is NULL.
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes and `error` must be NULL or
+/// valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_parse(
+    data: *const u8,
+    len: usize,
+    error: *mut *mut c_char,
+) -> *mut LlvmBitcode {
+    let data = if len == 0 {
+        &[][..]
+    } else {
+        slice::from_raw_parts(data, len)
+    };
+    let result = Bitcode::new(data).and_then(|bitcode| {
+        let info = BitcodeInfo::peek(data)?;
+        Ok((bitcode.into_owned(), info))
+    });
+    // Streams that are not LLVM IR modules have no symbols
+    let symbols = symbols::symbols(data)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|symbol| {
+            let name = c_string(Some(symbol.name.clone()));
+            (symbol, name)
+        })
+        .collect();
+    match result {
+        Ok((bitcode, info)) => Box::into_raw(Box::new(LlvmBitcode {
+            bitcode,
+            triple: c_string(info.triple),
+            data_layout: c_string(info.data_layout),
+            source_filename: c_string(info.source_filename),
+            producer: c_string(info.producer),
+            symbols,
+        })),
+        Err(err) => {
+            if !error.is_null() {
+                *error = c_string(Some(err.to_string())).map_or(ptr::null_mut(), CString::into_raw);
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Free bitcode returned by [`llvm_bitcode_parse`]
+///
+/// # Safety
+///
+/// `bitcode` must be NULL or a handle that was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_free(bitcode: *mut LlvmBitcode) {
+    if !bitcode.is_null() {
+        drop(Box::from_raw(bitcode));
+    }
+}
+
+/// Free a string returned by this API
+///
+/// # Safety
+///
+/// `s` must be NULL or a `char *` returned by this API that was not freed
+/// yet.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Magic number of the bitstream, `0xdec04342` for LLVM IR
+///
+/// # Safety
+///
+/// `bitcode` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_signature(bitcode: *const LlvmBitcode) -> u32 {
+    (*bitcode).bitcode.signature.into_inner()
+}
+
+/// Target triple of the module, or NULL
+///
+/// # Safety
+///
+/// `bitcode` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_triple(bitcode: *const LlvmBitcode) -> *const c_char {
+    as_ptr(&(*bitcode).triple)
+}
+
+/// Data layout string of the module, or NULL
+///
+/// # Safety
+///
+/// `bitcode` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_data_layout(bitcode: *const LlvmBitcode) -> *const c_char {
+    as_ptr(&(*bitcode).data_layout)
+}
+
+/// Source file name of the module, or NULL
+///
+/// # Safety
+///
+/// `bitcode` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_source_filename(
+    bitcode: *const LlvmBitcode,
+) -> *const c_char {
+    as_ptr(&(*bitcode).source_filename)
+}
+
+/// Producer string of the identification block, or NULL
+///
+/// # Safety
+///
+/// `bitcode` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_producer(bitcode: *const LlvmBitcode) -> *const c_char {
+    as_ptr(&(*bitcode).producer)
+}
+
+/// Dump the blocks and records, with their names where known
+///
+/// # Safety
+///
+/// `bitcode` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_dump(bitcode: *const LlvmBitcode) -> *mut c_char {
+    let dump = format!("{:#?}", (*bitcode).bitcode.annotated());
+    c_string(Some(dump)).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Call `callback` for every record, depth first in stream order
+///
+/// Payloads are not passed to the callback. Returns the first nonzero
+/// value returned by `callback`, or zero once all records were visited.
+///
+/// # Safety
+///
+/// `bitcode` must be a valid handle. `fields` is only valid during the
+/// call of `callback`.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_visit_records(
+    bitcode: *const LlvmBitcode,
+    callback: LlvmBitcodeRecordCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    unsafe fn visit(
+        block_id: u64,
+        elements: &[BitcodeElement],
+        callback: LlvmBitcodeRecordCallback,
+        user_data: *mut c_void,
+    ) -> c_int {
+        for element in elements {
+            let status = match element {
+                BitcodeElement::Record(record) => callback(
+                    user_data,
+                    block_id,
+                    record.id,
+                    record.fields.as_ptr(),
+                    record.fields.len(),
+                ),
+                BitcodeElement::Block(block) => {
+                    visit(block.id, &block.elements, callback, user_data)
+                }
+            };
+            if status != 0 {
+                return status;
+            }
+        }
+        0
+    }
+    visit(
+        BitStreamReader::TOP_LEVEL_BLOCK_ID,
+        &(*bitcode).bitcode.elements,
+        callback,
+        user_data,
+    )
+}
+
+/// The symbol at `index`, `None` if it is out of range
+unsafe fn symbol<'a>(
+    bitcode: *const LlvmBitcode,
+    index: usize,
+) -> Option<&'a (Symbol, Option<CString>)> {
+    let bitcode = &*bitcode;
+    bitcode.symbols.get(index)
+}
+
+/// Number of symbols of the module, see [`symbols::symbols`]
+///
+/// # Safety
+///
+/// `bitcode` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_symbol_count(bitcode: *const LlvmBitcode) -> usize {
+    let bitcode = &*bitcode;
+    bitcode.symbols.len()
+}
+
+/// Name of the symbol at `index` as seen by the linker, or NULL if `index`
+/// is out of range
+///
+/// # Safety
+///
+/// `bitcode` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_symbol_name(
+    bitcode: *const LlvmBitcode,
+    index: usize,
+) -> *const c_char {
+    symbol(bitcode, index).map_or(ptr::null(), |(_, name)| as_ptr(name))
+}
+
+/// Linkage of the symbol at `index`, one of `LLVM_BITCODE_LINKAGE_*`, or -1
+/// if `index` is out of range
+///
+/// # Safety
+///
+/// `bitcode` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_symbol_linkage(
+    bitcode: *const LlvmBitcode,
+    index: usize,
+) -> c_int {
+    symbol(bitcode, index).map_or(-1, |(symbol, _)| match symbol.linkage {
+        Linkage::External => 0,
+        Linkage::AvailableExternally => 1,
+        Linkage::LinkOnceAny => 2,
+        Linkage::LinkOnceOdr => 3,
+        Linkage::WeakAny => 4,
+        Linkage::WeakOdr => 5,
+        Linkage::Appending => 6,
+        Linkage::Internal => 7,
+        Linkage::Private => 8,
+        Linkage::ExternalWeak => 9,
+        Linkage::Common => 10,
+    })
+}
+
+/// Kind of the symbol at `index`, one of `LLVM_BITCODE_SYMBOL_*`, or -1 if
+/// `index` is out of range
+///
+/// # Safety
+///
+/// `bitcode` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_symbol_kind(
+    bitcode: *const LlvmBitcode,
+    index: usize,
+) -> c_int {
+    symbol(bitcode, index).map_or(-1, |(symbol, _)| match symbol.kind {
+        SymbolKind::Function => 0,
+        SymbolKind::Variable => 1,
+        SymbolKind::Alias => 2,
+        SymbolKind::IFunc => 3,
+        SymbolKind::Asm => 4,
+    })
+}
+
+/// Whether the symbol at `index` is defined for the linker: 1 if it is, 0
+/// if it is not, or -1 if `index` is out of range
+///
+/// # Safety
+///
+/// `bitcode` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn llvm_bitcode_symbol_defined(
+    bitcode: *const LlvmBitcode,
+    index: usize,
+) -> c_int {
+    symbol(bitcode, index).map_or(-1, |(symbol, _)| c_int::from(symbol.defined))
+}
@@ -0,0 +1,339 @@
+//! Semantic diff of bitcode, insensitive to how records are encoded
+//!
+//! Two streams encoding the same records with different abbreviations,
+//! or with a differently ordered `BLOCKINFO`, compare equal: records are
+//! compared by code and operands, the fields followed by the elements of
+//! their array, char6 or blob payload. `BLOCKINFO` blocks only describe the
+//! encoding and are not compared.
+//!
+//! Blocks are matched by id and position among the sibling blocks with that
+//! id. Records of matched blocks are aligned with a shortest edit script,
+//! and runs of removed and added records are reported as changed records
+//! where they pair up.
+use std::fmt;
+
+use crate::bitcode::{BitcodeElement, Record};
+use crate::Bitcode;
+
+/// Most removals and additions of records aligned in a block, blocks
+/// differing more have their records compared pairwise
+const MAX_EDITS: isize = 1000;
+
+/// Code and operands of a record, regardless of its abbreviation
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DecodedRecord {
+    /// Record code
+    pub id: u64,
+    pub operands: Vec<u64>,
+}
+
+impl<'a> From<&Record<'a>> for DecodedRecord {
+    fn from(record: &Record<'a>) -> Self {
+        Self {
+            id: record.id,
+            operands: record.operands(),
+        }
+    }
+}
+
+impl fmt::Display for DecodedRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:?}", self.id, self.operands)
+    }
+}
+
+/// A difference between two streams
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// Blocks containing the difference from the top level, as block ids
+    /// and positions among the sibling blocks with that id
+    pub path: Vec<(u64, usize)>,
+    pub change: Change,
+}
+
+/// Kind of a [`Difference`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// The streams have different signatures
+    Signature { left: u32, right: u32 },
+    /// A record at `index` among the records of the left block has no
+    /// counterpart on the right
+    RecordRemoved { index: usize, record: DecodedRecord },
+    /// A record at `index` among the records of the right block has no
+    /// counterpart on the left
+    RecordAdded { index: usize, record: DecodedRecord },
+    /// The record at `index` among the records of the left block differs
+    /// from its counterpart on the right
+    RecordChanged {
+        index: usize,
+        left: DecodedRecord,
+        right: DecodedRecord,
+    },
+    /// The left stream has a block more with this id at this position
+    BlockRemoved { id: u64, index: usize },
+    /// The right stream has a block more with this id at this position
+    BlockAdded { id: u64, index: usize },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            f.write_str("/")?;
+        }
+        for (id, index) in &self.path {
+            write!(f, "/{}[{}]", id, index)?;
+        }
+        match &self.change {
+            Change::Signature { left, right } => {
+                write!(f, ": signature {:#x} -> {:#x}", left, right)
+            }
+            Change::RecordRemoved { index, record } => {
+                write!(f, ": record {} removed: {}", index, record)
+            }
+            Change::RecordAdded { index, record } => {
+                write!(f, ": record {} added: {}", index, record)
+            }
+            Change::RecordChanged { index, left, right } => {
+                write!(f, ": record {} changed: {} -> {}", index, left, right)
+            }
+            Change::BlockRemoved { id, index } => write!(f, ": block {}[{}] removed", id, index),
+            Change::BlockAdded { id, index } => write!(f, ": block {}[{}] added", id, index),
+        }
+    }
+}
+
+/// Compare two parsed streams, returning their semantic differences
+///
+/// Differences in the records of a block come before the differences in
+/// its nested blocks.
+pub fn diff(left: &Bitcode<'_>, right: &Bitcode<'_>) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    let (left_signature, right_signature) =
+        (left.signature.into_inner(), right.signature.into_inner());
+    if left_signature != right_signature {
+        differences.push(Difference {
+            path: Vec::new(),
+            change: Change::Signature {
+                left: left_signature,
+                right: right_signature,
+            },
+        });
+    }
+    diff_elements(
+        &left.elements,
+        &right.elements,
+        &mut Vec::new(),
+        &mut differences,
+    );
+    differences
+}
+
+fn diff_elements(
+    left: &[BitcodeElement<'_>],
+    right: &[BitcodeElement<'_>],
+    path: &mut Vec<(u64, usize)>,
+    differences: &mut Vec<Difference>,
+) {
+    let records = |elements: &[BitcodeElement<'_>]| -> Vec<DecodedRecord> {
+        elements
+            .iter()
+            .filter_map(BitcodeElement::as_record)
+            .map(DecodedRecord::from)
+            .collect()
+    };
+    diff_records(&records(left), &records(right), path, differences);
+
+    let left_blocks: Vec<_> = left.iter().filter_map(BitcodeElement::as_block).collect();
+    let right_blocks: Vec<_> = right.iter().filter_map(BitcodeElement::as_block).collect();
+    // Block ids in order of first appearance, left then right
+    let mut ids: Vec<u64> = Vec::new();
+    for block in left_blocks.iter().chain(&right_blocks) {
+        if !ids.contains(&block.id) {
+            ids.push(block.id);
+        }
+    }
+    for id in ids {
+        let left_with_id = left_blocks.iter().filter(|block| block.id == id);
+        let mut right_with_id = right_blocks.iter().filter(|block| block.id == id);
+        let mut index = 0;
+        for left_block in left_with_id {
+            match right_with_id.next() {
+                Some(right_block) => {
+                    path.push((id, index));
+                    diff_elements(
+                        &left_block.elements,
+                        &right_block.elements,
+                        path,
+                        differences,
+                    );
+                    path.pop();
+                }
+                None => differences.push(Difference {
+                    path: path.clone(),
+                    change: Change::BlockRemoved { id, index },
+                }),
+            }
+            index += 1;
+        }
+        for _ in right_with_id {
+            differences.push(Difference {
+                path: path.clone(),
+                change: Change::BlockAdded { id, index },
+            });
+            index += 1;
+        }
+    }
+}
+
+fn diff_records(
+    left: &[DecodedRecord],
+    right: &[DecodedRecord],
+    path: &[(u64, usize)],
+    differences: &mut Vec<Difference>,
+) {
+    let prefix = left
+        .iter()
+        .zip(right)
+        .take_while(|(left, right)| left == right)
+        .count();
+    let suffix = left[prefix..]
+        .iter()
+        .rev()
+        .zip(right[prefix..].iter().rev())
+        .take_while(|(left, right)| left == right)
+        .count();
+    let left_rest = &left[prefix..left.len() - suffix];
+    let right_rest = &right[prefix..right.len() - suffix];
+    // Records too different to be aligned are all compared pairwise
+    let edits = edit_script(left_rest, right_rest).unwrap_or_else(|| {
+        let mut edits = vec![Edit::Remove; left_rest.len()];
+        edits.resize(left_rest.len() + right_rest.len(), Edit::Add);
+        edits
+    });
+
+    // Runs of removed and added records are paired up as changed records,
+    // the records left over are reported as removed or added
+    let (mut left_index, mut right_index) = (prefix, prefix);
+    let mut edits = edits.into_iter().peekable();
+    while let Some(edit) = edits.next() {
+        if edit == Edit::Equal {
+            left_index += 1;
+            right_index += 1;
+            continue;
+        }
+        let (mut removed, mut added) = (0, 0);
+        for edit in std::iter::once(edit).chain(std::iter::from_fn(|| {
+            edits.next_if(|edit| *edit != Edit::Equal)
+        })) {
+            match edit {
+                Edit::Remove => removed += 1,
+                _ => added += 1,
+            }
+        }
+        let changed = removed.min(added);
+        let mut push = |change| {
+            differences.push(Difference {
+                path: path.to_vec(),
+                change,
+            })
+        };
+        for offset in 0..changed {
+            push(Change::RecordChanged {
+                index: left_index + offset,
+                left: left[left_index + offset].clone(),
+                right: right[right_index + offset].clone(),
+            });
+        }
+        for offset in changed..removed {
+            push(Change::RecordRemoved {
+                index: left_index + offset,
+                record: left[left_index + offset].clone(),
+            });
+        }
+        for offset in changed..added {
+            push(Change::RecordAdded {
+                index: right_index + offset,
+                record: right[right_index + offset].clone(),
+            });
+        }
+        left_index += removed;
+        right_index += added;
+    }
+}
+
+/// Operation of an edit script turning the left records into the right
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Equal,
+    Remove,
+    Add,
+}
+
+/// Find a shortest edit script with Myers' algorithm, `None` if it needs
+/// more than [`MAX_EDITS`] removals and additions
+///
+/// Removals come before additions in runs of edits.
+fn edit_script(left: &[DecodedRecord], right: &[DecodedRecord]) -> Option<Vec<Edit>> {
+    let (n, m) = (left.len() as isize, right.len() as isize);
+    let max = (n + m).min(MAX_EDITS);
+    // Furthest reaching x on diagonal k = x - y, at index k + max + 1
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let at = |k: isize| (k + max + 1) as usize;
+    // v as it was before each round, for the diagonals -d..=d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let mut rounds = None;
+    for d in 0..=max {
+        trace.push(v[at(-d)..=at(d)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[at(k - 1)] < v[at(k + 1)]) {
+                v[at(k + 1)]
+            } else {
+                v[at(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && left[x as usize] == right[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[at(k)] = x;
+            if x >= n && y >= m {
+                rounds = Some(d);
+                break;
+            }
+        }
+        if rounds.is_some() {
+            break;
+        }
+    }
+
+    // Walk back from the end through the diagonals each round came from
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (0..=rounds?).rev() {
+        let snapshot = &trace[d as usize];
+        let get = |k: isize| snapshot[(k + d) as usize];
+        let k = x - y;
+        let (prev_x, prev_y) = if d == 0 {
+            (0, 0)
+        } else {
+            let prev_k = if k == -d || (k != d && get(k - 1) < get(k + 1)) {
+                k + 1
+            } else {
+                k - 1
+            };
+            (get(prev_k), get(prev_k) - prev_k)
+        };
+        while x > prev_x && y > prev_y {
+            edits.push(Edit::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            edits.push(if x == prev_x { Edit::Add } else { Edit::Remove });
+            x = prev_x;
+            y = prev_y;
+        }
+    }
+    edits.reverse();
+    Some(edits)
+}
@@ -0,0 +1,136 @@
+use super::{Bytes, EmbeddedSection, Error, SectionKind};
+
+/// `ClassID` of bigobj headers
+const BIGOBJ_CLASS_ID: [u8; 16] = [
+    0xc7, 0xa1, 0xba, 0xd1, 0xee, 0xba, 0xa9, 0x4b, 0xaf, 0x20, 0xfa, 0xf6, 0x6a, 0xa4, 0xdc, 0xb8,
+];
+/// Machine types of the object files written by LLVM
+const MACHINES: [u16; 6] = [
+    0x014c, // i386
+    0x8664, // x86-64
+    0x01c0, // ARM
+    0x01c4, // ARM Thumb-2
+    0xaa64, // ARM64
+    0xa641, // ARM64EC
+];
+const SECTION_HEADER_SIZE: usize = 40;
+
+/// Find the `.llvmbc` and `.llvmcmd` sections of a COFF object file or PE
+/// image, as written by clang-cl with `-fembed-bitcode`
+///
+/// Both regular and bigobj (`/bigobj`) object files are supported.
+pub fn coff_sections(data: &[u8]) -> Result<Vec<EmbeddedSection<'_>>, Error> {
+    let bytes = Bytes {
+        data,
+        big_endian: false,
+    };
+    let layout = layout(bytes).ok_or(Error::UnknownFormat)?;
+    let strings = layout.symbol_table.and_then(|offset| {
+        let size = bytes.usize32(offset).ok()?;
+        bytes.get(offset, size).ok()
+    });
+
+    let mut sections = Vec::new();
+    for index in 0..layout.section_count {
+        let header = index
+            .checked_mul(SECTION_HEADER_SIZE)
+            .and_then(|offset| offset.checked_add(layout.section_table))
+            .ok_or(Error::InvalidObject)?;
+        let name = section_name(bytes.get(header, 8)?, strings).ok_or(Error::InvalidObject)?;
+        let kind = match name {
+            b".llvmbc" => SectionKind::Bitcode,
+            b".llvmcmd" => SectionKind::CommandLine,
+            _ => continue,
+        };
+        let virtual_size = bytes.usize32(header + 8)?;
+        let raw_size = bytes.usize32(header + 16)?;
+        let offset = bytes.usize32(header + 20)?;
+        // Sections of images are padded to the file alignment
+        let size = match virtual_size {
+            0 => raw_size,
+            size => size.min(raw_size),
+        };
+        sections.push(EmbeddedSection {
+            kind,
+            segment: String::new(),
+            section: String::from_utf8_lossy(name).into_owned(),
+            cpu_type: None,
+            data: bytes.get(offset, size)?,
+        });
+    }
+    Ok(sections)
+}
+
+/// Where the tables of a COFF file are
+struct Layout {
+    section_table: usize,
+    section_count: usize,
+    /// Offset of the string table, right after the symbol table
+    symbol_table: Option<usize>,
+}
+
+/// Read the file header, `None` if the data is not a COFF file
+fn layout(bytes: Bytes<'_>) -> Option<Layout> {
+    // PE images start with a DOS stub pointing to the PE signature
+    let mut header = 0;
+    if bytes.get(0, 2).ok()? == b"MZ" {
+        header = bytes.usize32(0x3c).ok()?;
+        if bytes.get(header, 4).ok()? != b"PE\0\0" {
+            return None;
+        }
+        header += 4;
+    }
+    let machine = bytes.u16(header).ok()?;
+    if header == 0 && machine == 0 && bytes.u16(2).ok()? == 0xffff {
+        // Import libraries members share the first fields of bigobj headers
+        if bytes.u16(4).ok()? < 2 || bytes.get(12, 16).ok()? != BIGOBJ_CLASS_ID {
+            return None;
+        }
+        let section_count = bytes.usize32(44).ok()?;
+        let symbol_count = bytes.usize32(52).ok()?;
+        return Some(Layout {
+            section_table: 56,
+            section_count,
+            symbol_table: string_table(bytes.usize32(48).ok()?, symbol_count, 20),
+        });
+    }
+    if header == 0 && !MACHINES.contains(&machine) {
+        return None;
+    }
+    let section_count = usize::from(bytes.u16(header + 2).ok()?);
+    let symbol_count = bytes.usize32(header + 12).ok()?;
+    let optional_header_size = usize::from(bytes.u16(header + 16).ok()?);
+    Some(Layout {
+        section_table: header + 20 + optional_header_size,
+        section_count,
+        symbol_table: string_table(bytes.usize32(header + 8).ok()?, symbol_count, 18),
+    })
+}
+
+/// Offset of the string table following a symbol table, `None` without a
+/// symbol table
+fn string_table(symbol_table: usize, symbol_count: usize, symbol_size: usize) -> Option<usize> {
+    if symbol_table == 0 {
+        return None;
+    }
+    symbol_count
+        .checked_mul(symbol_size)
+        .and_then(|size| size.checked_add(symbol_table))
+}
+
+/// Resolve a section name, which is either inline and NUL padded or a
+/// `/offset` reference to the string table
+fn section_name<'a>(name: &'a [u8], strings: Option<&'a [u8]>) -> Option<&'a [u8]> {
+    let name = match name.iter().position(|&byte| byte == 0) {
+        Some(end) => &name[..end],
+        None => name,
+    };
+    match name.strip_prefix(b"/") {
+        Some(offset) if !offset.is_empty() && offset.iter().all(u8::is_ascii_digit) => {
+            let offset: usize = std::str::from_utf8(offset).ok()?.parse().ok()?;
+            let name = strings?.get(offset..)?;
+            name.split(|&byte| byte == 0).next()
+        }
+        _ => Some(name),
+    }
+}
@@ -0,0 +1,125 @@
+use super::{Bytes, EmbeddedSection, Error, SectionKind};
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ELFCLASS32: u8 = 1;
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const ELFDATA2MSB: u8 = 2;
+const SHT_NOBITS: u32 = 8;
+const SHN_XINDEX: u16 = 0xffff;
+
+/// Find the `.llvmbc` and `.llvmcmd` sections of an ELF file, written by
+/// `-fembed-bitcode` and by rustc for LTO
+pub fn elf_sections(data: &[u8]) -> Result<Vec<EmbeddedSection<'_>>, Error> {
+    let ident = data.get(..16).ok_or(Error::UnknownFormat)?;
+    if &ident[..4] != ELF_MAGIC {
+        return Err(Error::UnknownFormat);
+    }
+    let is_64 = match ident[4] {
+        ELFCLASS32 => false,
+        ELFCLASS64 => true,
+        _ => return Err(Error::UnknownFormat),
+    };
+    let big_endian = match ident[5] {
+        ELFDATA2LSB => false,
+        ELFDATA2MSB => true,
+        _ => return Err(Error::UnknownFormat),
+    };
+    let bytes = Bytes { data, big_endian };
+    // Field offsets of the file header and of section headers
+    let (table_offset, entry_size, count, string_index) = if is_64 {
+        (
+            bytes.usize64(0x28)?,
+            bytes.u16(0x3a)?,
+            bytes.u16(0x3c)?,
+            bytes.u16(0x3e)?,
+        )
+    } else {
+        (
+            bytes.usize32(0x20)?,
+            bytes.u16(0x2e)?,
+            bytes.u16(0x30)?,
+            bytes.u16(0x32)?,
+        )
+    };
+    if table_offset == 0 {
+        return Ok(Vec::new());
+    }
+    let entry_size = usize::from(entry_size);
+    if entry_size < if is_64 { 64 } else { 40 } {
+        return Err(Error::InvalidObject);
+    }
+    let header = |index: usize| -> Result<SectionHeader, Error> {
+        let offset = index
+            .checked_mul(entry_size)
+            .and_then(|offset| offset.checked_add(table_offset))
+            .ok_or(Error::InvalidObject)?;
+        bytes.get(offset, entry_size)?;
+        Ok(if is_64 {
+            SectionHeader {
+                name: bytes.usize32(offset)?,
+                kind: bytes.u32(offset + 4)?,
+                offset: bytes.usize64(offset + 24)?,
+                size: bytes.usize64(offset + 32)?,
+                link: bytes.usize32(offset + 40)?,
+            }
+        } else {
+            SectionHeader {
+                name: bytes.usize32(offset)?,
+                kind: bytes.u32(offset + 4)?,
+                offset: bytes.usize32(offset + 16)?,
+                size: bytes.usize32(offset + 20)?,
+                link: bytes.usize32(offset + 24)?,
+            }
+        })
+    };
+    // With many sections, the real count and string table index are stored
+    // in the first section header
+    let first = header(0)?;
+    let count = match count {
+        0 => first.size,
+        count => usize::from(count),
+    };
+    let string_index = match string_index {
+        SHN_XINDEX => first.link,
+        index => usize::from(index),
+    };
+    let strings = header(string_index)?;
+    let strings = bytes.get(strings.offset, strings.size)?;
+
+    let mut sections = Vec::new();
+    for index in 0..count {
+        let header = header(index)?;
+        let name = strings
+            .get(header.name..)
+            .and_then(|name| name.split(|&byte| byte == 0).next())
+            .ok_or(Error::InvalidObject)?;
+        let kind = match name {
+            b".llvmbc" => SectionKind::Bitcode,
+            b".llvmcmd" => SectionKind::CommandLine,
+            _ => continue,
+        };
+        let data = if header.kind == SHT_NOBITS {
+            &[][..]
+        } else {
+            bytes.get(header.offset, header.size)?
+        };
+        sections.push(EmbeddedSection {
+            kind,
+            segment: String::new(),
+            section: String::from_utf8_lossy(name).into_owned(),
+            cpu_type: None,
+            data,
+        });
+    }
+    Ok(sections)
+}
+
+struct SectionHeader {
+    /// Offset of the name in the section name string table
+    name: usize,
+    kind: u32,
+    offset: usize,
+    size: usize,
+    link: usize,
+}
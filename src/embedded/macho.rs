@@ -0,0 +1,131 @@
+use super::{Bytes, CpuType, EmbeddedSection, Error, SectionKind};
+
+const MH_MAGIC: u32 = 0xfeed_face;
+const MH_MAGIC_64: u32 = 0xfeed_facf;
+const FAT_MAGIC: u32 = 0xcafe_babe;
+const FAT_MAGIC_64: u32 = 0xcafe_babf;
+const LC_SEGMENT: u32 = 0x1;
+const LC_SEGMENT_64: u32 = 0x19;
+/// Upper bound on the slices of a fat file, to tell them from Java class
+/// files which share their magic number
+const MAX_FAT_ARCHS: u32 = 64;
+
+/// Find the embedded bitcode sections of a Mach-O file
+///
+/// Only the slices of `cpu_type` are searched, or all of them if it is
+/// `None`. Sections are looked up by section and segment name, so
+/// object files, whose sections all belong to a single unnamed segment, are
+/// supported.
+pub fn macho_sections(
+    data: &[u8],
+    cpu_type: Option<CpuType>,
+) -> Result<Vec<EmbeddedSection<'_>>, Error> {
+    let magic = Bytes {
+        data,
+        big_endian: true,
+    }
+    .u32(0)
+    .map_err(|_| Error::UnknownFormat)?;
+    let mut sections = Vec::new();
+    match magic {
+        FAT_MAGIC | FAT_MAGIC_64 => {
+            let fat = Bytes {
+                data,
+                big_endian: true,
+            };
+            let count = fat.u32(4)?;
+            if count > MAX_FAT_ARCHS {
+                return Err(Error::UnknownFormat);
+            }
+            let is_64 = magic == FAT_MAGIC_64;
+            let arch_size = if is_64 { 32 } else { 20 };
+            for index in 0..count as usize {
+                let arch = 8 + index * arch_size;
+                let (offset, size) = if is_64 {
+                    (fat.usize64(arch + 8)?, fat.usize64(arch + 16)?)
+                } else {
+                    (fat.usize32(arch + 8)?, fat.usize32(arch + 12)?)
+                };
+                let slice = fat.get(offset, size)?;
+                thin_sections(slice, cpu_type, &mut sections)?;
+            }
+        }
+        _ => thin_sections(data, cpu_type, &mut sections)?,
+    }
+    Ok(sections)
+}
+
+/// Find the embedded bitcode sections of a single architecture Mach-O file,
+/// if it is of `cpu_type`
+fn thin_sections<'a>(
+    data: &'a [u8],
+    cpu_type: Option<CpuType>,
+    sections: &mut Vec<EmbeddedSection<'a>>,
+) -> Result<(), Error> {
+    let mut bytes = Bytes {
+        data,
+        big_endian: false,
+    };
+    let magic = bytes.u32(0).map_err(|_| Error::UnknownFormat)?;
+    let is_64 = match magic {
+        MH_MAGIC => false,
+        MH_MAGIC_64 => true,
+        _ if magic.swap_bytes() == MH_MAGIC => {
+            bytes.big_endian = true;
+            false
+        }
+        _ if magic.swap_bytes() == MH_MAGIC_64 => {
+            bytes.big_endian = true;
+            true
+        }
+        _ => return Err(Error::UnknownFormat),
+    };
+    let slice_cpu_type = CpuType::from(bytes.u32(4)?);
+    if cpu_type.is_some_and(|cpu_type| cpu_type != slice_cpu_type) {
+        return Ok(());
+    }
+    let command_count = bytes.u32(16)?;
+    let mut command = if is_64 { 32 } else { 28 };
+    for _ in 0..command_count {
+        let cmd = bytes.u32(command)?;
+        let cmd_size = bytes.usize32(command + 4)?;
+        if cmd_size < 8 {
+            return Err(Error::InvalidObject);
+        }
+        // Field offsets of the segment command and its sections
+        let (header_size, section_size, count_offset) = match cmd {
+            LC_SEGMENT_64 => (72, 80, 64),
+            LC_SEGMENT => (56, 68, 48),
+            _ => {
+                command = command.checked_add(cmd_size).ok_or(Error::InvalidObject)?;
+                continue;
+            }
+        };
+        let section_count = bytes.usize32(command + count_offset)?;
+        for index in 0..section_count {
+            let section = command + header_size + index * section_size;
+            let section_name = bytes.name(section, 16)?;
+            let segment_name = bytes.name(section + 16, 16)?;
+            let kind = match (segment_name.as_str(), section_name.as_str()) {
+                ("__LLVM", "__bitcode") => SectionKind::Bitcode,
+                ("__LLVM", "__bundle") => SectionKind::Bundle,
+                ("__LLVM", "__cmdline") => SectionKind::CommandLine,
+                _ => continue,
+            };
+            let (size, offset) = if is_64 {
+                (bytes.usize64(section + 40)?, bytes.usize32(section + 48)?)
+            } else {
+                (bytes.usize32(section + 36)?, bytes.usize32(section + 40)?)
+            };
+            sections.push(EmbeddedSection {
+                kind,
+                segment: segment_name,
+                section: section_name,
+                cpu_type: Some(slice_cpu_type),
+                data: bytes.get(offset, size)?,
+            });
+        }
+        command = command.checked_add(cmd_size).ok_or(Error::InvalidObject)?;
+    }
+    Ok(())
+}
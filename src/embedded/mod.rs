@@ -0,0 +1,205 @@
+//! Bitcode embedded in object files and binaries
+//!
+//! `-fembed-bitcode` stores the bitcode of an object next to its machine
+//! code, in the `__LLVM,__bitcode` section on Mach-O and in the `.llvmbc`
+//! section on ELF and COFF. rustc does the same for LTO. Linked Mach-O
+//! binaries carry a xar bundle of the bitcode of all their objects in
+//! `__LLVM,__bundle` instead, see the `xar` module. The options the bitcode
+//! was compiled with are stored in a separate section.
+use std::convert::TryFrom;
+use std::{error, fmt};
+
+use num_enum::{FromPrimitive, IntoPrimitive};
+
+use crate::bitcode::{Signature, LLVM_BITCODE_WRAPPER_MAGIC};
+
+mod coff;
+mod elf;
+mod macho;
+
+pub use self::coff::coff_sections;
+pub use self::elf::elf_sections;
+pub use self::macho::macho_sections;
+
+/// Errors locating embedded bitcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The data is not an object file of a supported format
+    UnknownFormat,
+    /// A header or table of the object file is truncated or out of bounds
+    InvalidObject,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnknownFormat => write!(f, "unknown object file format"),
+            Error::InvalidObject => write!(f, "invalid object file"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// CPU types of Mach-O files, also stored in bitcode wrapper headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u32)]
+pub enum CpuType {
+    X86 = 7,
+    X86_64 = 0x0100_0007,
+    Arm = 12,
+    Arm64 = 0x0100_000c,
+    /// 64-bit ARM with 32-bit pointers, used by watchOS
+    Arm64_32 = 0x0200_000c,
+    PowerPc = 18,
+    PowerPc64 = 0x0100_0012,
+    /// A CPU type unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u32),
+}
+
+/// CPU type stored in the wrapper header of wrapped bitcode, `None` for
+/// bitcode without a wrapper header
+pub fn wrapper_cpu_type(data: &[u8]) -> Option<CpuType> {
+    let word = |offset: usize| -> Option<u32> {
+        let bytes = data.get(offset..offset + 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    if word(0)? != LLVM_BITCODE_WRAPPER_MAGIC {
+        return None;
+    }
+    word(16).map(CpuType::from)
+}
+
+/// Find the embedded bitcode sections of a Mach-O, ELF or COFF file
+///
+/// The sections of all the slices of fat Mach-O files are returned, use
+/// [`macho_sections`] to select one CPU type.
+pub fn sections(data: &[u8]) -> Result<Vec<EmbeddedSection<'_>>, Error> {
+    match macho_sections(data, None) {
+        Err(Error::UnknownFormat) => {}
+        result => return result,
+    }
+    match elf_sections(data) {
+        Err(Error::UnknownFormat) => {}
+        result => return result,
+    }
+    coff_sections(data)
+}
+
+/// Contents of an embedded bitcode section
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SectionKind {
+    /// Bitcode of a single object
+    Bitcode,
+    /// Xar bundle of the bitcode of the objects of a linked binary
+    Bundle,
+    /// Compiler options, separated by NUL bytes
+    CommandLine,
+}
+
+/// A section holding embedded bitcode or its compiler options
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddedSection<'a> {
+    pub kind: SectionKind,
+    /// Name of the segment the section belongs to, empty outside of Mach-O
+    pub segment: String,
+    pub section: String,
+    /// CPU type of the slice the section was found in, for Mach-O files
+    pub cpu_type: Option<CpuType>,
+    /// Contents of the section
+    pub data: &'a [u8],
+}
+
+impl<'a> EmbeddedSection<'a> {
+    /// Whether the section is a placeholder written by
+    /// `-fembed-bitcode-marker`, holding no bitcode
+    pub fn is_marker(&self) -> bool {
+        self.data.len() <= 1
+    }
+
+    /// Whether the section holds LLVM IR bitcode, wrapped or not
+    pub fn is_bitcode(&self) -> bool {
+        self.kind == SectionKind::Bitcode
+            && matches!(Signature::parse(self.data), Ok((signature, _)) if signature.is_llvm_ir())
+    }
+
+    /// Compiler options of a [`SectionKind::CommandLine`] section
+    pub fn command_line(&self) -> Vec<&'a str> {
+        self.data
+            .split(|&byte| byte == 0)
+            .filter(|arg| !arg.is_empty())
+            .filter_map(|arg| std::str::from_utf8(arg).ok())
+            .collect()
+    }
+
+    /// Parse the xar bundle of a [`SectionKind::Bundle`] section
+    #[cfg(feature = "xar")]
+    pub fn bundle(&self) -> Result<crate::xar::XarArchive<'a>, crate::xar::Error> {
+        crate::xar::XarArchive::parse(self.data)
+    }
+}
+
+/// Reads fixed size integers of either byte order, failing when out of
+/// bounds
+#[derive(Debug, Clone, Copy)]
+struct Bytes<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Bytes<'a> {
+    fn get(&self, offset: usize, len: usize) -> Result<&'a [u8], Error> {
+        offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or(Error::InvalidObject)
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16, Error> {
+        let bytes = self.get(offset, 2)?;
+        let bytes = [bytes[0], bytes[1]];
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32, Error> {
+        let bytes = self.get(offset, 4)?;
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn u64(&self, offset: usize) -> Result<u64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.get(offset, 8)?);
+        Ok(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    /// A 32-bit offset or size
+    fn usize32(&self, offset: usize) -> Result<usize, Error> {
+        usize::try_from(self.u32(offset)?).map_err(|_| Error::InvalidObject)
+    }
+
+    /// A 64-bit offset or size
+    fn usize64(&self, offset: usize) -> Result<usize, Error> {
+        usize::try_from(self.u64(offset)?).map_err(|_| Error::InvalidObject)
+    }
+
+    /// A NUL padded name of `len` bytes
+    fn name(&self, offset: usize, len: usize) -> Result<String, Error> {
+        let bytes = self.get(offset, len)?;
+        let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(len);
+        Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+    }
+}
@@ -0,0 +1,411 @@
+use std::convert::TryFrom;
+
+use num_enum::{FromPrimitive, IntoPrimitive};
+
+use crate::bitcode::Signature;
+use crate::bitstream::Operand;
+use crate::read::{BlockContext, BlockItem, BlockIter, Error, RecordIter};
+use crate::Bitcode;
+
+/// Magic number of clang AST files, `CPCH`
+pub const AST_MAGIC: u32 = 0x4843_5043;
+
+/// Size in bytes of the signature of an AST file
+const AST_SIGNATURE_SIZE: usize = 20;
+
+/// Block ids of clang AST files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum BlockId {
+    /// Declarations, types, identifiers and the other tables of the AST
+    Ast = 8,
+    SourceManager = 9,
+    Preprocessor = 10,
+    /// Serialized declarations and types, nested in the AST block
+    DeclTypes = 11,
+    PreprocessorDetail = 12,
+    /// Module map information, nested in the AST block
+    Submodule = 13,
+    Comments = 14,
+    /// Metadata needed to decide whether the file can be used, read first
+    Control = 15,
+    /// Files the AST was built from, nested in the control block
+    InputFiles = 16,
+    /// Compiler options, nested in the control block
+    Options = 17,
+    /// Contents of a module file extension
+    Extension = 18,
+    /// Control information excluded from the signature of the file
+    UnhashedControl = 19,
+    /// A block id unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// Record codes of the control block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum ControlRecordId {
+    /// METADATA: [major, minor, clang major, clang minor, relocatable, ...,
+    /// clang version]
+    Metadata = 1,
+    /// IMPORTS: [kind, location, size, modification time, signature, name,
+    /// file name]...
+    Imports = 2,
+    /// ORIGINAL_FILE: [file id, name]
+    OriginalFile = 3,
+    OriginalPchDir = 4,
+    OriginalFileId = 5,
+    InputFileOffsets = 6,
+    /// MODULE_NAME: [name]
+    ModuleName = 7,
+    ModuleMapFile = 8,
+    /// MODULE_DIRECTORY: [directory]
+    ModuleDirectory = 9,
+    /// A record code unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// Record codes of the options block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum OptionsRecordId {
+    LanguageOptions = 1,
+    /// TARGET_OPTIONS: [triple, cpu, ...]
+    TargetOptions = 2,
+    FileSystemOptions = 3,
+    HeaderSearchOptions = 4,
+    PreprocessorOptions = 5,
+    /// A record code unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// Record codes of the input files block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum InputFileRecordId {
+    /// INPUT_FILE: [id, size, modification time, overridden, transient, ...,
+    /// name]
+    InputFile = 1,
+    InputFileHash = 2,
+    /// A record code unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// How an imported AST file was built
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum ModuleKind {
+    /// Built implicitly, into the module cache
+    ImplicitModule = 0,
+    /// Built explicitly with `-emit-module`
+    ExplicitModule = 1,
+    Pch = 2,
+    Preamble = 3,
+    MainFile = 4,
+    /// Found with `-fprebuilt-module-path`
+    PrebuiltModule = 5,
+    /// A module kind unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// Versions and flags of the `METADATA` record
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /// Major version of the AST file format, files of another major version
+    /// can't be read
+    pub version_major: u64,
+    pub version_minor: u64,
+    pub clang_major: u64,
+    pub clang_minor: u64,
+    /// Whether paths are relative to the module directory
+    pub relocatable: bool,
+    /// Full version string of the compiler that wrote the file
+    pub clang_version: String,
+}
+
+/// An AST file imported by this one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    pub kind: ModuleKind,
+    /// Size of the imported file, `0` if it is checked with `signature`
+    pub size: u64,
+    /// Modification time of the imported file, `0` if it is checked with
+    /// `signature`
+    pub modification_time: u64,
+    /// Signature of the imported file, all zeros if it has none
+    pub signature: [u8; AST_SIGNATURE_SIZE],
+    pub module_name: String,
+    pub file_name: String,
+}
+
+/// A file the AST was built from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputFile {
+    pub id: u64,
+    pub size: u64,
+    pub modification_time: u64,
+    /// The contents were replaced, with `-remap-file` for instance
+    pub overridden: bool,
+    /// The file may change without invalidating the AST
+    pub transient: bool,
+    pub name: String,
+}
+
+/// The decoded contents of the control block and the blocks it contains
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ControlBlock {
+    pub metadata: Option<Metadata>,
+    pub module_name: Option<String>,
+    /// Directory relative paths are resolved against
+    pub module_directory: Option<String>,
+    /// Main file of a precompiled header
+    pub original_file: Option<String>,
+    pub imports: Vec<Import>,
+    pub input_files: Vec<InputFile>,
+    pub target_triple: Option<String>,
+    /// Raw `LANGUAGE_OPTIONS` fields, whose layout follows the
+    /// `LangOptions.def` of the clang version that wrote the file
+    pub language_options: Vec<u64>,
+}
+
+impl ControlBlock {
+    /// Resolve a path read from the file against the module directory
+    pub fn resolve_path(&self, path: &str) -> String {
+        match &self.module_directory {
+            Some(directory) if !path.is_empty() && !path.starts_with('/') => {
+                format!("{}/{}", directory.trim_end_matches('/'), path)
+            }
+            _ => path.to_string(),
+        }
+    }
+
+    /// Files of the imported modules followed by the input files, resolved
+    /// with [`ControlBlock::resolve_path`]
+    pub fn dependencies(&self) -> impl Iterator<Item = String> + '_ {
+        self.imports
+            .iter()
+            .map(|import| import.file_name.as_str())
+            .chain(self.input_files.iter().map(|file| file.name.as_str()))
+            .map(move |path| self.resolve_path(path))
+    }
+}
+
+/// A clang AST file: a precompiled header or module (`.pch`, `.pcm`)
+///
+/// Only the control block is decoded, the other top level blocks are
+/// skipped without decoding and listed with their offsets in
+/// [`AstFile::blocks`]. Records are decoded with the layout written by
+/// clang 11 to 16.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AstFile {
+    /// Top level blocks, in file order
+    pub blocks: Vec<BlockContext>,
+    pub control: ControlBlock,
+}
+
+impl AstFile {
+    /// Parse an AST file from bytes
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let (signature, mut reader) = Bitcode::reader(data)?;
+        if signature != Signature::new(AST_MAGIC) {
+            return Err(Error::InvalidSignature(signature.into_inner()));
+        }
+        let mut blocks = Vec::new();
+        let mut control = ControlBlock::default();
+        let mut top_level = reader.iter_top_level();
+        while let Some(item) = top_level.next()? {
+            if let BlockItem::Block(mut block) = item {
+                blocks.push(block.context());
+                if BlockId::from(block.id) == BlockId::Control {
+                    read_control_block(&mut block, &mut control)?;
+                }
+            }
+        }
+        Ok(Self { blocks, control })
+    }
+
+    /// Top level block with the given id
+    pub fn block(&self, id: BlockId) -> Option<BlockContext> {
+        let id = u64::from(id);
+        self.blocks.iter().find(|block| block.id == id).copied()
+    }
+}
+
+fn read_control_block(
+    block: &mut BlockIter<'_, '_>,
+    control: &mut ControlBlock,
+) -> Result<(), Error> {
+    while let Some(item) = block.next()? {
+        let mut record = match item {
+            BlockItem::Block(mut block) => {
+                match BlockId::from(block.id) {
+                    BlockId::InputFiles => read_input_files_block(&mut block, control)?,
+                    BlockId::Options => read_options_block(&mut block, control)?,
+                    _ => {}
+                }
+                continue;
+            }
+            BlockItem::Record(record) => record,
+        };
+        match ControlRecordId::from(record.id) {
+            ControlRecordId::Metadata => {
+                let version_major = record.u64()?;
+                let version_minor = record.u64()?;
+                let clang_major = record.u64()?;
+                let clang_minor = record.u64()?;
+                let relocatable = record.bool()?;
+                control.metadata = Some(Metadata {
+                    version_major,
+                    version_minor,
+                    clang_major,
+                    clang_minor,
+                    relocatable,
+                    clang_version: read_text(&mut record)?,
+                });
+            }
+            ControlRecordId::Imports => {
+                let fields = record.array()?;
+                let mut fields = Fields {
+                    id: record.id,
+                    fields: &fields,
+                };
+                while !fields.fields.is_empty() {
+                    control.imports.push(read_import(&mut fields)?);
+                }
+            }
+            ControlRecordId::OriginalFile => {
+                record.u64()?;
+                control.original_file = Some(read_text(&mut record)?);
+            }
+            ControlRecordId::ModuleName => control.module_name = Some(read_text(&mut record)?),
+            ControlRecordId::ModuleDirectory => {
+                control.module_directory = Some(read_text(&mut record)?)
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_input_files_block(
+    block: &mut BlockIter<'_, '_>,
+    control: &mut ControlBlock,
+) -> Result<(), Error> {
+    while let Some(item) = block.next()? {
+        if let BlockItem::Record(mut record) = item {
+            if InputFileRecordId::from(record.id) == InputFileRecordId::InputFile {
+                let id = record.u64()?;
+                let size = record.u64()?;
+                let modification_time = record.u64()?;
+                let overridden = record.bool()?;
+                let transient = record.bool()?;
+                control.input_files.push(InputFile {
+                    id,
+                    size,
+                    modification_time,
+                    overridden,
+                    transient,
+                    name: read_text(&mut record)?,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_options_block(
+    block: &mut BlockIter<'_, '_>,
+    control: &mut ControlBlock,
+) -> Result<(), Error> {
+    while let Some(item) = block.next()? {
+        if let BlockItem::Record(mut record) = item {
+            match OptionsRecordId::from(record.id) {
+                OptionsRecordId::LanguageOptions => control.language_options = record.array()?,
+                OptionsRecordId::TargetOptions => {
+                    let fields = record.array()?;
+                    let mut fields = Fields {
+                        id: record.id,
+                        fields: &fields,
+                    };
+                    control.target_triple = Some(fields.string()?);
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_import(fields: &mut Fields<'_>) -> Result<Import, Error> {
+    let kind = ModuleKind::from(fields.u64()?);
+    // Import location, only meaningful to the importing AST
+    fields.u64()?;
+    let size = fields.u64()?;
+    let modification_time = fields.u64()?;
+    let mut signature = [0; AST_SIGNATURE_SIZE];
+    for byte in &mut signature {
+        let value = fields.u64()?;
+        *byte = u8::try_from(value).map_err(|_| Error::ValueOutOfRange(value))?;
+    }
+    Ok(Import {
+        kind,
+        size,
+        modification_time,
+        signature,
+        module_name: fields.string()?,
+        file_name: fields.string()?,
+    })
+}
+
+/// Read the text ending a record: its blob if it has one, or else the
+/// remaining fields, one character per field
+fn read_text(record: &mut RecordIter<'_, '_>) -> Result<String, Error> {
+    let has_blob = record
+        .abbrev_info()
+        .is_some_and(|info| info.abbrev.operands.last().is_some_and(Operand::is_blob));
+    if has_blob {
+        let id = record.id;
+        String::from_utf8(record.blob()?.into_owned()).map_err(|_| Error::InvalidString(id))
+    } else {
+        record.string()
+    }
+}
+
+/// Fields of a record holding several variable length entries
+struct Fields<'a> {
+    /// Record code, for errors
+    id: u64,
+    fields: &'a [u64],
+}
+
+impl Fields<'_> {
+    fn u64(&mut self) -> Result<u64, Error> {
+        let (first, rest) = self
+            .fields
+            .split_first()
+            .ok_or(Error::MissingField(self.id))?;
+        self.fields = rest;
+        Ok(*first)
+    }
+
+    /// A string stored as its length followed by one character per field
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.u64()?;
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|&len| len <= self.fields.len())
+            .ok_or(Error::MissingField(self.id))?;
+        let (chars, rest) = self.fields.split_at(len);
+        self.fields = rest;
+        let bytes = chars
+            .iter()
+            .map(|&c| u8::try_from(c).map_err(|_| Error::InvalidString(self.id)))
+            .collect::<Result<Vec<u8>, Error>>()?;
+        String::from_utf8(bytes).map_err(|_| Error::InvalidString(self.id))
+    }
+}
@@ -0,0 +1,97 @@
+use crate::bitcode::{Signature, LLVM_BITCODE_WRAPPER_MAGIC};
+use crate::formats::{clang_ast, diagnostics, index_store};
+use crate::read::{BitStreamReader, BlockItem};
+use crate::schema::blocks::BlockId;
+
+/// Magic number of LLVM remarks files, `RMRK`
+pub const REMARKS_MAGIC: u32 = 0x4B52_4D52;
+
+/// Magic number of Swift modules, `E2 9C A8 0E`
+pub const SWIFT_MODULE_MAGIC: u32 = 0x0EA8_9CE2;
+
+/// Kind of a file, as told by [`detect`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FormatKind {
+    /// LLVM IR bitcode
+    LlvmIr,
+    /// LLVM IR bitcode behind a wrapper header, as written for Darwin targets
+    WrappedLlvmIr,
+    /// Clang serialized diagnostics, see [`diagnostics`]
+    Diagnostics,
+    /// LLVM optimization remarks in the bitstream format
+    Remarks,
+    /// Clang AST file, precompiled header or module, see [`clang_ast`]
+    ClangAst,
+    /// Clang index-store unit file, see [`index_store`]
+    IndexUnit,
+    /// Clang index-store record file, see [`index_store`]
+    IndexRecord,
+    /// Swift module
+    SwiftModule,
+    /// A bitstream whose magic or top level blocks are unknown to this crate
+    UnknownBitstream(Signature),
+    /// Not a bitstream
+    Unknown,
+}
+
+/// Tell the format of a file from its magic number and top level blocks
+///
+/// Only the start of the stream is read. A known magic number followed by
+/// top level blocks other than the ones of its format is reported as
+/// [`FormatKind::UnknownBitstream`].
+pub fn detect(data: &[u8]) -> FormatKind {
+    let wrapped = data.get(..4) == Some(&LLVM_BITCODE_WRAPPER_MAGIC.to_le_bytes()[..]);
+    let (signature, stream) = match Signature::parse(data) {
+        Ok(parsed) => parsed,
+        Err(_) => return FormatKind::Unknown,
+    };
+    let first_block = match first_block_id(stream) {
+        Some(id) => id,
+        None => return FormatKind::Unknown,
+    };
+    let (kind, first_blocks): (_, &[u64]) = match signature.into_inner() {
+        _ if signature.is_llvm_ir() => {
+            let kind = if wrapped {
+                FormatKind::WrappedLlvmIr
+            } else {
+                FormatKind::LlvmIr
+            };
+            (
+                kind,
+                &[BlockId::Identification.into(), BlockId::Module.into()],
+            )
+        }
+        diagnostics::DIAGNOSTICS_MAGIC => (
+            FormatKind::Diagnostics,
+            &[diagnostics::BlockId::Meta.into()],
+        ),
+        REMARKS_MAGIC => (FormatKind::Remarks, &[8]),
+        clang_ast::AST_MAGIC => (FormatKind::ClangAst, &[clang_ast::BlockId::Control.into()]),
+        index_store::UNIT_MAGIC => (
+            FormatKind::IndexUnit,
+            &[index_store::UnitBlockId::Version.into()],
+        ),
+        index_store::RECORD_MAGIC => (
+            FormatKind::IndexRecord,
+            &[index_store::RecordBlockId::Version.into()],
+        ),
+        SWIFT_MODULE_MAGIC => (FormatKind::SwiftModule, &[8]),
+        _ => return FormatKind::UnknownBitstream(signature),
+    };
+    if first_blocks.contains(&first_block) {
+        kind
+    } else {
+        FormatKind::UnknownBitstream(signature)
+    }
+}
+
+/// Id of the first top level block other than `BLOCKINFO`, `None` if the
+/// stream doesn't start with one
+fn first_block_id(stream: &[u8]) -> Option<u64> {
+    let mut reader = BitStreamReader::new(stream);
+    let mut top_level = reader.iter_top_level();
+    match top_level.next() {
+        Ok(Some(BlockItem::Block(block))) => Some(block.id),
+        _ => None,
+    }
+}
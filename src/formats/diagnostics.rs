@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use num_enum::{FromPrimitive, IntoPrimitive};
+
+use crate::bitcode::Signature;
+use crate::read::{BlockItem, BlockIter, Error, RecordIter};
+use crate::Bitcode;
+
+/// Magic number of serialized diagnostics files, `DIAG`
+pub const DIAGNOSTICS_MAGIC: u32 = 0x4741_4944;
+
+/// Block ids of serialized diagnostics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum BlockId {
+    /// Version of the format
+    Meta = 8,
+    /// A diagnostic, with its notes as nested blocks
+    Diag = 9,
+    /// A block id unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// Record codes of serialized diagnostics
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum RecordId {
+    /// VERSION: [version]
+    Version = 1,
+    /// DIAG: [severity, location, category, flag, message size, message]
+    Diag = 2,
+    /// SOURCE_RANGE: [start location, end location]
+    SourceRange = 3,
+    /// DIAG_FLAG: [flag id, name size, name]
+    DiagFlag = 4,
+    /// CATEGORY: [category id, name size, name]
+    Category = 5,
+    /// FILENAME: [file id, file size, modification time, name size, name]
+    Filename = 6,
+    /// FIXIT: [start location, end location, text size, text]
+    FixIt = 7,
+    /// A record code unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// Severity of a diagnostic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum Severity {
+    Ignored = 0,
+    Note = 1,
+    Warning = 2,
+    Error = 3,
+    Fatal = 4,
+    Remark = 5,
+    /// A severity unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// A location in a source file
+///
+/// A `file` id of `0` means the location is unknown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    /// Id of the file in [`SerializedDiagnostics::files`]
+    pub file: u64,
+    /// Line number, starting from 1
+    pub line: u32,
+    /// Column number, starting from 1
+    pub column: u32,
+    /// Offset in bytes from the start of the file
+    pub offset: u32,
+}
+
+/// A range of source, both ends included
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SourceRange {
+    pub start: SourceLocation,
+    pub end: SourceLocation,
+}
+
+/// A suggested replacement of a source range
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixIt {
+    pub range: SourceRange,
+    pub text: String,
+}
+
+/// A source file referenced by diagnostics
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub name: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Modification time, in seconds since the Unix epoch
+    pub modification_time: u64,
+}
+
+/// A diagnostic and its notes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub location: SourceLocation,
+    /// Id of the category, `0` if there is none
+    pub category: u64,
+    /// Id of the warning flag controlling the diagnostic, `0` if there is none
+    pub flag: u64,
+    pub message: String,
+    /// Highlighted source ranges
+    pub ranges: Vec<SourceRange>,
+    pub fix_its: Vec<FixIt>,
+    /// Notes attached to the diagnostic
+    pub children: Vec<Diagnostic>,
+}
+
+/// A location resolved to the file it belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvedLocation<'a> {
+    pub file: &'a SourceFile,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl fmt::Display for ResolvedLocation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file.name, self.line, self.column)
+    }
+}
+
+/// Clang serialized diagnostics, as written by `clang --serialize-diagnostics`
+///
+/// File names, categories and flags are written once, the first time a
+/// diagnostic refers to them, and are collected in tables keyed by id.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SerializedDiagnostics {
+    /// Version of the format
+    pub version: u64,
+    /// Top level diagnostics, in the order they were emitted
+    pub diagnostics: Vec<Diagnostic>,
+    pub files: HashMap<u64, SourceFile>,
+    /// Category names by id
+    pub categories: HashMap<u64, String>,
+    /// Warning flag names by id
+    pub flags: HashMap<u64, String>,
+}
+
+impl SerializedDiagnostics {
+    /// Parse serialized diagnostics from bytes
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let (signature, mut reader) = Bitcode::reader(data)?;
+        if signature != Signature::new(DIAGNOSTICS_MAGIC) {
+            return Err(Error::InvalidSignature(signature.into_inner()));
+        }
+        let mut diagnostics = Self::default();
+        let mut top_level = reader.iter_top_level();
+        while let Some(item) = top_level.next()? {
+            if let BlockItem::Block(mut block) = item {
+                match BlockId::from(block.id) {
+                    BlockId::Meta => diagnostics.read_meta(&mut block)?,
+                    BlockId::Diag => {
+                        let diagnostic = diagnostics.read_diagnostic(&mut block)?;
+                        diagnostics.diagnostics.push(diagnostic);
+                    }
+                    BlockId::Unknown(_) => {}
+                }
+            }
+        }
+        Ok(diagnostics)
+    }
+
+    /// Resolve a location to its file, `None` if the location is unknown
+    pub fn resolve(&self, location: &SourceLocation) -> Option<ResolvedLocation<'_>> {
+        let file = self.files.get(&location.file)?;
+        Some(ResolvedLocation {
+            file,
+            line: location.line,
+            column: location.column,
+        })
+    }
+
+    /// Name of the category of a diagnostic
+    pub fn category(&self, diagnostic: &Diagnostic) -> Option<&str> {
+        self.categories
+            .get(&diagnostic.category)
+            .map(String::as_str)
+    }
+
+    /// Name of the warning flag of a diagnostic, without the `-W` prefix
+    pub fn flag(&self, diagnostic: &Diagnostic) -> Option<&str> {
+        self.flags.get(&diagnostic.flag).map(String::as_str)
+    }
+
+    fn read_meta(&mut self, block: &mut BlockIter<'_, '_>) -> Result<(), Error> {
+        while let Some(item) = block.next()? {
+            if let BlockItem::Record(mut record) = item {
+                if RecordId::from(record.id) == RecordId::Version {
+                    self.version = record.u64()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn read_diagnostic(&mut self, block: &mut BlockIter<'_, '_>) -> Result<Diagnostic, Error> {
+        let mut diagnostic = None;
+        let mut ranges = Vec::new();
+        let mut fix_its = Vec::new();
+        let mut children = Vec::new();
+        while let Some(item) = block.next()? {
+            let mut record = match item {
+                BlockItem::Block(mut block) => {
+                    if BlockId::from(block.id) == BlockId::Diag {
+                        children.push(self.read_diagnostic(&mut block)?);
+                    }
+                    continue;
+                }
+                BlockItem::Record(record) => record,
+            };
+            match RecordId::from(record.id) {
+                RecordId::Diag => {
+                    let severity = Severity::from(record.u64()?);
+                    let location = read_location(&mut record)?;
+                    let category = record.u64()?;
+                    let flag = record.u64()?;
+                    let message = read_text(&mut record)?;
+                    diagnostic = Some((severity, location, category, flag, message));
+                }
+                RecordId::SourceRange => ranges.push(read_range(&mut record)?),
+                RecordId::FixIt => {
+                    let range = read_range(&mut record)?;
+                    let text = read_text(&mut record)?;
+                    fix_its.push(FixIt { range, text });
+                }
+                RecordId::DiagFlag => {
+                    let id = record.u64()?;
+                    self.flags.insert(id, read_text(&mut record)?);
+                }
+                RecordId::Category => {
+                    let id = record.u64()?;
+                    self.categories.insert(id, read_text(&mut record)?);
+                }
+                RecordId::Filename => {
+                    let id = record.u64()?;
+                    let size = record.u64()?;
+                    let modification_time = record.u64()?;
+                    let name = read_text(&mut record)?;
+                    self.files.insert(
+                        id,
+                        SourceFile {
+                            name,
+                            size,
+                            modification_time,
+                        },
+                    );
+                }
+                RecordId::Version | RecordId::Unknown(_) => {}
+            }
+        }
+        let (severity, location, category, flag, message) =
+            diagnostic.ok_or_else(|| Error::MissingField(RecordId::Diag.into()))?;
+        Ok(Diagnostic {
+            severity,
+            location,
+            category,
+            flag,
+            message,
+            ranges,
+            fix_its,
+            children,
+        })
+    }
+}
+
+fn read_location(record: &mut RecordIter<'_, '_>) -> Result<SourceLocation, Error> {
+    Ok(SourceLocation {
+        file: record.u64()?,
+        line: record.u32()?,
+        column: record.u32()?,
+        offset: record.u32()?,
+    })
+}
+
+fn read_range(record: &mut RecordIter<'_, '_>) -> Result<SourceRange, Error> {
+    Ok(SourceRange {
+        start: read_location(record)?,
+        end: read_location(record)?,
+    })
+}
+
+/// Read the text blob ending a record, skipping its size field
+fn read_text(record: &mut RecordIter<'_, '_>) -> Result<String, Error> {
+    let id = record.id;
+    String::from_utf8(record.blob()?.into_owned()).map_err(|_| Error::InvalidString(id))
+}
@@ -0,0 +1,497 @@
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::ops::Range;
+
+use num_enum::{FromPrimitive, IntoPrimitive};
+
+use crate::bitcode::Signature;
+use crate::read::{BlockItem, BlockIter, Error, RecordIter};
+use crate::Bitcode;
+
+/// Magic number of unit files, `IDXU`
+pub const UNIT_MAGIC: u32 = 0x5558_4449;
+/// Magic number of record files, `IDXR`
+pub const RECORD_MAGIC: u32 = 0x5258_4449;
+
+/// Block ids of unit files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum UnitBlockId {
+    Version = 8,
+    Info = 9,
+    Dependencies = 10,
+    Includes = 11,
+    /// Paths referenced by the other blocks
+    Paths = 12,
+    /// Module names referenced by dependencies
+    Modules = 13,
+    /// A block id unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// Record codes of unit files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum UnitRecordId {
+    /// UNIT_VERSION: [version]
+    Version = 0,
+    /// UNIT_INFO: [is system, working directory, output file, sysroot, main
+    /// path, is debug, is module, name sizes..., names]
+    Info = 1,
+    /// UNIT_DEPENDENCY: [kind, is system, path, module, reserved, reserved, name]
+    Dependency = 2,
+    /// UNIT_INCLUDE: [source path, line, target path]
+    Include = 3,
+    /// UNIT_PATH: [kind, directory offset, size, file name offset, size]
+    Path = 4,
+    /// UNIT_PATH_BUFFER: [buffer]
+    PathBuffer = 5,
+    /// UNIT_MODULE: [name offset, size]
+    Module = 6,
+    /// UNIT_MODULE_BUFFER: [buffer]
+    ModuleBuffer = 7,
+    /// A record code unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// Block ids of record files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum RecordBlockId {
+    Version = 8,
+    Decls = 9,
+    DeclOffsets = 10,
+    DeclOccurrences = 11,
+    /// A block id unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// Record codes of record files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum RecordRecordId {
+    /// REC_VERSION: [version]
+    Version = 0,
+    /// REC_DECLINFO: [kind, sub-kind, language, properties, roles, related
+    /// roles, name size, USR size, name + USR + codegen name]
+    DeclInfo = 1,
+    /// REC_DECLOFFSETS: [count, offsets]
+    DeclOffsets = 2,
+    /// REC_DECLOCCURRENCE: [decl, roles, line, column, relation count,
+    /// [roles, decl]...]
+    DeclOccurrence = 3,
+    /// A record code unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// What a unit depends on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum DependencyKind {
+    /// Another unit, of a module or precompiled header
+    Unit = 0,
+    /// The record file of symbols indexed in a file
+    Record = 1,
+    /// A file without indexed symbols
+    File = 2,
+    /// A dependency kind unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
+
+/// A dependency of a unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitDependency {
+    pub kind: DependencyKind,
+    pub is_system: bool,
+    /// Path of the file the dependency was created from
+    pub file_path: Option<String>,
+    pub module_name: Option<String>,
+    /// Name of the unit or record file, empty for file dependencies
+    pub name: String,
+}
+
+/// An `#include` directive of a unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnitInclude {
+    /// File containing the directive
+    pub source_path: Option<String>,
+    /// Line of the directive
+    pub line: u64,
+    /// Included file
+    pub target_path: Option<String>,
+}
+
+/// A unit file, describing a compilation and what it depends on
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnitFile {
+    /// Version of the format
+    pub version: u64,
+    pub is_system: bool,
+    pub is_debug_compilation: bool,
+    pub is_module_unit: bool,
+    pub working_directory: String,
+    pub output_file: String,
+    pub sysroot: String,
+    /// Main source file of the compilation
+    pub main_file: Option<String>,
+    pub module_name: String,
+    /// Identifier and version of the indexing tool
+    pub provider_identifier: String,
+    pub provider_version: String,
+    pub target: String,
+    pub dependencies: Vec<UnitDependency>,
+    pub includes: Vec<UnitInclude>,
+}
+
+/// A path stored in the paths block, relative to a known directory
+#[derive(Debug, Clone)]
+struct UnitPath {
+    /// `0` for absolute paths, `1` for the working directory, `2` for the
+    /// sysroot
+    kind: u64,
+    directory: Range<usize>,
+    file_name: Range<usize>,
+}
+
+/// Indices read from a unit, resolved once the paths and modules are known
+#[derive(Debug, Default)]
+struct RawUnit {
+    working_directory: Range<usize>,
+    output_file: Range<usize>,
+    sysroot: Range<usize>,
+    main_path: Option<u64>,
+    /// Dependencies with their path and module indices
+    dependencies: Vec<(UnitDependency, Option<u64>, Option<u64>)>,
+    /// Includes with their source and target path indices
+    includes: Vec<(Option<u64>, u64, Option<u64>)>,
+    paths: Vec<UnitPath>,
+    path_buffer: Vec<u8>,
+    modules: Vec<Range<usize>>,
+    module_buffer: Vec<u8>,
+}
+
+impl UnitFile {
+    /// Parse a unit file from bytes
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let (signature, mut reader) = Bitcode::reader(data)?;
+        if signature != Signature::new(UNIT_MAGIC) {
+            return Err(Error::InvalidSignature(signature.into_inner()));
+        }
+        let mut unit = Self::default();
+        let mut raw = RawUnit::default();
+        let mut top_level = reader.iter_top_level();
+        while let Some(item) = top_level.next()? {
+            if let BlockItem::Block(mut block) = item {
+                let id = UnitBlockId::from(block.id);
+                while let Some(item) = block.next()? {
+                    if let BlockItem::Record(mut record) = item {
+                        unit.read_record(id, &mut record, &mut raw)?;
+                    }
+                }
+            }
+        }
+        unit.resolve(raw);
+        Ok(unit)
+    }
+
+    fn read_record(
+        &mut self,
+        block_id: UnitBlockId,
+        record: &mut RecordIter<'_, '_>,
+        raw: &mut RawUnit,
+    ) -> Result<(), Error> {
+        match (block_id, UnitRecordId::from(record.id)) {
+            (UnitBlockId::Version, UnitRecordId::Version) => self.version = record.u64()?,
+            (UnitBlockId::Info, UnitRecordId::Info) => {
+                self.is_system = record.bool()?;
+                raw.working_directory = record.range()?;
+                raw.output_file = record.range()?;
+                raw.sysroot = record.range()?;
+                raw.main_path = record.nullable_u64()?;
+                self.is_debug_compilation = record.bool()?;
+                self.is_module_unit = record.bool()?;
+                let module_name = record.u64()?;
+                let provider_identifier = record.u64()?;
+                let provider_version = record.u64()?;
+                let blob = record.blob()?;
+                let mut blob = Strings {
+                    id: record.id,
+                    bytes: &blob,
+                };
+                self.module_name = blob.take(module_name)?;
+                self.provider_identifier = blob.take(provider_identifier)?;
+                self.provider_version = blob.take(provider_version)?;
+                self.target = blob.take(blob.bytes.len() as u64)?;
+            }
+            (UnitBlockId::Dependencies, UnitRecordId::Dependency) => {
+                let kind = DependencyKind::from(record.u64()?);
+                let is_system = record.bool()?;
+                let path = record.nullable_u64()?;
+                let module = record.nullable_u64()?;
+                let name = blob_string(record)?;
+                let dependency = UnitDependency {
+                    kind,
+                    is_system,
+                    file_path: None,
+                    module_name: None,
+                    name,
+                };
+                raw.dependencies.push((dependency, path, module));
+            }
+            (UnitBlockId::Includes, UnitRecordId::Include) => {
+                let source = record.nullable_u64()?;
+                let line = record.u64()?;
+                let target = record.nullable_u64()?;
+                raw.includes.push((source, line, target));
+            }
+            (UnitBlockId::Paths, UnitRecordId::Path) => {
+                let kind = record.u64()?;
+                let directory = record.range()?;
+                let file_name = record.range()?;
+                raw.paths.push(UnitPath {
+                    kind,
+                    directory,
+                    file_name,
+                });
+            }
+            (UnitBlockId::Paths, UnitRecordId::PathBuffer) => {
+                raw.path_buffer = record.blob()?.into_owned();
+            }
+            (UnitBlockId::Modules, UnitRecordId::Module) => raw.modules.push(record.range()?),
+            (UnitBlockId::Modules, UnitRecordId::ModuleBuffer) => {
+                raw.module_buffer = record.blob()?.into_owned();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Resolve the indices of `raw` into paths and module names
+    ///
+    /// Indices and ranges out of bounds resolve to nothing.
+    fn resolve(&mut self, raw: RawUnit) {
+        let buffer_str = |buffer: &[u8], range: Range<usize>| -> String {
+            buffer
+                .get(range)
+                .map(String::from_utf8_lossy)
+                .map(Cow::into_owned)
+                .unwrap_or_default()
+        };
+        let working_directory = buffer_str(&raw.path_buffer, raw.working_directory.clone());
+        let sysroot = buffer_str(&raw.path_buffer, raw.sysroot.clone());
+        let path = |index: Option<u64>| -> Option<String> {
+            let path = raw.paths.get(usize::try_from(index?).ok()?)?;
+            let directory = buffer_str(&raw.path_buffer, path.directory.clone());
+            let file_name = buffer_str(&raw.path_buffer, path.file_name.clone());
+            let prefix = match path.kind {
+                1 => working_directory.as_str(),
+                2 => sysroot.as_str(),
+                _ => "",
+            };
+            let mut full = String::new();
+            for part in &[prefix, directory.as_str()] {
+                if !part.is_empty() {
+                    full.push_str(part.trim_end_matches('/'));
+                    full.push('/');
+                }
+            }
+            full.push_str(&file_name);
+            Some(full)
+        };
+        let module = |index: Option<u64>| -> Option<String> {
+            let range = raw.modules.get(usize::try_from(index?).ok()?)?;
+            Some(buffer_str(&raw.module_buffer, range.clone()))
+        };
+        self.main_file = path(raw.main_path);
+        self.dependencies = raw
+            .dependencies
+            .iter()
+            .map(|(dependency, path_index, module_index)| UnitDependency {
+                file_path: path(*path_index),
+                module_name: module(*module_index),
+                ..dependency.clone()
+            })
+            .collect();
+        self.includes = raw
+            .includes
+            .iter()
+            .map(|(source, line, target)| UnitInclude {
+                source_path: path(*source),
+                line: *line,
+                target_path: path(*target),
+            })
+            .collect();
+        self.output_file = buffer_str(&raw.path_buffer, raw.output_file.clone());
+        self.working_directory = working_directory;
+        self.sysroot = sysroot;
+    }
+}
+
+/// A symbol declared or referenced in a record file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexSymbol {
+    /// `indexstore_symbol_kind_t` value
+    pub kind: u64,
+    /// `indexstore_symbol_subkind_t` value
+    pub sub_kind: u64,
+    /// `indexstore_symbol_language_t` value
+    pub language: u64,
+    /// `indexstore_symbol_property_t` bits
+    pub properties: u64,
+    /// `indexstore_symbol_role_t` bits of all the occurrences
+    pub roles: u64,
+    /// `indexstore_symbol_role_t` bits of all the relations
+    pub related_roles: u64,
+    pub name: String,
+    pub usr: String,
+    pub codegen_name: String,
+}
+
+/// A relation of an occurrence to another symbol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexRelation {
+    /// `indexstore_symbol_role_t` bits
+    pub roles: u64,
+    /// Index of the related symbol in [`RecordFile::symbols`]
+    pub symbol: usize,
+}
+
+/// An occurrence of a symbol in the file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexOccurrence {
+    /// Index of the symbol in [`RecordFile::symbols`]
+    pub symbol: usize,
+    /// `indexstore_symbol_role_t` bits
+    pub roles: u64,
+    pub line: u64,
+    pub column: u64,
+    pub relations: Vec<IndexRelation>,
+}
+
+/// A record file, holding the symbols indexed in a source file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordFile {
+    /// Version of the format
+    pub version: u64,
+    pub symbols: Vec<IndexSymbol>,
+    pub occurrences: Vec<IndexOccurrence>,
+}
+
+impl RecordFile {
+    /// Parse a record file from bytes
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let (signature, mut reader) = Bitcode::reader(data)?;
+        if signature != Signature::new(RECORD_MAGIC) {
+            return Err(Error::InvalidSignature(signature.into_inner()));
+        }
+        let mut file = Self::default();
+        let mut top_level = reader.iter_top_level();
+        while let Some(item) = top_level.next()? {
+            if let BlockItem::Block(mut block) = item {
+                file.read_block(&mut block)?;
+            }
+        }
+        Ok(file)
+    }
+
+    fn read_block(&mut self, block: &mut BlockIter<'_, '_>) -> Result<(), Error> {
+        let block_id = RecordBlockId::from(block.id);
+        while let Some(item) = block.next()? {
+            let mut record = match item {
+                BlockItem::Record(record) => record,
+                BlockItem::Block(_) => continue,
+            };
+            match (block_id, RecordRecordId::from(record.id)) {
+                (RecordBlockId::Version, RecordRecordId::Version) => self.version = record.u64()?,
+                (RecordBlockId::Decls, RecordRecordId::DeclInfo) => {
+                    let kind = record.u64()?;
+                    let sub_kind = record.u64()?;
+                    let language = record.u64()?;
+                    let properties = record.u64()?;
+                    let roles = record.u64()?;
+                    let related_roles = record.u64()?;
+                    let name = record.u64()?;
+                    let usr = record.u64()?;
+                    let blob = record.blob()?;
+                    let mut blob = Strings {
+                        id: record.id,
+                        bytes: &blob,
+                    };
+                    self.symbols.push(IndexSymbol {
+                        kind,
+                        sub_kind,
+                        language,
+                        properties,
+                        roles,
+                        related_roles,
+                        name: blob.take(name)?,
+                        usr: blob.take(usr)?,
+                        codegen_name: blob.take(blob.bytes.len() as u64)?,
+                    });
+                }
+                (RecordBlockId::DeclOccurrences, RecordRecordId::DeclOccurrence) => {
+                    let symbol = self.symbol_index(record.u64()?)?;
+                    let roles = record.u64()?;
+                    let line = record.u64()?;
+                    let column = record.u64()?;
+                    let count = record.u64()?;
+                    let mut relations = Vec::new();
+                    for _ in 0..count {
+                        let roles = record.u64()?;
+                        let symbol = self.symbol_index(record.u64()?)?;
+                        relations.push(IndexRelation { roles, symbol });
+                    }
+                    self.occurrences.push(IndexOccurrence {
+                        symbol,
+                        roles,
+                        line,
+                        column,
+                        relations,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Index in `symbols` of a symbol id, which starts from 1
+    fn symbol_index(&self, id: u64) -> Result<usize, Error> {
+        usize::try_from(id)
+            .ok()
+            .and_then(|id| id.checked_sub(1))
+            .filter(|&index| index < self.symbols.len())
+            .ok_or(Error::ValueOutOfRange(id))
+    }
+}
+
+/// Read a record blob as a string, skipping the remaining fields
+fn blob_string(record: &mut RecordIter<'_, '_>) -> Result<String, Error> {
+    let id = record.id;
+    String::from_utf8(record.blob()?.into_owned()).map_err(|_| Error::InvalidString(id))
+}
+
+/// Consecutive strings packed in a blob
+struct Strings<'a> {
+    /// Record code, for errors
+    id: u64,
+    bytes: &'a [u8],
+}
+
+impl Strings<'_> {
+    /// Take the next `len` bytes as a string
+    fn take(&mut self, len: u64) -> Result<String, Error> {
+        let len = usize::try_from(len)
+            .ok()
+            .filter(|&len| len <= self.bytes.len())
+            .ok_or(Error::ValueOutOfRange(len))?;
+        let (string, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        String::from_utf8(string.to_vec()).map_err(|_| Error::InvalidString(self.id))
+    }
+}
@@ -0,0 +1,10 @@
+/// Clang AST files, precompiled headers and modules (`.pch`, `.pcm`)
+pub mod clang_ast;
+/// Format detection
+pub mod detect;
+/// Clang serialized diagnostics (`.dia`)
+pub mod diagnostics;
+/// Clang index-while-building store unit and record files
+pub mod index_store;
+
+pub use self::detect::{detect, FormatKind};
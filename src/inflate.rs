@@ -0,0 +1,281 @@
+//! A small zlib and deflate decoder for xar bundles and legacy rustc bytecode
+//!
+//! Decoding follows RFC 1950 and RFC 1951. Only correctness matters here,
+//! not speed.
+
+/// Order in which the code lengths of the code length alphabet are stored
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Decompress zlib data, failing on malformed data or once the output
+/// exceeds `max_len` bytes
+#[cfg(feature = "xar")]
+pub(crate) fn zlib_decompress(data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    let (&cmf, &flg) = (data.first()?, data.get(1)?);
+    // Deflate with a window of at most 32K, no preset dictionary
+    if cmf & 0x0f != 8
+        || cmf >> 4 > 7
+        || flg & 0x20 != 0
+        || (u16::from(cmf) << 8 | u16::from(flg)) % 31 != 0
+    {
+        return None;
+    }
+    let mut input = Bits::new(&data[2..]);
+    let output = inflate(&mut input, max_len)?;
+    let trailer = input.data.get(input.pos..input.pos + 4)?;
+    let checksum = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    if checksum != adler32(&output) {
+        return None;
+    }
+    Some(output)
+}
+
+/// Decompress raw deflate data, without a zlib header or trailer
+pub(crate) fn deflate_decompress(data: &[u8], max_len: usize) -> Option<Vec<u8>> {
+    inflate(&mut Bits::new(data), max_len)
+}
+
+#[cfg(feature = "xar")]
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+/// Reads bits least significant first
+struct Bits<'a> {
+    data: &'a [u8],
+    /// Next byte to load into `buffer`
+    pos: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        while self.count < count {
+            let byte = *self.data.get(self.pos)?;
+            self.pos += 1;
+            self.buffer |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u64 << count) - 1) as u32;
+        self.buffer >>= count;
+        self.count -= count;
+        Some(value)
+    }
+
+    /// Drop the bits left in the current byte
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code
+struct Huffman {
+    /// Number of codes of each length
+    counts: [u16; 16],
+    /// Symbols ordered by code
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build the code from the code length of each symbol, failing if the
+    /// lengths are over-subscribed
+    fn new(lengths: &[u8]) -> Option<Self> {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[usize::from(length)] += 1;
+        }
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return None;
+            }
+        }
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                let offset = &mut offsets[usize::from(length)];
+                symbols[usize::from(*offset)] = symbol as u16;
+                *offset += 1;
+            }
+        }
+        Some(Self { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits<'_>) -> Option<u16> {
+        // First code and index of the symbols of the current length
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+fn inflate(bits: &mut Bits<'_>, max_len: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::new();
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let header = bits.data.get(bits.pos..bits.pos + 4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let nlen = u16::from_le_bytes([header[2], header[3]]);
+                if len != !nlen {
+                    return None;
+                }
+                let start = bits.pos + 4;
+                let stored = bits.data.get(start..start + usize::from(len))?;
+                if output.len() + stored.len() > max_len {
+                    return None;
+                }
+                output.extend_from_slice(stored);
+                bits.pos = start + usize::from(len);
+            }
+            1 => {
+                let mut lengths = [0u8; 288 + 30];
+                lengths[..144].iter_mut().for_each(|length| *length = 8);
+                lengths[144..256].iter_mut().for_each(|length| *length = 9);
+                lengths[256..280].iter_mut().for_each(|length| *length = 7);
+                lengths[280..288].iter_mut().for_each(|length| *length = 8);
+                lengths[288..].iter_mut().for_each(|length| *length = 5);
+                let literals = Huffman::new(&lengths[..288])?;
+                let distances = Huffman::new(&lengths[288..])?;
+                inflate_block(bits, &literals, &distances, &mut output, max_len)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(bits)?;
+                inflate_block(bits, &literals, &distances, &mut output, max_len)?;
+            }
+            _ => return None,
+        }
+        if last {
+            // The trailer starts at the next byte
+            bits.pos -= (bits.count / 8) as usize;
+            bits.align();
+            return Some(output);
+        }
+    }
+}
+
+fn read_dynamic_codes(bits: &mut Bits<'_>) -> Option<(Huffman, Huffman)> {
+    let literal_count = bits.bits(5)? as usize + 257;
+    let distance_count = bits.bits(5)? as usize + 1;
+    let code_length_count = bits.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return None;
+    }
+    let mut code_lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[index] = bits.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_length_code.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => (*lengths.get(index.checked_sub(1)?)?, 3 + bits.bits(2)?),
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        let end = index + repeat as usize;
+        lengths
+            .get_mut(index..end)?
+            .iter_mut()
+            .for_each(|length| *length = value);
+        index = end;
+    }
+    // A block without an end of block code can't be decoded
+    if lengths[256] == 0 {
+        return None;
+    }
+    let literals = Huffman::new(&lengths[..literal_count])?;
+    let distances = Huffman::new(&lengths[literal_count..])?;
+    Some((literals, distances))
+}
+
+fn inflate_block(
+    bits: &mut Bits<'_>,
+    literals: &Huffman,
+    distances: &Huffman,
+    output: &mut Vec<u8>,
+    max_len: usize,
+) -> Option<()> {
+    loop {
+        let symbol = usize::from(literals.decode(bits)?);
+        match symbol {
+            0..=255 => {
+                if output.len() >= max_len {
+                    return None;
+                }
+                output.push(symbol as u8);
+            }
+            256 => return Some(()),
+            _ => {
+                let index = symbol - 257;
+                let length = usize::from(*LENGTH_BASE.get(index)?)
+                    + bits.bits(u32::from(LENGTH_EXTRA[index]))? as usize;
+                let index = usize::from(distances.decode(bits)?);
+                let distance = usize::from(*DISTANCE_BASE.get(index)?)
+                    + bits.bits(u32::from(DISTANCE_EXTRA[index]))? as usize;
+                if distance > output.len() || output.len() + length > max_len {
+                    return None;
+                }
+                let start = output.len() - distance;
+                for i in start..start + length {
+                    output.push(output[i]);
+                }
+            }
+        }
+    }
+}
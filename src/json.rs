@@ -0,0 +1,172 @@
+//! JSON dump of the block and record tree, for querying with tools like `jq`
+//!
+//! The dump is an object with the `signature` of the stream and its top
+//! level `elements`. Each element is an object with a `type` of `block` or
+//! `record`, its `id` and its `name` when known. Blocks have nested
+//! `elements`, records have `fields` and, depending on the payload, an
+//! `array` of integers, a `char6` string or a `blob`:
+//!
+//! ```json
+//! {"signature":1195460932,"elements":[{"type":"block","id":8,"name":"Meta","elements":[
+//!   {"type":"record","id":1,"name":"Version","fields":[1]}]}, ...]}
+//! ```
+use std::fmt::Write;
+
+use crate::bitcode::{NameTable, Payload};
+use crate::read::{BlockItem, BlockIter, Error};
+use crate::Bitcode;
+
+/// Offset in bits of the first item, after the magic number
+const MAGIC_BITS: u64 = 32;
+
+/// How blob payloads are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlobEncoding {
+    /// Standard base64 with padding
+    #[default]
+    Base64,
+    /// A string, with invalid UTF-8 replaced
+    String,
+}
+
+/// Options of [`to_json`]
+#[derive(Debug, Clone, Default)]
+pub struct JsonOptions {
+    pub blobs: BlobEncoding,
+    /// Add the bit `offset` of each element, counted from the magic number
+    /// of the bitstream
+    pub offsets: bool,
+    /// Names taking precedence over the stream's `BLOCKINFO`
+    pub names: Option<NameTable>,
+}
+
+/// Dump bitcode as JSON, with the block and record names of its
+/// `BLOCKINFO`
+///
+/// Accepts both LLVM bitcode and bitcode wrapper formats
+pub fn to_json(data: &[u8], options: &JsonOptions) -> Result<String, Error> {
+    let (signature, mut reader) = Bitcode::reader(data)?;
+    let mut out = String::new();
+    write!(
+        out,
+        "{{\"signature\":{},\"elements\":",
+        signature.into_inner()
+    )
+    .unwrap();
+    write_elements(&mut reader.iter_top_level(), options, &mut out)?;
+    out.push('}');
+    Ok(out)
+}
+
+fn write_elements(
+    block: &mut BlockIter<'_, '_>,
+    options: &JsonOptions,
+    out: &mut String,
+) -> Result<(), Error> {
+    let block_id = block.id;
+    out.push('[');
+    let mut first = true;
+    while let Some(item) = block.next()? {
+        if !first {
+            out.push(',');
+        }
+        first = false;
+        // The offset of an item is only known once it has been read, write
+        // it to its own buffer first
+        let mut element = String::new();
+        let (kind, id) = match item {
+            BlockItem::Block(mut child) => {
+                let id = child.id;
+                element.push_str(",\"elements\":");
+                write_elements(&mut child, options, &mut element)?;
+                ("block", id)
+            }
+            BlockItem::Record(record) => {
+                let record = record.into_record()?;
+                write!(element, ",\"fields\":{:?}", record.fields).unwrap();
+                match &record.payload {
+                    Some(Payload::Array(array)) => {
+                        write!(element, ",\"array\":{:?}", array).unwrap()
+                    }
+                    Some(Payload::Char6String(s)) => {
+                        element.push_str(",\"char6\":");
+                        write_string(s, &mut element);
+                    }
+                    Some(Payload::Blob(blob)) => {
+                        element.push_str(",\"blob\":");
+                        match options.blobs {
+                            BlobEncoding::Base64 => {
+                                write!(element, "\"{}\"", base64(blob)).unwrap()
+                            }
+                            BlobEncoding::String => {
+                                write_string(&String::from_utf8_lossy(blob), &mut element)
+                            }
+                        }
+                    }
+                    None => {}
+                }
+                ("record", record.id)
+            }
+        };
+        write!(out, "{{\"type\":\"{}\",\"id\":{}", kind, id).unwrap();
+        let custom = options.names.as_ref();
+        let name = if kind == "block" {
+            custom
+                .and_then(|names| names.block_name(id))
+                .or_else(|| block.block_name(id))
+        } else {
+            custom
+                .and_then(|names| names.record_name(block_id, id))
+                .or_else(|| block.record_name(block_id, id))
+        };
+        if let Some(name) = name {
+            out.push_str(",\"name\":");
+            write_string(name, out);
+        }
+        if options.offsets {
+            write!(out, ",\"offset\":{}", block.item_offset() + MAGIC_BITS).unwrap();
+        }
+        out.push_str(&element);
+        out.push('}');
+    }
+    out.push(']');
+    Ok(())
+}
+
+fn write_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let group = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (group >> (18 - 6 * index)) & 0x3f;
+                encoded.push(char::from(ALPHABET[sextet as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -1,15 +1,53 @@
 //! LLVM Bitcode parser in Rust
 
+/// `ar` archives
+pub mod archive;
+/// Arena-backed bitcode storage
+pub mod arena;
 /// Bitcode definitions
 pub mod bitcode;
-mod bits;
+/// Bit-level primitives
+pub mod bits;
 /// Bitstream definitions
 pub mod bitstream;
+/// C API
+#[cfg(feature = "capi")]
+pub mod capi;
+/// Semantic diff of bitcode
+pub mod diff;
+/// Bitcode embedded in object files
+pub mod embedded;
+/// Bitstream formats other than LLVM IR
+pub mod formats;
+mod inflate;
+/// JSON dump of bitcode
+pub mod json;
+/// Memory-mapped bitcode files
+#[cfg(feature = "mmap")]
+pub mod mmap;
 /// Bitstream reader
 pub mod read;
+/// Bitcode of Rust libraries
+pub mod rlib;
+/// Bitcode schema definitions
+pub mod schema;
+/// Random access to bitcode in seekable readers
+pub mod seekable;
+/// Size attribution of bitcode modules
+pub mod size;
+/// Streaming bitstream reader
+pub mod stream;
+/// Symbol listing of bitcode modules
+pub mod symbols;
+/// Cross-checking against LLVM's bitcode reader
+#[cfg(feature = "llvm-validation")]
+pub mod validate;
 /// Bitstream visitor
 pub mod visitor;
+/// Apple xar bitcode bundles
+#[cfg(feature = "xar")]
+pub mod xar;
 
-pub use self::bitcode::Bitcode;
+pub use self::bitcode::{Bitcode, BitcodeInfo};
 pub use self::read::BitStreamReader;
-pub use self::visitor::BitStreamVisitor;
+pub use self::visitor::{BitStreamVisitor, TryBitStreamVisitor};
@@ -0,0 +1,59 @@
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::arena::BitcodeArena;
+use crate::read::Error;
+use crate::Bitcode;
+
+/// A bitcode file mapped into memory
+///
+/// Parsed bitcode borrows blob payloads from the map, so it cannot outlive
+/// the `MappedBitcode` it was parsed from.
+#[derive(Debug)]
+pub struct MappedBitcode {
+    map: Mmap,
+}
+
+impl MappedBitcode {
+    /// Memory-map the file at `path`
+    ///
+    /// The file must not be modified while it is mapped, changes made by
+    /// other processes are visible through the map.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // SAFETY: the map is read-only, and modifying a file while it is
+        // being parsed is documented as unsupported.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Self { map })
+    }
+
+    /// Parse the mapped bytes
+    ///
+    /// Accepts both LLVM bitcode and bitcode wrapper formats
+    pub fn parse(&self) -> Result<Bitcode<'_>, Error> {
+        Bitcode::new(&self.map)
+    }
+
+    /// Parse the mapped bytes into a [`BitcodeArena`]
+    pub fn parse_arena(&self) -> Result<BitcodeArena<'_>, Error> {
+        BitcodeArena::new(&self.map)
+    }
+}
+
+impl Deref for MappedBitcode {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.map
+    }
+}
+
+impl AsRef<[u8]> for MappedBitcode {
+    fn as_ref(&self) -> &[u8] {
+        &self.map
+    }
+}
@@ -1,9 +1,15 @@
-use std::{collections::HashMap, convert::TryFrom, error, fmt, mem};
+use std::{
+    borrow::Cow, collections::HashMap, convert::TryFrom, error, fmt, mem, num::NonZeroU64,
+    ops::Range, sync::Arc,
+};
 
 use crate::bitcode::{BlockInfo, Payload, Record, Signature};
 use crate::bits::{self, Bits, Cursor};
-use crate::bitstream::{Abbreviation, BlockInfoCode, BuiltinAbbreviationId, Operand};
-use crate::visitor::BitStreamVisitor;
+use crate::bitstream::{AbbrevInfo, Abbreviation, BlockInfoCode, BuiltinAbbreviationId, Operand};
+use crate::visitor::{LazyVisitor, TryBitStreamVisitor};
+
+/// Widest fixed or VBR field, and widest abbreviation id, LLVM writes
+const MAX_CHUNK_WIDTH: usize = 32;
 
 /// Bitstream reader errors
 #[derive(Debug, Clone)]
@@ -14,9 +20,162 @@ pub enum Error {
     MissingSetBid,
     InvalidBlockInfoRecord(u64),
     AbbrevWidthTooSmall(usize),
-    NoSuchAbbrev { block_id: u64, abbrev_id: usize },
+    AbbrevWidthTooLarge(usize),
+    /// The bitcode wrapper header points outside of the input
+    InvalidWrapper,
+    /// [`BitStreamReader::read_signature`] was called after reading from
+    /// the stream
+    SignatureNotAtStart,
+    NoSuchAbbrev {
+        block_id: u64,
+        abbrev_id: usize,
+    },
     MissingEndBlock(u64),
+    MissingField(u64),
+    ValueOutOfRange(u64),
+    InvalidString(u64),
+    /// No block body starts at the bit offset given to
+    /// [`BitStreamReader::resume`]
+    NoBlockAt(u64),
+    /// The input ended before the item being read, more data must be fed
+    /// to a [`StreamReader`](crate::stream::StreamReader) to read it
+    NeedMoreData,
     ReadBits(bits::Error),
+    /// A limit set in [`ParseOptions`] was exceeded
+    LimitExceeded(Limit),
+    /// An error with the location in the stream where it occurred
+    Context(Box<ErrorContext>),
+}
+
+/// A limit of [`ParseOptions`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    BlockDepth,
+    RecordFields,
+    PayloadLength,
+    DecodedElements,
+    /// See [`CollectOptions::max_records`](crate::visitor::CollectOptions::max_records)
+    CollectedRecords,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::BlockDepth => write!(f, "block depth"),
+            Limit::RecordFields => write!(f, "record fields"),
+            Limit::PayloadLength => write!(f, "payload length"),
+            Limit::DecodedElements => write!(f, "decoded elements"),
+            Limit::CollectedRecords => write!(f, "collected records"),
+        }
+    }
+}
+
+/// Limits on the work done reading a stream, for untrusted input
+///
+/// Exceeding a limit fails the read with [`Error::LimitExceeded`]. Every
+/// limit but the block depth is unbounded by default: blocks are read
+/// recursively, so the depth is bounded to fail a deeply nested stream
+/// before it overflows the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Maximum nesting depth of blocks, top level blocks being at depth 1,
+    /// [`ParseOptions::DEFAULT_MAX_BLOCK_DEPTH`] by default
+    pub max_block_depth: usize,
+    /// Maximum number of fields of a record, or operands of an abbreviation
+    pub max_record_fields: usize,
+    /// Maximum number of elements of an array payload, or bytes of a blob
+    pub max_payload_len: usize,
+    /// Maximum number of record fields and array elements decoded in total
+    pub max_decoded_elements: u64,
+}
+
+impl ParseOptions {
+    /// Default nesting depth limit, far deeper than any format built on
+    /// bitstreams nests its blocks
+    pub const DEFAULT_MAX_BLOCK_DEPTH: usize = 256;
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            max_block_depth: Self::DEFAULT_MAX_BLOCK_DEPTH,
+            max_record_fields: usize::MAX,
+            max_payload_len: usize::MAX,
+            max_decoded_elements: u64::MAX,
+        }
+    }
+}
+
+/// Tracks the decoding work done against [`ParseOptions`]
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Budget {
+    pub(crate) options: ParseOptions,
+    decoded: u64,
+}
+
+impl Budget {
+    /// Account for `count` decoded elements
+    pub(crate) fn decode(&mut self, count: u64) -> Result<(), Error> {
+        self.decoded = self.decoded.saturating_add(count);
+        if self.decoded > self.options.max_decoded_elements {
+            return Err(Error::LimitExceeded(Limit::DecodedElements));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_fields(&self, count: usize) -> Result<(), Error> {
+        if count > self.options.max_record_fields {
+            return Err(Error::LimitExceeded(Limit::RecordFields));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn check_depth(&self, depth: usize) -> Result<(), Error> {
+        if depth > self.options.max_block_depth {
+            return Err(Error::LimitExceeded(Limit::BlockDepth));
+        }
+        Ok(())
+    }
+
+    fn check_payload(&self, len: usize) -> Result<(), Error> {
+        if len > self.options.max_payload_len {
+            return Err(Error::LimitExceeded(Limit::PayloadLength));
+        }
+        Ok(())
+    }
+}
+
+/// Where in the stream an error occurred
+#[derive(Debug, Clone)]
+pub struct ErrorContext {
+    /// The error
+    pub error: Error,
+    /// Bit offset of the item being read, relative to the start of the
+    /// reader's buffer
+    pub offset: u64,
+    /// Ids of the blocks enclosing the item, outermost first
+    pub block_path: Vec<u64>,
+    /// Code and abbreviation id of the record being read, if the error
+    /// occurred while decoding a record
+    pub record: Option<(u64, u64)>,
+}
+
+impl Error {
+    /// The error without the [`ErrorContext`] it may be wrapped in
+    pub fn root_cause(&self) -> &Error {
+        match self {
+            Error::Context(context) => context.error.root_cause(),
+            err => err,
+        }
+    }
+
+    /// The location of the error, if known
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Error::Context(context) => Some(context),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -34,6 +193,11 @@ impl fmt::Display for Error {
             Error::AbbrevWidthTooSmall(width) => {
                 write!(f, "abbreviation width `{}` is too small", width)
             }
+            Error::AbbrevWidthTooLarge(width) => {
+                write!(f, "abbreviation width `{}` is too large", width)
+            }
+            Error::InvalidWrapper => write!(f, "invalid bitcode wrapper header"),
+            Error::SignatureNotAtStart => write!(f, "signature not at the start of the stream"),
             Error::NoSuchAbbrev {
                 block_id,
                 abbrev_id,
@@ -43,12 +207,41 @@ impl fmt::Display for Error {
                 abbrev_id, block_id
             ),
             Error::MissingEndBlock(block_id) => write!(f, "missing end block for `{}`", block_id),
+            Error::MissingField(record_id) => {
+                write!(f, "missing field in record `{}`", record_id)
+            }
+            Error::ValueOutOfRange(value) => write!(f, "value `{}` is out of range", value),
+            Error::InvalidString(record_id) => {
+                write!(f, "invalid string in record `{}`", record_id)
+            }
+            Error::NoBlockAt(offset) => write!(f, "no block starts at bit `{}`", offset),
+            Error::NeedMoreData => write!(f, "need more data"),
             Error::ReadBits(err) => err.fmt(f),
+            Error::LimitExceeded(limit) => write!(f, "{} limit exceeded", limit),
+            Error::Context(context) => {
+                write!(
+                    f,
+                    "{} at bit {} in block path {:?}",
+                    context.error, context.offset, context.block_path
+                )?;
+                if let Some((record_id, abbrev_id)) = context.record {
+                    write!(f, ", record `{}` with abbrev `{}`", record_id, abbrev_id)?;
+                }
+                Ok(())
+            }
         }
     }
 }
 
-impl error::Error for Error {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::ReadBits(err) => Some(err),
+            Error::Context(context) => Some(&context.error),
+            _ => None,
+        }
+    }
+}
 
 impl From<bits::Error> for Error {
     fn from(err: bits::Error) -> Self {
@@ -59,10 +252,27 @@ impl From<bits::Error> for Error {
 /// Bitstream reader
 #[derive(Debug, Clone)]
 pub struct BitStreamReader<'a> {
-    cursor: Cursor<'a>,
+    pub(crate) cursor: Cursor<'a>,
     /// Block information
     pub(crate) block_info: HashMap<u64, BlockInfo>,
-    global_abbrevs: HashMap<u64, Vec<Abbreviation>>,
+    pub(crate) global_abbrevs: BlockTable<Vec<Arc<Abbreviation>>>,
+    /// Nesting depth of the innermost block being read
+    depth: usize,
+    /// Ids of the blocks being read, outermost first
+    path: Vec<u64>,
+    pub(crate) budget: Budget,
+    /// Abbreviation definitions read and not yet reported to the visitor,
+    /// `None` unless reading with a visitor
+    defined_abbrevs: Option<Vec<DefinedAbbrev>>,
+}
+
+/// An abbreviation definition to report with
+/// [`TryBitStreamVisitor::did_define_abbrev`]
+#[derive(Debug, Clone)]
+struct DefinedAbbrev {
+    block_id: u64,
+    abbrev: AbbrevInfo,
+    in_block_info: bool,
 }
 
 impl<'a> BitStreamReader<'a> {
@@ -75,33 +285,123 @@ impl<'a> BitStreamReader<'a> {
         Self {
             cursor,
             block_info: HashMap::new(),
-            global_abbrevs: HashMap::new(),
+            global_abbrevs: BlockTable::default(),
+            depth: 0,
+            path: Vec::new(),
+            budget: Budget::default(),
+            defined_abbrevs: None,
+        }
+    }
+
+    /// Enforce the limits of `options` for the rest of the read
+    pub fn set_options(&mut self, options: ParseOptions) {
+        self.budget.options = options;
+    }
+
+    /// Bit offset of the next item to read, relative to the start of the
+    /// buffer
+    pub fn bit_position(&self) -> u64 {
+        self.cursor.offset()
+    }
+
+    /// Move to the bit offset `bit` of the buffer
+    ///
+    /// Reading resumes from there, e.g. with [`BitStreamReader::read_block`]
+    /// from the [`offset`](BlockContext::offset) and with the
+    /// [`abbrev_width`](BlockContext::abbrev_width) of a block seen earlier,
+    /// or with [`BitStreamReader::iter_top_level`] from the
+    /// [`header_offset`](BlockContext::header_offset) of a top level block.
+    /// Abbreviations defined by `BLOCKINFO` blocks read so far remain
+    /// available.
+    pub fn seek_to(&mut self, bit: u64) -> Result<(), Error> {
+        Ok(self.cursor.seek(bit)?)
+    }
+
+    /// Create a reader positioned at the body of a block, with the
+    /// abbreviations and names of the `BLOCKINFO` blocks before it loaded
+    ///
+    /// `offset` is the [`offset`](BlockContext::offset) of the block in
+    /// `buffer`, e.g. recorded by an earlier pass over the stream; read the
+    /// block with [`BitStreamReader::read_block`] and the id and
+    /// abbreviation width of the block. `BLOCKINFO` blocks are looked for
+    /// at the top level and in the blocks enclosing the block, which are
+    /// scanned up to it, while other blocks are skipped without being read.
+    pub fn resume(buffer: &'a [u8], offset: u64) -> Result<Self, Error> {
+        let mut scan = Self::new(buffer);
+        if !find_block(&mut scan.iter_top_level(), offset)? {
+            return Err(Error::NoBlockAt(offset));
+        }
+        let mut reader = Self::new(buffer);
+        reader.global_abbrevs = scan.global_abbrevs;
+        reader.block_info = scan.block_info;
+        reader.seek_to(offset)?;
+        Ok(reader)
+    }
+
+    /// Read fields ahead of the current position without consuming them
+    ///
+    /// The fields read with the returned [`Peek`] are consumed only if it is
+    /// [committed](Peek::commit), the reader stays where it was otherwise.
+    pub fn peek(&mut self) -> Peek<'_, 'a> {
+        let cursor = self.cursor.clone();
+        Peek {
+            reader: self,
+            cursor,
         }
     }
 
     /// Read signature, aka. Magic Number
+    ///
+    /// Fails with [`Error::SignatureNotAtStart`] unless nothing was read
+    /// from the stream yet.
     pub fn read_signature(&mut self) -> Result<Signature, Error> {
-        assert!(self.cursor.is_at_start());
+        if !self.cursor.is_at_start() {
+            return Err(Error::SignatureNotAtStart);
+        }
         let bits = self.cursor.read(mem::size_of::<u32>() * 8)? as u32;
         Ok(Signature::new(bits))
     }
 
     /// Read abbreviated operand
     pub fn read_abbrev_op(&mut self) -> Result<Operand, Error> {
+        match self.read_abbrev_scalar_op()? {
+            Some(op) => Ok(op),
+            None => {
+                let element = self.read_abbrev_scalar_op()?.ok_or(Error::InvalidAbbrev)?;
+                Ok(Operand::Array(Box::new(element)))
+            }
+        }
+    }
+
+    /// Read an abbreviated operand, returning `None` for the start of an array
+    fn read_abbrev_scalar_op(&mut self) -> Result<Option<Operand>, Error> {
         let is_literal = self.cursor.read(1)?;
         if is_literal == 1 {
-            return Ok(Operand::Literal(self.cursor.read_vbr(8)?));
+            return Ok(Some(Operand::Literal(self.cursor.read_vbr(8)?)));
         }
         let op_type = self.cursor.read(3)?;
         let op = match op_type {
-            1 => Operand::Fixed(self.cursor.read_vbr(5)? as u8),
-            2 => Operand::Vbr(self.cursor.read_vbr(5)? as u8),
-            3 => Operand::Array(Box::new(self.read_abbrev_op()?)),
+            1 | 2 => {
+                let width = read_operand_width(&mut self.cursor)?;
+                if width == 0 {
+                    // Like LLVM, zero-width fixed and VBR fields are read as
+                    // a literal zero
+                    Operand::Literal(0)
+                } else if op_type == 1 {
+                    Operand::Fixed(width)
+                } else if width >= 2 {
+                    Operand::Vbr(width)
+                } else {
+                    // VBR chunks need a value bit besides the continuation bit
+                    return Err(Error::InvalidAbbrev);
+                }
+            }
+            3 => return Ok(None),
             4 => Operand::Char6,
             5 => Operand::Blob,
             _ => return Err(Error::InvalidAbbrev),
         };
-        Ok(op)
+        Ok(Some(op))
     }
 
     /// Read abbreviation
@@ -109,95 +409,125 @@ impl<'a> BitStreamReader<'a> {
         if num_ops == 0 {
             return Err(Error::InvalidAbbrev);
         }
+        self.budget.check_fields(num_ops)?;
         let mut operands = Vec::new();
         for i in 0..num_ops {
             let op = self.read_abbrev_op()?;
             let is_array = op.is_array();
             let is_blob = op.is_blob();
             operands.push(op);
+            if (is_array || is_blob) && i == 0 {
+                // The record code can't be a payload
+                return Err(Error::InvalidAbbrev);
+            }
             if is_array {
-                if i == num_ops - 2 {
+                if i + 2 == num_ops {
                     break;
                 } else {
                     return Err(Error::InvalidAbbrev);
                 }
-            } else if is_blob {
-                if i != num_ops - 1 {
-                    return Err(Error::InvalidAbbrev);
-                }
+            } else if is_blob && i + 1 != num_ops {
+                return Err(Error::InvalidAbbrev);
             }
         }
         Ok(Abbreviation { operands })
     }
 
-    fn read_single_abbreviated_record_operand(&mut self, operand: &Operand) -> Result<u64, Error> {
-        match operand {
-            Operand::Char6 => {
-                let value = self.cursor.read(6)?;
-                return match value {
-                    0..=25 => Ok(value + u64::from('a' as u32)),
-                    26..=51 => Ok(value + u64::from('A' as u32) - 26),
-                    52..=61 => Ok(value + u64::from('0' as u32) - 52),
-                    62 => Ok(u64::from('.' as u32)),
-                    63 => Ok(u64::from('_' as u32)),
-                    _ => Err(Error::InvalidAbbrev),
-                };
-            }
-            Operand::Literal(value) => Ok(*value),
-            Operand::Fixed(width) => Ok(self.cursor.read(*width as usize)?),
-            Operand::Vbr(width) => Ok(self.cursor.read_vbr(*width as usize)?),
-            Operand::Array(_) | Operand::Blob => Err(Error::InvalidAbbrev),
-        }
-    }
-
     /// Read abbreviated data record
-    pub fn read_abbreviated_record(&mut self, abbrev: &Abbreviation) -> Result<Record, Error> {
-        let code =
-            self.read_single_abbreviated_record_operand(&abbrev.operands.first().unwrap())?;
-        let last_operand = abbrev.operands.last().unwrap();
-        let last_regular_operand_index =
-            abbrev.operands.len() - (if last_operand.is_payload() { 1 } else { 0 });
+    ///
+    /// The abbreviation id is not known here, so the returned record has no
+    /// [`AbbrevInfo`].
+    pub fn read_abbreviated_record(&mut self, abbrev: &Abbreviation) -> Result<Record<'a>, Error> {
+        let (code_operand, operands) = abbrev.operands.split_first().ok_or(Error::InvalidAbbrev)?;
+        let code = read_scalar_operand(&mut self.cursor, code_operand)?;
+        let (operands, payload_operand) = match operands.split_last() {
+            Some((last, operands)) if last.is_payload() => (operands, Some(last)),
+            _ => (operands, None),
+        };
+        self.budget.decode(operands.len() as u64)?;
         let mut fields = Vec::new();
-        for op in &abbrev.operands[1..last_regular_operand_index] {
-            fields.push(self.read_single_abbreviated_record_operand(op)?);
-        }
-        let payload = if last_operand.is_payload() {
-            match last_operand {
-                Operand::Array(element) => {
-                    let length = self.cursor.read_vbr(6)? as usize;
-                    let mut elements = Vec::with_capacity(length);
-                    for _ in 0..length {
-                        elements.push(self.read_single_abbreviated_record_operand(element)?);
-                    }
-                    if matches!(**element, Operand::Char6) {
-                        let s: String = elements
-                            .into_iter()
-                            .map(|x| std::char::from_u32(x as u32).unwrap())
-                            .collect();
-                        Some(Payload::Char6String(s))
-                    } else {
-                        Some(Payload::Array(elements))
-                    }
-                }
-                Operand::Blob => {
-                    let length = self.cursor.read_vbr(6)? as usize;
-                    self.cursor.advance(32)?;
-                    let data = self.cursor.read_bytes(length)?;
-                    self.cursor.advance(32)?;
-                    Some(Payload::Blob(data))
-                }
-                _ => unreachable!(),
-            }
-        } else {
-            None
+        for op in operands {
+            fields.push(read_scalar_operand(&mut self.cursor, op)?);
+        }
+        let payload = match payload_operand {
+            Some(op) => Some(read_payload(
+                &mut self.cursor,
+                op,
+                Vec::new(),
+                &mut self.budget,
+            )?),
+            None => None,
         };
         Ok(Record {
             id: code,
             fields,
             payload,
+            abbrev: None,
+            bit_range: None,
         })
     }
 
+    /// Read the header of the next item of the block `block_id`, whose
+    /// abbreviations in scope are `abbrevs`
+    ///
+    /// Abbreviation definitions are added to `abbrevs`. Nested blocks,
+    /// `BLOCKINFO` included, and record bodies are left to the caller.
+    pub(crate) fn read_item_header(
+        &mut self,
+        block_id: u64,
+        abbrev_width: usize,
+        abbrevs: &mut BlockAbbrevs,
+    ) -> Result<RawItemHeader, Error> {
+        use BuiltinAbbreviationId::*;
+
+        let abbrev_id = self.cursor.read(abbrev_width)?;
+        match BuiltinAbbreviationId::try_from(abbrev_id) {
+            Ok(EndBlock) => {
+                self.cursor.advance(32)?;
+                Ok(RawItemHeader::EndBlock)
+            }
+            Ok(EnterSubBlock) => {
+                let id = self.cursor.read_vbr(8)?;
+                let abbrev_width = read_abbrev_width(&mut self.cursor)?;
+                self.cursor.advance(32)?;
+                let length = self.cursor.read(32)? as usize;
+                Ok(RawItemHeader::EnterBlock {
+                    id,
+                    abbrev_width,
+                    length,
+                })
+            }
+            Ok(DefineAbbreviation) => {
+                let num_ops = read_count(&mut self.cursor, 5)?;
+                let abbrev = Arc::new(self.read_abbrev(num_ops)?);
+                abbrevs.local.push(abbrev.clone());
+                let id = 3 + (abbrevs.global_count + abbrevs.local.len()) as u64;
+                Ok(RawItemHeader::DefineAbbrev(AbbrevInfo { id, abbrev }))
+            }
+            Ok(UnabbreviatedRecord) => {
+                let id = self.cursor.read_vbr(6)?;
+                let num_ops = read_count(&mut self.cursor, 6)?;
+                self.budget.check_fields(num_ops)?;
+                Ok(RawItemHeader::UnabbreviatedRecord { id, num_ops })
+            }
+            Err(_) => {
+                let abbrev = abbrevs
+                    .get(&self.global_abbrevs, block_id, abbrev_id)
+                    .ok_or(Error::NoSuchAbbrev {
+                        block_id,
+                        abbrev_id: abbrev_id as usize,
+                    })?;
+                if abbrev.operands.is_empty() {
+                    return Err(Error::InvalidAbbrev);
+                }
+                Ok(RawItemHeader::AbbreviatedRecord(AbbrevInfo {
+                    id: abbrev_id,
+                    abbrev,
+                }))
+            }
+        }
+    }
+
     /// Read block info block
     pub fn read_block_info_block(&mut self, abbrev_width: usize) -> Result<(), Error> {
         use BuiltinAbbreviationId::*;
@@ -218,29 +548,32 @@ impl<'a> BitStreamReader<'a> {
                 }
                 DefineAbbreviation => {
                     if let Some(block_id) = current_block_id {
-                        let num_ops = self.cursor.read_vbr(5)? as usize;
-                        let abbrev = self.read_abbrev(num_ops)?;
-                        let abbrevs = self
-                            .global_abbrevs
-                            .entry(block_id)
-                            .or_insert_with(|| Vec::new());
-                        abbrevs.push(abbrev);
+                        let num_ops = read_count(&mut self.cursor, 5)?;
+                        let abbrev = Arc::new(self.read_abbrev(num_ops)?);
+                        let abbrevs = self.global_abbrevs.get_or_default(block_id);
+                        abbrevs.push(abbrev.clone());
+                        let id = 3 + abbrevs.len() as u64;
+                        if let Some(defined) = &mut self.defined_abbrevs {
+                            defined.push(DefinedAbbrev {
+                                block_id,
+                                abbrev: AbbrevInfo { id, abbrev },
+                                in_block_info: true,
+                            });
+                        }
                     } else {
                         return Err(Error::MissingSetBid);
                     }
                 }
                 UnabbreviatedRecord => {
                     let code = self.cursor.read_vbr(6)?;
-                    let num_ops = self.cursor.read_vbr(6)? as usize;
-                    let mut operands = Vec::with_capacity(num_ops);
+                    let num_ops = read_count(&mut self.cursor, 6)?;
+                    self.budget.check_fields(num_ops)?;
+                    // Every operand takes at least 6 bits, don't trust `num_ops` further
+                    let mut operands = Vec::with_capacity(capacity(num_ops, &self.cursor, 6));
                     for _ in 0..num_ops {
                         operands.push(self.cursor.read_vbr(6)?);
                     }
-                    match BlockInfoCode::try_from(
-                        u8::try_from(code).map_err(|_| Error::InvalidBlockInfoRecord(code))?,
-                    )
-                    .map_err(|_| Error::InvalidBlockInfoRecord(code))?
-                    {
+                    match BlockInfoCode::from(code) {
                         BlockInfoCode::SetBid => {
                             if operands.len() != 1 {
                                 return Err(Error::InvalidBlockInfoRecord(code));
@@ -249,10 +582,7 @@ impl<'a> BitStreamReader<'a> {
                         }
                         BlockInfoCode::BlockName => {
                             if let Some(block_id) = current_block_id {
-                                let block_info = self
-                                    .block_info
-                                    .entry(block_id)
-                                    .or_insert_with(|| BlockInfo::default());
+                                let block_info = self.block_info.entry(block_id).or_default();
                                 let name = String::from_utf8(
                                     operands.into_iter().map(|x| x as u8).collect::<Vec<u8>>(),
                                 )
@@ -265,10 +595,7 @@ impl<'a> BitStreamReader<'a> {
                         BlockInfoCode::SetRecordName => {
                             if let Some(block_id) = current_block_id {
                                 if let Some(record_id) = operands.first().cloned() {
-                                    let block_info = self
-                                        .block_info
-                                        .entry(block_id)
-                                        .or_insert_with(|| BlockInfo::default());
+                                    let block_info = self.block_info.entry(block_id).or_default();
                                     let name = String::from_utf8(
                                         operands
                                             .into_iter()
@@ -285,6 +612,7 @@ impl<'a> BitStreamReader<'a> {
                                 return Err(Error::MissingSetBid);
                             }
                         }
+                        BlockInfoCode::Unknown(_) => {}
                     }
                 }
             }
@@ -292,79 +620,1138 @@ impl<'a> BitStreamReader<'a> {
     }
 
     /// Read block with visitor
-    pub fn read_block<V: BitStreamVisitor>(
+    pub fn read_block<V: TryBitStreamVisitor<'a>>(
         &mut self,
         id: u64,
         abbrev_width: usize,
         visitor: &mut V,
-    ) -> Result<(), Error> {
-        use BuiltinAbbreviationId::*;
+    ) -> Result<(), V::Error> {
+        let context = self.context_to_end(id, abbrev_width);
+        let depth = self.depth;
+        self.reporting_abbrevs(|reader| {
+            BlockIter::new(reader, context, depth).accept(visitor, None)
+        })
+    }
 
-        while !self.cursor.is_at_end() {
-            let abbrev_id = self.cursor.read(abbrev_width)?;
-            match BuiltinAbbreviationId::try_from(abbrev_id) {
-                Ok(abbrev_id) => match abbrev_id {
-                    EndBlock => {
-                        self.cursor.advance(32)?;
-                        visitor.did_exit_block();
-                        return Ok(());
+    /// Read block with visitor, decoding every record into the same scratch
+    /// record and passing it to [`TryBitStreamVisitor::visit_ref`]
+    ///
+    /// Once the scratch buffers have grown to the largest record, reading
+    /// does not allocate per record.
+    pub fn read_block_reusing<V: TryBitStreamVisitor<'a>>(
+        &mut self,
+        id: u64,
+        abbrev_width: usize,
+        visitor: &mut V,
+    ) -> Result<(), V::Error> {
+        let context = self.context_to_end(id, abbrev_width);
+        let depth = self.depth;
+        let mut scratch = Record {
+            id: 0,
+            fields: Vec::new(),
+            payload: None,
+            abbrev: None,
+            bit_range: None,
+        };
+        self.reporting_abbrevs(|reader| {
+            BlockIter::new(reader, context, depth).accept(visitor, Some(&mut scratch))
+        })
+    }
+
+    /// Read block with a visitor receiving records before their fields are
+    /// decoded
+    pub fn read_block_lazy<V: LazyVisitor<'a>>(
+        &mut self,
+        id: u64,
+        abbrev_width: usize,
+        visitor: &mut V,
+    ) -> Result<(), V::Error> {
+        let context = self.context_to_end(id, abbrev_width);
+        let depth = self.depth;
+        BlockIter::new(self, context, depth).accept_lazy(visitor)
+    }
+
+    /// Read block with visitor, recovering from malformed input
+    ///
+    /// When an item of a block can't be read, the error is added to
+    /// `diagnostics` and reading resumes after the end of that block, as
+    /// declared in its header. Errors returned by the visitor still abort
+    /// the read.
+    pub fn read_block_recovering<V: TryBitStreamVisitor<'a>>(
+        &mut self,
+        id: u64,
+        abbrev_width: usize,
+        visitor: &mut V,
+        diagnostics: &mut Vec<Error>,
+    ) -> Result<(), V::Error> {
+        let context = self.context_to_end(id, abbrev_width);
+        let depth = self.depth;
+        self.reporting_abbrevs(|reader| {
+            BlockIter::new(reader, context, depth).accept_recovering(visitor, diagnostics)
+        })
+    }
+
+    /// Run `read` recording the abbreviation definitions read, for
+    /// [`BlockIter`] to report them to the visitor
+    fn reporting_abbrevs<T>(&mut self, read: impl FnOnce(&mut Self) -> T) -> T {
+        let previous = self.defined_abbrevs.replace(Vec::new());
+        let res = read(self);
+        self.defined_abbrevs = previous;
+        res
+    }
+
+    /// Iterate over the top level items of the stream
+    ///
+    /// See [`BlockIter`] for the reading semantics.
+    pub fn iter_top_level(&mut self) -> BlockIter<'_, 'a> {
+        let context = self.context_to_end(Self::TOP_LEVEL_BLOCK_ID, 2);
+        let depth = self.depth;
+        BlockIter::new(self, context, depth)
+    }
+
+    /// Iterate over the items of a block whose body starts at the current
+    /// position and spans the rest of the stream
+    pub(crate) fn iter_block(&mut self, id: u64, abbrev_width: usize) -> BlockIter<'_, 'a> {
+        let context = self.context_to_end(id, abbrev_width);
+        let depth = self.depth;
+        BlockIter::new(self, context, depth)
+    }
+
+    /// Context of a block starting at the current position and spanning
+    /// the rest of the stream
+    fn context_to_end(&self, id: u64, abbrev_width: usize) -> BlockContext {
+        let offset = self.cursor.offset();
+        BlockContext {
+            id,
+            abbrev_width,
+            // At most a quarter of the buffer length, so it fits in `usize`
+            length: ((self.cursor.bit_len() - offset) / 32) as usize,
+            offset,
+            header_offset: offset,
+        }
+    }
+}
+
+/// Read `block` up to the nested block whose body starts at `offset`,
+/// returning whether it was found
+fn find_block(block: &mut BlockIter<'_, '_>, offset: u64) -> Result<bool, Error> {
+    while let Some(item) = block.next()? {
+        if let BlockItem::Block(mut nested) = item {
+            let context = nested.context();
+            if context.offset == offset {
+                return Ok(true);
+            }
+            if context.bit_range().contains(&offset) {
+                return find_block(&mut nested, offset);
+            }
+        }
+        if block.item_offset() > offset {
+            break;
+        }
+    }
+    Ok(false)
+}
+
+/// Fields read ahead of a [`BitStreamReader`], see [`BitStreamReader::peek`]
+///
+/// Dropping it discards the fields read.
+#[derive(Debug)]
+pub struct Peek<'reader, 'input> {
+    reader: &'reader mut BitStreamReader<'input>,
+    cursor: Cursor<'input>,
+}
+
+impl<'reader, 'input> Peek<'reader, 'input> {
+    /// Bit offset of the next field to read, relative to the start of the
+    /// buffer
+    pub fn offset(&self) -> u64 {
+        self.cursor.offset()
+    }
+
+    /// Read a fixed-width field of at most 64 bits
+    pub fn fixed(&mut self, width: usize) -> Result<u64, Error> {
+        if width > 64 {
+            return Err(Error::ValueOutOfRange(width as u64));
+        }
+        Ok(self.cursor.read(width)?)
+    }
+
+    /// Read a VBR field with chunks of 2 to 32 bits
+    pub fn vbr(&mut self, width: usize) -> Result<u64, Error> {
+        if !(2..=MAX_CHUNK_WIDTH).contains(&width) {
+            return Err(Error::ValueOutOfRange(width as u64));
+        }
+        Ok(self.cursor.read_vbr(width)?)
+    }
+
+    /// Skip to the next multiple of 32 bits, like a block header does
+    pub fn align32(&mut self) -> Result<(), Error> {
+        Ok(self.cursor.advance(32)?)
+    }
+
+    /// Consume the fields read, moving the reader after them
+    pub fn commit(self) {
+        self.reader.cursor = self.cursor;
+    }
+
+    /// Leave the reader where it was
+    pub fn discard(self) {}
+}
+
+/// A pull-based reader over the items of a single block
+///
+/// Items are read lazily with [`BlockIter::next`]:
+///
+/// * Nested blocks are yielded as another `BlockIter` borrowing this one.
+///   A nested block that is dropped before being read to its end is skipped
+///   using the length declared in its header, so skipping a block costs no
+///   more than reading its header.
+/// * Records are yielded as a [`RecordIter`] whose fields are decoded on
+///   demand. Fields left unread are skipped when the next item is requested.
+/// * `BLOCKINFO` blocks are consumed by the reader itself and never yielded;
+///   the abbreviations they define apply to every later block with the
+///   matching id.
+/// * Abbreviation ids are resolved against the `BLOCKINFO` abbreviations for
+///   this block id followed by the abbreviations defined inside this block.
+///   Abbreviations defined inside a block do not outlive it.
+#[derive(Debug)]
+pub struct BlockIter<'reader, 'input> {
+    /// Block ID
+    pub id: u64,
+    reader: &'reader mut BitStreamReader<'input>,
+    context: BlockContext,
+    /// Nesting depth of this block, used to detect unfinished child blocks
+    depth: usize,
+    abbrevs: BlockAbbrevs,
+    /// End offset in bits of the last yielded child block
+    child_end: Option<u64>,
+    record: RecordState,
+    done: bool,
+    /// Bit offset of the last item read, for error reporting
+    item_offset: u64,
+}
+
+/// The header of an item, read by [`BlockIter::read_header`]
+enum ItemHeader {
+    End,
+    Block(BlockContext),
+    /// A record whose state has been set up in [`BlockIter::record`]
+    Record {
+        id: u64,
+        abbrev_id: u64,
+        abbrev: Option<Arc<Abbreviation>>,
+    },
+}
+
+/// The abbreviations in scope in a block: the `BLOCKINFO` abbreviations for
+/// its id, followed by the abbreviations defined inside it
+#[derive(Debug, Clone, Default)]
+pub(crate) struct BlockAbbrevs {
+    /// Number of `BLOCKINFO` abbreviations visible in the block
+    global_count: usize,
+    local: Vec<Arc<Abbreviation>>,
+}
+
+impl BlockAbbrevs {
+    /// The abbreviations in scope when entering the block `block_id`
+    pub(crate) fn new(reader: &BitStreamReader<'_>, block_id: u64) -> Self {
+        Self {
+            global_count: reader.global_abbrevs.get(block_id).map_or(0, Vec::len),
+            local: Vec::new(),
+        }
+    }
+
+    /// Look up an abbreviation by its id in the block `block_id`
+    fn get(
+        &self,
+        global_abbrevs: &BlockTable<Vec<Arc<Abbreviation>>>,
+        block_id: u64,
+        abbrev_id: u64,
+    ) -> Option<Arc<Abbreviation>> {
+        let index = usize::try_from(abbrev_id.checked_sub(4)?).ok()?;
+        if index < self.global_count {
+            global_abbrevs.get(block_id)?.get(index).cloned()
+        } else {
+            self.local.get(index - self.global_count).cloned()
+        }
+    }
+}
+
+/// The header of an item, read by [`BitStreamReader::read_item_header`]
+pub(crate) enum RawItemHeader {
+    EndBlock,
+    /// The header of a nested block, up to its length
+    EnterBlock {
+        id: u64,
+        abbrev_width: usize,
+        length: usize,
+    },
+    /// An abbreviation definition, added to the abbreviations in scope
+    DefineAbbrev(AbbrevInfo),
+    UnabbreviatedRecord {
+        id: u64,
+        num_ops: usize,
+    },
+    /// An abbreviated record, before its code
+    AbbreviatedRecord(AbbrevInfo),
+}
+
+/// Information about a block read from its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockContext {
+    /// Block ID
+    pub id: u64,
+    /// Width in bits of the abbreviation ids in the block
+    pub abbrev_width: usize,
+    /// Declared length of the block body in 32-bit words
+    pub length: usize,
+    /// Bit offset of the block body, right after the header, relative to the
+    /// start of the reader's buffer
+    pub offset: u64,
+    /// Bit offset of the block header, at its abbreviation id. Equal to
+    /// `offset` for blocks without a header
+    pub header_offset: u64,
+}
+
+impl BlockContext {
+    /// Bits spanned by the block, from its header to its end
+    pub fn bit_range(&self) -> Range<u64> {
+        self.header_offset..self.end_offset()
+    }
+
+    /// Bit offset right after the end of the block
+    pub fn end_offset(&self) -> u64 {
+        (self.length as u64)
+            .checked_mul(32)
+            .and_then(|length| self.offset.checked_add(length))
+            .unwrap_or(u64::MAX)
+    }
+}
+
+/// An item read from a block
+#[derive(Debug)]
+pub enum BlockItem<'reader, 'input> {
+    /// Nested block
+    Block(BlockIter<'reader, 'input>),
+    /// Data record
+    Record(RecordIter<'reader, 'input>),
+}
+
+impl<'reader, 'input> BlockIter<'reader, 'input> {
+    fn new(
+        reader: &'reader mut BitStreamReader<'input>,
+        context: BlockContext,
+        depth: usize,
+    ) -> Self {
+        let abbrevs = BlockAbbrevs::new(reader, context.id);
+        Self {
+            id: context.id,
+            reader,
+            context,
+            depth,
+            abbrevs,
+            child_end: None,
+            record: RecordState::Done,
+            done: false,
+            item_offset: context.offset,
+        }
+    }
+
+    /// Width in bits of the abbreviation ids in this block
+    pub fn abbrev_width(&self) -> usize {
+        self.context.abbrev_width
+    }
+
+    /// Information read from the block header
+    ///
+    /// For the top level block and blocks read with
+    /// [`BitStreamReader::read_block`], there is no header and the block
+    /// spans the rest of the stream.
+    pub fn context(&self) -> BlockContext {
+        self.context
+    }
+
+    /// Bit offset of the last item read, relative to the start of the
+    /// reader's buffer
+    pub fn item_offset(&self) -> u64 {
+        self.item_offset
+    }
+
+    /// Name given to a block by the `BLOCKINFO` read so far
+    pub fn block_name(&self, block_id: u64) -> Option<&str> {
+        self.reader
+            .block_info
+            .get(&block_id)
+            .map(|info| info.name.as_str())
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Name given to a record by the `BLOCKINFO` read so far
+    pub fn record_name(&self, block_id: u64, record_id: u64) -> Option<&str> {
+        self.reader
+            .block_info
+            .get(&block_id)?
+            .record_names
+            .get(&record_id)
+            .map(String::as_str)
+    }
+
+    /// Read the next item, or `None` once the end of the block is reached
+    ///
+    /// Errors are wrapped in an [`ErrorContext`] locating them.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<BlockItem<'_, 'input>>, Error> {
+        match self.next_header()? {
+            Some(header) => Ok(Some(self.item(header))),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the header of the next item, or `None` once the end of the
+    /// block is reached
+    fn next_header(&mut self) -> Result<Option<ItemHeader>, Error> {
+        if self.done {
+            return Ok(None);
+        }
+        match self.read_header() {
+            Ok(ItemHeader::End) => {
+                self.done = true;
+                Ok(None)
+            }
+            Ok(header) => Ok(Some(header)),
+            Err(err) => Err(self.error_context(err, None)),
+        }
+    }
+
+    /// The item whose header has just been read
+    fn item(&mut self, header: ItemHeader) -> BlockItem<'_, 'input> {
+        match header {
+            ItemHeader::End => unreachable!(),
+            ItemHeader::Block(context) => {
+                self.child_end = Some(context.end_offset());
+                self.reader.depth = self.depth + 1;
+                self.reader.path.truncate(self.depth);
+                self.reader.path.push(context.id);
+                BlockItem::Block(BlockIter::new(self.reader, context, self.depth + 1))
+            }
+            ItemHeader::Record {
+                id,
+                abbrev_id,
+                abbrev,
+            } => BlockItem::Record(RecordIter {
+                id,
+                abbrev_id,
+                abbrev,
+                start: self.item_offset,
+                cursor: &mut self.reader.cursor,
+                budget: &mut self.reader.budget,
+                state: &mut self.record,
+            }),
+        }
+    }
+
+    /// Pass the abbreviation definitions read so far to `visitor`
+    fn report_abbrevs<V: TryBitStreamVisitor<'input>>(
+        &mut self,
+        visitor: &mut V,
+    ) -> Result<(), V::Error> {
+        if let Some(defined) = &mut self.reader.defined_abbrevs {
+            for defined in defined.drain(..) {
+                visitor.did_define_abbrev(
+                    defined.block_id,
+                    &defined.abbrev,
+                    defined.in_block_info,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Skip the rest of the previous item and read the header of the next one
+    fn read_header(&mut self) -> Result<ItemHeader, Error> {
+        self.finish_pending()?;
+        loop {
+            self.item_offset = self.reader.cursor.offset();
+            if self.reader.cursor.is_at_end() {
+                if self.id != BitStreamReader::TOP_LEVEL_BLOCK_ID {
+                    return Err(Error::MissingEndBlock(self.id));
+                }
+                return Ok(ItemHeader::End);
+            }
+            let header = self.reader.read_item_header(
+                self.id,
+                self.context.abbrev_width,
+                &mut self.abbrevs,
+            )?;
+            match header {
+                RawItemHeader::EndBlock => {
+                    self.reader.depth = self.depth.saturating_sub(1);
+                    return Ok(ItemHeader::End);
+                }
+                RawItemHeader::EnterBlock {
+                    id: 0,
+                    abbrev_width,
+                    ..
+                } => {
+                    self.reader.read_block_info_block(abbrev_width)?;
+                }
+                RawItemHeader::EnterBlock {
+                    id,
+                    abbrev_width,
+                    length,
+                } => {
+                    self.reader.budget.check_depth(self.depth + 1)?;
+                    return Ok(ItemHeader::Block(BlockContext {
+                        id,
+                        abbrev_width,
+                        length,
+                        offset: self.reader.cursor.offset(),
+                        header_offset: self.item_offset,
+                    }));
+                }
+                RawItemHeader::DefineAbbrev(abbrev) => {
+                    if let Some(defined) = &mut self.reader.defined_abbrevs {
+                        defined.push(DefinedAbbrev {
+                            block_id: self.id,
+                            abbrev,
+                            in_block_info: false,
+                        });
                     }
-                    EnterSubBlock => {
-                        let block_id = self.cursor.read_vbr(8)?;
-                        let new_abbrev_width = self.cursor.read_vbr(4)? as usize;
-                        self.cursor.advance(32)?;
-                        let block_length = self.cursor.read(32)? as usize * 4;
-                        match block_id {
-                            0 => self.read_block_info_block(new_abbrev_width)?,
-                            _ => {
-                                if !visitor.should_enter_block(block_id) {
-                                    self.cursor.skip_bytes(block_length)?;
-                                    break;
-                                }
-                                self.read_block(block_id, new_abbrev_width, visitor)?;
+                }
+                RawItemHeader::UnabbreviatedRecord { id, num_ops } => {
+                    self.record = RecordState::Unabbreviated { remaining: num_ops };
+                    return Ok(ItemHeader::Record {
+                        id,
+                        abbrev_id: BuiltinAbbreviationId::UnabbreviatedRecord as u64,
+                        abbrev: None,
+                    });
+                }
+                RawItemHeader::AbbreviatedRecord(AbbrevInfo {
+                    id: abbrev_id,
+                    abbrev,
+                }) => {
+                    let id = match abbrev.operands.first() {
+                        Some(op) => read_scalar_operand(&mut self.reader.cursor, op)?,
+                        None => return Err(Error::InvalidAbbrev),
+                    };
+                    self.record = RecordState::Abbreviated {
+                        abbrev: abbrev.clone(),
+                        next: 1,
+                    };
+                    return Ok(ItemHeader::Record {
+                        id,
+                        abbrev_id,
+                        abbrev: Some(abbrev),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Wrap an error in an [`ErrorContext`] locating the last item read
+    fn error_context(&self, error: Error, record: Option<(u64, u64)>) -> Error {
+        if let Error::Context(_) = error {
+            return error;
+        }
+        let depth = self.depth.min(self.reader.path.len());
+        let mut block_path = self.reader.path[..depth].to_vec();
+        if block_path.is_empty() && self.id != BitStreamReader::TOP_LEVEL_BLOCK_ID {
+            block_path.push(self.id);
+        }
+        Error::Context(Box::new(ErrorContext {
+            error,
+            offset: self.item_offset,
+            block_path,
+            record,
+        }))
+    }
+
+    /// Skip whatever is left of the previously yielded item
+    fn finish_pending(&mut self) -> Result<(), Error> {
+        if let Some(end) = self.child_end.take() {
+            if self.reader.depth > self.depth {
+                self.reader.cursor.seek(end)?;
+                self.reader.depth = self.depth;
+            }
+        }
+        RecordIter {
+            id: 0,
+            abbrev_id: 0,
+            abbrev: None,
+            start: 0,
+            cursor: &mut self.reader.cursor,
+            budget: &mut self.reader.budget,
+            state: &mut self.record,
+        }
+        .skip_remaining()
+    }
+
+    /// Read the rest of this block with a visitor, see
+    /// [`BitStreamReader::read_block_recovering`]
+    fn accept_recovering<V: TryBitStreamVisitor<'input>>(
+        &mut self,
+        visitor: &mut V,
+        diagnostics: &mut Vec<Error>,
+    ) -> Result<(), V::Error> {
+        let id = self.id;
+        loop {
+            let header = self.next_header();
+            self.report_abbrevs(visitor)?;
+            let header = match header {
+                Ok(Some(header)) => header,
+                Ok(None) => return Ok(()),
+                Err(err) => {
+                    diagnostics.push(err);
+                    self.skip_to_end();
+                    return Ok(());
+                }
+            };
+            match self.item(header) {
+                BlockItem::Block(mut block) => {
+                    if visitor.should_enter_block_with_context(&block.context)? {
+                        block.accept_recovering(visitor, diagnostics)?;
+                        visitor.did_exit_block()?;
+                    }
+                }
+                BlockItem::Record(record) => {
+                    let location = Some((record.id, record.abbrev_id));
+                    if visitor.should_visit_record(id, record.id, record.abbrev_id)? {
+                        match record.into_record() {
+                            Ok(record) => visitor.visit(record)?,
+                            Err(err) => {
+                                diagnostics.push(self.error_context(err, location));
+                                self.skip_to_end();
+                                return Ok(());
                             }
                         }
                     }
-                    DefineAbbreviation => {
-                        let num_ops = self.cursor.read_vbr(5)? as usize;
-                        let abbrev = self.read_abbrev(num_ops)?;
-                        let abbrev_info =
-                            self.global_abbrevs.entry(id).or_insert_with(|| Vec::new());
-                        abbrev_info.push(abbrev);
+                }
+            }
+        }
+    }
+
+    /// Give up on the rest of this block, moving to its declared end
+    ///
+    /// The top level block and blocks whose end lies past the end of the
+    /// input are skipped to the end of the input.
+    fn skip_to_end(&mut self) {
+        let end = self.context.end_offset();
+        let cursor = &mut self.reader.cursor;
+        if self.id == BitStreamReader::TOP_LEVEL_BLOCK_ID || cursor.seek(end).is_err() {
+            let len = cursor.bit_len();
+            cursor.seek(len).ok();
+        }
+        self.record = RecordState::Done;
+        self.child_end = None;
+        self.reader.depth = self.depth.saturating_sub(1);
+        self.done = true;
+    }
+
+    /// Read the rest of this block with a visitor
+    ///
+    /// Records are decoded into `scratch` and passed by reference if given.
+    fn accept<V: TryBitStreamVisitor<'input>>(
+        &mut self,
+        visitor: &mut V,
+        mut scratch: Option<&mut Record<'input>>,
+    ) -> Result<(), V::Error> {
+        let id = self.id;
+        loop {
+            let header = self.next_header();
+            self.report_abbrevs(visitor)?;
+            let header = match header? {
+                Some(header) => header,
+                None => return Ok(()),
+            };
+            match self.item(header) {
+                BlockItem::Block(mut block) => {
+                    if visitor.should_enter_block_with_context(&block.context)? {
+                        block.accept(visitor, scratch.as_deref_mut())?;
+                        visitor.did_exit_block()?;
                     }
-                    UnabbreviatedRecord => {
-                        let code = self.cursor.read_vbr(6)?;
-                        let num_ops = self.cursor.read_vbr(6)? as usize;
-                        let mut operands = Vec::with_capacity(num_ops);
-                        for _ in 0..num_ops {
-                            operands.push(self.cursor.read_vbr(6)?);
+                }
+                BlockItem::Record(record) => {
+                    let location = Some((record.id, record.abbrev_id));
+                    if visitor.should_visit_record(id, record.id, record.abbrev_id)? {
+                        match scratch.as_deref_mut() {
+                            Some(scratch) => {
+                                if let Err(err) = record.read_into(scratch) {
+                                    return Err(self.error_context(err, location).into());
+                                }
+                                visitor.visit_ref(scratch)?;
+                            }
+                            None => match record.into_record() {
+                                Ok(record) => visitor.visit(record)?,
+                                Err(err) => return Err(self.error_context(err, location).into()),
+                            },
                         }
-                        visitor.visit(Record {
-                            id: code,
-                            fields: operands,
-                            payload: None,
-                        });
                     }
-                },
-                Err(_) => {
-                    if let Some(abbrev_info) = self.global_abbrevs.get(&id).cloned() {
-                        let abbrev_id = abbrev_id as usize;
-                        if abbrev_id - 4 < abbrev_info.len() {
-                            visitor
-                                .visit(self.read_abbreviated_record(&abbrev_info[abbrev_id - 4])?);
-                            continue;
-                        }
+                }
+            }
+        }
+    }
+
+    /// Read the rest of this block with a visitor receiving records
+    /// undecoded
+    fn accept_lazy<V: LazyVisitor<'input>>(&mut self, visitor: &mut V) -> Result<(), V::Error> {
+        let id = self.id;
+        while let Some(item) = self.next()? {
+            match item {
+                BlockItem::Block(mut block) => {
+                    if visitor.should_enter_block_with_context(&block.context)? {
+                        block.accept_lazy(visitor)?;
+                        visitor.did_exit_block()?;
                     }
-                    return Err(Error::NoSuchAbbrev {
-                        block_id: id,
-                        abbrev_id: abbrev_id as usize,
-                    });
                 }
+                BlockItem::Record(record) => visitor.visit(id, record)?,
             }
         }
-        if id != Self::TOP_LEVEL_BLOCK_ID {
-            return Err(Error::MissingEndBlock(id));
+        Ok(())
+    }
+}
+
+/// Decoding state of the record last yielded by a [`BlockIter`]
+#[derive(Debug)]
+enum RecordState {
+    /// Nothing left to read
+    Done,
+    /// Unabbreviated record with `remaining` vbr6 fields left
+    Unabbreviated { remaining: usize },
+    /// Abbreviated record whose next operand to read is `next`
+    Abbreviated {
+        abbrev: Arc<Abbreviation>,
+        next: usize,
+    },
+}
+
+/// A pull-based reader over the fields of a single record
+///
+/// Fields are the scalar values of the record, see [`Record::fields`].
+/// The trailing array or blob operand of an abbreviated record is read
+/// separately with [`RecordIter::payload`].
+#[derive(Debug)]
+pub struct RecordIter<'reader, 'input> {
+    /// Record code
+    pub id: u64,
+    abbrev_id: u64,
+    abbrev: Option<Arc<Abbreviation>>,
+    /// Bit offset of the abbreviation id of the record
+    start: u64,
+    cursor: &'reader mut Cursor<'input>,
+    budget: &'reader mut Budget,
+    state: &'reader mut RecordState,
+}
+
+impl<'reader, 'input> RecordIter<'reader, 'input> {
+    /// Bit offset of the record, at its abbreviation id, relative to the
+    /// start of the reader's buffer
+    pub fn offset(&self) -> u64 {
+        self.start
+    }
+
+    /// Abbreviation id the record was read with, `3` for unabbreviated records
+    pub fn abbrev_id(&self) -> u64 {
+        self.abbrev_id
+    }
+
+    /// Abbreviation the record was read with, `None` for unabbreviated records
+    pub fn abbrev_info(&self) -> Option<AbbrevInfo> {
+        self.abbrev.as_ref().map(|abbrev| AbbrevInfo {
+            id: self.abbrev_id,
+            abbrev: abbrev.clone(),
+        })
+    }
+
+    /// Read the next field, or `None` once all fields have been read
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<u64>, Error> {
+        match self.state {
+            RecordState::Done => Ok(None),
+            RecordState::Unabbreviated { remaining } => {
+                if *remaining == 0 {
+                    *self.state = RecordState::Done;
+                    return Ok(None);
+                }
+                *remaining -= 1;
+                self.budget.decode(1)?;
+                Ok(Some(self.cursor.read_vbr(6)?))
+            }
+            RecordState::Abbreviated { abbrev, next } => match abbrev.operands.get(*next) {
+                Some(op) if !op.is_payload() => {
+                    *next += 1;
+                    self.budget.decode(1)?;
+                    Ok(Some(read_scalar_operand(self.cursor, op)?))
+                }
+                Some(_) => Ok(None),
+                None => {
+                    *self.state = RecordState::Done;
+                    Ok(None)
+                }
+            },
         }
+    }
+
+    /// Skip the remaining fields and read the payload, if the record has one
+    pub fn payload(&mut self) -> Result<Option<Payload<'input>>, Error> {
+        self.payload_with(Vec::new())
+    }
+
+    /// Read the payload, using `elements` as the buffer of an array payload
+    fn payload_with(&mut self, elements: Vec<u64>) -> Result<Option<Payload<'input>>, Error> {
+        while self.next()?.is_some() {}
+        let payload = match self.state {
+            RecordState::Abbreviated { abbrev, next } => match abbrev.operands.get(*next) {
+                Some(op) => Some(read_payload(self.cursor, op, elements, self.budget)?),
+                None => None,
+            },
+            _ => None,
+        };
+        *self.state = RecordState::Done;
+        Ok(payload)
+    }
+
+    /// Read the remaining fields and the payload into a [`Record`]
+    pub fn into_record(mut self) -> Result<Record<'input>, Error> {
+        let mut fields = match self.state {
+            RecordState::Unabbreviated { remaining } => {
+                Vec::with_capacity(capacity(*remaining, self.cursor, 6))
+            }
+            _ => Vec::new(),
+        };
+        while let Some(field) = self.next()? {
+            fields.push(field);
+        }
+        let payload = self.payload()?;
+        Ok(Record {
+            id: self.id,
+            fields,
+            payload,
+            abbrev: self.abbrev_info(),
+            bit_range: Some(self.start..self.cursor.offset()),
+        })
+    }
+
+    /// Read the remaining fields and the payload into an existing [`Record`],
+    /// reusing its buffers
+    pub fn read_into(mut self, record: &mut Record<'input>) -> Result<(), Error> {
+        record.fields.clear();
+        while let Some(field) = self.next()? {
+            record.fields.push(field);
+        }
+        let spare = match record.payload.take() {
+            Some(Payload::Array(mut elements)) => {
+                elements.clear();
+                elements
+            }
+            _ => Vec::new(),
+        };
+        record.payload = self.payload_with(spare)?;
+        record.id = self.id;
+        record.abbrev = self.abbrev_info();
+        record.bit_range = Some(self.start..self.cursor.offset());
+        Ok(())
+    }
+
+    /// Read the next field, failing if all fields have been read
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        self.next()?.ok_or(Error::MissingField(self.id))
+    }
+
+    /// Read the next field as a `u32`
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        let value = self.u64()?;
+        u32::try_from(value).map_err(|_| Error::ValueOutOfRange(value))
+    }
+
+    /// Read the next field as a `u8`
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        let value = self.u64()?;
+        u8::try_from(value).map_err(|_| Error::ValueOutOfRange(value))
+    }
+
+    /// Read the next field as a `bool`, which must be `0` or `1`
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match self.u64()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(Error::ValueOutOfRange(value)),
+        }
+    }
+
+    /// Read the next field as a signed VBR value, whose sign is stored in
+    /// the lowest bit
+    pub fn i64(&mut self) -> Result<i64, Error> {
+        let value = self.u64()?;
+        let magnitude = (value >> 1) as i64;
+        if value & 1 == 0 {
+            Ok(magnitude)
+        } else if value == 1 {
+            // `-0` is used to encode `i64::MIN`
+            Ok(i64::MIN)
+        } else {
+            Ok(-magnitude)
+        }
+    }
+
+    /// Read the next field, where `0` means "none"
+    pub fn nzu64(&mut self) -> Result<Option<NonZeroU64>, Error> {
+        Ok(NonZeroU64::new(self.u64()?))
+    }
+
+    /// Read the next +1 encoded field, where `0` means "none" and any other
+    /// value is one more than the actual value
+    pub fn nullable_u64(&mut self) -> Result<Option<u64>, Error> {
+        Ok(self.u64()?.checked_sub(1))
+    }
+
+    /// Read the next +1 encoded field as a `u32`, see [`RecordIter::nullable_u64`]
+    pub fn nullable_u32(&mut self) -> Result<Option<u32>, Error> {
+        match self.nullable_u64()? {
+            Some(value) => u32::try_from(value)
+                .map(Some)
+                .map_err(|_| Error::ValueOutOfRange(value)),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the next two fields as an offset and a size
+    pub fn range(&mut self) -> Result<Range<usize>, Error> {
+        let start = self.u64()?;
+        let size = self.u64()?;
+        let end = start
+            .checked_add(size)
+            .ok_or(Error::ValueOutOfRange(size))?;
+        let start = usize::try_from(start).map_err(|_| Error::ValueOutOfRange(start))?;
+        let end = usize::try_from(end).map_err(|_| Error::ValueOutOfRange(end))?;
+        Ok(start..end)
+    }
+
+    /// Read the remaining fields followed by the elements of the payload
+    pub fn array(&mut self) -> Result<Vec<u64>, Error> {
+        let mut elements = Vec::new();
+        while let Some(field) = self.next()? {
+            elements.push(field);
+        }
+        match self.payload()? {
+            Some(Payload::Array(array)) => elements.extend(array),
+            Some(Payload::Char6String(s)) => elements.extend(s.bytes().map(u64::from)),
+            Some(Payload::Blob(blob)) => elements.extend(blob.iter().copied().map(u64::from)),
+            None => {}
+        }
+        Ok(elements)
+    }
+
+    /// Read the remaining fields and the payload as a UTF-8 string, one
+    /// character per element
+    pub fn string(&mut self) -> Result<String, Error> {
+        let bytes = self
+            .array()?
+            .into_iter()
+            .map(|x| u8::try_from(x).map_err(|_| Error::InvalidString(self.id)))
+            .collect::<Result<Vec<u8>, Error>>()?;
+        String::from_utf8(bytes).map_err(|_| Error::InvalidString(self.id))
+    }
+
+    /// Skip the remaining fields and read the blob payload, failing if the
+    /// record has none
+    pub fn blob(&mut self) -> Result<Cow<'input, [u8]>, Error> {
+        match self.payload()? {
+            Some(Payload::Blob(blob)) => Ok(blob),
+            _ => Err(Error::MissingField(self.id)),
+        }
+    }
+
+    /// Skip the remaining fields and the payload
+    ///
+    /// Fixed-width and char6 array elements are skipped without being read,
+    /// only VBR elements are walked one by one.
+    fn skip_remaining(&mut self) -> Result<(), Error> {
+        while self.next()?.is_some() {}
+        if let RecordState::Abbreviated { abbrev, next } = self.state {
+            if let Some(op) = abbrev.operands.get(*next) {
+                skip_payload(self.cursor, op)?;
+            }
+        }
+        *self.state = RecordState::Done;
         Ok(())
     }
 }
+
+/// A table keyed by block id
+///
+/// Block ids are small in practice, so they index a vector directly. Larger
+/// ids fall back to a map.
+#[derive(Debug, Clone)]
+pub(crate) struct BlockTable<T> {
+    dense: Vec<Option<T>>,
+    spill: HashMap<u64, T>,
+}
+
+impl<T> BlockTable<T> {
+    /// Number of block ids stored in `dense`
+    const DENSE_LEN: u64 = 64;
+
+    pub(crate) fn get(&self, id: u64) -> Option<&T> {
+        if id < Self::DENSE_LEN {
+            self.dense.get(id as usize)?.as_ref()
+        } else {
+            self.spill.get(&id)
+        }
+    }
+}
+
+impl<T: Default> BlockTable<T> {
+    pub(crate) fn get_or_default(&mut self, id: u64) -> &mut T {
+        if id < Self::DENSE_LEN {
+            let index = id as usize;
+            if self.dense.len() <= index {
+                self.dense.resize_with(index + 1, || None);
+            }
+            self.dense[index].get_or_insert_with(T::default)
+        } else {
+            self.spill.entry(id).or_default()
+        }
+    }
+}
+
+impl<T> Default for BlockTable<T> {
+    fn default() -> Self {
+        Self {
+            dense: Vec::new(),
+            spill: HashMap::new(),
+        }
+    }
+}
+
+/// Read the abbreviation id width of a block from its header
+pub(crate) fn read_abbrev_width(cursor: &mut Cursor<'_>) -> Result<usize, Error> {
+    let width = cursor.read_vbr(4)?;
+    if width > MAX_CHUNK_WIDTH as u64 {
+        return Err(Error::AbbrevWidthTooLarge(width as usize));
+    }
+    Ok(width as usize)
+}
+
+/// Read the width of a fixed or VBR abbreviation operand, which must be at
+/// most 32 bits
+fn read_operand_width(cursor: &mut Cursor<'_>) -> Result<u8, Error> {
+    let width = cursor.read_vbr(5)?;
+    if width > MAX_CHUNK_WIDTH as u64 {
+        return Err(Error::InvalidAbbrev);
+    }
+    Ok(width as u8)
+}
+
+/// Read a non-payload abbreviation operand
+fn read_scalar_operand(cursor: &mut Cursor<'_>, operand: &Operand) -> Result<u64, Error> {
+    match operand {
+        Operand::Char6 => bits::char6_decode(cursor.read(6)?)
+            .map(u64::from)
+            .ok_or(Error::InvalidAbbrev),
+        Operand::Literal(value) => Ok(*value),
+        Operand::Fixed(width) => Ok(cursor.read(*width as usize)?),
+        Operand::Vbr(width) => Ok(cursor.read_vbr(*width as usize)?),
+        Operand::Array(_) | Operand::Blob => Err(Error::InvalidAbbrev),
+    }
+}
+
+/// Read the length of an array payload
+///
+/// Elements may take no bits at all, so like LLVM, lengths larger than the
+/// number of bits left are rejected rather than trusted.
+fn read_array_length(cursor: &mut Cursor<'_>) -> Result<usize, Error> {
+    let length = read_count(cursor, 6)?;
+    if length as u64 > cursor.remaining() {
+        return Err(bits::Error::BufferOverflow.into());
+    }
+    Ok(length)
+}
+
+/// Read a VBR-encoded number of items
+///
+/// Counts that don't fit in `usize` can't be backed by the buffer, so they
+/// are reported as overflowing it.
+pub(crate) fn read_count(cursor: &mut Cursor<'_>, width: usize) -> Result<usize, Error> {
+    let count = cursor.read_vbr(width)?;
+    usize::try_from(count).map_err(|_| bits::Error::BufferOverflow.into())
+}
+
+/// Capacity to reserve for `count` items taking at least `bits` bits each
+///
+/// Bounded by what is left in the buffer, so that a corrupted count can't
+/// cause a huge allocation.
+pub(crate) fn capacity(count: usize, cursor: &Cursor<'_>, bits: u64) -> usize {
+    usize::try_from(cursor.remaining() / bits).map_or(count, |max| count.min(max))
+}
+
+/// Read an array or blob abbreviation operand
+///
+/// Array elements are read into `elements`, which must be empty.
+fn read_payload<'input>(
+    cursor: &mut Cursor<'input>,
+    operand: &Operand,
+    mut elements: Vec<u64>,
+    budget: &mut Budget,
+) -> Result<Payload<'input>, Error> {
+    match operand {
+        Operand::Array(element) => {
+            let length = read_array_length(cursor)?;
+            budget.check_payload(length)?;
+            budget.decode(length as u64)?;
+            elements.reserve(length);
+            for _ in 0..length {
+                elements.push(read_scalar_operand(cursor, element)?);
+            }
+            if matches!(**element, Operand::Char6) {
+                let s: String = elements
+                    .into_iter()
+                    .map(|x| std::char::from_u32(x as u32).unwrap())
+                    .collect();
+                Ok(Payload::Char6String(s))
+            } else {
+                Ok(Payload::Array(elements))
+            }
+        }
+        Operand::Blob => {
+            let length = read_count(cursor, 6)?;
+            budget.check_payload(length)?;
+            let data = cursor.read_aligned_slice(length)?;
+            cursor.advance(32)?;
+            Ok(Payload::Blob(Cow::Borrowed(data)))
+        }
+        _ => Err(Error::InvalidAbbrev),
+    }
+}
+
+/// Skip an array or blob abbreviation operand
+fn skip_payload(cursor: &mut Cursor<'_>, operand: &Operand) -> Result<(), Error> {
+    match operand {
+        Operand::Array(element) => {
+            let length = read_array_length(cursor)?;
+            // Only VBR elements have to be read to find where they end
+            let width = match **element {
+                Operand::Fixed(width) => u64::from(width),
+                Operand::Char6 => 6,
+                Operand::Literal(_) => 0,
+                Operand::Vbr(width) => {
+                    for _ in 0..length {
+                        cursor.read_vbr(width as usize)?;
+                    }
+                    return Ok(());
+                }
+                Operand::Array(_) | Operand::Blob => return Err(Error::InvalidAbbrev),
+            };
+            let bits = (length as u64)
+                .checked_mul(width)
+                .ok_or(bits::Error::BufferOverflow)?;
+            Ok(cursor.skip_bits(bits)?)
+        }
+        Operand::Blob => {
+            let length = read_count(cursor, 6)?;
+            cursor.skip_aligned_bytes(length)?;
+            cursor.advance(32)?;
+            Ok(())
+        }
+        _ => Err(Error::InvalidAbbrev),
+    }
+}
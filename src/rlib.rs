@@ -0,0 +1,156 @@
+//! Bitcode of Rust libraries, for link time optimization
+//!
+//! rustc embeds the bitcode of each codegen unit of an rlib in the `.llvmbc`
+//! section of its object file. Older versions stored it next to the object
+//! instead, in a member holding deflate compressed bitcode behind a
+//! `RUST_OBJECT` header.
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::{error, fmt};
+
+use crate::archive::{self, Archive};
+use crate::bitcode::Signature;
+use crate::embedded::{self, SectionKind};
+use crate::inflate;
+use crate::read;
+use crate::Bitcode;
+
+/// Magic string of legacy compressed bytecode members
+pub const RUST_OBJECT_MAGIC: &[u8] = b"RUST_OBJECT";
+
+/// Upper bound on the compression ratio of deflate
+const MAX_DEFLATE_RATIO: usize = 1032;
+
+/// Errors extracting bitcode from an rlib
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The rlib is not a valid archive
+    Archive(archive::Error),
+    /// The member with this name is an invalid object file
+    InvalidObject(String),
+    /// The member with this name holds corrupt or unsupported compressed
+    /// bytecode
+    InvalidBytecode(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Archive(err) => err.fmt(f),
+            Error::InvalidObject(name) => write!(f, "invalid object file `{}`", name),
+            Error::InvalidBytecode(name) => write!(f, "invalid compressed bytecode `{}`", name),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Archive(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<archive::Error> for Error {
+    fn from(err: archive::Error) -> Self {
+        Error::Archive(err)
+    }
+}
+
+/// Bitcode of a codegen unit of an rlib
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RlibBitcode<'a> {
+    /// Name of the archive member the bitcode was found in
+    pub member: &'a str,
+    /// Bitcode, borrowed from the rlib unless it was compressed
+    pub data: Cow<'a, [u8]>,
+}
+
+impl RlibBitcode<'_> {
+    /// Parse the bitcode
+    pub fn bitcode(&self) -> Result<Bitcode<'_>, read::Error> {
+        Bitcode::new(&self.data)
+    }
+}
+
+/// Extract the bitcode of all the codegen units of an rlib
+///
+/// Members holding plain bitcode are returned as is. Members that are not
+/// object files, such as the crate metadata of some targets, are skipped.
+pub fn rlib_bitcode(data: &[u8]) -> Result<Vec<RlibBitcode<'_>>, Error> {
+    let mut bitcode = Vec::new();
+    for member in Archive::parse(data)?.members() {
+        let member = member?;
+        let contents = match member.data {
+            Some(contents) => contents,
+            None => continue,
+        };
+        if contents.starts_with(RUST_OBJECT_MAGIC) {
+            let decoded = decode_rust_object(contents)
+                .ok_or_else(|| Error::InvalidBytecode(member.name.to_string()))?;
+            bitcode.push(RlibBitcode {
+                member: member.name,
+                data: Cow::Owned(decoded),
+            });
+            continue;
+        }
+        if member.is_bitcode() {
+            bitcode.push(RlibBitcode {
+                member: member.name,
+                data: Cow::Borrowed(contents),
+            });
+            continue;
+        }
+        let sections = match embedded::sections(contents) {
+            Ok(sections) => sections,
+            Err(embedded::Error::UnknownFormat) => continue,
+            Err(embedded::Error::InvalidObject) => {
+                return Err(Error::InvalidObject(member.name.to_string()))
+            }
+        };
+        bitcode.extend(
+            sections
+                .into_iter()
+                .filter(|section| section.kind == SectionKind::Bitcode && section.is_bitcode())
+                .map(|section| RlibBitcode {
+                    member: member.name,
+                    data: Cow::Borrowed(section.data),
+                }),
+        );
+    }
+    Ok(bitcode)
+}
+
+/// Decompress the bitcode of a legacy `RUST_OBJECT` member
+///
+/// Versions 1 and 2 of the format are supported, version 2 adds the name of
+/// the codegen unit to the header. Returns `None` if the header is invalid
+/// or the decompressed data is not bitcode.
+pub fn decode_rust_object(data: &[u8]) -> Option<Vec<u8>> {
+    let u32_at = |offset: usize| -> Option<usize> {
+        let bytes = data.get(offset..offset.checked_add(4)?)?;
+        usize::try_from(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).ok()
+    };
+    if !data.starts_with(RUST_OBJECT_MAGIC) {
+        return None;
+    }
+    let mut offset = RUST_OBJECT_MAGIC.len();
+    match u32_at(offset)? {
+        1 => offset += 4,
+        // Skip the codegen unit name
+        2 => offset = (offset + 8).checked_add(u32_at(offset + 4)?)?,
+        _ => return None,
+    }
+    let mut size = [0; 8];
+    size.copy_from_slice(data.get(offset..offset.checked_add(8)?)?);
+    let size = usize::try_from(u64::from_le_bytes(size)).ok()?;
+    let start = offset + 8;
+    let deflated = data.get(start..start.checked_add(size)?)?;
+    let decoded =
+        inflate::deflate_decompress(deflated, deflated.len().saturating_mul(MAX_DEFLATE_RATIO))?;
+    match Signature::parse(&decoded) {
+        Ok((signature, _)) if signature.is_llvm_ir() => Some(decoded),
+        _ => None,
+    }
+}
@@ -0,0 +1,53 @@
+use num_enum::{FromPrimitive, IntoPrimitive};
+
+/// Block ids used by LLVM IR bitcode
+///
+/// Block IDs 0-7 are reserved for standard blocks, only `BLOCKINFO` is
+/// currently defined. Ids not known to this crate are kept as `Unknown`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromPrimitive, IntoPrimitive)]
+#[repr(u64)]
+pub enum BlockId {
+    /// `BLOCKINFO` block, holding abbreviations and names for other blocks
+    BlockInfo = 0,
+    /// Top level block of a module
+    Module = 8,
+    /// Parameter attribute lists
+    ParamAttr = 9,
+    /// Parameter attribute groups
+    ParamAttrGroup = 10,
+    /// Constants, either module level or local to a function
+    Constants = 11,
+    /// Function body
+    Function = 12,
+    /// Producer and epoch of the bitcode
+    Identification = 13,
+    /// Value symbol table
+    ValueSymtab = 14,
+    /// Metadata nodes
+    Metadata = 15,
+    /// Metadata attachments of a function
+    MetadataAttachment = 16,
+    /// Type table
+    TypeNew = 17,
+    /// Use-list orders
+    UseList = 18,
+    /// Module paths of a combined summary
+    ModuleStrtab = 19,
+    /// Per-module or combined global value summary
+    GlobalValSummary = 20,
+    /// Operand bundle tags
+    OperandBundleTags = 21,
+    /// Metadata kind names
+    MetadataKind = 22,
+    /// String table
+    Strtab = 23,
+    /// Global value summary of a regular LTO module
+    FullLtoGlobalValSummary = 24,
+    /// Symbol table
+    Symtab = 25,
+    /// Synchronization scope names
+    SyncScopeNames = 26,
+    /// A block id unknown to this crate
+    #[num_enum(catch_all)]
+    Unknown(u64),
+}
@@ -0,0 +1,37 @@
+// `IDENTIFICATION_BLOCK` records
+pub const IDENTIFICATION_CODE_STRING: u64 = 1;
+
+// `MODULE_BLOCK` records
+pub const MODULE_CODE_VERSION: u64 = 1;
+pub const MODULE_CODE_TRIPLE: u64 = 2;
+pub const MODULE_CODE_DATALAYOUT: u64 = 3;
+pub const MODULE_CODE_GLOBALVAR: u64 = 7;
+pub const MODULE_CODE_FUNCTION: u64 = 8;
+pub const MODULE_CODE_ALIAS_OLD: u64 = 9;
+pub const MODULE_CODE_ALIAS: u64 = 14;
+pub const MODULE_CODE_IFUNC: u64 = 15;
+pub const MODULE_CODE_SOURCE_FILENAME: u64 = 16;
+
+// `TYPE_BLOCK_ID_NEW` records
+pub const TYPE_CODE_NUMENTRY: u64 = 1;
+pub const TYPE_CODE_FUNCTION_OLD: u64 = 9;
+pub const TYPE_CODE_STRUCT_NAME: u64 = 19;
+pub const TYPE_CODE_FUNCTION: u64 = 21;
+
+// `FUNCTION_BLOCK` records
+pub const FUNC_CODE_DECLAREBLOCKS: u64 = 1;
+pub const FUNC_CODE_DEBUG_LOC_AGAIN: u64 = 33;
+pub const FUNC_CODE_DEBUG_LOC: u64 = 35;
+
+// `VALUE_SYMTAB_BLOCK` records
+pub const VST_CODE_ENTRY: u64 = 1;
+pub const VST_CODE_FNENTRY: u64 = 3;
+
+// `METADATA_KIND_BLOCK` records
+pub const METADATA_KIND: u64 = 6;
+
+// `STRTAB_BLOCK` records
+pub const STRTAB_BLOB: u64 = 1;
+
+// `SYMTAB_BLOCK` records
+pub const SYMTAB_BLOB: u64 = 1;
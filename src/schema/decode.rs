@@ -0,0 +1,342 @@
+//! Declarative decoding of bitstream formats
+//!
+//! Formats built on the bitstream container define their own blocks and
+//! records. [`bitstream_record!`](crate::bitstream_record) and
+//! [`bitstream_block!`](crate::bitstream_block) describe them as structs and
+//! generate the code reading them from a [`BlockIter`]:
+//!
+//! ```
+//! use llvm_bitcode::{bitstream_block, bitstream_record};
+//!
+//! bitstream_record! {
+//!     /// VERSION: [version]
+//!     #[derive(Debug)]
+//!     pub struct Version = 1 {
+//!         pub version: u32,
+//!     }
+//! }
+//!
+//! bitstream_record! {
+//!     /// NAME: [name size, name]
+//!     #[derive(Debug)]
+//!     pub struct Name = 2 {
+//!         pub size: u64,
+//!         pub name: String,
+//!     }
+//! }
+//!
+//! bitstream_block! {
+//!     /// Version and names
+//!     #[derive(Debug)]
+//!     pub struct Meta = 8 {
+//!         pub version: Option<Version>,
+//!         pub names: Vec<Name>,
+//!     }
+//! }
+//! ```
+//!
+//! Record fields are read in order with [`Field`], trailing fields not
+//! described are ignored. Block members are [`Option`]s, keeping the last
+//! matching item, or [`Vec`]s, keeping all of them. Items not matching any
+//! member are skipped.
+use std::ops::Range;
+
+use crate::read::{BlockItem, BlockIter, Error, RecordIter};
+
+/// A value read from the fields of a record
+pub trait Field: Sized {
+    /// Read the value from the next fields of `record`
+    fn read(record: &mut RecordIter<'_, '_>) -> Result<Self, Error>;
+}
+
+impl Field for u64 {
+    fn read(record: &mut RecordIter<'_, '_>) -> Result<Self, Error> {
+        record.u64()
+    }
+}
+
+impl Field for u32 {
+    fn read(record: &mut RecordIter<'_, '_>) -> Result<Self, Error> {
+        record.u32()
+    }
+}
+
+impl Field for u8 {
+    fn read(record: &mut RecordIter<'_, '_>) -> Result<Self, Error> {
+        record.u8()
+    }
+}
+
+impl Field for bool {
+    fn read(record: &mut RecordIter<'_, '_>) -> Result<Self, Error> {
+        record.bool()
+    }
+}
+
+/// A signed VBR value, see [`RecordIter::i64`]
+impl Field for i64 {
+    fn read(record: &mut RecordIter<'_, '_>) -> Result<Self, Error> {
+        record.i64()
+    }
+}
+
+/// A +1 encoded value, see [`RecordIter::nullable_u64`]
+impl Field for Option<u64> {
+    fn read(record: &mut RecordIter<'_, '_>) -> Result<Self, Error> {
+        record.nullable_u64()
+    }
+}
+
+/// A +1 encoded value, see [`RecordIter::nullable_u32`]
+impl Field for Option<u32> {
+    fn read(record: &mut RecordIter<'_, '_>) -> Result<Self, Error> {
+        record.nullable_u32()
+    }
+}
+
+/// An offset and a size, see [`RecordIter::range`]
+impl Field for Range<usize> {
+    fn read(record: &mut RecordIter<'_, '_>) -> Result<Self, Error> {
+        record.range()
+    }
+}
+
+/// The remaining fields and the payload, see [`RecordIter::array`]
+impl Field for Vec<u64> {
+    fn read(record: &mut RecordIter<'_, '_>) -> Result<Self, Error> {
+        record.array()
+    }
+}
+
+/// The remaining fields and the payload as a string, see [`RecordIter::string`]
+impl Field for String {
+    fn read(record: &mut RecordIter<'_, '_>) -> Result<Self, Error> {
+        record.string()
+    }
+}
+
+/// The blob payload, skipping the remaining fields, see [`RecordIter::blob`]
+impl Field for Vec<u8> {
+    fn read(record: &mut RecordIter<'_, '_>) -> Result<Self, Error> {
+        Ok(record.blob()?.into_owned())
+    }
+}
+
+/// A record with a fixed code, implemented by [`bitstream_record!`](crate::bitstream_record)
+pub trait DecodeRecord: Sized {
+    /// Record code
+    const CODE: u64;
+
+    /// Read the record, whose code has already been checked
+    fn decode_record(record: &mut RecordIter<'_, '_>) -> Result<Self, Error>;
+}
+
+/// A block with a fixed id, implemented by [`bitstream_block!`](crate::bitstream_block)
+pub trait DecodeBlock: Sized {
+    /// Block id
+    const ID: u64;
+
+    /// Read the block, whose id has already been checked
+    fn decode_block(block: &mut BlockIter<'_, '_>) -> Result<Self, Error>;
+}
+
+/// A record or block read from an item of a block
+pub trait Decode: Sized {
+    /// Read `item` if it is this record or block, `None` otherwise
+    fn decode(item: &mut BlockItem<'_, '_>) -> Result<Option<Self>, Error>;
+}
+
+/// A boxed record or block, for members of recursive blocks
+impl<T: Decode> Decode for Box<T> {
+    fn decode(item: &mut BlockItem<'_, '_>) -> Result<Option<Self>, Error> {
+        Ok(T::decode(item)?.map(Box::new))
+    }
+}
+
+/// Implement [`Decode`] for a [`DecodeRecord`]
+#[doc(hidden)]
+pub fn decode_record<T: DecodeRecord>(item: &mut BlockItem<'_, '_>) -> Result<Option<T>, Error> {
+    match item {
+        BlockItem::Record(record) if record.id == T::CODE => T::decode_record(record).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// Implement [`Decode`] for a [`DecodeBlock`]
+#[doc(hidden)]
+pub fn decode_block<T: DecodeBlock>(item: &mut BlockItem<'_, '_>) -> Result<Option<T>, Error> {
+    match item {
+        BlockItem::Block(block) if block.id == T::ID => T::decode_block(block).map(Some),
+        _ => Ok(None),
+    }
+}
+
+/// A member of a block declared with [`bitstream_block!`](crate::bitstream_block)
+pub trait Member: Default {
+    /// Read `item` if it belongs to this member, returning whether it did
+    fn offer(&mut self, item: &mut BlockItem<'_, '_>) -> Result<bool, Error>;
+}
+
+/// Keeps the last matching item
+impl<T: Decode> Member for Option<T> {
+    fn offer(&mut self, item: &mut BlockItem<'_, '_>) -> Result<bool, Error> {
+        match T::decode(item)? {
+            Some(value) => {
+                *self = Some(value);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Keeps all matching items, in order
+impl<T: Decode> Member for Vec<T> {
+    fn offer(&mut self, item: &mut BlockItem<'_, '_>) -> Result<bool, Error> {
+        match T::decode(item)? {
+            Some(value) => {
+                self.push(value);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+/// Read all items of `block` matching `T`, skipping the others
+///
+/// With [`BitStreamReader::iter_top_level`](crate::BitStreamReader::iter_top_level),
+/// this reads the top level blocks of a stream.
+pub fn decode_all<T: Decode>(block: &mut BlockIter<'_, '_>) -> Result<Vec<T>, Error> {
+    let mut values = Vec::new();
+    while let Some(mut item) = block.next()? {
+        values.offer(&mut item)?;
+    }
+    Ok(values)
+}
+
+/// Declare a record struct and how to read it
+///
+/// The code is a literal or a parenthesized constant expression. Each field
+/// is read in order with [`Field`].
+///
+/// ```
+/// use llvm_bitcode::bitstream_record;
+///
+/// const FILENAME: u64 = 6;
+///
+/// bitstream_record! {
+///     /// FILENAME: [file id, size, modification time, name size, name]
+///     pub struct Filename = (FILENAME) {
+///         pub id: u64,
+///         pub size: u64,
+///         pub modification_time: u64,
+///         name_size: u64,
+///         pub name: String,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! bitstream_record {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident = $code:tt {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident : $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty,)*
+        }
+
+        impl $crate::schema::decode::DecodeRecord for $name {
+            #[allow(unused_parens)]
+            const CODE: u64 = $code;
+
+            fn decode_record(
+                record: &mut $crate::read::RecordIter<'_, '_>,
+            ) -> ::std::result::Result<Self, $crate::read::Error> {
+                ::std::result::Result::Ok(Self {
+                    $($field: <$ty as $crate::schema::decode::Field>::read(record)?,)*
+                })
+            }
+        }
+
+        impl $crate::schema::decode::Decode for $name {
+            fn decode(
+                item: &mut $crate::read::BlockItem<'_, '_>,
+            ) -> ::std::result::Result<::std::option::Option<Self>, $crate::read::Error> {
+                $crate::schema::decode::decode_record(item)
+            }
+        }
+    };
+}
+
+/// Declare a block struct and how to read it
+///
+/// The id is a literal or a parenthesized constant expression. Members are
+/// [`Option`]s or [`Vec`]s of records and blocks, see [`Member`]. A block
+/// may contain itself, as a [`Vec`] or a boxed [`Option`] member.
+///
+/// ```
+/// use llvm_bitcode::{bitstream_block, bitstream_record};
+///
+/// bitstream_record! {
+///     pub struct Message = 2 {
+///         pub severity: u64,
+///         pub text: String,
+///     }
+/// }
+///
+/// bitstream_block! {
+///     /// A message and the notes attached to it
+///     pub struct Diagnostic = 9 {
+///         pub message: Option<Message>,
+///         pub notes: Vec<Diagnostic>,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! bitstream_block {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident = $id:tt {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident : $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty,)*
+        }
+
+        impl $crate::schema::decode::DecodeBlock for $name {
+            #[allow(unused_parens)]
+            const ID: u64 = $id;
+
+            #[allow(unused_mut, unused_variables)]
+            fn decode_block(
+                block: &mut $crate::read::BlockIter<'_, '_>,
+            ) -> ::std::result::Result<Self, $crate::read::Error> {
+                let mut value = Self {
+                    $($field: ::std::default::Default::default(),)*
+                };
+                while let ::std::option::Option::Some(mut item) = block.next()? {
+                    $(
+                        if $crate::schema::decode::Member::offer(&mut value.$field, &mut item)? {
+                            continue;
+                        }
+                    )*
+                }
+                ::std::result::Result::Ok(value)
+            }
+        }
+
+        impl $crate::schema::decode::Decode for $name {
+            fn decode(
+                item: &mut $crate::read::BlockItem<'_, '_>,
+            ) -> ::std::result::Result<::std::option::Option<Self>, $crate::read::Error> {
+                $crate::schema::decode::decode_block(item)
+            }
+        }
+    };
+}
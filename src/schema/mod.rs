@@ -0,0 +1,6 @@
+/// LLVM IR block ids
+pub mod blocks;
+/// LLVM IR record codes, as named by `LLVMBitCodes.h`
+pub mod codes;
+/// Declarative decoding of user-defined formats
+pub mod decode;
@@ -0,0 +1,368 @@
+//! Random access to blocks of bitcode in a seekable reader
+//!
+//! A [`SeekableReader`] reads single blocks out of a file without loading
+//! or mapping the rest of it: blocks are located with the
+//! [`BlockContext`]s of an earlier pass over the stream, e.g. the
+//! [bit ranges](crate::bitcode::Block::bit_range) of the blocks it read,
+//! and only the bytes of a block are read. Recently read bytes are kept in
+//! a cache bounded in size.
+//!
+//! Offsets are relative to the end of the signature, like the offsets
+//! reported when reading with [`Bitcode::reader`](crate::Bitcode::reader).
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::bitcode::{BitcodeElement, Block, BlockInfo, Signature, LLVM_BITCODE_WRAPPER_MAGIC};
+use crate::bits::{self, Bits, Cursor};
+use crate::bitstream::{Abbreviation, BuiltinAbbreviationId};
+use crate::read::{read_abbrev_width, BitStreamReader, BlockContext, BlockItem, BlockTable, Error};
+use crate::visitor::CollectingVisitor;
+
+/// Default capacity of the cache in bytes
+const DEFAULT_CACHE_CAPACITY: usize = 16 * 1024 * 1024;
+/// Bytes first read when looking for `BLOCKINFO` at the start of a block
+const SCAN_CHUNK_SIZE: usize = 64 * 1024;
+/// Longest top level block header: abbreviation id, block id, abbreviation
+/// width, alignment and length
+const MAX_HEADER_SIZE: usize = 24;
+
+/// Abbreviations and names defined by `BLOCKINFO` blocks
+#[derive(Debug, Clone, Default)]
+struct BlockInfoState {
+    global_abbrevs: BlockTable<Vec<Arc<Abbreviation>>>,
+    block_info: HashMap<u64, BlockInfo>,
+}
+
+/// A top level block and the `BLOCKINFO` state its nested blocks are read
+/// with
+#[derive(Debug)]
+struct TopLevel {
+    context: BlockContext,
+    /// State left by the top level `BLOCKINFO` blocks before this block
+    inherited: BlockInfoState,
+    /// `inherited` extended with the `BLOCKINFO` blocks at the start of this
+    /// block, once they have been looked for
+    state: Option<BlockInfoState>,
+}
+
+/// A bitcode reader over [`Read`] + [`Seek`] input, reading blocks on demand
+///
+/// `BLOCKINFO` blocks are found at the top level, and at the start of each
+/// top level block, before its first nested block, where LLVM writes them.
+#[derive(Debug)]
+pub struct SeekableReader<R> {
+    inner: R,
+    signature: Signature,
+    /// Offset in bytes of the stream after the signature in `inner`
+    base: u64,
+    /// Length in bytes of the stream after the signature
+    len: u64,
+    top_level: Option<Vec<TopLevel>>,
+    /// Recently read ranges of the stream, by offset in bytes, most recently
+    /// used last
+    cache: VecDeque<(u64, Arc<[u8]>)>,
+    cache_size: usize,
+    cache_capacity: usize,
+}
+
+impl<R: Read + Seek> SeekableReader<R> {
+    /// Read the signature of `inner`
+    ///
+    /// Accepts both LLVM bitcode and bitcode wrapper formats
+    pub fn new(mut inner: R) -> io::Result<Self> {
+        let mut header = [0; 4];
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_exact(&mut header)?;
+        let mut signature = u32::from_le_bytes(header);
+        let end = inner.seek(SeekFrom::End(0))?;
+        let (base, len) = if signature == LLVM_BITCODE_WRAPPER_MAGIC {
+            let mut wrapper = [0; 16];
+            inner.seek(SeekFrom::Start(0))?;
+            inner.read_exact(&mut wrapper)?;
+            let word = |index: usize| {
+                u64::from(u32::from_le_bytes([
+                    wrapper[index],
+                    wrapper[index + 1],
+                    wrapper[index + 2],
+                    wrapper[index + 3],
+                ]))
+            };
+            let (offset, size) = (word(8), word(12));
+            if size < 4 || offset + size > end {
+                return Err(invalid_data(Error::InvalidWrapper));
+            }
+            inner.seek(SeekFrom::Start(offset))?;
+            inner.read_exact(&mut header)?;
+            signature = u32::from_le_bytes(header);
+            (offset + 4, size - 4)
+        } else {
+            (4, end.saturating_sub(4))
+        };
+        Ok(Self {
+            inner,
+            signature: Signature::new(signature),
+            base,
+            len,
+            top_level: None,
+            cache: VecDeque::new(),
+            cache_size: 0,
+            cache_capacity: DEFAULT_CACHE_CAPACITY,
+        })
+    }
+
+    /// Signature of the stream
+    pub fn signature(&self) -> Signature {
+        self.signature
+    }
+
+    /// Bound the bytes kept in the cache, 16 MiB by default
+    ///
+    /// The most recently read range is kept even if it is larger.
+    pub fn set_cache_capacity(&mut self, bytes: usize) {
+        self.cache_capacity = bytes;
+        self.evict();
+    }
+
+    /// Give back the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// List the top level blocks, reading only their headers
+    ///
+    /// Top level `BLOCKINFO` blocks are read and not listed.
+    pub fn top_level_blocks(&mut self) -> io::Result<Vec<BlockContext>> {
+        Ok(self
+            .top_level()?
+            .iter()
+            .map(|block| block.context)
+            .collect())
+    }
+
+    /// Read the block of `context`, with its nested blocks
+    ///
+    /// `context` is a top level block or a block nested in one, as found by
+    /// an earlier read of the same stream. Bit ranges of the elements read
+    /// are relative to the end of the signature.
+    pub fn read_block(&mut self, context: &BlockContext) -> io::Result<Block<'static>> {
+        let index = self
+            .top_level()?
+            .iter()
+            .position(|block| block.context.bit_range().contains(&context.offset))
+            .ok_or_else(|| invalid_input("block outside of the top level blocks"))?;
+        let top_level = &self.top_level.as_ref().unwrap()[index];
+        let state = if top_level.context.offset == context.offset {
+            top_level.inherited.clone()
+        } else {
+            self.nested_state(index)?
+        };
+
+        let (start, end) = (context.offset / 8, context.end_offset() / 8);
+        if context.offset & 31 != 0 || end > self.len || start > end {
+            return Err(invalid_input("block context outside of the stream"));
+        }
+        let (buffer, skip) = self.load(start, (end - start) as usize)?;
+        let mut reader = BitStreamReader::new(&buffer[skip..skip + (end - start) as usize]);
+        reader.global_abbrevs = state.global_abbrevs;
+        reader.block_info = state.block_info;
+        let mut visitor = CollectingVisitor::new();
+        reader
+            .read_block(context.id, context.abbrev_width, &mut visitor)
+            .map_err(invalid_data)?;
+        let mut elements: Vec<_> = visitor
+            .finalize_top_level_elements()
+            .into_iter()
+            .map(BitcodeElement::into_owned)
+            .collect();
+        shift(&mut elements, context.offset);
+        Ok(Block {
+            id: context.id,
+            elements,
+            bit_range: Some(context.bit_range()),
+            unparsed: None,
+        })
+    }
+
+    /// Read the headers of the top level blocks, once
+    fn top_level(&mut self) -> io::Result<&[TopLevel]> {
+        if self.top_level.is_none() {
+            let mut blocks = Vec::new();
+            let mut state = BlockInfoState::default();
+            let mut offset = 0;
+            while offset < self.len {
+                let size = MAX_HEADER_SIZE.min((self.len - offset) as usize);
+                let (buffer, skip) = self.load(offset, size)?;
+                let header = read_header(&buffer[skip..skip + size]).map_err(invalid_data)?;
+                let mut context = match header {
+                    Some(context) => context,
+                    // Trailing padding
+                    None => break,
+                };
+                context.header_offset += offset * 8;
+                context.offset += offset * 8;
+                let end = context.end_offset() / 8;
+                if end > self.len {
+                    return Err(invalid_data(Error::ReadBits(bits::Error::BufferOverflow)));
+                }
+                if context.id == 0 {
+                    // BLOCKINFO applies to the rest of the stream
+                    let start = context.offset / 8;
+                    let (buffer, skip) = self.load(start, (end - start) as usize)?;
+                    let mut reader =
+                        BitStreamReader::new(&buffer[skip..skip + (end - start) as usize]);
+                    reader.global_abbrevs = state.global_abbrevs;
+                    reader.block_info = state.block_info;
+                    reader
+                        .read_block_info_block(context.abbrev_width)
+                        .map_err(invalid_data)?;
+                    state = BlockInfoState {
+                        global_abbrevs: reader.global_abbrevs,
+                        block_info: reader.block_info,
+                    };
+                } else {
+                    blocks.push(TopLevel {
+                        context,
+                        inherited: state.clone(),
+                        state: None,
+                    });
+                }
+                offset = end;
+            }
+            self.top_level = Some(blocks);
+        }
+        Ok(self.top_level.as_deref().unwrap())
+    }
+
+    /// `BLOCKINFO` state for the blocks nested in the top level block
+    /// `index`, reading the start of the block up to its first nested block
+    fn nested_state(&mut self, index: usize) -> io::Result<BlockInfoState> {
+        let top_level = &self.top_level.as_ref().unwrap()[index];
+        if let Some(state) = &top_level.state {
+            return Ok(state.clone());
+        }
+        let context = top_level.context;
+        let inherited = top_level.inherited.clone();
+        let (start, end) = (context.offset / 8, context.end_offset() / 8);
+        let mut size = SCAN_CHUNK_SIZE.min((end - start) as usize);
+        let state = loop {
+            let (buffer, skip) = self.load(start, size)?;
+            let mut reader = BitStreamReader::new(&buffer[skip..skip + size]);
+            reader.global_abbrevs = inherited.global_abbrevs.clone();
+            reader.block_info = inherited.block_info.clone();
+            let mut block = reader.iter_block(context.id, context.abbrev_width);
+            let result = loop {
+                match block.next() {
+                    Ok(Some(BlockItem::Record(_))) => {}
+                    Ok(Some(BlockItem::Block(_))) | Ok(None) => break Ok(()),
+                    Err(err) => break Err(err),
+                }
+            };
+            match result {
+                Ok(()) => {
+                    break BlockInfoState {
+                        global_abbrevs: reader.global_abbrevs,
+                        block_info: reader.block_info,
+                    }
+                }
+                Err(err)
+                    if matches!(
+                        err.root_cause(),
+                        Error::ReadBits(bits::Error::BufferOverflow)
+                    ) && size < (end - start) as usize =>
+                {
+                    size = (size * 2).min((end - start) as usize);
+                }
+                Err(err) => return Err(invalid_data(err)),
+            }
+        };
+        self.top_level.as_mut().unwrap()[index].state = Some(state.clone());
+        Ok(state)
+    }
+
+    /// Bytes `start..start + size` of the stream, as a buffer and the offset
+    /// of the range in it
+    fn load(&mut self, start: u64, size: usize) -> io::Result<(Arc<[u8]>, usize)> {
+        let hit = self.cache.iter().position(|(offset, buffer)| {
+            *offset <= start && start + size as u64 <= offset + buffer.len() as u64
+        });
+        if let Some(index) = hit {
+            let entry = self.cache.remove(index).unwrap();
+            self.cache.push_back(entry.clone());
+            return Ok((entry.1, (start - entry.0) as usize));
+        }
+        let mut buffer = vec![0; size];
+        self.inner.seek(SeekFrom::Start(self.base + start))?;
+        self.inner.read_exact(&mut buffer)?;
+        let buffer: Arc<[u8]> = buffer.into();
+        self.cache_size += size;
+        self.cache.push_back((start, buffer.clone()));
+        self.evict();
+        Ok((buffer, 0))
+    }
+
+    /// Drop the least recently used ranges until the cache fits its capacity
+    fn evict(&mut self) {
+        while self.cache_size > self.cache_capacity && self.cache.len() > 1 {
+            if let Some((_, buffer)) = self.cache.pop_front() {
+                self.cache_size -= buffer.len();
+            }
+        }
+    }
+}
+
+/// Read the header of a top level block, `None` if `data` holds padding
+/// instead
+fn read_header(data: &[u8]) -> Result<Option<BlockContext>, Error> {
+    let mut cursor = Cursor::new(Bits::new(data));
+    let header_offset = cursor.offset();
+    let abbrev_id = cursor.read(2)?;
+    if abbrev_id == 0 && data.iter().all(|&byte| byte == 0) {
+        return Ok(None);
+    }
+    if abbrev_id != u64::from(BuiltinAbbreviationId::EnterSubBlock as u8) {
+        return Err(Error::NoSuchAbbrev {
+            block_id: BitStreamReader::TOP_LEVEL_BLOCK_ID,
+            abbrev_id: abbrev_id as usize,
+        });
+    }
+    let id = cursor.read_vbr(8)?;
+    let abbrev_width = read_abbrev_width(&mut cursor)?;
+    cursor.advance(32)?;
+    let length = cursor.read(32)? as usize;
+    Ok(Some(BlockContext {
+        id,
+        abbrev_width,
+        length,
+        offset: cursor.offset(),
+        header_offset,
+    }))
+}
+
+/// Make the bit ranges of `elements`, read from a buffer starting at bit
+/// `offset` of the stream, relative to the stream
+fn shift(elements: &mut [BitcodeElement<'_>], offset: u64) {
+    let shift_range = |range: &mut Option<Range<u64>>| {
+        if let Some(range) = range {
+            *range = range.start + offset..range.end + offset;
+        }
+    };
+    for element in elements {
+        match element {
+            BitcodeElement::Block(block) => {
+                shift_range(&mut block.bit_range);
+                shift(&mut block.elements, offset);
+            }
+            BitcodeElement::Record(record) => shift_range(&mut record.bit_range),
+        }
+    }
+}
+
+fn invalid_data(err: Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,457 @@
+//! Where the bytes of a module go
+//!
+//! A [`SizeReport`] attributes the bits of a module to block ids, to the
+//! body of each function, and to categories of metadata, e.g. debug info
+//! types, locations and strings. Its `Display` output is a report of the
+//! largest contributors:
+//!
+//! ```text
+//! total: 1908 bytes
+//!
+//! blocks (including nested blocks):
+//!       1776 bytes      1 x Module
+//! ...
+//! functions:
+//!        180 bytes add
+//!        140 bytes loop
+//! ...
+//! ```
+//!
+//! A [`MemoryUsage`] attributes the heap memory of a parsed [`Bitcode`] to
+//! block ids instead, see [`Bitcode::memory_usage`].
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem::size_of;
+
+use crate::bitcode::{BitcodeElement, Block, BlockInfo, Payload, Record};
+use crate::read::{BlockItem, BlockIter, Error};
+use crate::schema::blocks::BlockId;
+use crate::schema::codes::{
+    FUNC_CODE_DEBUG_LOC, FUNC_CODE_DEBUG_LOC_AGAIN, METADATA_KIND, STRTAB_BLOB,
+};
+use crate::symbols::ModuleRecords;
+use crate::Bitcode;
+
+/// Category of metadata records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MetadataCategory {
+    /// Metadata strings
+    Strings,
+    /// Debug locations, `!DILocation` nodes and the debug locations of
+    /// instructions
+    Locations,
+    /// Debug info types, enumerators, subranges and template parameters
+    Types,
+    /// Compile units, files, subprograms, lexical blocks, namespaces and
+    /// modules
+    Scopes,
+    /// Variables, labels and expressions
+    Variables,
+    /// Generic nodes and values
+    Nodes,
+    /// Named metadata and metadata kind names
+    Names,
+    /// Attachments of metadata to functions, instructions and globals
+    Attachments,
+    /// Everything else, like imported entities, macros and the index of
+    /// metadata offsets
+    Other,
+}
+
+impl MetadataCategory {
+    /// Category of a record of a metadata block
+    fn of_metadata_record(code: u64) -> Self {
+        use MetadataCategory::*;
+
+        match code {
+            // STRING_OLD, STRINGS
+            1 | 35 => Strings,
+            // LOCATION
+            7 => Locations,
+            // SUBRANGE, ENUMERATOR, BASIC_TYPE, DERIVED_TYPE,
+            // COMPOSITE_TYPE, SUBROUTINE_TYPE, TEMPLATE_TYPE,
+            // TEMPLATE_VALUE, STRING_TYPE, GENERIC_SUBRANGE
+            13 | 14 | 15 | 17 | 18 | 19 | 25 | 26 | 41 | 45 => Types,
+            // FILE, COMPILE_UNIT, SUBPROGRAM, LEXICAL_BLOCK,
+            // LEXICAL_BLOCK_FILE, NAMESPACE, MODULE, COMMON_BLOCK
+            16 | 20 | 21 | 22 | 23 | 24 | 32 | 44 => Scopes,
+            // GLOBAL_VAR, LOCAL_VAR, EXPRESSION, GLOBAL_VAR_EXPR, LABEL
+            27 | 28 | 29 | 37 | 40 => Variables,
+            // VALUE, NODE, DISTINCT_NODE, OLD_NODE, OLD_FN_NODE,
+            // GENERIC_DEBUG, ARG_LIST, ASSIGN_ID
+            2 | 3 | 5 | 8 | 9 | 12 | 46 | 47 => Nodes,
+            // NAME, KIND, NAMED_NODE
+            4 | 6 | 10 => Names,
+            // ATTACHMENT, GLOBAL_DECL_ATTACHMENT
+            11 | 36 => Attachments,
+            _ => Other,
+        }
+    }
+}
+
+/// Size of the blocks with an id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSize {
+    pub id: BlockId,
+    /// Number of blocks with this id
+    pub count: usize,
+    /// Bits of the blocks, including their headers and nested blocks
+    pub bits: u64,
+}
+
+/// Size of a function body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSize {
+    /// IR name of the function, empty if it isn't known
+    pub name: String,
+    /// Bits of the function block, including its nested blocks
+    pub bits: u64,
+}
+
+/// Size of a category of metadata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataSize {
+    pub category: MetadataCategory,
+    pub records: usize,
+    /// Bits of the records, including abbreviation definitions right after
+    /// them
+    pub bits: u64,
+}
+
+/// Size attribution of a module, each list sorted from the largest
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeReport {
+    /// Size of the input in bytes
+    pub total_bytes: usize,
+    pub blocks: Vec<BlockSize>,
+    /// Function bodies of the first module
+    pub functions: Vec<FunctionSize>,
+    pub metadata: Vec<MetadataSize>,
+}
+
+impl SizeReport {
+    /// Attribute the bits of the bitcode in `data`
+    ///
+    /// Accepts both LLVM bitcode and bitcode wrapper formats
+    pub fn new(data: &[u8]) -> Result<Self, Error> {
+        let (_, mut reader) = Bitcode::reader(data)?;
+        let mut profiler = Profiler::default();
+        profiler.walk(&mut reader.iter_top_level())?;
+
+        let strtab = profiler.strtab.unwrap_or_default();
+        let names = profiler.module.function_bodies(&strtab);
+        let mut functions: Vec<_> = profiler
+            .function_bits
+            .into_iter()
+            .enumerate()
+            .map(|(index, bits)| FunctionSize {
+                name: names.get(index).cloned().unwrap_or_default(),
+                bits,
+            })
+            .collect();
+        functions.sort_by(|a, b| b.bits.cmp(&a.bits).then_with(|| a.name.cmp(&b.name)));
+        let mut blocks: Vec<_> = profiler
+            .blocks
+            .into_iter()
+            .map(|(id, (count, bits))| BlockSize {
+                id: BlockId::from(id),
+                count,
+                bits,
+            })
+            .collect();
+        blocks.sort_by(|a, b| {
+            b.bits
+                .cmp(&a.bits)
+                .then_with(|| u64::from(a.id).cmp(&u64::from(b.id)))
+        });
+        let mut metadata: Vec<_> = profiler
+            .metadata
+            .into_iter()
+            .map(|(category, (records, bits))| MetadataSize {
+                category,
+                records,
+                bits,
+            })
+            .collect();
+        metadata.sort_by(|a, b| b.bits.cmp(&a.bits).then(a.category.cmp(&b.category)));
+        Ok(Self {
+            total_bytes: data.len(),
+            blocks,
+            functions,
+            metadata,
+        })
+    }
+}
+
+impl fmt::Display for SizeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "total: {} bytes", self.total_bytes)?;
+        writeln!(f, "\nblocks (including nested blocks):")?;
+        for block in &self.blocks {
+            writeln!(
+                f,
+                "{:>10} bytes {:>6} x {:?}",
+                block.bits / 8,
+                block.count,
+                block.id
+            )?;
+        }
+        if !self.functions.is_empty() {
+            writeln!(f, "\nfunctions:")?;
+            for function in &self.functions {
+                writeln!(f, "{:>10} bytes {}", function.bits / 8, function.name)?;
+            }
+        }
+        if !self.metadata.is_empty() {
+            writeln!(f, "\nmetadata:")?;
+            for metadata in &self.metadata {
+                writeln!(
+                    f,
+                    "{:>10} bytes {:>6} x {:?}",
+                    metadata.bits / 8,
+                    metadata.records,
+                    metadata.category
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Memory used by the blocks with an id, in bytes, excluding their nested
+/// blocks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockMemory {
+    pub id: BlockId,
+    /// Number of blocks with this id
+    pub count: usize,
+    /// Number of records directly in the blocks
+    pub records: usize,
+    /// Element lists of the blocks, holding the records and the nested
+    /// blocks themselves
+    pub elements: usize,
+    /// Record fields
+    pub fields: usize,
+    /// Record arrays, char6 strings and owned blobs. Blobs borrowed from the
+    /// input use no memory
+    pub payloads: usize,
+}
+
+impl BlockMemory {
+    /// Bytes used by the blocks
+    pub fn total(&self) -> usize {
+        self.elements + self.fields + self.payloads
+    }
+}
+
+/// Heap memory used by a parsed [`Bitcode`], in bytes
+///
+/// Sizes are computed from the capacity of the allocations, and don't
+/// include allocator overhead. Abbreviations shared by records are not
+/// counted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    /// Usage by block id, sorted from the largest
+    pub blocks: Vec<BlockMemory>,
+    /// List of the top level elements
+    pub top_level: usize,
+    /// Block and record names read from `BLOCKINFO`
+    pub block_info: usize,
+}
+
+impl MemoryUsage {
+    /// Measure the memory used by `bitcode`
+    pub fn new(bitcode: &Bitcode<'_>) -> Self {
+        let mut blocks = HashMap::new();
+        for element in &bitcode.elements {
+            if let BitcodeElement::Block(block) = element {
+                measure_block(block, &mut blocks);
+            }
+        }
+        let mut blocks: Vec<_> = blocks.into_values().collect();
+        blocks.sort_by(|a, b| {
+            b.total()
+                .cmp(&a.total())
+                .then_with(|| u64::from(a.id).cmp(&u64::from(b.id)))
+        });
+        let block_info = bitcode
+            .block_info
+            .values()
+            .map(|info| {
+                info.name.capacity()
+                    + info.record_names.capacity() * size_of::<(u64, String)>()
+                    + info
+                        .record_names
+                        .values()
+                        .map(String::capacity)
+                        .sum::<usize>()
+            })
+            .sum::<usize>()
+            + bitcode.block_info.capacity() * size_of::<(u64, BlockInfo)>();
+        Self {
+            blocks,
+            top_level: bitcode.elements.capacity() * size_of::<BitcodeElement<'_>>(),
+            block_info,
+        }
+    }
+
+    /// Bytes used by the bitcode
+    pub fn total(&self) -> usize {
+        self.blocks.iter().map(BlockMemory::total).sum::<usize>() + self.top_level + self.block_info
+    }
+}
+
+impl fmt::Display for MemoryUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "total: {} bytes", self.total())?;
+        writeln!(f, "\nblocks (excluding nested blocks):")?;
+        for block in &self.blocks {
+            writeln!(
+                f,
+                "{:>10} bytes {:>6} x {:?}: {} records, {} elements, {} fields, {} payloads",
+                block.total(),
+                block.count,
+                block.id,
+                block.records,
+                block.elements,
+                block.fields,
+                block.payloads
+            )?;
+        }
+        writeln!(f, "\n{:>10} bytes top level", self.top_level)?;
+        writeln!(f, "{:>10} bytes block info", self.block_info)
+    }
+}
+
+/// Add the memory used by `block` and its nested blocks to `blocks`
+fn measure_block(block: &Block<'_>, blocks: &mut HashMap<u64, BlockMemory>) {
+    let usage = blocks.entry(block.id).or_insert_with(|| BlockMemory {
+        id: BlockId::from(block.id),
+        count: 0,
+        records: 0,
+        elements: 0,
+        fields: 0,
+        payloads: 0,
+    });
+    usage.count += 1;
+    usage.elements += block.elements.capacity() * size_of::<BitcodeElement<'_>>();
+    for record in block.elements.iter().filter_map(BitcodeElement::as_record) {
+        usage.records += 1;
+        usage.fields += record.fields.capacity() * size_of::<u64>();
+        usage.payloads += payload_size(record);
+    }
+    for nested in block.elements.iter().filter_map(BitcodeElement::as_block) {
+        measure_block(nested, blocks);
+    }
+}
+
+/// Heap memory used by the payload of `record`
+fn payload_size(record: &Record<'_>) -> usize {
+    match &record.payload {
+        None => 0,
+        Some(Payload::Array(array)) => array.capacity() * size_of::<u64>(),
+        Some(Payload::Char6String(s)) => s.capacity(),
+        Some(Payload::Blob(Cow::Owned(blob))) => blob.capacity(),
+        Some(Payload::Blob(Cow::Borrowed(_))) => 0,
+    }
+}
+
+/// An item of a block, once it has been read
+enum Item {
+    Record(Option<MetadataCategory>),
+    /// A nested block with its id and end offset
+    Block(u64, u64),
+}
+
+#[derive(Default)]
+struct Profiler {
+    /// Count and bits by block id
+    blocks: HashMap<u64, (usize, u64)>,
+    /// Bits of the function blocks of the first module, in order
+    function_bits: Vec<u64>,
+    /// Count and bits of records by category
+    metadata: HashMap<MetadataCategory, (usize, u64)>,
+    module: ModuleRecords,
+    modules: usize,
+    strtab: Option<Vec<u8>>,
+}
+
+impl Profiler {
+    fn walk(&mut self, block: &mut BlockIter<'_, '_>) -> Result<(), Error> {
+        let block_id = BlockId::from(block.id);
+        let first_module = self.modules == 1;
+        // A record is measured up to the start of the next item
+        let mut pending: Option<(MetadataCategory, u64)> = None;
+        loop {
+            let item = match block.next()? {
+                None => None,
+                Some(BlockItem::Record(mut record)) => {
+                    let code = record.id;
+                    match block_id {
+                        BlockId::Module if first_module => {
+                            self.module.read_record(&record.into_record()?)
+                        }
+                        BlockId::Strtab if code == STRTAB_BLOB && self.strtab.is_none() => {
+                            self.strtab = Some(record.blob()?.into_owned())
+                        }
+                        _ => {}
+                    }
+                    let category = match block_id {
+                        BlockId::Metadata => Some(MetadataCategory::of_metadata_record(code)),
+                        BlockId::MetadataAttachment => Some(MetadataCategory::Attachments),
+                        BlockId::MetadataKind if code == METADATA_KIND => {
+                            Some(MetadataCategory::Names)
+                        }
+                        BlockId::Function
+                            if code == FUNC_CODE_DEBUG_LOC || code == FUNC_CODE_DEBUG_LOC_AGAIN =>
+                        {
+                            Some(MetadataCategory::Locations)
+                        }
+                        _ => None,
+                    };
+                    Some(Item::Record(category))
+                }
+                Some(BlockItem::Block(mut child)) => {
+                    let id = child.id;
+                    let end = child.context().end_offset();
+                    if id == u64::from(BlockId::Module) {
+                        self.modules += 1;
+                    }
+                    if id == u64::from(BlockId::ValueSymtab) && block_id == BlockId::Module {
+                        if first_module {
+                            self.module.read_vst(&mut child)?;
+                        }
+                    } else {
+                        self.walk(&mut child)?;
+                    }
+                    Some(Item::Block(id, end))
+                }
+            };
+            let offset = block.item_offset();
+            if let Some((category, start)) = pending.take() {
+                let entry = self.metadata.entry(category).or_default();
+                entry.0 += 1;
+                entry.1 += offset.saturating_sub(start);
+            }
+            match item {
+                None => return Ok(()),
+                Some(Item::Record(category)) => {
+                    pending = category.map(|category| (category, offset))
+                }
+                Some(Item::Block(id, end)) => {
+                    let bits = end.saturating_sub(offset);
+                    let entry = self.blocks.entry(id).or_default();
+                    entry.0 += 1;
+                    entry.1 += bits;
+                    if id == u64::from(BlockId::Function)
+                        && block_id == BlockId::Module
+                        && first_module
+                    {
+                        self.function_bits.push(bits);
+                    }
+                }
+            }
+        }
+    }
+}
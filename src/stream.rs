@@ -0,0 +1,468 @@
+//! Pull-based reading of bitstream events
+//!
+//! [`Events`] iterates over the blocks and records of a stream held in
+//! memory, and a [`StreamReader`] reads them from input fed in chunks, such
+//! as a pipe or a socket, without buffering the whole stream.
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::mem;
+use std::sync::Arc;
+
+use crate::bitcode::{BlockInfo, Record, Signature, LLVM_BITCODE_WRAPPER_MAGIC};
+use crate::bits;
+use crate::bitstream::Abbreviation;
+use crate::read::{
+    capacity, BitStreamReader, BlockAbbrevs, BlockContext, BlockTable, Budget, Error, ParseOptions,
+    RawItemHeader,
+};
+
+/// Size of the chunks read by [`StreamReader::fill_from`]
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// An item read by [`Events`] or a [`StreamReader`]
+#[derive(Debug, Clone)]
+pub enum Event<'input> {
+    /// Entered a block, its elements follow until the matching `ExitBlock`
+    EnterBlock(BlockContext),
+    /// Exited the innermost block
+    ExitBlock,
+    /// Data record
+    Record(Record<'input>),
+}
+
+impl Event<'_> {
+    /// Returns an event that owns its record payload
+    pub fn into_owned(self) -> Event<'static> {
+        match self {
+            Event::EnterBlock(context) => Event::EnterBlock(context),
+            Event::ExitBlock => Event::ExitBlock,
+            Event::Record(record) => Event::Record(record.into_owned()),
+        }
+    }
+}
+
+/// An item read by a [`StreamReader`], owning its data
+pub type StreamEvent = Event<'static>;
+
+/// A block being read by a [`StreamReader`]
+#[derive(Debug)]
+struct Frame {
+    id: u64,
+    abbrev_width: usize,
+    abbrevs: BlockAbbrevs,
+}
+
+impl Frame {
+    fn top_level() -> Self {
+        Self {
+            id: BitStreamReader::TOP_LEVEL_BLOCK_ID,
+            abbrev_width: 2,
+            abbrevs: BlockAbbrevs::default(),
+        }
+    }
+}
+
+/// A bitstream reader that is fed the input in chunks
+///
+/// Input is appended with [`StreamReader::feed`] or read from an
+/// [`io::Read`] with [`StreamReader::fill_from`]. When an item is not
+/// completely buffered, [`StreamReader::next`] returns
+/// [`Error::NeedMoreData`] without consuming anything, and reading resumes
+/// from the same item once more input is available. An incomplete item is
+/// only read again once the input buffered from its start has doubled, so
+/// that reading a large item takes time linear in its size. Input that has
+/// been read is discarded, so the whole stream is never held in memory.
+///
+/// Both LLVM bitcode and bitcode wrapper formats are accepted. Block
+/// offsets in [`StreamEvent::EnterBlock`] are relative to the end of the
+/// signature, like the offsets reported by [`BitStreamReader`].
+#[derive(Debug, Default)]
+pub struct StreamReader {
+    /// Unread input
+    buffer: Vec<u8>,
+    /// Offset in bits of `buffer` in the stream
+    base: u64,
+    /// Offset in bits of the next item in `buffer`
+    offset: u64,
+    /// Length of `buffer` to reach before reading an incomplete item again
+    retry_len: usize,
+    /// No more input will be fed
+    eof: bool,
+    /// Length in bytes of the stream after the signature, if known from a
+    /// bitcode wrapper header
+    stream_len: Option<usize>,
+    signature: Option<Signature>,
+    stack: Vec<Frame>,
+    global_abbrevs: BlockTable<Vec<Arc<Abbreviation>>>,
+    block_info: HashMap<u64, BlockInfo>,
+    budget: Budget,
+}
+
+impl StreamReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Enforce the limits of `options` for the rest of the read
+    pub fn set_options(&mut self, options: ParseOptions) {
+        self.budget.options = options;
+    }
+
+    /// Append input
+    pub fn feed(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Mark the end of the input
+    ///
+    /// From then on, incomplete items are reported as errors instead of
+    /// [`Error::NeedMoreData`].
+    pub fn finish(&mut self) {
+        self.eof = true;
+    }
+
+    /// Read the next chunk of `input`, calling [`StreamReader::finish`] when
+    /// it is exhausted
+    ///
+    /// Returns the number of bytes read.
+    pub fn fill_from<R: Read>(&mut self, input: &mut R) -> io::Result<usize> {
+        let len = self.buffer.len();
+        self.buffer.resize(len + CHUNK_SIZE, 0);
+        let read = match input.read(&mut self.buffer[len..]) {
+            Ok(read) => read,
+            Err(err) => {
+                self.buffer.truncate(len);
+                return Err(err);
+            }
+        };
+        self.buffer.truncate(len + read);
+        if read == 0 {
+            self.finish();
+        }
+        Ok(read)
+    }
+
+    /// Signature of the stream, once it has been read
+    pub fn signature(&self) -> Option<Signature> {
+        self.signature
+    }
+
+    /// Block information read from `BLOCKINFO` blocks so far
+    pub fn block_info(&self) -> &HashMap<u64, BlockInfo> {
+        &self.block_info
+    }
+
+    /// Read the next event, or `None` at the end of the stream
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<StreamEvent>, Error> {
+        if self.signature.is_none() {
+            self.read_signature()?;
+            self.stack.push(Frame::top_level());
+        }
+        if self.stack.is_empty() {
+            return Ok(None);
+        }
+        if let Some(len) = self.stream_len {
+            // Ignore whatever follows the stream in a wrapper
+            let base = (self.base / 8) as usize;
+            if base + self.buffer.len() >= len {
+                self.buffer.truncate(len - base);
+                self.eof = true;
+            } else if self.eof {
+                // The wrapper header points past the end of the input
+                return Err(Error::InvalidWrapper);
+            }
+        }
+        if self.buffer.len() < self.retry_len && !self.eof {
+            return Err(Error::NeedMoreData);
+        }
+
+        loop {
+            let mut reader = BitStreamReader::new(&self.buffer);
+            reader.cursor.seek(self.offset)?;
+            reader.global_abbrevs = mem::take(&mut self.global_abbrevs);
+            reader.block_info = mem::take(&mut self.block_info);
+            reader.budget = self.budget.clone();
+            let result = read_step(&mut reader, &mut self.stack, self.base, self.eof);
+            let offset = reader.cursor.offset();
+            self.global_abbrevs = reader.global_abbrevs;
+            self.block_info = reader.block_info;
+            let step = match result {
+                // Incomplete items are read again, only account for complete ones
+                Ok(step) => {
+                    self.budget = reader.budget;
+                    step.into_owned()
+                }
+                Err(Error::ReadBits(bits::Error::BufferOverflow)) => {
+                    // Wait for twice as much of the item before reading it again
+                    let start = (self.offset / 8) as usize;
+                    self.retry_len = self.buffer.len() + (self.buffer.len() - start).max(1);
+                    return Err(self.incomplete());
+                }
+                Err(err) => return Err(err),
+            };
+            self.retry_len = 0;
+            self.offset = offset;
+            self.compact();
+            match step {
+                Step::Event(event) => return Ok(Some(event)),
+                Step::End => return Ok(None),
+                Step::Continue => {}
+            }
+        }
+    }
+
+    /// Read the next event, pulling input from `input` as needed
+    ///
+    /// Bitstream errors are reported as [`io::ErrorKind::InvalidData`].
+    pub fn next_from<R: Read>(&mut self, input: &mut R) -> io::Result<Option<StreamEvent>> {
+        loop {
+            match self.next() {
+                Err(Error::NeedMoreData) => {
+                    self.fill_from(input)?;
+                }
+                Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+                Ok(event) => return Ok(event),
+            }
+        }
+    }
+
+    /// Read the signature, skipping the bitcode wrapper header if any
+    fn read_signature(&mut self) -> Result<(), Error> {
+        let word = |buffer: &[u8], index: usize| {
+            u32::from_le_bytes([
+                buffer[index],
+                buffer[index + 1],
+                buffer[index + 2],
+                buffer[index + 3],
+            ])
+        };
+        if self.buffer.len() < 4 {
+            return Err(self.incomplete());
+        }
+        let mut signature = word(&self.buffer, 0);
+        if signature == LLVM_BITCODE_WRAPPER_MAGIC {
+            if self.buffer.len() < 16 {
+                return Err(self.incomplete());
+            }
+            let offset = word(&self.buffer, 8) as usize;
+            let size = word(&self.buffer, 12) as usize;
+            // Same checks as `Signature::parse`, the end of the wrapped
+            // stream is checked once the input ends
+            if offset.checked_add(size).is_none() || size < 4 {
+                return Err(Error::InvalidWrapper);
+            }
+            if self.buffer.len() < offset + 4 {
+                return Err(if self.eof {
+                    Error::InvalidWrapper
+                } else {
+                    Error::NeedMoreData
+                });
+            }
+            self.buffer.drain(..offset);
+            self.stream_len = Some(size - 4);
+            signature = word(&self.buffer, 0);
+        }
+        self.signature = Some(Signature::new(signature));
+        self.buffer.drain(..4);
+        Ok(())
+    }
+
+    /// Error to report when the buffered input ends in the middle of an item
+    fn incomplete(&self) -> Error {
+        if self.eof {
+            Error::ReadBits(bits::Error::BufferOverflow)
+        } else {
+            Error::NeedMoreData
+        }
+    }
+
+    /// Discard the input that has been read
+    ///
+    /// Whole 32-bit words are discarded so that alignment is preserved.
+    fn compact(&mut self) {
+        // In bounds of the buffer, so it fits in `usize`
+        let consumed = (self.offset / 32 * 4) as usize;
+        if consumed >= CHUNK_SIZE && consumed * 2 >= self.buffer.len() {
+            self.buffer.drain(..consumed);
+            self.base += consumed as u64 * 8;
+            self.offset -= consumed as u64 * 8;
+        }
+    }
+}
+
+/// An iterator over the events of a bitstream held in memory
+///
+/// The pull-based counterpart of a visitor: each block is reported by an
+/// [`Event::EnterBlock`], followed by its records and nested blocks, and an
+/// [`Event::ExitBlock`]. Blob payloads borrow from the input. `BLOCKINFO`
+/// blocks are read and not reported, like with a visitor. Iteration stops
+/// after the first error.
+///
+/// ```
+/// use llvm_bitcode::read::Error;
+/// use llvm_bitcode::stream::Event;
+/// use llvm_bitcode::Bitcode;
+///
+/// fn count_records(data: &[u8]) -> Result<usize, Error> {
+///     let mut count = 0;
+///     for event in Bitcode::events(data)? {
+///         if let Event::Record(_) = event? {
+///             count += 1;
+///         }
+///     }
+///     Ok(count)
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Events<'input> {
+    reader: BitStreamReader<'input>,
+    stack: Vec<Frame>,
+    /// The end of the stream or an error has been reached
+    done: bool,
+}
+
+impl<'input> Events<'input> {
+    /// Iterate over the events of `reader`, from its current position to
+    /// the end of its buffer
+    pub fn new(reader: BitStreamReader<'input>) -> Self {
+        Self {
+            reader,
+            stack: vec![Frame::top_level()],
+            done: false,
+        }
+    }
+
+    /// Block information read from `BLOCKINFO` blocks so far
+    pub fn block_info(&self) -> &HashMap<u64, BlockInfo> {
+        &self.reader.block_info
+    }
+}
+
+impl<'input> Iterator for Events<'input> {
+    type Item = Result<Event<'input>, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            match read_step(&mut self.reader, &mut self.stack, 0, true) {
+                Ok(Step::Event(event)) => return Some(Ok(event)),
+                Ok(Step::Continue) => {}
+                Ok(Step::End) => self.done = true,
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Outcome of reading one item
+enum Step<'input> {
+    Event(Event<'input>),
+    /// An item that is not reported, like an abbreviation definition
+    Continue,
+    End,
+}
+
+impl Step<'_> {
+    fn into_owned(self) -> Step<'static> {
+        match self {
+            Step::Event(event) => Step::Event(event.into_owned()),
+            Step::Continue => Step::Continue,
+            Step::End => Step::End,
+        }
+    }
+}
+
+/// Read one item from `reader`
+///
+/// `stack` is only modified if the item is read successfully, and `BLOCKINFO`
+/// blocks are only read once they are completely buffered.
+fn read_step<'input>(
+    reader: &mut BitStreamReader<'input>,
+    stack: &mut Vec<Frame>,
+    base: u64,
+    eof: bool,
+) -> Result<Step<'input>, Error> {
+    let depth = stack.len();
+    let frame = match stack.last_mut() {
+        Some(frame) => frame,
+        None => return Ok(Step::End),
+    };
+    if reader.cursor.is_at_end() {
+        if !eof {
+            return Err(Error::NeedMoreData);
+        }
+        if frame.id != BitStreamReader::TOP_LEVEL_BLOCK_ID {
+            return Err(Error::MissingEndBlock(frame.id));
+        }
+        stack.pop();
+        return Ok(Step::End);
+    }
+    let start = base + reader.cursor.offset();
+    let header = reader.read_item_header(frame.id, frame.abbrev_width, &mut frame.abbrevs)?;
+    match header {
+        RawItemHeader::EndBlock => {
+            let id = frame.id;
+            stack.pop();
+            if id == BitStreamReader::TOP_LEVEL_BLOCK_ID {
+                return Ok(Step::End);
+            }
+            Ok(Step::Event(Event::ExitBlock))
+        }
+        RawItemHeader::EnterBlock {
+            id: 0,
+            abbrev_width,
+            length,
+        } => {
+            let end = reader.cursor.offset() + length as u64 * 32;
+            if end > reader.cursor.bit_len() && !eof {
+                return Err(Error::NeedMoreData);
+            }
+            reader.read_block_info_block(abbrev_width)?;
+            Ok(Step::Continue)
+        }
+        RawItemHeader::EnterBlock {
+            id,
+            abbrev_width,
+            length,
+        } => {
+            reader.budget.check_depth(depth)?;
+            stack.push(Frame {
+                id,
+                abbrev_width,
+                abbrevs: BlockAbbrevs::new(reader, id),
+            });
+            Ok(Step::Event(Event::EnterBlock(BlockContext {
+                id,
+                abbrev_width,
+                length,
+                offset: base + reader.cursor.offset(),
+                header_offset: start,
+            })))
+        }
+        RawItemHeader::DefineAbbrev(_) => Ok(Step::Continue),
+        RawItemHeader::UnabbreviatedRecord { id, num_ops } => {
+            reader.budget.decode(num_ops as u64)?;
+            let mut fields = Vec::with_capacity(capacity(num_ops, &reader.cursor, 6));
+            for _ in 0..num_ops {
+                fields.push(reader.cursor.read_vbr(6)?);
+            }
+            Ok(Step::Event(Event::Record(Record {
+                id,
+                fields,
+                payload: None,
+                abbrev: None,
+                bit_range: Some(start..base + reader.cursor.offset()),
+            })))
+        }
+        RawItemHeader::AbbreviatedRecord(abbrev) => {
+            let mut record = reader.read_abbreviated_record(&abbrev.abbrev)?;
+            record.abbrev = Some(abbrev);
+            record.bit_range = Some(start..base + reader.cursor.offset());
+            Ok(Step::Event(Event::Record(record)))
+        }
+    }
+}
@@ -0,0 +1,688 @@
+//! Symbols defined and referenced by a module, like `llvm-nm` lists them
+//!
+//! Bitcode written by LLVM 5 and later carries a symbol table (`SYMTAB`
+//! block) with the names the linker sees, mangled for the target, and
+//! symbols of module-level inline assembly. It is preferred when present,
+//! joined with the global value records of the module for the properties
+//! it doesn't store. Otherwise symbols are read from the module records
+//! and mangled from the data layout, which misses inline assembly symbols.
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+
+use crate::bitcode::Record;
+use crate::read::{BlockItem, BlockIter, Error};
+use crate::schema::blocks::BlockId;
+use crate::schema::codes::{
+    MODULE_CODE_ALIAS, MODULE_CODE_ALIAS_OLD, MODULE_CODE_DATALAYOUT, MODULE_CODE_FUNCTION,
+    MODULE_CODE_GLOBALVAR, MODULE_CODE_IFUNC, MODULE_CODE_VERSION, STRTAB_BLOB, SYMTAB_BLOB,
+    VST_CODE_ENTRY, VST_CODE_FNENTRY,
+};
+use crate::Bitcode;
+
+/// Size in bytes of a symbol of the symbol table
+const SYMTAB_SYMBOL_SIZE: usize = 24;
+/// Flag bits of symbol table symbols
+const FB_UNDEFINED: u32 = 1 << 3;
+const FB_WEAK: u32 = 1 << 4;
+const FB_COMMON: u32 = 1 << 5;
+const FB_TLS: u32 = 1 << 8;
+const FB_GLOBAL: u32 = 1 << 10;
+const FB_FORMAT_SPECIFIC: u32 = 1 << 11;
+const FB_EXECUTABLE: u32 = 1 << 13;
+
+/// Kind of global value a symbol belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SymbolKind {
+    Function,
+    Variable,
+    Alias,
+    IFunc,
+    /// A symbol of module-level inline assembly
+    Asm,
+}
+
+/// Linkage of a global value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Linkage {
+    External,
+    AvailableExternally,
+    LinkOnceAny,
+    LinkOnceOdr,
+    WeakAny,
+    WeakOdr,
+    Appending,
+    Internal,
+    Private,
+    ExternalWeak,
+    Common,
+}
+
+impl From<u64> for Linkage {
+    /// Decode a linkage as stored in module records, mapping obsolete
+    /// linkages like LLVM does
+    fn from(code: u64) -> Self {
+        match code {
+            2 => Linkage::Appending,
+            3 => Linkage::Internal,
+            7 => Linkage::ExternalWeak,
+            8 => Linkage::Common,
+            9 | 13 | 14 => Linkage::Private,
+            12 => Linkage::AvailableExternally,
+            1 | 16 => Linkage::WeakAny,
+            10 | 17 => Linkage::WeakOdr,
+            4 | 18 => Linkage::LinkOnceAny,
+            11 | 19 => Linkage::LinkOnceOdr,
+            // Unknown linkages are mapped to external as well
+            _ => Linkage::External,
+        }
+    }
+}
+
+impl Linkage {
+    /// Whether the linkage is internal or private
+    pub fn is_local(self) -> bool {
+        matches!(self, Linkage::Internal | Linkage::Private)
+    }
+
+    /// Whether the linkage is a weak or link-once one
+    pub fn is_weak(self) -> bool {
+        matches!(
+            self,
+            Linkage::LinkOnceAny
+                | Linkage::LinkOnceOdr
+                | Linkage::WeakAny
+                | Linkage::WeakOdr
+                | Linkage::ExternalWeak
+        )
+    }
+
+    /// Whether the linkage is a link-once one, discarded if unreferenced
+    pub fn is_link_once(self) -> bool {
+        matches!(self, Linkage::LinkOnceAny | Linkage::LinkOnceOdr)
+    }
+
+    /// Whether the linker may pick another definition of the global value,
+    /// like LLVM's `isWeakForLinker`
+    pub fn is_weak_for_linker(self) -> bool {
+        matches!(
+            self,
+            Linkage::LinkOnceAny
+                | Linkage::LinkOnceOdr
+                | Linkage::WeakAny
+                | Linkage::WeakOdr
+                | Linkage::ExternalWeak
+                | Linkage::Common
+        )
+    }
+
+    /// Whether the definition may be replaced by one with different
+    /// semantics at link time, like LLVM's `isInterposableLinkage`
+    ///
+    /// The ODR linkages can be replaced by equivalent definitions only.
+    pub fn is_interposable(self) -> bool {
+        matches!(
+            self,
+            Linkage::LinkOnceAny | Linkage::WeakAny | Linkage::Common | Linkage::ExternalWeak
+        )
+    }
+
+    /// Whether the definition follows the one definition rule, so that all
+    /// definitions of the global value are equivalent
+    pub fn is_odr(self) -> bool {
+        matches!(
+            self,
+            Linkage::LinkOnceOdr | Linkage::WeakOdr | Linkage::AvailableExternally
+        )
+    }
+
+    /// Whether the global value may be dropped when it isn't used, like
+    /// LLVM's `isDiscardableIfUnused`
+    pub fn is_discardable_if_unused(self) -> bool {
+        self.is_link_once() || self.is_local() || self == Linkage::AvailableExternally
+    }
+}
+
+/// Visibility of a global value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Visibility {
+    Default,
+    Hidden,
+    Protected,
+}
+
+impl From<u64> for Visibility {
+    fn from(code: u64) -> Self {
+        match code {
+            1 => Visibility::Hidden,
+            2 => Visibility::Protected,
+            _ => Visibility::Default,
+        }
+    }
+}
+
+/// DLL storage class of a global value, for Windows targets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DllStorageClass {
+    Default,
+    DllImport,
+    DllExport,
+}
+
+impl From<u64> for DllStorageClass {
+    fn from(code: u64) -> Self {
+        match code {
+            1 => DllStorageClass::DllImport,
+            2 => DllStorageClass::DllExport,
+            _ => DllStorageClass::Default,
+        }
+    }
+}
+
+impl DllStorageClass {
+    /// Storage class of a record with the `class` field, or implied by the
+    /// obsolete `dllimport` and `dllexport` linkages of records older than
+    /// the field
+    fn decode(class: Option<u64>, linkage: u64) -> Self {
+        match (class, linkage) {
+            (Some(class), _) => class.into(),
+            (None, 5) => DllStorageClass::DllImport,
+            (None, 6) => DllStorageClass::DllExport,
+            (None, _) => DllStorageClass::Default,
+        }
+    }
+}
+
+/// A symbol of a module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    /// Name seen by the linker, mangled for the target
+    pub name: String,
+    /// Name of the global value in the IR, `None` for inline assembly
+    /// symbols
+    pub ir_name: Option<String>,
+    pub kind: SymbolKind,
+    pub linkage: Linkage,
+    pub visibility: Visibility,
+    /// Always `Default` for inline assembly symbols
+    pub dll_storage_class: DllStorageClass,
+    pub dso_local: bool,
+    pub thread_local: bool,
+    /// Whether the symbol is a constant variable
+    pub constant: bool,
+    /// Whether the symbol is defined for the linker, `false` for
+    /// declarations and `available_externally` definitions
+    pub defined: bool,
+    /// Whether the symbol refers to code: functions, ifuncs and aliases of
+    /// functions
+    pub executable: bool,
+    /// Whether the symbol is private or an LLVM intrinsic or special
+    /// variable, hidden by `llvm-nm` unless it lists all symbols
+    pub format_specific: bool,
+}
+
+impl Symbol {
+    /// Whether the symbol has weak or link-once linkage
+    pub fn is_weak(&self) -> bool {
+        self.linkage.is_weak()
+    }
+
+    /// Whether the symbol is a common symbol
+    pub fn is_common(&self) -> bool {
+        self.linkage == Linkage::Common
+    }
+
+    /// Whether the symbol is visible outside of the module
+    pub fn is_global(&self) -> bool {
+        !self.linkage.is_local()
+    }
+
+    /// Visibility of the symbol for the linker
+    ///
+    /// Symbols with local linkage or a DLL storage class always have default
+    /// visibility, whatever the record says, as LLVM's verifier requires.
+    pub fn effective_visibility(&self) -> Visibility {
+        if self.linkage.is_local() || self.dll_storage_class != DllStorageClass::Default {
+            Visibility::Default
+        } else {
+            self.visibility
+        }
+    }
+
+    /// Symbol type letter printed by `llvm-nm`
+    ///
+    /// `U` for undefined, `w`/`W` for weak undefined and defined, `C` for
+    /// common, `t`/`T` for code and `d`/`D` for data, lowercase for local
+    /// symbols. `llvm-nm` prints `s`/`S` instead of `d`/`D` for the
+    /// [constants](Symbol::constant) of Darwin targets.
+    pub fn nm_type(&self) -> char {
+        if self.is_weak() {
+            return if self.defined { 'W' } else { 'w' };
+        }
+        if !self.defined {
+            return 'U';
+        }
+        if self.is_common() {
+            return 'C';
+        }
+        let letter = if self.executable { 't' } else { 'd' };
+        if self.is_global() {
+            letter.to_ascii_uppercase()
+        } else {
+            letter
+        }
+    }
+}
+
+/// List the symbols of the module in `data`, in module order
+///
+/// Accepts both LLVM bitcode and bitcode wrapper formats
+pub fn symbols(data: &[u8]) -> Result<Vec<Symbol>, Error> {
+    let (signature, mut reader) = Bitcode::reader(data)?;
+    if !signature.is_llvm_ir() {
+        return Err(Error::InvalidSignature(signature.into_inner()));
+    }
+    let mut module = ModuleRecords::default();
+    let mut strtab = None;
+    let mut symtab = None;
+    let mut top_level = reader.iter_top_level();
+    while let Some(item) = top_level.next()? {
+        let mut block = match item {
+            BlockItem::Block(block) => block,
+            BlockItem::Record(_) => continue,
+        };
+        match BlockId::from(block.id) {
+            // Only the first module of a file is listed
+            BlockId::Module if module.values.is_empty() => module.read(&mut block)?,
+            BlockId::Strtab => strtab = read_blob(&mut block, STRTAB_BLOB)?.or(strtab),
+            BlockId::Symtab => symtab = read_blob(&mut block, SYMTAB_BLOB)?.or(symtab),
+            _ => {}
+        }
+    }
+
+    let strtab = strtab.as_deref().unwrap_or_default();
+    let values = module.resolve(strtab);
+    let symbols = symtab
+        .and_then(|symtab| from_symtab(&symtab, strtab, &values))
+        .unwrap_or_else(|| {
+            values
+                .iter()
+                .map(|value| value.to_symbol(module.mangling))
+                .collect()
+        });
+    Ok(symbols)
+}
+
+/// Read the blob of the first record with code `code` of a block
+fn read_blob<'input>(
+    block: &mut BlockIter<'_, 'input>,
+    code: u64,
+) -> Result<Option<Cow<'input, [u8]>>, Error> {
+    while let Some(item) = block.next()? {
+        if let BlockItem::Record(mut record) = item {
+            if record.id == code {
+                return Ok(Some(record.blob()?));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// A global value read from the module records
+#[derive(Debug, Clone)]
+struct GlobalValue {
+    name: String,
+    kind: SymbolKind,
+    linkage: Linkage,
+    visibility: Visibility,
+    dll_storage_class: DllStorageClass,
+    dso_local: bool,
+    thread_local: bool,
+    constant: bool,
+    declaration: bool,
+    /// Value id of the aliasee or resolver of aliases and ifuncs
+    target: Option<u64>,
+    /// Whether the value refers to code, set by [`ModuleRecords::resolve`]
+    executable: bool,
+}
+
+impl GlobalValue {
+    fn new() -> Self {
+        Self {
+            name: String::new(),
+            kind: SymbolKind::Variable,
+            linkage: Linkage::External,
+            visibility: Visibility::Default,
+            dll_storage_class: DllStorageClass::Default,
+            dso_local: false,
+            thread_local: false,
+            constant: false,
+            declaration: false,
+            target: None,
+            executable: false,
+        }
+    }
+
+    fn format_specific(&self) -> bool {
+        self.linkage == Linkage::Private || self.name.starts_with("llvm.")
+    }
+
+    fn to_symbol(&self, mangling: Option<u8>) -> Symbol {
+        Symbol {
+            name: mangle(&self.name, mangling),
+            ir_name: Some(self.name.clone()),
+            kind: self.kind,
+            linkage: self.linkage,
+            visibility: self.visibility,
+            dll_storage_class: self.dll_storage_class,
+            dso_local: self.dso_local,
+            thread_local: self.thread_local,
+            constant: self.constant,
+            defined: !self.declaration && self.linkage != Linkage::AvailableExternally,
+            executable: self.executable,
+            format_specific: self.format_specific(),
+        }
+    }
+}
+
+/// The global value records of a module
+#[derive(Debug, Default)]
+pub(crate) struct ModuleRecords {
+    version: u64,
+    /// Mangling mode of the data layout, e.g. `o` for Mach-O
+    mangling: Option<u8>,
+    /// Global values in value id order, with the strtab range of their
+    /// name since version 2
+    values: Vec<(GlobalValue, Option<(usize, usize)>)>,
+    /// Names from the value symbol table before version 2, by value id
+    vst_names: Vec<(u64, String)>,
+}
+
+impl ModuleRecords {
+    fn read(&mut self, module: &mut BlockIter<'_, '_>) -> Result<(), Error> {
+        while let Some(item) = module.next()? {
+            match item {
+                BlockItem::Block(mut block) => {
+                    if block.id == u64::from(BlockId::ValueSymtab) {
+                        self.read_vst(&mut block)?;
+                    }
+                }
+                BlockItem::Record(record) => {
+                    let record = record.into_record()?;
+                    self.read_record(&record);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_record(&mut self, record: &Record) {
+        let operands = record.operands();
+        if record.id == MODULE_CODE_VERSION {
+            self.version = operands.first().copied().unwrap_or(0);
+            return;
+        }
+        if record.id == MODULE_CODE_DATALAYOUT {
+            let layout: String = operands.iter().map(|&c| c as u8 as char).collect();
+            self.mangling = layout
+                .split('-')
+                .find_map(|spec| spec.strip_prefix("m:"))
+                .and_then(|mode| mode.bytes().next());
+            return;
+        }
+        // Since version 2 records start with the strtab offset and size of
+        // the name
+        let (name, operands) = if self.version >= 2 {
+            match operands.get(..2) {
+                Some(&[offset, size]) => (
+                    usize::try_from(offset).ok().zip(usize::try_from(size).ok()),
+                    &operands[2..],
+                ),
+                _ => return,
+            }
+        } else {
+            (None, &operands[..])
+        };
+        let field = |index: usize| operands.get(index).copied().unwrap_or(0);
+        let dll_storage_class = |index: usize, linkage: usize| {
+            DllStorageClass::decode(operands.get(index).copied(), field(linkage))
+        };
+        let value = match record.id {
+            // [type, isconst, initid, linkage, alignment, section,
+            //  visibility, threadlocal, unnamed_addr, externally_initialized,
+            //  dllstorageclass, comdat, attributes, dso_local]
+            MODULE_CODE_GLOBALVAR => GlobalValue {
+                kind: SymbolKind::Variable,
+                constant: field(1) & 1 != 0,
+                declaration: field(2) == 0,
+                linkage: field(3).into(),
+                visibility: field(6).into(),
+                thread_local: field(7) != 0,
+                dll_storage_class: dll_storage_class(10, 3),
+                dso_local: field(13) != 0,
+                ..GlobalValue::new()
+            },
+            // [type, callingconv, isproto, linkage, paramattrs, alignment,
+            //  section, visibility, gc, unnamed_addr, prologuedata,
+            //  dllstorageclass, comdat, prefixdata, personalityfn, dso_local]
+            MODULE_CODE_FUNCTION => GlobalValue {
+                kind: SymbolKind::Function,
+                declaration: field(2) != 0,
+                linkage: field(3).into(),
+                visibility: field(7).into(),
+                dll_storage_class: dll_storage_class(11, 3),
+                dso_local: field(15) != 0,
+                executable: true,
+                ..GlobalValue::new()
+            },
+            // [type, addrspace, aliasee, linkage, visibility,
+            //  dllstorageclass, threadlocal, unnamed_addr, dso_local]
+            MODULE_CODE_ALIAS => GlobalValue {
+                kind: SymbolKind::Alias,
+                target: Some(field(2)),
+                linkage: field(3).into(),
+                visibility: field(4).into(),
+                dll_storage_class: dll_storage_class(5, 3),
+                thread_local: field(6) != 0,
+                dso_local: field(8) != 0,
+                ..GlobalValue::new()
+            },
+            // [type, aliasee, linkage, visibility, dllstorageclass,
+            //  threadlocal, unnamed_addr]
+            MODULE_CODE_ALIAS_OLD => GlobalValue {
+                kind: SymbolKind::Alias,
+                target: Some(field(1)),
+                linkage: field(2).into(),
+                visibility: field(3).into(),
+                dll_storage_class: dll_storage_class(4, 2),
+                thread_local: field(5) != 0,
+                ..GlobalValue::new()
+            },
+            // [type, addrspace, resolver, linkage, visibility, dso_local]
+            MODULE_CODE_IFUNC => GlobalValue {
+                kind: SymbolKind::IFunc,
+                target: Some(field(2)),
+                linkage: field(3).into(),
+                visibility: field(4).into(),
+                dso_local: field(5) != 0,
+                executable: true,
+                ..GlobalValue::new()
+            },
+            _ => return,
+        };
+        self.values.push((value, name));
+    }
+
+    /// Read the names of a module-level value symbol table
+    pub(crate) fn read_vst(&mut self, vst: &mut BlockIter<'_, '_>) -> Result<(), Error> {
+        while let Some(item) = vst.next()? {
+            if let BlockItem::Record(record) = item {
+                let record = record.into_record()?;
+                // [valueid, namechar x N] or [valueid, offset, namechar x N]
+                let skip = match record.id {
+                    VST_CODE_ENTRY => 1,
+                    VST_CODE_FNENTRY => 2,
+                    _ => continue,
+                };
+                let operands = record.operands();
+                // Since version 2 function entries only hold the offset of
+                // the function block
+                let name = operands.get(skip..).filter(|name| !name.is_empty());
+                if let (Some(&id), Some(name)) = (operands.first(), name) {
+                    let name = name.iter().map(|&c| c as u8).collect::<Vec<_>>();
+                    self.vst_names
+                        .push((id, String::from_utf8_lossy(&name).into_owned()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// IR names of the functions with a body, in the order of their function
+    /// blocks
+    pub(crate) fn function_bodies(&self, strtab: &[u8]) -> Vec<String> {
+        self.resolve(strtab)
+            .into_iter()
+            .filter(|value| value.kind == SymbolKind::Function && !value.declaration)
+            .map(|value| value.name)
+            .collect()
+    }
+
+    /// Name the global values and find which aliases refer to code
+    fn resolve(&self, strtab: &[u8]) -> Vec<GlobalValue> {
+        let mut values: Vec<GlobalValue> = self
+            .values
+            .iter()
+            .map(|(value, name)| {
+                let name = name
+                    .and_then(|(offset, size)| strtab.get(offset..offset.checked_add(size)?))
+                    .map(|name| String::from_utf8_lossy(name).into_owned());
+                GlobalValue {
+                    name: name.unwrap_or_default(),
+                    ..value.clone()
+                }
+            })
+            .collect();
+        for (id, name) in &self.vst_names {
+            if let Some(value) = usize::try_from(*id).ok().and_then(|id| values.get_mut(id)) {
+                value.name = name.clone();
+            }
+        }
+        // Aliases may point to other aliases, follow at most as many links
+        // as there are values to stop on cycles
+        for index in 0..values.len() {
+            if values[index].kind != SymbolKind::Alias {
+                continue;
+            }
+            let mut target = values[index].target;
+            for _ in 0..values.len() {
+                let aliasee = match target
+                    .and_then(|id| usize::try_from(id).ok())
+                    .and_then(|id| values.get(id))
+                {
+                    Some(aliasee) => aliasee,
+                    // Aliases of constant expressions are treated as data
+                    None => break,
+                };
+                if aliasee.kind == SymbolKind::Alias {
+                    target = aliasee.target;
+                } else {
+                    values[index].executable = aliasee.executable;
+                    break;
+                }
+            }
+        }
+        for value in &mut values {
+            // Implied by the linkage and visibility, like LLVM does
+            if value.linkage.is_local()
+                || (value.visibility != Visibility::Default
+                    && value.linkage != Linkage::ExternalWeak)
+            {
+                value.dso_local = true;
+            }
+        }
+        values
+    }
+}
+
+/// Read the symbols of a symbol table blob, `None` if it is malformed
+fn from_symtab(symtab: &[u8], strtab: &[u8], values: &[GlobalValue]) -> Option<Vec<Symbol>> {
+    let word = |offset: usize| -> Option<u32> {
+        let bytes = symtab.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    };
+    let string = |offset: usize| -> Option<String> {
+        let start = usize::try_from(word(offset)?).ok()?;
+        let size = usize::try_from(word(offset + 4)?).ok()?;
+        let bytes = strtab.get(start..start.checked_add(size)?)?;
+        Some(String::from_utf8_lossy(bytes).into_owned())
+    };
+    // Header: version, producer, modules, comdats, symbols, ...
+    let start = usize::try_from(word(28)?).ok()?;
+    let count = usize::try_from(word(32)?).ok()?;
+    let mut by_name = HashMap::new();
+    for value in values {
+        by_name.entry(value.name.as_str()).or_insert(value);
+    }
+    let mut symbols = Vec::new();
+    for index in 0..count {
+        // Symbol: name, IR name, comdat index, flags
+        let offset = start.checked_add(index.checked_mul(SYMTAB_SYMBOL_SIZE)?)?;
+        let name = string(offset)?;
+        let ir_name = Some(string(offset + 8)?).filter(|name| !name.is_empty());
+        let flags = word(offset + 20)?;
+        let value = ir_name
+            .as_deref()
+            .and_then(|ir_name| by_name.get(ir_name).copied());
+        let symbol = match value {
+            Some(value) => Symbol {
+                name,
+                ..value.to_symbol(None)
+            },
+            None => {
+                let linkage = if flags & FB_COMMON != 0 {
+                    Linkage::Common
+                } else if flags & FB_WEAK != 0 {
+                    if flags & FB_UNDEFINED != 0 {
+                        Linkage::ExternalWeak
+                    } else {
+                        Linkage::WeakAny
+                    }
+                } else if flags & FB_GLOBAL != 0 {
+                    Linkage::External
+                } else {
+                    Linkage::Internal
+                };
+                Symbol {
+                    name,
+                    kind: SymbolKind::Asm,
+                    linkage,
+                    visibility: Visibility::from(u64::from(flags & 3)),
+                    dll_storage_class: DllStorageClass::Default,
+                    dso_local: linkage.is_local(),
+                    thread_local: flags & FB_TLS != 0,
+                    constant: false,
+                    defined: flags & FB_UNDEFINED == 0,
+                    executable: flags & FB_EXECUTABLE != 0,
+                    format_specific: flags & FB_FORMAT_SPECIFIC != 0,
+                    ir_name,
+                }
+            }
+        };
+        symbols.push(symbol);
+    }
+    Some(symbols)
+}
+
+/// Mangle an IR name according to the mangling mode of the data layout
+fn mangle(name: &str, mangling: Option<u8>) -> String {
+    // Names starting with \1 are used verbatim
+    if let Some(name) = name.strip_prefix('\u{1}') {
+        return name.to_string();
+    }
+    match mangling {
+        // Mach-O and 32-bit x86 Windows prefix global symbols with `_`
+        Some(b'o') | Some(b'x') => format!("_{}", name),
+        _ => name.to_string(),
+    }
+}
@@ -0,0 +1,465 @@
+//! Cross-checking against LLVM's own bitcode reader
+//!
+//! [`validate`] summarizes a module from its records and compares the
+//! summary with the module loaded by libLLVM through its C API: target
+//! information, the functions and global variables with their names, the
+//! parameter counts of function types and the basic block and instruction
+//! counts of function bodies. A mismatch points at a bug in the bitstream
+//! reader, or at records whose meaning changed between LLVM versions.
+//!
+//! libLLVM is linked by the build script, set `LLVM_CONFIG` to pick the
+//! `llvm-config` of a particular installation.
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::fmt::{self, Debug};
+use std::os::raw::c_char;
+use std::{error, ptr, slice};
+
+use crate::bitcode::{Block, Payload};
+use crate::read;
+use crate::schema::blocks::BlockId;
+use crate::schema::codes::{
+    FUNC_CODE_DEBUG_LOC, FUNC_CODE_DEBUG_LOC_AGAIN, FUNC_CODE_DECLAREBLOCKS, MODULE_CODE_FUNCTION,
+    MODULE_CODE_GLOBALVAR, MODULE_CODE_VERSION, STRTAB_BLOB, TYPE_CODE_FUNCTION,
+    TYPE_CODE_FUNCTION_OLD, TYPE_CODE_NUMENTRY, TYPE_CODE_STRUCT_NAME,
+};
+use crate::{Bitcode, BitcodeInfo};
+
+/// Function block records that don't produce an instruction:
+/// DECLAREBLOCKS, DEBUG_LOC_AGAIN, DEBUG_LOC, OPERAND_BUNDLE,
+/// BLOCKADDR_USERS and the DEBUG_RECORD_* records of LLVM 19
+const NON_INSTRUCTION_CODES: [u64; 10] = [
+    FUNC_CODE_DECLAREBLOCKS,
+    FUNC_CODE_DEBUG_LOC_AGAIN,
+    FUNC_CODE_DEBUG_LOC,
+    55,
+    60,
+    61,
+    62,
+    63,
+    64,
+    65,
+];
+
+/// Errors validating a module
+#[derive(Debug, Clone)]
+pub enum Error {
+    /// This crate failed to read the bitcode
+    Read(read::Error),
+    /// LLVM failed to load the bitcode, with its error message
+    Llvm(String),
+    /// The bitcode is not an LLVM IR module
+    NotModule,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Read(err) => write!(f, "{}", err),
+            Error::Llvm(message) => write!(f, "LLVM failed to load the bitcode: {}", message),
+            Error::NotModule => write!(f, "not an LLVM IR module"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Read(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<read::Error> for Error {
+    fn from(err: read::Error) -> Self {
+        Error::Read(err)
+    }
+}
+
+/// A property of the module on which this crate and LLVM disagree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The property, e.g. `function 2 (main) instruction count`
+    pub property: String,
+    /// Value derived from the records read by this crate
+    pub parsed: String,
+    /// Value reported by LLVM
+    pub llvm: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: parsed {}, LLVM {}",
+            self.property, self.parsed, self.llvm
+        )
+    }
+}
+
+/// Compare the module in `data` as read by this crate and by LLVM
+///
+/// Returns the mismatches found, an empty list if both agree. Names are
+/// only compared for bitcode with a string table, written by LLVM 5 and
+/// later.
+pub fn validate(data: &[u8]) -> Result<Vec<Mismatch>, Error> {
+    let parsed = ModuleSummary::parse(data)?;
+    let llvm = unsafe { ModuleSummary::load(data)? };
+    let mut mismatches = Vec::new();
+    let mut check = |property: String, parsed: &dyn Debug, llvm: &dyn Debug| {
+        let (parsed, llvm) = (format!("{:?}", parsed), format!("{:?}", llvm));
+        if parsed != llvm {
+            mismatches.push(Mismatch {
+                property,
+                parsed,
+                llvm,
+            });
+        }
+    };
+    check("triple".into(), &parsed.triple, &llvm.triple);
+    check("data layout".into(), &parsed.data_layout, &llvm.data_layout);
+    // LLVM names the module after the buffer without a source file name
+    if parsed.source_filename.is_some() {
+        check(
+            "source file name".into(),
+            &parsed.source_filename,
+            &llvm.source_filename,
+        );
+    }
+    check(
+        "function count".into(),
+        &parsed.functions.len(),
+        &llvm.functions.len(),
+    );
+    for (index, (ours, theirs)) in parsed.functions.iter().zip(&llvm.functions).enumerate() {
+        let label = format!(
+            "function {} ({})",
+            index,
+            theirs.name.as_deref().unwrap_or("")
+        );
+        let property = |what: &str| format!("{} {}", label, what);
+        if parsed.has_names {
+            check(property("name"), &ours.name, &theirs.name);
+        }
+        check(
+            property("declaration"),
+            &ours.declaration,
+            &theirs.declaration,
+        );
+        check(property("parameter count"), &ours.params, &theirs.params);
+        check(property("vararg"), &ours.vararg, &theirs.vararg);
+        check(
+            property("basic block count"),
+            &ours.basic_blocks,
+            &theirs.basic_blocks,
+        );
+        check(
+            property("instruction count"),
+            &ours.instructions,
+            &theirs.instructions,
+        );
+    }
+    check(
+        "global variable count".into(),
+        &parsed.globals.len(),
+        &llvm.globals.len(),
+    );
+    if parsed.has_names {
+        for (index, (ours, theirs)) in parsed.globals.iter().zip(&llvm.globals).enumerate() {
+            check(format!("global variable {} name", index), ours, theirs);
+        }
+    }
+    Ok(mismatches)
+}
+
+#[derive(Debug, Default)]
+struct ModuleSummary {
+    triple: Option<String>,
+    data_layout: Option<String>,
+    source_filename: Option<String>,
+    /// Whether the names of functions and globals are known
+    has_names: bool,
+    functions: Vec<FunctionSummary>,
+    /// Names of the global variables
+    globals: Vec<Option<String>>,
+}
+
+#[derive(Debug, Default)]
+struct FunctionSummary {
+    name: Option<String>,
+    declaration: bool,
+    params: Option<usize>,
+    vararg: Option<bool>,
+    /// Zero for declarations
+    basic_blocks: usize,
+    instructions: usize,
+}
+
+impl ModuleSummary {
+    /// Summarize the records of the module
+    fn parse(data: &[u8]) -> Result<Self, Error> {
+        let bitcode = Bitcode::new(data)?;
+        if !bitcode.signature.is_llvm_ir() {
+            return Err(Error::NotModule);
+        }
+        let info = BitcodeInfo::peek(data)?;
+        let module = bitcode
+            .find_block(BlockId::Module)
+            .ok_or(Error::NotModule)?;
+        let strtab = bitcode
+            .find_block(BlockId::Strtab)
+            .and_then(|block| block.records(STRTAB_BLOB).last())
+            .and_then(|record| match &record.payload {
+                Some(Payload::Blob(blob)) => Some(&blob[..]),
+                _ => None,
+            });
+        // Since version 2 names are stored in the string table, records
+        // start with their offset and size
+        let version = module
+            .records(MODULE_CODE_VERSION)
+            .next()
+            .and_then(|record| record.fields.first().copied())
+            .unwrap_or(0);
+        let has_names = version >= 2 && strtab.is_some();
+        let name = |operands: &[u64]| -> Option<String> {
+            let strtab = strtab.filter(|_| has_names)?;
+            let start = usize::try_from(*operands.first()?).ok()?;
+            let size = usize::try_from(*operands.get(1)?).ok()?;
+            let bytes = strtab.get(start..start.checked_add(size)?)?;
+            Some(String::from_utf8_lossy(bytes).into_owned())
+        };
+        let skip = if version >= 2 { 2 } else { 0 };
+
+        // Parameter count and vararg flag of each function type, by type id
+        let mut types: Vec<Option<(usize, bool)>> = Vec::new();
+        for record in module
+            .blocks(BlockId::TypeNew)
+            .flat_map(|block| block.elements.iter())
+            .filter_map(|element| element.as_record())
+        {
+            let operands = record.operands();
+            types.push(match record.id {
+                TYPE_CODE_NUMENTRY | TYPE_CODE_STRUCT_NAME => continue,
+                // [vararg, retty, paramty...]
+                TYPE_CODE_FUNCTION if !operands.is_empty() => {
+                    Some((operands.len().saturating_sub(2), operands[0] != 0))
+                }
+                // [vararg, attrid, retty, paramty...]
+                TYPE_CODE_FUNCTION_OLD if !operands.is_empty() => {
+                    Some((operands.len().saturating_sub(3), operands[0] != 0))
+                }
+                _ => None,
+            });
+        }
+
+        let mut functions = Vec::new();
+        let mut globals = Vec::new();
+        for record in module
+            .elements
+            .iter()
+            .filter_map(|element| element.as_record())
+        {
+            let operands = record.operands();
+            match record.id {
+                MODULE_CODE_GLOBALVAR => globals.push(name(&operands)),
+                // [type, callingconv, isproto, ...] after the name
+                MODULE_CODE_FUNCTION => {
+                    let function_type = operands
+                        .get(skip)
+                        .and_then(|&id| usize::try_from(id).ok())
+                        .and_then(|id| types.get(id).copied().flatten());
+                    functions.push(FunctionSummary {
+                        name: name(&operands),
+                        declaration: operands.get(skip + 2).is_some_and(|&proto| proto != 0),
+                        params: function_type.map(|(params, _)| params),
+                        vararg: function_type.map(|(_, vararg)| vararg),
+                        ..FunctionSummary::default()
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        // Bodies are written in module order
+        let bodies = module.blocks(BlockId::Function);
+        for (function, body) in functions
+            .iter_mut()
+            .filter(|function| !function.declaration)
+            .zip(bodies)
+        {
+            summarize_body(function, body);
+        }
+        Ok(Self {
+            triple: info.triple.filter(|s| !s.is_empty()),
+            data_layout: info.data_layout.filter(|s| !s.is_empty()),
+            source_filename: info.source_filename.filter(|s| !s.is_empty()),
+            has_names,
+            functions,
+            globals,
+        })
+    }
+
+    /// Load the module with LLVM and summarize it
+    unsafe fn load(data: &[u8]) -> Result<Self, Error> {
+        let context = ffi::LLVMContextCreate();
+        let buffer = ffi::LLVMCreateMemoryBufferWithMemoryRange(
+            data.as_ptr() as *const c_char,
+            data.len(),
+            b"bitcode\0".as_ptr() as *const c_char,
+            0,
+        );
+        let mut module = ptr::null_mut();
+        let mut message = ptr::null_mut();
+        let failed = ffi::LLVMParseBitcodeInContext(context, buffer, &mut module, &mut message);
+        ffi::LLVMDisposeMemoryBuffer(buffer);
+        if failed != 0 {
+            let error = if message.is_null() {
+                String::new()
+            } else {
+                let error = CStr::from_ptr(message).to_string_lossy().into_owned();
+                ffi::LLVMDisposeMessage(message);
+                error
+            };
+            ffi::LLVMContextDispose(context);
+            return Err(Error::Llvm(error));
+        }
+
+        let string = |s: *const c_char| -> Option<String> {
+            if s.is_null() {
+                None
+            } else {
+                Some(CStr::from_ptr(s).to_string_lossy().into_owned())
+            }
+        };
+        let sized = |s: *const c_char, len: usize| -> Option<String> {
+            if s.is_null() {
+                None
+            } else {
+                let bytes = slice::from_raw_parts(s as *const u8, len);
+                Some(String::from_utf8_lossy(bytes).into_owned())
+            }
+        };
+        let name = |value: ffi::LLVMValueRef| {
+            let mut len = 0;
+            let name = ffi::LLVMGetValueName2(value, &mut len);
+            sized(name, len)
+        };
+        let mut len = 0;
+        let source_filename = ffi::LLVMGetSourceFileName(module, &mut len);
+        let mut summary = ModuleSummary {
+            triple: string(ffi::LLVMGetTarget(module)).filter(|s| !s.is_empty()),
+            data_layout: string(ffi::LLVMGetDataLayoutStr(module)).filter(|s| !s.is_empty()),
+            source_filename: sized(source_filename, len).filter(|s| !s.is_empty()),
+            has_names: true,
+            ..ModuleSummary::default()
+        };
+
+        let mut function = ffi::LLVMGetFirstFunction(module);
+        while !function.is_null() {
+            let function_type = ffi::LLVMGlobalGetValueType(function);
+            let mut instructions = 0;
+            let mut block = ffi::LLVMGetFirstBasicBlock(function);
+            while !block.is_null() {
+                let mut instruction = ffi::LLVMGetFirstInstruction(block);
+                while !instruction.is_null() {
+                    instructions += 1;
+                    instruction = ffi::LLVMGetNextInstruction(instruction);
+                }
+                block = ffi::LLVMGetNextBasicBlock(block);
+            }
+            summary.functions.push(FunctionSummary {
+                name: name(function),
+                declaration: ffi::LLVMIsDeclaration(function) != 0,
+                params: Some(ffi::LLVMCountParams(function) as usize),
+                vararg: Some(ffi::LLVMIsFunctionVarArg(function_type) != 0),
+                basic_blocks: ffi::LLVMCountBasicBlocks(function) as usize,
+                instructions,
+            });
+            function = ffi::LLVMGetNextFunction(function);
+        }
+        let mut global = ffi::LLVMGetFirstGlobal(module);
+        while !global.is_null() {
+            summary.globals.push(name(global));
+            global = ffi::LLVMGetNextGlobal(global);
+        }
+
+        ffi::LLVMDisposeModule(module);
+        ffi::LLVMContextDispose(context);
+        Ok(summary)
+    }
+}
+
+/// Count the basic blocks and instructions of a function block
+fn summarize_body(function: &mut FunctionSummary, body: &Block) {
+    for record in body
+        .elements
+        .iter()
+        .filter_map(|element| element.as_record())
+    {
+        if record.id == FUNC_CODE_DECLAREBLOCKS {
+            function.basic_blocks = record.fields.first().map_or(0, |&count| count as usize);
+        }
+        if !NON_INSTRUCTION_CODES.contains(&record.id) {
+            function.instructions += 1;
+        }
+    }
+}
+
+/// The parts of the LLVM C API used for validation
+#[allow(non_camel_case_types)]
+mod ffi {
+    use std::os::raw::{c_char, c_int, c_uint};
+
+    pub enum LLVMOpaqueContext {}
+    pub enum LLVMOpaqueMemoryBuffer {}
+    pub enum LLVMOpaqueModule {}
+    pub enum LLVMOpaqueValue {}
+    pub enum LLVMOpaqueBasicBlock {}
+    pub enum LLVMOpaqueType {}
+
+    pub type LLVMContextRef = *mut LLVMOpaqueContext;
+    pub type LLVMMemoryBufferRef = *mut LLVMOpaqueMemoryBuffer;
+    pub type LLVMModuleRef = *mut LLVMOpaqueModule;
+    pub type LLVMValueRef = *mut LLVMOpaqueValue;
+    pub type LLVMBasicBlockRef = *mut LLVMOpaqueBasicBlock;
+    pub type LLVMTypeRef = *mut LLVMOpaqueType;
+    pub type LLVMBool = c_int;
+
+    extern "C" {
+        pub fn LLVMContextCreate() -> LLVMContextRef;
+        pub fn LLVMContextDispose(context: LLVMContextRef);
+        pub fn LLVMCreateMemoryBufferWithMemoryRange(
+            data: *const c_char,
+            len: usize,
+            name: *const c_char,
+            requires_null_terminator: LLVMBool,
+        ) -> LLVMMemoryBufferRef;
+        pub fn LLVMDisposeMemoryBuffer(buffer: LLVMMemoryBufferRef);
+        pub fn LLVMParseBitcodeInContext(
+            context: LLVMContextRef,
+            buffer: LLVMMemoryBufferRef,
+            module: *mut LLVMModuleRef,
+            message: *mut *mut c_char,
+        ) -> LLVMBool;
+        pub fn LLVMDisposeMessage(message: *mut c_char);
+        pub fn LLVMDisposeModule(module: LLVMModuleRef);
+        pub fn LLVMGetTarget(module: LLVMModuleRef) -> *const c_char;
+        pub fn LLVMGetDataLayoutStr(module: LLVMModuleRef) -> *const c_char;
+        pub fn LLVMGetSourceFileName(module: LLVMModuleRef, len: *mut usize) -> *const c_char;
+        pub fn LLVMGetFirstFunction(module: LLVMModuleRef) -> LLVMValueRef;
+        pub fn LLVMGetNextFunction(function: LLVMValueRef) -> LLVMValueRef;
+        pub fn LLVMGetFirstGlobal(module: LLVMModuleRef) -> LLVMValueRef;
+        pub fn LLVMGetNextGlobal(global: LLVMValueRef) -> LLVMValueRef;
+        pub fn LLVMGetValueName2(value: LLVMValueRef, len: *mut usize) -> *const c_char;
+        pub fn LLVMIsDeclaration(global: LLVMValueRef) -> LLVMBool;
+        pub fn LLVMGlobalGetValueType(global: LLVMValueRef) -> LLVMTypeRef;
+        pub fn LLVMIsFunctionVarArg(function_type: LLVMTypeRef) -> LLVMBool;
+        pub fn LLVMCountParams(function: LLVMValueRef) -> c_uint;
+        pub fn LLVMCountBasicBlocks(function: LLVMValueRef) -> c_uint;
+        pub fn LLVMGetFirstBasicBlock(function: LLVMValueRef) -> LLVMBasicBlockRef;
+        pub fn LLVMGetNextBasicBlock(block: LLVMBasicBlockRef) -> LLVMBasicBlockRef;
+        pub fn LLVMGetFirstInstruction(block: LLVMBasicBlockRef) -> LLVMValueRef;
+        pub fn LLVMGetNextInstruction(instruction: LLVMValueRef) -> LLVMValueRef;
+    }
+}
@@ -1,4 +1,9 @@
+use std::ops::Range;
+
 use crate::bitcode::{BitcodeElement, Block, Record, Signature};
+use crate::bitstream::AbbrevInfo;
+use crate::read::{BlockContext, Error, Limit, RecordIter};
+use crate::schema::blocks::BlockId;
 use crate::BitStreamReader;
 
 /// A visitor which receives callbacks while reading a bitstream.
@@ -10,46 +15,1083 @@ pub trait BitStreamVisitor {
     /// Called when a new block is encountered. Return `true` to enter the block
     /// and read its contents, or `false` to skip it.
     fn should_enter_block(&mut self, id: u64) -> bool;
+    /// Called when a new block is encountered with the information read from
+    /// its header. Defaults to calling `should_enter_block` with the block id.
+    fn should_enter_block_with_context(&mut self, context: &BlockContext) -> bool {
+        self.should_enter_block(context.id)
+    }
+    /// Called when a new record is encountered, before its fields are decoded.
+    /// Return `true` to decode the record and pass it to `visit`, or `false`
+    /// to skip it.
+    ///
+    /// `abbrev_id` is the abbreviation id the record is encoded with, `3`
+    /// for unabbreviated records.
+    fn should_visit_record(&mut self, _block_id: u64, _record_id: u64, _abbrev_id: u64) -> bool {
+        true
+    }
     /// Called when a block is exited.
     fn did_exit_block(&mut self);
+    /// Called when an abbreviation is defined, before the item following
+    /// the definition.
+    ///
+    /// `block_id` is the block the abbreviation applies to: the enclosing
+    /// block for a local definition, or the block selected with `SETBID` for
+    /// a definition inside the BLOCKINFO block, in which case
+    /// `in_block_info` is `true` and the abbreviation applies to every
+    /// following block with that id. `abbrev.id` is the abbreviation id
+    /// records use to refer to it.
+    fn did_define_abbrev(&mut self, _block_id: u64, _abbrev: &AbbrevInfo, _in_block_info: bool) {}
+    /// Called whenever a record is encountered.
+    ///
+    /// Blob payloads borrow from the input, use [`Record::into_owned`] to
+    /// keep the record around.
+    fn visit(&mut self, record: Record<'_>);
+    /// Called whenever a record is encountered when reading with a reused
+    /// scratch record, see [`Bitcode::read_reusing`].
+    ///
+    /// The record is overwritten by the next one, so visitors that only
+    /// inspect records can override this to avoid allocating per record.
+    /// Defaults to passing a copy of the record to `visit`.
+    ///
+    /// [`Bitcode::read_reusing`]: crate::Bitcode::read_reusing
+    fn visit_ref(&mut self, record: &Record<'_>) {
+        self.visit(record.clone());
+    }
+}
+
+/// A visitor whose callbacks can fail, aborting the read.
+///
+/// Every [`BitStreamVisitor`] is also a `TryBitStreamVisitor` that never fails.
+/// Errors returned by the callbacks are propagated as soon as they occur;
+/// bitstream reader errors are converted into `Self::Error`.
+///
+/// Records passed to `visit` may borrow blob payloads from the `'input`
+/// buffer being read.
+pub trait TryBitStreamVisitor<'input> {
+    /// Error type returned by the callbacks
+    type Error: From<Error>;
+
+    /// Validate a bitstream's signature or "magic number".
+    fn validate(&self, _signature: Signature) -> bool {
+        true
+    }
+    /// Called when a new block is encountered. Return `true` to enter the block
+    /// and read its contents, or `false` to skip it.
+    fn should_enter_block(&mut self, id: u64) -> Result<bool, Self::Error>;
+    /// Called when a new block is encountered with the information read from
+    /// its header. Defaults to calling `should_enter_block` with the block id.
+    fn should_enter_block_with_context(
+        &mut self,
+        context: &BlockContext,
+    ) -> Result<bool, Self::Error> {
+        self.should_enter_block(context.id)
+    }
+    /// Called when a new record is encountered, before its fields are decoded.
+    /// See [`BitStreamVisitor::should_visit_record`].
+    fn should_visit_record(
+        &mut self,
+        _block_id: u64,
+        _record_id: u64,
+        _abbrev_id: u64,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+    /// Called when a block is exited.
+    fn did_exit_block(&mut self) -> Result<(), Self::Error>;
+    /// Called when an abbreviation is defined.
+    /// See [`BitStreamVisitor::did_define_abbrev`].
+    fn did_define_abbrev(
+        &mut self,
+        _block_id: u64,
+        _abbrev: &AbbrevInfo,
+        _in_block_info: bool,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
     /// Called whenever a record is encountered.
-    fn visit(&mut self, record: Record);
+    fn visit(&mut self, record: Record<'input>) -> Result<(), Self::Error>;
+    /// Called whenever a record is encountered when reading with a reused
+    /// scratch record. See [`BitStreamVisitor::visit_ref`].
+    fn visit_ref(&mut self, record: &Record<'input>) -> Result<(), Self::Error> {
+        self.visit(record.clone())
+    }
+}
+
+impl<'input, V: BitStreamVisitor> TryBitStreamVisitor<'input> for V {
+    type Error = Error;
+
+    fn validate(&self, signature: Signature) -> bool {
+        BitStreamVisitor::validate(self, signature)
+    }
+
+    fn should_enter_block(&mut self, id: u64) -> Result<bool, Self::Error> {
+        Ok(BitStreamVisitor::should_enter_block(self, id))
+    }
+
+    fn should_enter_block_with_context(
+        &mut self,
+        context: &BlockContext,
+    ) -> Result<bool, Self::Error> {
+        Ok(BitStreamVisitor::should_enter_block_with_context(
+            self, context,
+        ))
+    }
+
+    fn should_visit_record(
+        &mut self,
+        block_id: u64,
+        record_id: u64,
+        abbrev_id: u64,
+    ) -> Result<bool, Self::Error> {
+        Ok(BitStreamVisitor::should_visit_record(
+            self, block_id, record_id, abbrev_id,
+        ))
+    }
+
+    fn did_exit_block(&mut self) -> Result<(), Self::Error> {
+        BitStreamVisitor::did_exit_block(self);
+        Ok(())
+    }
+
+    fn did_define_abbrev(
+        &mut self,
+        block_id: u64,
+        abbrev: &AbbrevInfo,
+        in_block_info: bool,
+    ) -> Result<(), Self::Error> {
+        BitStreamVisitor::did_define_abbrev(self, block_id, abbrev, in_block_info);
+        Ok(())
+    }
+
+    fn visit(&mut self, record: Record<'input>) -> Result<(), Self::Error> {
+        BitStreamVisitor::visit(self, record);
+        Ok(())
+    }
+
+    fn visit_ref(&mut self, record: &Record<'input>) -> Result<(), Self::Error> {
+        BitStreamVisitor::visit_ref(self, record);
+        Ok(())
+    }
+}
+
+/// A visitor receiving records before their fields are decoded
+///
+/// Records are passed as a [`RecordIter`] decoding fields on demand, so
+/// visitors that ignore most records, or only need their first fields,
+/// don't pay for decoding the rest. Fields left unread are skipped. Read
+/// with [`Bitcode::read_lazy`](crate::Bitcode::read_lazy) or
+/// [`BitStreamReader::read_block_lazy`].
+pub trait LazyVisitor<'input> {
+    /// Error type returned by the callbacks
+    type Error: From<Error>;
+
+    /// Validate a bitstream's signature or "magic number".
+    fn validate(&self, _signature: Signature) -> bool {
+        true
+    }
+    /// Called when a new block is encountered with the information read from
+    /// its header. Return `true` to enter the block and read its contents, or
+    /// `false` to skip it. Defaults to entering every block.
+    fn should_enter_block_with_context(
+        &mut self,
+        _context: &BlockContext,
+    ) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+    /// Called when a block is exited.
+    fn did_exit_block(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    /// Called whenever a record is encountered inside the block `block_id`.
+    fn visit(&mut self, block_id: u64, record: RecordIter<'_, 'input>) -> Result<(), Self::Error>;
+}
+
+/// What a [`CollectingVisitor`] collects
+///
+/// Blocks left unparsed are kept with their header in
+/// [`Block::unparsed`] and no elements, see
+/// [`Bitcode::parse_block`](crate::Bitcode::parse_block). Everything is
+/// collected by default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CollectOptions {
+    /// Deepest blocks parsed, top level blocks being at depth 1. Deeper
+    /// blocks are left unparsed
+    pub max_depth: usize,
+    /// Ids of the blocks left unparsed
+    pub unparsed_blocks: Vec<u64>,
+    /// Maximum number of records collected, exceeding it fails the read
+    /// with [`Error::LimitExceeded`]
+    pub max_records: usize,
+}
+
+impl Default for CollectOptions {
+    fn default() -> Self {
+        Self {
+            max_depth: usize::MAX,
+            unparsed_blocks: Vec::new(),
+            max_records: usize::MAX,
+        }
+    }
 }
 
 /// A basic visitor that collects all the blocks and records in a stream.
-pub struct CollectingVisitor {
-    stack: Vec<(u64, Vec<BitcodeElement>)>,
+pub struct CollectingVisitor<'input> {
+    /// Blocks being read, never empty: the top level is never popped
+    stack: Vec<(u64, Option<Range<u64>>, Vec<BitcodeElement<'input>>)>,
+    options: CollectOptions,
+    records: usize,
 }
 
-impl CollectingVisitor {
+impl<'input> CollectingVisitor<'input> {
     pub fn new() -> Self {
+        Self::with_options(CollectOptions::default())
+    }
+
+    /// A visitor collecting only what `options` allows
+    pub fn with_options(options: CollectOptions) -> Self {
         Self {
-            stack: vec![(BitStreamReader::TOP_LEVEL_BLOCK_ID, Vec::new())],
+            stack: vec![(BitStreamReader::TOP_LEVEL_BLOCK_ID, None, Vec::new())],
+            options,
+            records: 0,
+        }
+    }
+
+    /// The top level elements collected
+    ///
+    /// Blocks left open, by a read that failed midway, are closed with the
+    /// elements read so far.
+    pub fn finalize_top_level_elements(mut self) -> Vec<BitcodeElement<'input>> {
+        while self.stack.len() > 1 {
+            self.close_block();
         }
+        self.stack
+            .pop()
+            .map(|(_, _, elements)| elements)
+            .unwrap_or_default()
     }
 
-    pub fn finalize_top_level_elements(mut self) -> Vec<BitcodeElement> {
-        assert_eq!(self.stack.len(), 1);
-        self.stack.pop().unwrap().1
+    /// Move the innermost block into its parent
+    fn close_block(&mut self) {
+        if self.stack.len() < 2 {
+            return;
+        }
+        if let Some((id, bit_range, elements)) = self.stack.pop() {
+            let block = Block {
+                id,
+                elements,
+                bit_range,
+                unparsed: None,
+            };
+            if let Some(last) = self.stack.last_mut() {
+                last.2.push(BitcodeElement::Block(block));
+            }
+        }
     }
 }
 
-impl BitStreamVisitor for CollectingVisitor {
-    fn should_enter_block(&mut self, id: u64) -> bool {
-        self.stack.push((id, Vec::new()));
+impl Default for CollectingVisitor<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'input> TryBitStreamVisitor<'input> for CollectingVisitor<'input> {
+    type Error = Error;
+
+    fn should_enter_block(&mut self, id: u64) -> Result<bool, Error> {
+        self.stack.push((id, None, Vec::new()));
+        Ok(true)
+    }
+
+    fn should_enter_block_with_context(&mut self, context: &BlockContext) -> Result<bool, Error> {
+        let depth = self.stack.len();
+        if depth > self.options.max_depth || self.options.unparsed_blocks.contains(&context.id) {
+            let block = Block {
+                id: context.id,
+                elements: Vec::new(),
+                bit_range: Some(context.bit_range()),
+                unparsed: Some(*context),
+            };
+            let last = self.stack.last_mut().unwrap();
+            last.2.push(BitcodeElement::Block(block));
+            return Ok(false);
+        }
+        self.stack
+            .push((context.id, Some(context.bit_range()), Vec::new()));
+        Ok(true)
+    }
+
+    fn did_exit_block(&mut self) -> Result<(), Error> {
+        self.close_block();
+        Ok(())
+    }
+
+    fn visit(&mut self, record: Record<'input>) -> Result<(), Error> {
+        self.records += 1;
+        if self.records > self.options.max_records {
+            return Err(Error::LimitExceeded(Limit::CollectedRecords));
+        }
+        let last = self.stack.last_mut().unwrap();
+        last.2.push(BitcodeElement::Record(record));
+        Ok(())
+    }
+}
+
+/// A visitor receiving typed LLVM IR block ids, with one callback per block kind.
+///
+/// Wrap it in a [`BlockIdAdapter`] to read a bitstream with it. Records are
+/// passed to [`BlockIdVisitor::visit_record`], which by default dispatches to
+/// the callback matching the enclosing block. Every callback does nothing by
+/// default.
+#[allow(unused_variables)]
+pub trait BlockIdVisitor {
+    /// Called when a new block is encountered. Return `true` to enter the block
+    /// and read its contents, or `false` to skip it.
+    fn should_enter_block(&mut self, id: BlockId) -> bool {
         true
     }
+    /// Called when a block is exited.
+    fn did_exit_block(&mut self, id: BlockId) {}
+    /// Called whenever a record is encountered inside the block `block_id`.
+    fn visit_record(&mut self, block_id: BlockId, record: Record) {
+        match block_id {
+            BlockId::Module => self.visit_module_record(record),
+            BlockId::ParamAttr => self.visit_param_attr_record(record),
+            BlockId::ParamAttrGroup => self.visit_param_attr_group_record(record),
+            BlockId::Constants => self.visit_constants_record(record),
+            BlockId::Function => self.visit_function_record(record),
+            BlockId::Identification => self.visit_identification_record(record),
+            BlockId::ValueSymtab => self.visit_value_symtab_record(record),
+            BlockId::Metadata => self.visit_metadata_record(record),
+            BlockId::MetadataAttachment => self.visit_metadata_attachment_record(record),
+            BlockId::TypeNew => self.visit_type_record(record),
+            BlockId::UseList => self.visit_use_list_record(record),
+            BlockId::ModuleStrtab => self.visit_module_strtab_record(record),
+            BlockId::GlobalValSummary | BlockId::FullLtoGlobalValSummary => {
+                self.visit_summary_record(block_id, record)
+            }
+            BlockId::OperandBundleTags => self.visit_operand_bundle_tags_record(record),
+            BlockId::MetadataKind => self.visit_metadata_kind_record(record),
+            BlockId::Strtab => self.visit_strtab_record(record),
+            BlockId::Symtab => self.visit_symtab_record(record),
+            BlockId::SyncScopeNames => self.visit_sync_scope_names_record(record),
+            BlockId::BlockInfo | BlockId::Unknown(_) => self.visit_unknown_record(block_id, record),
+        }
+    }
+    /// Called for records in a `MODULE_BLOCK`.
+    fn visit_module_record(&mut self, record: Record) {}
+    /// Called for records in a `PARAMATTR_BLOCK`.
+    fn visit_param_attr_record(&mut self, record: Record) {}
+    /// Called for records in a `PARAMATTR_GROUP_BLOCK`.
+    fn visit_param_attr_group_record(&mut self, record: Record) {}
+    /// Called for records in a `CONSTANTS_BLOCK`.
+    fn visit_constants_record(&mut self, record: Record) {}
+    /// Called for records in a `FUNCTION_BLOCK`.
+    fn visit_function_record(&mut self, record: Record) {}
+    /// Called for records in an `IDENTIFICATION_BLOCK`.
+    fn visit_identification_record(&mut self, record: Record) {}
+    /// Called for records in a `VALUE_SYMTAB_BLOCK`.
+    fn visit_value_symtab_record(&mut self, record: Record) {}
+    /// Called for records in a `METADATA_BLOCK`.
+    fn visit_metadata_record(&mut self, record: Record) {}
+    /// Called for records in a `METADATA_ATTACHMENT` block.
+    fn visit_metadata_attachment_record(&mut self, record: Record) {}
+    /// Called for records in a `TYPE_BLOCK_NEW`.
+    fn visit_type_record(&mut self, record: Record) {}
+    /// Called for records in a `USELIST_BLOCK`.
+    fn visit_use_list_record(&mut self, record: Record) {}
+    /// Called for records in a `MODULE_STRTAB_BLOCK`.
+    fn visit_module_strtab_record(&mut self, record: Record) {}
+    /// Called for records in a `GLOBALVAL_SUMMARY_BLOCK` or a
+    /// `FULL_LTO_GLOBALVAL_SUMMARY_BLOCK`.
+    fn visit_summary_record(&mut self, block_id: BlockId, record: Record) {}
+    /// Called for records in an `OPERAND_BUNDLE_TAGS_BLOCK`.
+    fn visit_operand_bundle_tags_record(&mut self, record: Record) {}
+    /// Called for records in a `METADATA_KIND_BLOCK`.
+    fn visit_metadata_kind_record(&mut self, record: Record) {}
+    /// Called for records in a `STRTAB_BLOCK`.
+    fn visit_strtab_record(&mut self, record: Record) {}
+    /// Called for records in a `SYMTAB_BLOCK`.
+    fn visit_symtab_record(&mut self, record: Record) {}
+    /// Called for records in a `SYNC_SCOPE_NAMES_BLOCK`.
+    fn visit_sync_scope_names_record(&mut self, record: Record) {}
+    /// Called for records in blocks unknown to this crate, including records
+    /// at the top level of the stream.
+    fn visit_unknown_record(&mut self, block_id: BlockId, record: Record) {}
+}
+
+/// Adapts a [`BlockIdVisitor`] into a [`BitStreamVisitor`].
+pub struct BlockIdAdapter<V> {
+    visitor: V,
+    stack: Vec<BlockId>,
+}
+
+impl<V: BlockIdVisitor> BlockIdAdapter<V> {
+    pub fn new(visitor: V) -> Self {
+        Self {
+            visitor,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Returns the wrapped visitor
+    pub fn into_inner(self) -> V {
+        self.visitor
+    }
+}
+
+impl<V: BlockIdVisitor> BitStreamVisitor for BlockIdAdapter<V> {
+    fn should_enter_block(&mut self, id: u64) -> bool {
+        let id = BlockId::from(id);
+        if self.visitor.should_enter_block(id) {
+            self.stack.push(id);
+            true
+        } else {
+            false
+        }
+    }
 
     fn did_exit_block(&mut self) {
-        if let Some((id, elements)) = self.stack.pop() {
-            let block = Block { id, elements };
-            let last = self.stack.last_mut().unwrap();
-            last.1.push(BitcodeElement::Block(block));
+        if let Some(id) = self.stack.pop() {
+            self.visitor.did_exit_block(id);
         }
     }
 
     fn visit(&mut self, record: Record) {
-        let last = self.stack.last_mut().unwrap();
-        last.1.push(BitcodeElement::Record(record));
+        let block_id = self
+            .stack
+            .last()
+            .copied()
+            .unwrap_or(BlockId::Unknown(BitStreamReader::TOP_LEVEL_BLOCK_ID));
+        self.visitor.visit_record(block_id, record);
+    }
+}
+
+/// A visitor receiving the path of enclosing block ids with every callback.
+///
+/// Wrap it in a [`PathTracker`] to read a bitstream with it. Paths start at
+/// the outermost block; the top level of the stream has an empty path.
+pub trait PathVisitor {
+    /// Called when a new block `id` is encountered inside the blocks in `path`.
+    /// Return `true` to enter the block and read its contents, or `false` to
+    /// skip it.
+    fn should_enter_block(&mut self, path: &[u64], id: u64) -> bool;
+    /// Called before a record inside the blocks in `path` is decoded.
+    /// See [`BitStreamVisitor::should_visit_record`].
+    fn should_visit_record(&mut self, _path: &[u64], _record_id: u64, _abbrev_id: u64) -> bool {
+        true
+    }
+    /// Called when the last block in `path` is exited.
+    fn did_exit_block(&mut self, path: &[u64]);
+    /// Called whenever a record is encountered inside the blocks in `path`.
+    fn visit(&mut self, path: &[u64], record: Record);
+}
+
+/// Adapts a [`PathVisitor`] into a [`BitStreamVisitor`] by maintaining the
+/// current block path.
+pub struct PathTracker<V> {
+    visitor: V,
+    path: Vec<u64>,
+}
+
+impl<V: PathVisitor> PathTracker<V> {
+    pub fn new(visitor: V) -> Self {
+        Self {
+            visitor,
+            path: Vec::new(),
+        }
+    }
+
+    /// Path of the blocks currently being read
+    pub fn path(&self) -> &[u64] {
+        &self.path
+    }
+
+    /// Returns the wrapped visitor
+    pub fn into_inner(self) -> V {
+        self.visitor
+    }
+}
+
+impl<V: PathVisitor> BitStreamVisitor for PathTracker<V> {
+    fn should_enter_block(&mut self, id: u64) -> bool {
+        if self.visitor.should_enter_block(&self.path, id) {
+            self.path.push(id);
+            true
+        } else {
+            false
+        }
+    }
+
+    fn should_visit_record(&mut self, _block_id: u64, record_id: u64, abbrev_id: u64) -> bool {
+        self.visitor
+            .should_visit_record(&self.path, record_id, abbrev_id)
+    }
+
+    fn did_exit_block(&mut self) {
+        self.visitor.did_exit_block(&self.path);
+        self.path.pop();
+    }
+
+    fn visit(&mut self, record: Record) {
+        self.visitor.visit(&self.path, record);
+    }
+}
+
+/// Dispatches callbacks to visitors registered by block id or block path.
+///
+/// A block matching a route is read by its visitor, which is asked whether
+/// to enter it and receives its records, nested blocks and exit. Blocks
+/// nested in it that match another route are read by that route's visitor
+/// instead. The first registered route matching a block is used. Records
+/// outside of routed blocks are skipped without being decoded, and blocks
+/// that can't contain a routed block are skipped.
+///
+/// ```
+/// use llvm_bitcode::bitcode::Record;
+/// use llvm_bitcode::read::Error;
+/// use llvm_bitcode::schema::blocks::BlockId;
+/// use llvm_bitcode::visitor::Router;
+/// use llvm_bitcode::{BitStreamVisitor, Bitcode};
+///
+/// #[derive(Default)]
+/// struct Counter(usize);
+///
+/// impl BitStreamVisitor for Counter {
+///     fn should_enter_block(&mut self, _id: u64) -> bool {
+///         true
+///     }
+///     fn did_exit_block(&mut self) {}
+///     fn visit(&mut self, _record: Record) {
+///         self.0 += 1;
+///     }
+/// }
+///
+/// fn count_records(data: &[u8]) -> Result<(usize, usize), Error> {
+///     let (mut functions, mut strtab) = (Counter::default(), Counter::default());
+///     let mut router = Router::new()
+///         .route(BlockId::Function, &mut functions)
+///         .route_path(&[BlockId::Strtab], &mut strtab);
+///     Bitcode::read(data, &mut router)?;
+///     Ok((functions.0, strtab.0))
+/// }
+/// ```
+pub struct Router<'a> {
+    routes: Vec<Route<'a>>,
+    /// Ids of the blocks being read, outermost first
+    path: Vec<u64>,
+    /// For every block being read, the route reading it
+    owners: Vec<Option<usize>>,
+}
+
+struct Route<'a> {
+    /// Block id, or full path from the top level
+    pattern: RoutePattern,
+    visitor: &'a mut dyn BitStreamVisitor,
+}
+
+enum RoutePattern {
+    Id(u64),
+    Path(Vec<u64>),
+}
+
+impl<'a> Router<'a> {
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            path: Vec::new(),
+            owners: Vec::new(),
+        }
+    }
+
+    /// Read every block with the id `id` with `visitor`
+    pub fn route(mut self, id: impl Into<u64>, visitor: &'a mut dyn BitStreamVisitor) -> Self {
+        self.routes.push(Route {
+            pattern: RoutePattern::Id(id.into()),
+            visitor,
+        });
+        self
+    }
+
+    /// Read the blocks reached by following `path` from the top level with
+    /// `visitor`, e.g. `&[BlockId::Module, BlockId::Function]` for every
+    /// function block
+    pub fn route_path<I>(mut self, path: &[I], visitor: &'a mut dyn BitStreamVisitor) -> Self
+    where
+        I: Copy + Into<u64>,
+    {
+        self.routes.push(Route {
+            pattern: RoutePattern::Path(path.iter().map(|&id| id.into()).collect()),
+            visitor,
+        });
+        self
+    }
+
+    /// Route reading the current block
+    fn owner(&mut self) -> Option<&mut (dyn BitStreamVisitor + 'a)> {
+        let index = (*self.owners.last()?)?;
+        Some(&mut *self.routes[index].visitor)
+    }
+
+    /// Decide whether to enter the block `id`, asking the visitor reading it
+    /// with `should_enter`
+    fn enter(
+        &mut self,
+        id: u64,
+        should_enter: impl FnOnce(&mut dyn BitStreamVisitor) -> bool,
+    ) -> bool {
+        let path = &self.path;
+        let routed = self.routes.iter().position(|route| match &route.pattern {
+            RoutePattern::Id(route_id) => *route_id == id,
+            RoutePattern::Path(route_path) => {
+                route_path.len() == path.len() + 1
+                    && route_path[..path.len()] == path[..]
+                    && route_path[path.len()] == id
+            }
+        });
+        let owner = routed.or_else(|| *self.owners.last()?);
+        let enter = match owner {
+            Some(index) => should_enter(&mut *self.routes[index].visitor),
+            // A routed block may be nested in it
+            None => self.routes.iter().any(|route| match &route.pattern {
+                RoutePattern::Id(_) => true,
+                RoutePattern::Path(route_path) => {
+                    route_path.len() > path.len() + 1
+                        && route_path[..path.len()] == path[..]
+                        && route_path[path.len()] == id
+                }
+            }),
+        };
+        if enter {
+            self.path.push(id);
+            self.owners.push(owner);
+        }
+        enter
+    }
+}
+
+impl Default for Router<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BitStreamVisitor for Router<'_> {
+    fn should_enter_block(&mut self, id: u64) -> bool {
+        self.enter(id, |visitor| visitor.should_enter_block(id))
+    }
+
+    fn should_enter_block_with_context(&mut self, context: &BlockContext) -> bool {
+        self.enter(context.id, |visitor| {
+            visitor.should_enter_block_with_context(context)
+        })
+    }
+
+    fn should_visit_record(&mut self, block_id: u64, record_id: u64, abbrev_id: u64) -> bool {
+        match self.owner() {
+            Some(visitor) => visitor.should_visit_record(block_id, record_id, abbrev_id),
+            None => false,
+        }
+    }
+
+    fn did_exit_block(&mut self) {
+        if let Some(visitor) = self.owner() {
+            visitor.did_exit_block();
+        }
+        self.path.pop();
+        self.owners.pop();
+    }
+
+    fn did_define_abbrev(&mut self, block_id: u64, abbrev: &AbbrevInfo, in_block_info: bool) {
+        if let Some(visitor) = self.owner() {
+            visitor.did_define_abbrev(block_id, abbrev, in_block_info);
+        }
+    }
+
+    fn visit(&mut self, record: Record<'_>) {
+        if let Some(visitor) = self.owner() {
+            visitor.visit(record);
+        }
+    }
+
+    fn visit_ref(&mut self, record: &Record<'_>) {
+        if let Some(visitor) = self.owner() {
+            visitor.visit_ref(record);
+        }
+    }
+}
+
+/// Forwards a single read of a stream to several visitors.
+///
+/// Every visitor is asked whether to enter a block, and the block is read
+/// if any of them enters it; visitors that declined it receive nothing
+/// until it is exited. Records are decoded once if any visitor reading
+/// their block wants them, and passed to each of those visitors, cloned
+/// for all but the last one. Callbacks are made in the order the visitors
+/// were added, and the first error aborts the read.
+///
+/// ```
+/// use llvm_bitcode::bitcode::{BitcodeElement, Record};
+/// use llvm_bitcode::read::Error;
+/// use llvm_bitcode::visitor::{CollectingVisitor, Tee};
+/// use llvm_bitcode::{BitStreamVisitor, Bitcode};
+///
+/// #[derive(Default)]
+/// struct Counter(usize);
+///
+/// impl BitStreamVisitor for Counter {
+///     fn should_enter_block(&mut self, _id: u64) -> bool {
+///         true
+///     }
+///     fn did_exit_block(&mut self) {}
+///     fn visit(&mut self, _record: Record) {
+///         self.0 += 1;
+///     }
+/// }
+///
+/// fn count_and_collect(data: &[u8]) -> Result<(usize, Vec<BitcodeElement>), Error> {
+///     let mut counter = Counter::default();
+///     let mut collector = CollectingVisitor::new();
+///     let mut tee = Tee::new().with(&mut counter).with(&mut collector);
+///     Bitcode::read(data, &mut tee)?;
+///     Ok((counter.0, collector.finalize_top_level_elements()))
+/// }
+/// ```
+pub struct Tee<'a, 'input> {
+    branches: Vec<Branch<'a, 'input>>,
+}
+
+struct Branch<'a, 'input> {
+    visitor: &'a mut dyn TryBitStreamVisitor<'input, Error = Error>,
+    /// Number of blocks being read that the visitor declined or that are
+    /// nested in one it declined, `0` while it reads the current block
+    skipped: usize,
+    /// Whether the visitor wants the record being read
+    wants_record: bool,
+}
+
+impl<'a, 'input> Tee<'a, 'input> {
+    pub fn new() -> Self {
+        Self {
+            branches: Vec::new(),
+        }
+    }
+
+    /// Also forward the stream to `visitor`
+    pub fn with(mut self, visitor: &'a mut dyn TryBitStreamVisitor<'input, Error = Error>) -> Self {
+        self.branches.push(Branch {
+            visitor,
+            skipped: 0,
+            wants_record: true,
+        });
+        self
+    }
+
+    /// Decide whether to enter a block, asking the visitors reading the
+    /// current block with `should_enter`
+    fn enter(
+        &mut self,
+        mut should_enter: impl FnMut(
+            &mut dyn TryBitStreamVisitor<'input, Error = Error>,
+        ) -> Result<bool, Error>,
+    ) -> Result<bool, Error> {
+        let mut entered = Vec::with_capacity(self.branches.len());
+        for branch in &mut self.branches {
+            entered.push(branch.skipped == 0 && should_enter(&mut *branch.visitor)?);
+        }
+        if !entered.contains(&true) {
+            return Ok(false);
+        }
+        for (branch, entered) in self.branches.iter_mut().zip(entered) {
+            if !entered {
+                branch.skipped += 1;
+            }
+        }
+        Ok(true)
+    }
+
+    /// Visitors that want the record being read
+    fn readers(&mut self) -> impl Iterator<Item = &mut Branch<'a, 'input>> {
+        self.branches
+            .iter_mut()
+            .filter(|branch| branch.skipped == 0 && branch.wants_record)
+    }
+}
+
+impl Default for Tee<'_, '_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'input> TryBitStreamVisitor<'input> for Tee<'_, 'input> {
+    type Error = Error;
+
+    fn validate(&self, signature: Signature) -> bool {
+        self.branches
+            .iter()
+            .all(|branch| branch.visitor.validate(signature))
+    }
+
+    fn should_enter_block(&mut self, id: u64) -> Result<bool, Error> {
+        self.enter(|visitor| visitor.should_enter_block(id))
+    }
+
+    fn should_enter_block_with_context(&mut self, context: &BlockContext) -> Result<bool, Error> {
+        self.enter(|visitor| visitor.should_enter_block_with_context(context))
+    }
+
+    fn should_visit_record(
+        &mut self,
+        block_id: u64,
+        record_id: u64,
+        abbrev_id: u64,
+    ) -> Result<bool, Error> {
+        let mut any = false;
+        for branch in &mut self.branches {
+            branch.wants_record = branch.skipped == 0
+                && branch
+                    .visitor
+                    .should_visit_record(block_id, record_id, abbrev_id)?;
+            any |= branch.wants_record;
+        }
+        Ok(any)
+    }
+
+    fn did_exit_block(&mut self) -> Result<(), Error> {
+        for branch in &mut self.branches {
+            if branch.skipped > 0 {
+                branch.skipped -= 1;
+            } else {
+                branch.visitor.did_exit_block()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn did_define_abbrev(
+        &mut self,
+        block_id: u64,
+        abbrev: &AbbrevInfo,
+        in_block_info: bool,
+    ) -> Result<(), Error> {
+        for branch in &mut self.branches {
+            if branch.skipped == 0 {
+                branch
+                    .visitor
+                    .did_define_abbrev(block_id, abbrev, in_block_info)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn visit(&mut self, record: Record<'input>) -> Result<(), Error> {
+        let mut readers = self.readers().peekable();
+        while let Some(branch) = readers.next() {
+            if readers.peek().is_some() {
+                branch.visitor.visit(record.clone())?;
+            } else {
+                return branch.visitor.visit(record);
+            }
+        }
+        Ok(())
+    }
+
+    fn visit_ref(&mut self, record: &Record<'input>) -> Result<(), Error> {
+        for branch in self.readers() {
+            branch.visitor.visit_ref(record)?;
+        }
+        Ok(())
+    }
+}
+
+/// Limits what a wrapped visitor reads, declaratively.
+///
+/// With [`blocks`](Self::blocks), only the blocks with the given ids and
+/// the blocks nested in them are passed to the visitor. Blocks outside of
+/// them are still read, as they may contain selected blocks, but the
+/// visitor isn't told about them and their records are skipped without
+/// being decoded. Blocks passed to [`skip_blocks`](Self::skip_blocks) are
+/// never read, and [`records`](Self::records) selects records by record id.
+/// The wrapped visitor's own callbacks still apply to what passes the
+/// filter.
+///
+/// ```
+/// use llvm_bitcode::bitcode::Record;
+/// use llvm_bitcode::read::Error;
+/// use llvm_bitcode::schema::blocks::BlockId;
+/// use llvm_bitcode::visitor::Filter;
+/// use llvm_bitcode::{BitStreamVisitor, Bitcode};
+///
+/// #[derive(Default)]
+/// struct Counter(usize);
+///
+/// impl BitStreamVisitor for Counter {
+///     fn should_enter_block(&mut self, _id: u64) -> bool {
+///         true
+///     }
+///     fn did_exit_block(&mut self) {}
+///     fn visit(&mut self, _record: Record) {
+///         self.0 += 1;
+///     }
+/// }
+///
+/// // Records of function and metadata blocks, except debug locations
+/// fn count_records(data: &[u8]) -> Result<usize, Error> {
+///     const DEBUG_LOC: u64 = 35;
+///     let mut filter = Filter::new(Counter::default())
+///         .blocks([BlockId::Function, BlockId::Metadata])
+///         .records(|id| id != DEBUG_LOC);
+///     Bitcode::read(data, &mut filter)?;
+///     Ok(filter.into_inner().0)
+/// }
+/// ```
+pub struct Filter<'a, V> {
+    visitor: V,
+    /// Ids of the blocks passed to the visitor, `None` for every block
+    blocks: Option<Vec<u64>>,
+    skipped_blocks: Vec<u64>,
+    records: Option<Box<dyn FnMut(u64) -> bool + 'a>>,
+    /// For every block being read, whether the visitor reads it
+    stack: Vec<bool>,
+}
+
+impl<'a, V> Filter<'a, V> {
+    pub fn new(visitor: V) -> Self {
+        Self {
+            visitor,
+            blocks: None,
+            skipped_blocks: Vec::new(),
+            records: None,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Only pass the blocks with these ids, and the blocks nested in them,
+    /// to the visitor
+    pub fn blocks<I>(mut self, ids: impl IntoIterator<Item = I>) -> Self
+    where
+        I: Into<u64>,
+    {
+        self.blocks = Some(ids.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Skip the blocks with these ids, wherever they are nested
+    pub fn skip_blocks<I>(mut self, ids: impl IntoIterator<Item = I>) -> Self
+    where
+        I: Into<u64>,
+    {
+        self.skipped_blocks.extend(ids.into_iter().map(Into::into));
+        self
+    }
+
+    /// Only pass the records whose record id `predicate` returns `true`
+    /// for to the visitor
+    pub fn records(mut self, predicate: impl FnMut(u64) -> bool + 'a) -> Self {
+        self.records = Some(Box::new(predicate));
+        self
+    }
+
+    /// Returns the wrapped visitor
+    pub fn into_inner(self) -> V {
+        self.visitor
+    }
+
+    /// Whether the visitor reads the current block
+    fn reading(&self) -> bool {
+        match self.stack.last() {
+            Some(&reading) => reading,
+            None => self.blocks.is_none(),
+        }
+    }
+}
+
+impl<'a, 'input, V: TryBitStreamVisitor<'input>> Filter<'a, V> {
+    /// Decide whether to enter the block `id`, asking the visitor with
+    /// `should_enter` if it reads the block
+    fn enter(
+        &mut self,
+        id: u64,
+        should_enter: impl FnOnce(&mut V) -> Result<bool, V::Error>,
+    ) -> Result<bool, V::Error> {
+        if self.skipped_blocks.contains(&id) {
+            return Ok(false);
+        }
+        let reading =
+            self.reading() || matches!(&self.blocks, Some(blocks) if blocks.contains(&id));
+        if reading && !should_enter(&mut self.visitor)? {
+            return Ok(false);
+        }
+        self.stack.push(reading);
+        Ok(true)
+    }
+}
+
+impl<'input, V: TryBitStreamVisitor<'input>> TryBitStreamVisitor<'input> for Filter<'_, V> {
+    type Error = V::Error;
+
+    fn validate(&self, signature: Signature) -> bool {
+        self.visitor.validate(signature)
+    }
+
+    fn should_enter_block(&mut self, id: u64) -> Result<bool, Self::Error> {
+        self.enter(id, |visitor| visitor.should_enter_block(id))
+    }
+
+    fn should_enter_block_with_context(
+        &mut self,
+        context: &BlockContext,
+    ) -> Result<bool, Self::Error> {
+        self.enter(context.id, |visitor| {
+            visitor.should_enter_block_with_context(context)
+        })
+    }
+
+    fn should_visit_record(
+        &mut self,
+        block_id: u64,
+        record_id: u64,
+        abbrev_id: u64,
+    ) -> Result<bool, Self::Error> {
+        if !self.reading() {
+            return Ok(false);
+        }
+        if let Some(predicate) = &mut self.records {
+            if !predicate(record_id) {
+                return Ok(false);
+            }
+        }
+        self.visitor
+            .should_visit_record(block_id, record_id, abbrev_id)
+    }
+
+    fn did_exit_block(&mut self) -> Result<(), Self::Error> {
+        if self.stack.pop() == Some(true) {
+            self.visitor.did_exit_block()?;
+        }
+        Ok(())
+    }
+
+    fn did_define_abbrev(
+        &mut self,
+        block_id: u64,
+        abbrev: &AbbrevInfo,
+        in_block_info: bool,
+    ) -> Result<(), Self::Error> {
+        // BLOCKINFO abbreviations may apply to blocks the visitor reads
+        if in_block_info || self.reading() {
+            self.visitor
+                .did_define_abbrev(block_id, abbrev, in_block_info)?;
+        }
+        Ok(())
+    }
+
+    fn visit(&mut self, record: Record<'input>) -> Result<(), Self::Error> {
+        self.visitor.visit(record)
+    }
+
+    fn visit_ref(&mut self, record: &Record<'input>) -> Result<(), Self::Error> {
+        self.visitor.visit_ref(record)
     }
 }
@@ -0,0 +1,280 @@
+//! Apple xar bitcode bundles
+//!
+//! Binaries built with `-fembed-bitcode` carry the bitcode of each object in
+//! a xar archive in their `__LLVM,__bundle` section. The table of contents of
+//! the archive also records how the objects were compiled and linked.
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use std::{error, fmt, str};
+
+use crate::bitcode::Signature;
+use crate::inflate;
+use crate::read;
+use crate::Bitcode;
+
+mod xml;
+
+/// Magic number of xar archives, `xar!`
+pub const XAR_MAGIC: u32 = 0x7861_7221;
+
+/// Size in bytes of the fixed part of the header
+const HEADER_SIZE: usize = 28;
+
+/// Errors reading a xar archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The header is missing or is not a xar header
+    InvalidHeader,
+    /// The table of contents can't be decompressed or parsed
+    InvalidToc,
+    /// A file is stored with an encoding other than zlib or none
+    UnsupportedEncoding(String),
+    /// The data of the file with this id is missing, out of bounds or corrupt
+    InvalidFile(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidHeader => write!(f, "invalid xar header"),
+            Error::InvalidToc => write!(f, "invalid xar table of contents"),
+            Error::UnsupportedEncoding(encoding) => {
+                write!(f, "unsupported xar file encoding `{}`", encoding)
+            }
+            Error::InvalidFile(id) => write!(f, "invalid data for xar file `{}`", id),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Kind of a file in a bitcode bundle
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FileType {
+    /// Bitcode of an object file
+    Bitcode,
+    /// An object file without bitcode, e.g. written by the assembler
+    Object,
+    /// A nested bundle, parse it with [`XarArchive::parse`]
+    Bundle,
+    /// Bitcode of an object built for link time optimization
+    Lto,
+    /// A file type unknown to this crate
+    Unknown(String),
+}
+
+impl From<&str> for FileType {
+    fn from(name: &str) -> Self {
+        match name {
+            "Bitcode" => FileType::Bitcode,
+            "Object" => FileType::Object,
+            "Bundle" => FileType::Bundle,
+            "LTO" => FileType::Lto,
+            _ => FileType::Unknown(name.to_string()),
+        }
+    }
+}
+
+/// Linker information of a bitcode bundle, from the `Ld` subdocument of
+/// the table of contents
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BundleMetadata {
+    /// Version of the bundle format
+    pub version: Option<String>,
+    pub architecture: Option<String>,
+    pub platform: Option<String>,
+    pub sdk_version: Option<String>,
+    /// Dynamic libraries linked against, paths relative to the SDK start
+    /// with `{SDKPATH}`
+    pub dylibs: Vec<String>,
+    /// Linker options
+    pub link_options: Vec<String>,
+}
+
+/// A file of a xar archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XarFile<'a> {
+    /// Id of the file in the table of contents
+    pub id: String,
+    /// Path of the file in the archive
+    pub name: String,
+    /// Kind of the file, `None` outside of bitcode bundles
+    pub file_type: Option<FileType>,
+    /// Compiler options the file was built with, without the compiler path
+    pub command_line: Vec<String>,
+    /// Contents of the file, borrowed from the archive unless compressed
+    pub data: Cow<'a, [u8]>,
+}
+
+impl XarFile<'_> {
+    /// Whether the contents of the file are LLVM IR bitcode
+    pub fn is_bitcode(&self) -> bool {
+        matches!(Signature::parse(&self.data), Ok((signature, _)) if signature.is_llvm_ir())
+    }
+
+    /// Parse the contents of the file as bitcode
+    pub fn bitcode(&self) -> Result<Bitcode<'_>, read::Error> {
+        Bitcode::new(&self.data)
+    }
+}
+
+/// A xar archive, such as an embedded bitcode bundle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XarArchive<'a> {
+    /// Linker information, empty unless the archive is a bitcode bundle
+    pub metadata: BundleMetadata,
+    /// Regular files, in table of contents order
+    pub files: Vec<XarFile<'a>>,
+}
+
+impl<'a> XarArchive<'a> {
+    /// Parse a xar archive from bytes
+    ///
+    /// Files are decompressed when they are stored with zlib, other
+    /// compressions are not supported.
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        let header = data.get(..HEADER_SIZE).ok_or(Error::InvalidHeader)?;
+        let u16_at = |offset: usize| u16::from_be_bytes([header[offset], header[offset + 1]]);
+        let u64_at = |offset: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&header[offset..offset + 8]);
+            u64::from_be_bytes(bytes)
+        };
+        let magic = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let header_size = usize::from(u16_at(4));
+        if magic != XAR_MAGIC || header_size < HEADER_SIZE {
+            return Err(Error::InvalidHeader);
+        }
+        let toc_length = usize::try_from(u64_at(8)).map_err(|_| Error::InvalidToc)?;
+        let toc_size = usize::try_from(u64_at(16)).map_err(|_| Error::InvalidToc)?;
+        let heap_start = header_size
+            .checked_add(toc_length)
+            .filter(|&end| end <= data.len())
+            .ok_or(Error::InvalidToc)?;
+        let toc = inflate::zlib_decompress(&data[header_size..heap_start], toc_size)
+            .ok_or(Error::InvalidToc)?;
+        let toc = str::from_utf8(&toc).map_err(|_| Error::InvalidToc)?;
+        let root = xml::parse(toc).ok_or(Error::InvalidToc)?;
+        if root.name != "xar" {
+            return Err(Error::InvalidToc);
+        }
+
+        let metadata = root
+            .children("subdoc")
+            .find(|subdoc| subdoc.attribute("subdoc_name") == Some("Ld"))
+            .map(read_metadata)
+            .unwrap_or_default();
+        let mut files = Vec::new();
+        let heap = &data[heap_start..];
+        for entry in root.child("toc").ok_or(Error::InvalidToc)?.children("file") {
+            read_files(entry, "", heap, &mut files)?;
+        }
+        Ok(Self { metadata, files })
+    }
+
+    /// Files holding bitcode, see [`XarFile::is_bitcode`]
+    pub fn bitcode_files(&self) -> impl Iterator<Item = &XarFile<'a>> {
+        self.files.iter().filter(|file| file.is_bitcode())
+    }
+}
+
+fn read_metadata(subdoc: &xml::Element) -> BundleMetadata {
+    let text = |name: &str| subdoc.child_text(name).map(str::to_string);
+    let list = |name: &str, item: &str| {
+        subdoc
+            .child(name)
+            .map(|list| {
+                list.children(item)
+                    .map(|item| item.text.trim().to_string())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+    BundleMetadata {
+        version: text("version"),
+        architecture: text("architecture"),
+        platform: text("platform"),
+        sdk_version: text("sdkversion"),
+        dylibs: list("dylibs", "lib"),
+        link_options: list("link-options", "option"),
+    }
+}
+
+/// Collect the regular files of a table of contents entry, recursing into
+/// directories
+fn read_files<'a>(
+    entry: &xml::Element,
+    parent: &str,
+    heap: &'a [u8],
+    files: &mut Vec<XarFile<'a>>,
+) -> Result<(), Error> {
+    let name = entry.child_text("name").unwrap_or_default();
+    let name = if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    };
+    match entry.child_text("type") {
+        Some("directory") => {
+            for child in entry.children("file") {
+                read_files(child, &name, heap, files)?;
+            }
+            Ok(())
+        }
+        Some("file") | None => {
+            let id = entry.attribute("id").unwrap_or_default().to_string();
+            let data = match entry.child("data") {
+                Some(data) => read_data(data, &id, heap)?,
+                None => Cow::Borrowed(&[][..]),
+            };
+            let command_line = entry
+                .child("clang")
+                .or_else(|| entry.child("swift"))
+                .map(|tool| {
+                    tool.children("cmd")
+                        .map(|cmd| cmd.text.trim().to_string())
+                        .collect()
+                })
+                .unwrap_or_default();
+            files.push(XarFile {
+                id,
+                name,
+                file_type: entry.child_text("file-type").map(FileType::from),
+                command_line,
+                data,
+            });
+            Ok(())
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+/// Read the contents of the file `id` from the heap
+fn read_data<'a>(data: &xml::Element, id: &str, heap: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+    let invalid = || Error::InvalidFile(id.to_string());
+    let number = |name: &str| -> Result<usize, Error> {
+        data.child_text(name)
+            .and_then(|text| text.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let offset = number("offset")?;
+    let length = number("length")?;
+    let archived = offset
+        .checked_add(length)
+        .and_then(|end| heap.get(offset..end))
+        .ok_or_else(invalid)?;
+    let encoding = data
+        .child("encoding")
+        .and_then(|encoding| encoding.attribute("style"))
+        .unwrap_or("application/octet-stream");
+    match encoding {
+        "application/octet-stream" => Ok(Cow::Borrowed(archived)),
+        // xar calls zlib streams gzip
+        "application/x-gzip" => {
+            let extracted =
+                inflate::zlib_decompress(archived, number("size")?).ok_or_else(invalid)?;
+            Ok(Cow::Owned(extracted))
+        }
+        _ => Err(Error::UnsupportedEncoding(encoding.to_string())),
+    }
+}
@@ -0,0 +1,204 @@
+//! A minimal XML reader for xar tables of contents
+//!
+//! Only elements, attributes, text and the predefined and numeric entities
+//! are supported. Processing instructions, comments, doctypes and CDATA
+//! sections are skipped.
+
+/// An element and everything nested in it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Element {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<Element>,
+    /// Text directly inside the element, concatenated
+    pub text: String,
+}
+
+impl Element {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    /// Trimmed text of a child element
+    pub fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|child| child.text.trim())
+    }
+}
+
+/// Parse a document, returning its root element
+pub(crate) fn parse(input: &str) -> Option<Element> {
+    let mut parser = Parser { input, pos: 0 };
+    // Open elements, the last one being the innermost
+    let mut stack: Vec<Element> = Vec::new();
+    loop {
+        let rest = &input[parser.pos..];
+        if rest.is_empty() {
+            return None;
+        }
+        if let Some(rest) = rest.strip_prefix('<') {
+            if rest.starts_with('?') {
+                parser.skip_past("?>")?;
+            } else if rest.starts_with("!--") {
+                parser.skip_past("-->")?;
+            } else if rest.starts_with("![CDATA[") {
+                parser.pos += "<![CDATA[".len();
+                let start = parser.pos;
+                parser.skip_past("]]>")?;
+                let text = &input[start..parser.pos - "]]>".len()];
+                stack.last_mut()?.text.push_str(text);
+            } else if rest.starts_with('!') {
+                parser.skip_past(">")?;
+            } else if rest.starts_with('/') {
+                parser.pos += 2;
+                let name = parser.name()?;
+                parser.skip_whitespace();
+                parser.expect(">")?;
+                let element = stack.pop()?;
+                if element.name != name {
+                    return None;
+                }
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Some(element),
+                }
+            } else {
+                parser.pos += 1;
+                let (element, closed) = parser.start_tag()?;
+                if !closed {
+                    stack.push(element);
+                } else {
+                    match stack.last_mut() {
+                        Some(parent) => parent.children.push(element),
+                        None => return Some(element),
+                    }
+                }
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = unescape(&rest[..end])?;
+            match stack.last_mut() {
+                Some(element) => element.text.push_str(&text),
+                // Only whitespace may surround the root element
+                None if text.trim().is_empty() => {}
+                None => return None,
+            }
+            parser.pos += end;
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_past(&mut self, pattern: &str) -> Option<()> {
+        let index = self.rest().find(pattern)?;
+        self.pos += index + pattern.len();
+        Some(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    fn expect(&mut self, pattern: &str) -> Option<()> {
+        if self.rest().starts_with(pattern) {
+            self.pos += pattern.len();
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn name(&mut self) -> Option<&'a str> {
+        let rest = self.rest();
+        let end = rest
+            .find(|c: char| c.is_whitespace() || matches!(c, '/' | '>' | '='))
+            .unwrap_or(rest.len());
+        if end == 0 {
+            return None;
+        }
+        self.pos += end;
+        Some(&rest[..end])
+    }
+
+    /// Read a start tag after its `<`, returning the element and whether it
+    /// was self-closing
+    fn start_tag(&mut self) -> Option<(Element, bool)> {
+        let mut element = Element {
+            name: self.name()?.to_string(),
+            ..Element::default()
+        };
+        loop {
+            self.skip_whitespace();
+            if self.expect("/>").is_some() {
+                return Some((element, true));
+            }
+            if self.expect(">").is_some() {
+                return Some((element, false));
+            }
+            let key = self.name()?.to_string();
+            self.skip_whitespace();
+            self.expect("=")?;
+            self.skip_whitespace();
+            let quote = self
+                .rest()
+                .chars()
+                .next()
+                .filter(|c| matches!(c, '"' | '\''))?;
+            self.pos += 1;
+            let end = self.rest().find(quote)?;
+            let value = unescape(&self.rest()[..end])?;
+            self.pos += end + 1;
+            element.attributes.push((key, value));
+        }
+    }
+}
+
+fn unescape(text: &str) -> Option<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let end = rest.find(';')?;
+        let entity = &rest[..end];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()?
+                } else {
+                    entity.strip_prefix('#')?.parse().ok()?
+                };
+                char::from_u32(code)?
+            }
+        };
+        result.push(c);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Some(result)
+}
@@ -1,7 +1,188 @@
+use std::borrow::Cow;
 use std::fs;
 
-use llvm_bitcode::bitcode::{BitcodeElement, Payload, Record};
-use llvm_bitcode::{BitStreamVisitor, Bitcode};
+use llvm_bitcode::archive::{self, Archive};
+use llvm_bitcode::arena::BitcodeArena;
+use llvm_bitcode::bitcode::{BitcodeElement, Block, NameTable, Payload, Record, Signature};
+use llvm_bitcode::bits::{char6_decode, char6_encode};
+use llvm_bitcode::bitstream::{AbbrevInfo, Operand};
+use llvm_bitcode::diff::{diff, Change, DecodedRecord, Difference};
+use llvm_bitcode::embedded::{self, CpuType, SectionKind};
+use llvm_bitcode::formats::clang_ast::{self, AstFile, ModuleKind};
+use llvm_bitcode::formats::diagnostics::{self, SerializedDiagnostics, Severity};
+use llvm_bitcode::formats::index_store::{self, DependencyKind, RecordFile, UnitFile};
+use llvm_bitcode::formats::{detect, FormatKind};
+use llvm_bitcode::json::{to_json, BlobEncoding, JsonOptions};
+use llvm_bitcode::read::{BlockContext, BlockItem, Error, Limit, ParseOptions, RecordIter};
+use llvm_bitcode::rlib::{self, decode_rust_object, rlib_bitcode};
+use llvm_bitcode::schema::blocks::BlockId;
+use llvm_bitcode::schema::decode;
+use llvm_bitcode::seekable::SeekableReader;
+use llvm_bitcode::size::{MemoryUsage, MetadataCategory, SizeReport};
+use llvm_bitcode::stream::{Event, Events, StreamEvent, StreamReader};
+use llvm_bitcode::symbols::{symbols, DllStorageClass, Linkage, Symbol, SymbolKind, Visibility};
+#[cfg(feature = "llvm-validation")]
+use llvm_bitcode::validate;
+use llvm_bitcode::visitor::{
+    BlockIdAdapter, BlockIdVisitor, CollectOptions, CollectingVisitor, Filter, LazyVisitor,
+    PathTracker, PathVisitor, Router, Tee,
+};
+use llvm_bitcode::{
+    bitstream_block, bitstream_record, BitStreamReader, BitStreamVisitor, Bitcode, BitcodeInfo,
+    TryBitStreamVisitor,
+};
+
+/// Writes bitstreams for tests that need input the fixtures don't cover
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bit: usize,
+    /// Byte offsets of the length words of the open blocks
+    blocks: Vec<usize>,
+}
+
+impl BitWriter {
+    /// A writer that has written the bitcode magic number
+    fn new() -> Self {
+        let mut writer = Self::default();
+        writer.write(0xdec04342, 32);
+        writer
+    }
+
+    fn write(&mut self, value: u64, width: usize) {
+        for i in 0..width {
+            if self.bit.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if value >> i & 1 == 1 {
+                *self.bytes.last_mut().unwrap() |= 1 << (self.bit % 8);
+            }
+            self.bit += 1;
+        }
+    }
+
+    fn write_vbr(&mut self, mut value: u64, width: usize) {
+        let threshold = 1 << (width - 1);
+        while value >= threshold {
+            self.write(value & (threshold - 1) | threshold, width);
+            value >>= width - 1;
+        }
+        self.write(value, width);
+    }
+
+    fn align32(&mut self) {
+        while !self.bit.is_multiple_of(32) {
+            self.write(0, 1);
+        }
+    }
+
+    fn enter_block(&mut self, abbrev_width: usize, id: u64, new_abbrev_width: usize) {
+        self.write(1, abbrev_width);
+        self.write_vbr(id, 8);
+        self.write_vbr(new_abbrev_width as u64, 4);
+        self.align32();
+        self.blocks.push(self.bytes.len());
+        self.write(0, 32);
+    }
+
+    fn end_block(&mut self, abbrev_width: usize) {
+        self.write(0, abbrev_width);
+        self.align32();
+        let start = self.blocks.pop().unwrap();
+        let length = ((self.bytes.len() - start - 4) / 4) as u32;
+        self.bytes[start..start + 4].copy_from_slice(&length.to_le_bytes());
+    }
+
+    fn define_abbrev(&mut self, abbrev_width: usize, operands: &[Operand]) {
+        self.write(2, abbrev_width);
+        let count = operands.iter().map(|op| 1 + op.is_array() as u64).sum();
+        self.write_vbr(count, 5);
+        for op in operands {
+            self.abbrev_op(op);
+        }
+    }
+
+    fn abbrev_op(&mut self, op: &Operand) {
+        match op {
+            Operand::Literal(value) => {
+                self.write(1, 1);
+                self.write_vbr(*value, 8);
+            }
+            Operand::Fixed(width) | Operand::Vbr(width) => {
+                self.write(0, 1);
+                self.write(op.encoded_kind().into(), 3);
+                self.write_vbr((*width).into(), 5);
+            }
+            Operand::Char6 | Operand::Blob => {
+                self.write(0, 1);
+                self.write(op.encoded_kind().into(), 3);
+            }
+            Operand::Array(element) => {
+                self.write(0, 1);
+                self.write(op.encoded_kind().into(), 3);
+                self.abbrev_op(element);
+            }
+        }
+    }
+
+    /// Write a record with an abbreviation of scalar operands, optionally
+    /// ending with a blob
+    fn abbreviated_record(
+        &mut self,
+        abbrev_width: usize,
+        abbrev_id: u64,
+        operands: &[Operand],
+        fields: &[u64],
+        blob: &[u8],
+    ) {
+        self.write(abbrev_id, abbrev_width);
+        let mut fields = fields.iter();
+        for op in operands {
+            match op {
+                Operand::Literal(_) => {}
+                Operand::Fixed(width) => self.write(*fields.next().unwrap(), (*width).into()),
+                Operand::Vbr(width) => self.write_vbr(*fields.next().unwrap(), (*width).into()),
+                Operand::Blob => {
+                    self.write_vbr(blob.len() as u64, 6);
+                    self.align32();
+                    for &byte in blob {
+                        self.write(byte.into(), 8);
+                    }
+                    self.align32();
+                }
+                Operand::Char6 | Operand::Array(_) => unimplemented!(),
+            }
+        }
+    }
+
+    fn unabbreviated_record(&mut self, abbrev_width: usize, code: u64, fields: &[u64]) {
+        self.write(3, abbrev_width);
+        self.write_vbr(code, 6);
+        self.write_vbr(fields.len() as u64, 6);
+        for &field in fields {
+            self.write_vbr(field, 6);
+        }
+    }
+}
+
+/// Assert that two blocks have the same records and bit ranges
+fn assert_same_block(read: &Block<'_>, expected: &Block<'_>) {
+    assert_eq!(read.id, expected.id);
+    assert_eq!(read.bit_range, expected.bit_range);
+    assert_eq!(read.elements.len(), expected.elements.len());
+    for (read, expected) in read.elements.iter().zip(&expected.elements) {
+        match (read, expected) {
+            (BitcodeElement::Block(read), BitcodeElement::Block(expected)) => {
+                assert_same_block(read, expected)
+            }
+            (BitcodeElement::Record(read), BitcodeElement::Record(expected)) => {
+                assert_eq!(DecodedRecord::from(read), DecodedRecord::from(expected));
+                assert_eq!(read.bit_range, expected.bit_range);
+            }
+            _ => panic!("element kinds differ"),
+        }
+    }
+}
 
 #[test]
 fn test_bitcode() {
@@ -143,3 +324,3145 @@ fn test_bitstream_reader() {
         ]
     )
 }
+
+#[test]
+fn test_bitcode_info_peek() {
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let info = BitcodeInfo::peek(&data).unwrap();
+    assert_eq!(info.triple.as_deref(), Some("x86_64-apple-macosx11.0.0"));
+    assert_eq!(
+        info.data_layout.as_deref(),
+        Some("e-m:o-p270:32:32-p271:32:32-p272:64:64-i64:64-f80:128-n8:16:32:64-S128")
+    );
+    assert_eq!(info.source_filename.as_deref(), Some("hello.c"));
+    assert_eq!(info.producer.as_deref(), Some("APPLE_1_1200.0.32.29_0"));
+    assert!(!info.has_summary);
+}
+
+#[test]
+fn test_block_iter() {
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let (_, mut reader) = Bitcode::reader(&data).unwrap();
+    let mut top_level = reader.iter_top_level();
+    let mut block_ids = Vec::new();
+    let mut target_triple = None;
+    while let Some(item) = top_level.next().unwrap() {
+        let mut block = match item {
+            BlockItem::Block(block) => block,
+            BlockItem::Record(_) => panic!("unexpected top level record"),
+        };
+        block_ids.push(block.id);
+        if block.id != 8 {
+            continue;
+        }
+        while let Some(item) = block.next().unwrap() {
+            match item {
+                BlockItem::Record(mut record) if record.id == 2 => {
+                    let mut bytes = Vec::new();
+                    while let Some(field) = record.next().unwrap() {
+                        bytes.push(field as u8);
+                    }
+                    assert!(record.payload().unwrap().is_none());
+                    target_triple = Some(String::from_utf8(bytes).unwrap());
+                }
+                // Partially read records and blocks are skipped
+                BlockItem::Record(mut record) => {
+                    record.next().unwrap();
+                }
+                BlockItem::Block(mut block) => {
+                    block.next().unwrap();
+                }
+            }
+        }
+    }
+    assert_eq!(block_ids, vec![13, 8, 25, 23]);
+    assert_eq!(target_triple.as_deref(), Some("x86_64-apple-macosx11.0.0"));
+}
+
+#[test]
+fn test_record_iter_typed_fields() {
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let (_, mut reader) = Bitcode::reader(&data).unwrap();
+    let mut top_level = reader.iter_top_level();
+    let mut strings = Vec::new();
+    while let Some(item) = top_level.next().unwrap() {
+        let mut block = match item {
+            BlockItem::Block(block) if block.id == 8 => block,
+            _ => continue,
+        };
+        while let Some(item) = block.next().unwrap() {
+            if let BlockItem::Record(mut record) = item {
+                match record.id {
+                    // MODULE_CODE_VERSION
+                    1 => {
+                        assert_eq!(record.u32().unwrap(), 2);
+                        assert!(matches!(record.u64(), Err(Error::MissingField(1))));
+                    }
+                    // MODULE_CODE_TRIPLE, MODULE_CODE_SOURCE_FILENAME
+                    2 | 16 => strings.push(record.string().unwrap()),
+                    // MODULE_CODE_VSTOFFSET
+                    13 => assert!(matches!(record.u8(), Err(Error::ValueOutOfRange(526)))),
+                    _ => {}
+                }
+            }
+        }
+    }
+    assert_eq!(strings, vec!["x86_64-apple-macosx11.0.0", "hello.c"]);
+}
+
+#[test]
+fn test_should_visit_record() {
+    #[derive(Default)]
+    struct SkippingVisitor {
+        asked: usize,
+        visited: Vec<u64>,
+    }
+
+    impl BitStreamVisitor for SkippingVisitor {
+        fn should_enter_block(&mut self, _id: u64) -> bool {
+            true
+        }
+
+        fn should_visit_record(&mut self, block_id: u64, record_id: u64, _abbrev_id: u64) -> bool {
+            self.asked += 1;
+            !(block_id == 9 && record_id == 2)
+        }
+
+        fn did_exit_block(&mut self) {}
+
+        fn visit(&mut self, record: Record) {
+            self.visited.push(record.id);
+        }
+    }
+
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    let mut visitor = SkippingVisitor::default();
+    Bitcode::read(&data, &mut visitor).unwrap();
+    assert_eq!(visitor.asked, 28);
+    assert_eq!(visitor.visited, vec![1, 6, 6, 7, 7, 6, 6, 7, 7, 6, 3]);
+}
+
+#[test]
+fn test_try_visitor_aborts() {
+    #[derive(Debug)]
+    enum VisitError {
+        Read(Error),
+        UnexpectedBlob(u64),
+    }
+
+    impl From<Error> for VisitError {
+        fn from(err: Error) -> Self {
+            Self::Read(err)
+        }
+    }
+
+    #[derive(Default)]
+    struct BlobRejectingVisitor(Vec<u64>);
+
+    impl TryBitStreamVisitor<'_> for BlobRejectingVisitor {
+        type Error = VisitError;
+
+        fn should_enter_block(&mut self, _id: u64) -> Result<bool, VisitError> {
+            Ok(true)
+        }
+
+        fn did_exit_block(&mut self) -> Result<(), VisitError> {
+            Ok(())
+        }
+
+        fn visit(&mut self, record: Record) -> Result<(), VisitError> {
+            if let Some(Payload::Blob(_)) = record.payload {
+                return Err(VisitError::UnexpectedBlob(record.id));
+            }
+            self.0.push(record.id);
+            Ok(())
+        }
+    }
+
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    let mut visitor = BlobRejectingVisitor::default();
+    let err = Bitcode::read(&data, &mut visitor).unwrap_err();
+    assert!(matches!(err, VisitError::UnexpectedBlob(6)));
+    assert_eq!(visitor.0, vec![1]);
+
+    let mut visitor = BlobRejectingVisitor::default();
+    let err = match Bitcode::read(&data[..64], &mut visitor).unwrap_err() {
+        VisitError::Read(err) => err,
+        VisitError::UnexpectedBlob(id) => panic!("unexpected blob in record {}", id),
+    };
+    assert!(matches!(
+        err.root_cause(),
+        Error::ReadBits(_) | Error::MissingEndBlock(_)
+    ));
+}
+
+#[test]
+fn test_record_abbrev_info() {
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    let bitcode = Bitcode::new(&data).unwrap();
+    let version_record = bitcode.elements[0].as_block().unwrap().elements[0]
+        .as_record()
+        .unwrap();
+    let abbrev = version_record.abbrev.as_ref().unwrap();
+    assert_eq!(abbrev.id, 4);
+    assert!(matches!(
+        abbrev.abbrev.operands[..],
+        [Operand::Literal(1), Operand::Fixed(32)]
+    ));
+
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let bitcode = Bitcode::new(&data).unwrap();
+    let module_block = bitcode.elements[1].as_block().unwrap();
+    assert_eq!(module_block.id, 8);
+    let triple_record = module_block
+        .elements
+        .iter()
+        .filter_map(|ele| ele.as_record())
+        .find(|record| record.id == 2)
+        .unwrap();
+    assert!(triple_record.abbrev.is_none());
+}
+
+#[test]
+fn test_block_context() {
+    struct ContextVisitor(Vec<BlockContext>);
+
+    impl BitStreamVisitor for ContextVisitor {
+        fn should_enter_block(&mut self, _id: u64) -> bool {
+            unreachable!()
+        }
+
+        fn should_enter_block_with_context(&mut self, context: &BlockContext) -> bool {
+            self.0.push(*context);
+            false
+        }
+
+        fn did_exit_block(&mut self) {}
+
+        fn visit(&mut self, _record: Record) {}
+    }
+
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let mut visitor = ContextVisitor(Vec::new());
+    Bitcode::read(&data, &mut visitor).unwrap();
+    let ids: Vec<u64> = visitor.0.iter().map(|context| context.id).collect();
+    assert_eq!(ids, vec![13, 8, 25, 23]);
+    assert_eq!(visitor.0[0].abbrev_width, 5);
+    assert_eq!(visitor.0[0].offset, 64);
+    // Each top level block header is 64 bits: a 2-bit abbreviation id, the
+    // block id and abbreviation width, 32-bit alignment and the length word
+    for pair in visitor.0.windows(2) {
+        assert_eq!(pair[0].end_offset() + 64, pair[1].offset);
+    }
+}
+
+#[test]
+fn test_block_id_adapter() {
+    #[derive(Default)]
+    struct CountingVisitor {
+        module_records: Vec<u64>,
+        strtab_records: usize,
+        exited: Vec<BlockId>,
+    }
+
+    impl BlockIdVisitor for CountingVisitor {
+        fn should_enter_block(&mut self, id: BlockId) -> bool {
+            matches!(id, BlockId::Module | BlockId::Strtab)
+        }
+
+        fn did_exit_block(&mut self, id: BlockId) {
+            self.exited.push(id);
+        }
+
+        fn visit_module_record(&mut self, record: Record) {
+            self.module_records.push(record.id);
+        }
+
+        fn visit_strtab_record(&mut self, _record: Record) {
+            self.strtab_records += 1;
+        }
+    }
+
+    assert_eq!(BlockId::from(12), BlockId::Function);
+    assert_eq!(BlockId::from(99), BlockId::Unknown(99));
+    assert_eq!(u64::from(BlockId::Module), 8);
+
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let mut adapter = BlockIdAdapter::new(CountingVisitor::default());
+    Bitcode::read(&data, &mut adapter).unwrap();
+    let visitor = adapter.into_inner();
+    assert_eq!(visitor.module_records, vec![1, 2, 3, 16, 8, 13]);
+    assert_eq!(visitor.strtab_records, 1);
+    assert_eq!(visitor.exited, vec![BlockId::Module, BlockId::Strtab]);
+}
+
+#[test]
+fn test_path_tracker() {
+    #[derive(Default)]
+    struct ConstantsVisitor {
+        module_constants: usize,
+        function_constants: usize,
+        exited: Vec<Vec<u64>>,
+    }
+
+    impl PathVisitor for ConstantsVisitor {
+        fn should_enter_block(&mut self, path: &[u64], id: u64) -> bool {
+            matches!((path, id), ([], 8) | ([8], 11) | ([8], 12) | ([8, 12], 11))
+        }
+
+        fn did_exit_block(&mut self, path: &[u64]) {
+            self.exited.push(path.to_vec());
+        }
+
+        fn visit(&mut self, path: &[u64], _record: Record) {
+            match path {
+                [8, 11] => self.module_constants += 1,
+                [8, 12, 11] => self.function_constants += 1,
+                _ => {}
+            }
+        }
+    }
+
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let mut tracker = PathTracker::new(ConstantsVisitor::default());
+    Bitcode::read(&data, &mut tracker).unwrap();
+    assert!(tracker.path().is_empty());
+    let visitor = tracker.into_inner();
+    assert!(visitor.module_constants > 0);
+    assert!(visitor.function_constants > 0);
+    assert_eq!(visitor.exited.last().unwrap(), &vec![8]);
+    assert!(visitor.exited.contains(&vec![8, 12, 11]));
+}
+
+#[test]
+fn test_block_info_names() {
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    let bitcode = Bitcode::new(&data).unwrap();
+    assert_eq!(bitcode.block_name(8), Some("Meta"));
+    assert_eq!(bitcode.block_name(9), Some("Diag"));
+    assert_eq!(bitcode.block_name(10), None);
+    assert_eq!(bitcode.record_name(9, 2), Some("DiagInfo"));
+    assert_eq!(bitcode.record_name(9, 1), None);
+
+    let annotated = format!("{:?}", bitcode.annotated());
+    assert!(annotated.starts_with(
+        "Bitcode { signature: Signature(1195460932), elements: [Block { id: 8, name: \"Meta\", \
+         elements: [Record { id: 1, name: \"Version\", fields: [1], payload: None }] }, \
+         Block { id: 9, name: \"Diag\", elements: [Record { id: 6, name: \"FileName\""
+    ));
+}
+
+#[test]
+fn test_search_helpers() {
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let bitcode = Bitcode::new(&data).unwrap();
+    let module_block = bitcode.find_block(BlockId::Module).unwrap();
+    assert_eq!(module_block.id, 8);
+    assert_eq!(module_block.records(2).count(), 1);
+    assert_eq!(
+        module_block.blocks(BlockId::TypeNew).count(),
+        bitcode.query(&[BlockId::Module, BlockId::TypeNew]).count()
+    );
+    assert_eq!(bitcode.find_block(12u64).unwrap().id, 12);
+    let function_constants = bitcode.query(&[8u64, 12, 11]).count();
+    assert!(function_constants > 0);
+    assert_eq!(bitcode.query(&[BlockId::Function]).count(), 0);
+    assert_eq!(bitcode.query::<u64>(&[]).count(), 0);
+    assert!(bitcode.find_block(99u64).is_none());
+}
+
+#[test]
+fn test_borrowed_blob_payloads() {
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    let bitcode = Bitcode::new(&data).unwrap();
+    let file_name = bitcode.find_block(9u64).unwrap().records(6).next().unwrap();
+    let blob = match &file_name.payload {
+        Some(Payload::Blob(Cow::Borrowed(blob))) => *blob,
+        other => panic!("unexpected payload {:?}", other),
+    };
+    assert_eq!(blob.len(), 100);
+    assert!(data.as_ptr_range().contains(&blob.as_ptr()));
+
+    let owned: Bitcode<'static> = bitcode.clone().into_owned();
+    drop(data);
+    let file_name = owned.find_block(9u64).unwrap().records(6).next().unwrap();
+    assert!(matches!(
+        &file_name.payload,
+        Some(Payload::Blob(Cow::Owned(blob))) if blob.len() == 100
+    ));
+}
+
+#[test]
+fn test_read_reusing() {
+    #[derive(Default)]
+    struct SummingVisitor {
+        records: usize,
+        fields: u64,
+        payloads: usize,
+    }
+
+    impl BitStreamVisitor for SummingVisitor {
+        fn should_enter_block(&mut self, _id: u64) -> bool {
+            true
+        }
+
+        fn did_exit_block(&mut self) {}
+
+        fn visit(&mut self, record: Record) {
+            BitStreamVisitor::visit_ref(self, &record);
+        }
+
+        fn visit_ref(&mut self, record: &Record) {
+            self.records += 1;
+            self.fields = self.fields.wrapping_add(record.fields.iter().sum::<u64>());
+            self.payloads += record.payload.is_some() as usize;
+        }
+    }
+
+    for fixture in ["tests/fixtures/simple.bc", "tests/fixtures/serialized.dia"] {
+        let data = fs::read(fixture).unwrap();
+        let mut owned = SummingVisitor::default();
+        Bitcode::read(&data, &mut owned).unwrap();
+        let mut reused = SummingVisitor::default();
+        Bitcode::read_reusing(&data, &mut reused).unwrap();
+        assert!(reused.records > 0);
+        assert_eq!(
+            (reused.records, reused.fields, reused.payloads),
+            (owned.records, owned.fields, owned.payloads)
+        );
+    }
+}
+
+#[test]
+fn test_bitcode_arena() {
+    for fixture in ["tests/fixtures/simple.bc", "tests/fixtures/serialized.dia"] {
+        let data = fs::read(fixture).unwrap();
+        let bitcode = Bitcode::new(&data).unwrap();
+        let arena = BitcodeArena::new(&data).unwrap();
+        assert_eq!(
+            format!("{:?}", arena.to_bitcode().elements),
+            format!("{:?}", bitcode.elements)
+        );
+    }
+
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let arena = BitcodeArena::new(&data).unwrap();
+    let top_level: Vec<u64> = arena
+        .elements()
+        .filter_map(|element| element.as_block())
+        .map(|block| block.id())
+        .collect();
+    assert_eq!(top_level, [13, 8, 25, 23]);
+    let module = arena.elements().nth(1).unwrap().as_block().unwrap();
+    let triple = module
+        .elements()
+        .filter_map(|element| element.as_record())
+        .find(|record| record.id() == 2)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(triple.fields().iter().map(|&x| x as u8).collect()).unwrap(),
+        "x86_64-apple-macosx11.0.0"
+    );
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_open_mapped() {
+    let mapped = Bitcode::open("tests/fixtures/simple.bc").unwrap();
+    let bitcode = mapped.parse().unwrap();
+    let module = bitcode.find_block(BlockId::Module).unwrap();
+    assert_eq!(module.records(2).count(), 1);
+    assert_eq!(
+        mapped.len(),
+        fs::read("tests/fixtures/simple.bc").unwrap().len()
+    );
+}
+
+#[test]
+fn test_stream_reader() {
+    fn flatten(elements: &[BitcodeElement], events: &mut Vec<String>) {
+        for element in elements {
+            match element {
+                BitcodeElement::Block(block) => {
+                    events.push(format!("enter {}", block.id));
+                    flatten(&block.elements, events);
+                    events.push("exit".to_string());
+                }
+                BitcodeElement::Record(record) => events.push(format!("{:?}", record)),
+            }
+        }
+    }
+
+    fn describe(event: StreamEvent) -> String {
+        match event {
+            StreamEvent::EnterBlock(context) => format!("enter {}", context.id),
+            StreamEvent::ExitBlock => "exit".to_string(),
+            StreamEvent::Record(record) => format!("{:?}", record),
+        }
+    }
+
+    for fixture in ["tests/fixtures/simple.bc", "tests/fixtures/serialized.dia"] {
+        let data = fs::read(fixture).unwrap();
+        let mut expected = Vec::new();
+        flatten(&Bitcode::new(&data).unwrap().elements, &mut expected);
+
+        // Feed one byte at a time, resuming after every `NeedMoreData`
+        let mut reader = StreamReader::new();
+        let mut input = data.iter();
+        let mut events = Vec::new();
+        loop {
+            match reader.next() {
+                Ok(Some(event)) => events.push(describe(event)),
+                Ok(None) => break,
+                Err(Error::NeedMoreData) => match input.next() {
+                    Some(byte) => reader.feed(&[*byte]),
+                    None => reader.finish(),
+                },
+                Err(err) => panic!("{}", err),
+            }
+        }
+        assert_eq!(events, expected);
+
+        let mut reader = StreamReader::new();
+        let mut input = &data[..];
+        let mut events = Vec::new();
+        while let Some(event) = reader.next_from(&mut input).unwrap() {
+            events.push(describe(event));
+        }
+        assert_eq!(events, expected);
+    }
+
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    let mut reader = StreamReader::new();
+    reader.feed(&data[..data.len() - 4]);
+    reader.finish();
+    let result = std::iter::from_fn(|| reader.next().transpose()).find(Result::is_err);
+    assert!(matches!(result, Some(Err(Error::ReadBits(_)))));
+}
+
+#[test]
+fn test_malformed_input_does_not_panic() {
+    fn parse_all(data: &[u8]) {
+        let _ = Bitcode::new(data);
+        let _ = BitcodeInfo::peek(data);
+        let mut reader = StreamReader::new();
+        reader.feed(data);
+        reader.finish();
+        while let Ok(Some(_)) = reader.next() {}
+    }
+
+    parse_all(&[]);
+    parse_all(&[0x42, 0x43]);
+    for fixture in ["tests/fixtures/simple.bc", "tests/fixtures/serialized.dia"] {
+        let data = fs::read(fixture).unwrap();
+        for len in (0..data.len()).step_by(7) {
+            parse_all(&data[..len]);
+        }
+        for index in 0..data.len() {
+            let mut data = data.clone();
+            data[index] ^= 0xff;
+            parse_all(&data);
+        }
+    }
+}
+
+#[test]
+fn test_misuse_does_not_panic() {
+    let mut writer = BitWriter::new();
+    writer.enter_block(2, 8, 3);
+    writer.unabbreviated_record(3, 1, &[2]);
+    writer.unabbreviated_record(3, 1, &[3]);
+    writer.end_block(3);
+    let data = &writer.bytes[4..];
+
+    let mut reader = BitStreamReader::new(data);
+    reader.read_signature().unwrap();
+    assert!(matches!(
+        reader.read_signature(),
+        Err(Error::SignatureNotAtStart)
+    ));
+
+    // Cut in the second record, the read fails inside the block
+    let mut reader = BitStreamReader::new(&data[..data.len() - 4]);
+    let mut visitor = CollectingVisitor::new();
+    assert!(reader
+        .read_block(BitStreamReader::TOP_LEVEL_BLOCK_ID, 2, &mut visitor)
+        .is_err());
+    let elements = visitor.finalize_top_level_elements();
+    assert_eq!(elements.len(), 1);
+    let block = elements[0].as_block().unwrap();
+    assert_eq!(block.id, 8);
+    assert_eq!(block.elements.len(), 1);
+}
+
+#[test]
+fn test_unknown_block_info_records_are_ignored() {
+    let mut writer = BitWriter::new();
+    writer.enter_block(2, 0, 2);
+    writer.unabbreviated_record(2, 1, &[8]);
+    writer.unabbreviated_record(2, 42, &[1, 2, 3]);
+    writer.unabbreviated_record(2, 2, &[b'M' as u64]);
+    writer.end_block(2);
+    writer.enter_block(2, 8, 3);
+    writer.unabbreviated_record(3, 1, &[7]);
+    writer.end_block(3);
+
+    let bitcode = Bitcode::new(&writer.bytes).unwrap();
+    assert_eq!(bitcode.block_info[&8].name, "M");
+    let block = bitcode.find_block(8u64).unwrap();
+    assert_eq!(block.records(1).next().unwrap().fields, [7]);
+}
+
+#[test]
+fn test_error_context() {
+    let mut writer = BitWriter::new();
+    writer.enter_block(2, 8, 3);
+    writer.enter_block(3, 12, 4);
+    writer.unabbreviated_record(4, 1, &[2]);
+    let item_offset = writer.bit as u64 - 32;
+    // Abbreviated record without any abbreviation defined
+    writer.write(4, 4);
+    writer.end_block(4);
+    writer.end_block(3);
+
+    let err = Bitcode::new(&writer.bytes).unwrap_err();
+    assert!(matches!(
+        err.root_cause(),
+        Error::NoSuchAbbrev {
+            block_id: 12,
+            abbrev_id: 4
+        }
+    ));
+    let context = err.context().unwrap();
+    assert_eq!(context.block_path, [8, 12]);
+    assert_eq!(context.offset, item_offset);
+    assert!(context.record.is_none());
+    assert!(std::error::Error::source(&err).is_some());
+    assert!(err.to_string().contains("block path [8, 12]"));
+
+    // A record cut short reports the record being decoded
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    let err = Bitcode::new(&data[..400]).unwrap_err();
+    let context = err.context().unwrap();
+    assert!(!context.block_path.is_empty());
+    assert!(matches!(context.error, Error::ReadBits(_)));
+}
+
+#[test]
+fn test_parse_options_limits() {
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    assert!(Bitcode::new_with_options(&data, ParseOptions::default()).is_ok());
+
+    let limited = |options: ParseOptions| {
+        let err = Bitcode::new_with_options(&data, options).unwrap_err();
+        match err.root_cause() {
+            Error::LimitExceeded(limit) => *limit,
+            err => panic!("unexpected error {}", err),
+        }
+    };
+    let options = ParseOptions {
+        max_block_depth: 2,
+        ..Default::default()
+    };
+    assert_eq!(limited(options), Limit::BlockDepth);
+    let options = ParseOptions {
+        max_record_fields: 4,
+        ..Default::default()
+    };
+    assert_eq!(limited(options), Limit::RecordFields);
+    let options = ParseOptions {
+        max_payload_len: 8,
+        ..Default::default()
+    };
+    assert_eq!(limited(options), Limit::PayloadLength);
+    let options = ParseOptions {
+        max_decoded_elements: 100,
+        ..Default::default()
+    };
+    assert_eq!(limited(options), Limit::DecodedElements);
+
+    let mut reader = StreamReader::new();
+    reader.set_options(ParseOptions {
+        max_block_depth: 1,
+        ..Default::default()
+    });
+    reader.feed(&data);
+    reader.finish();
+    let err = std::iter::from_fn(|| reader.next().transpose())
+        .find_map(Result::err)
+        .unwrap();
+    assert!(matches!(err, Error::LimitExceeded(Limit::BlockDepth)));
+}
+
+#[test]
+fn test_deeply_nested_blocks() {
+    let nested = |depth: usize| {
+        let mut writer = BitWriter::new();
+        for _ in 0..depth {
+            writer.enter_block(2, 100, 2);
+        }
+        for _ in 0..depth {
+            writer.end_block(2);
+        }
+        writer.bytes
+    };
+    let block_depth = |result: Result<_, Error>| match result {
+        Err(err) => matches!(err.root_cause(), Error::LimitExceeded(Limit::BlockDepth)),
+        Ok(_) => false,
+    };
+
+    let data = nested(ParseOptions::DEFAULT_MAX_BLOCK_DEPTH);
+    assert!(Bitcode::new(&data).is_ok());
+    assert!(BitcodeArena::new(&data).is_ok());
+    assert!(SizeReport::new(&data).is_ok());
+    assert!(to_json(&data, &JsonOptions::default()).is_ok());
+
+    let data = nested(200_000);
+    assert!(block_depth(Bitcode::new(&data).map(drop)));
+    assert!(block_depth(BitcodeArena::new(&data).map(drop)));
+    assert!(block_depth(SizeReport::new(&data).map(drop)));
+    assert!(block_depth(
+        to_json(&data, &JsonOptions::default()).map(drop)
+    ));
+    let (_, diagnostics) = Bitcode::new_recovering(&data).unwrap();
+    assert!(!diagnostics.is_empty());
+}
+
+#[test]
+fn test_recovering_parse() {
+    let mut writer = BitWriter::new();
+    writer.enter_block(2, 8, 3);
+    writer.enter_block(3, 12, 4);
+    writer.unabbreviated_record(4, 1, &[2]);
+    // Abbreviated record without any abbreviation defined
+    writer.write(4, 4);
+    writer.unabbreviated_record(4, 2, &[3]);
+    writer.end_block(4);
+    writer.unabbreviated_record(3, 3, &[4]);
+    writer.end_block(3);
+    writer.enter_block(2, 9, 3);
+    writer.unabbreviated_record(3, 5, &[6]);
+    writer.end_block(3);
+
+    assert!(Bitcode::new(&writer.bytes).is_err());
+    let (bitcode, diagnostics) = Bitcode::new_recovering(&writer.bytes).unwrap();
+    assert_eq!(diagnostics.len(), 1);
+    assert!(matches!(
+        diagnostics[0].root_cause(),
+        Error::NoSuchAbbrev {
+            block_id: 12,
+            abbrev_id: 4
+        }
+    ));
+    assert_eq!(diagnostics[0].context().unwrap().block_path, [8, 12]);
+
+    let blocks: Vec<_> = bitcode
+        .elements
+        .iter()
+        .map(|element| element.as_block().unwrap())
+        .collect();
+    assert_eq!(blocks.len(), 2);
+    let outer = blocks[0];
+    assert_eq!(outer.id, 8);
+    assert_eq!(outer.elements.len(), 2);
+    let inner = outer.elements[0].as_block().unwrap();
+    assert_eq!(inner.id, 12);
+    assert_eq!(inner.elements.len(), 1);
+    assert_eq!(inner.elements[0].as_record().unwrap().fields, [2]);
+    assert_eq!(outer.elements[1].as_record().unwrap().id, 3);
+    assert_eq!(blocks[1].id, 9);
+    assert_eq!(blocks[1].elements[0].as_record().unwrap().fields, [6]);
+
+    // Well-formed input reads the same and reports nothing
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let (recovered, diagnostics) = Bitcode::new_recovering(&data).unwrap();
+    assert!(diagnostics.is_empty());
+    assert_eq!(
+        format!("{:?}", recovered.elements),
+        format!("{:?}", Bitcode::new(&data).unwrap().elements)
+    );
+}
+
+#[test]
+fn test_block_length_past_end() {
+    // A block declaring the largest possible length, ending far past the
+    // end of the input and past 2^32 bits
+    let mut writer = BitWriter::new();
+    writer.enter_block(2, 8, 3);
+    let start = writer.blocks.pop().unwrap();
+    writer.bytes[start..start + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+    writer.unabbreviated_record(3, 1, &[2]);
+
+    let (_, mut reader) = Bitcode::reader(&writer.bytes).unwrap();
+    let mut top_level = reader.iter_top_level();
+    match top_level.next().unwrap() {
+        Some(BlockItem::Block(block)) => {
+            let context = block.context();
+            assert_eq!(context.length, u32::MAX as usize);
+            assert_eq!(
+                context.end_offset(),
+                context.offset + u64::from(u32::MAX) * 32
+            );
+        }
+        _ => panic!("expected a block"),
+    }
+    // Skipping the block seeks past the end of the input
+    let err = top_level.next().unwrap_err();
+    assert!(matches!(err.root_cause(), Error::ReadBits(_)));
+    assert!(Bitcode::new(&writer.bytes).is_err());
+}
+
+/// Relies on zeroed allocations being lazily committed, so that the stream
+/// doesn't take its whole length in memory
+#[cfg(all(unix, target_pointer_width = "64"))]
+#[test]
+fn test_stream_larger_than_4gib() {
+    const BLOB_LEN: usize = (9 << 30) / 2;
+
+    let mut header = BitWriter::new();
+    header.enter_block(2, 8, 3);
+    // Abbreviation [literal 1, blob]
+    header.write(2, 3);
+    header.write_vbr(2, 5);
+    header.write(1, 1);
+    header.write_vbr(1, 8);
+    header.write(0, 1);
+    header.write(5, 3);
+    header.write(4, 3);
+    header.write_vbr(BLOB_LEN as u64, 6);
+    header.align32();
+    let mut trailer = BitWriter::default();
+    trailer.unabbreviated_record(3, 2, &[7]);
+    trailer.write(0, 3);
+    trailer.align32();
+
+    let len = header.bytes.len() + BLOB_LEN + trailer.bytes.len();
+    let mut data = vec![0u8; len];
+    data[..header.bytes.len()].copy_from_slice(&header.bytes);
+    data[len - trailer.bytes.len()..].copy_from_slice(&trailer.bytes);
+    let length_start = header.blocks[0];
+    let length = ((len - length_start - 4) / 4) as u32;
+    data[length_start..length_start + 4].copy_from_slice(&length.to_le_bytes());
+
+    let bitcode = Bitcode::new(&data).unwrap();
+    let block = bitcode.elements[0].as_block().unwrap();
+    match &block.elements[0].as_record().unwrap().payload {
+        Some(Payload::Blob(blob)) => assert_eq!(blob.len(), BLOB_LEN),
+        _ => panic!("expected a blob"),
+    }
+    assert_eq!(block.elements[1].as_record().unwrap().fields, [7]);
+
+    let (_, mut reader) = Bitcode::reader(&data).unwrap();
+    let mut top_level = reader.iter_top_level();
+    let mut block = match top_level.next().unwrap() {
+        Some(BlockItem::Block(block)) => block,
+        _ => panic!("expected a block"),
+    };
+    assert_eq!(block.context().end_offset(), (len as u64 - 4) * 8);
+    // Skip the blob record to read the record after it
+    block.next().unwrap();
+    match block.next().unwrap() {
+        Some(BlockItem::Record(record)) => assert_eq!(record.id, 2),
+        _ => panic!("expected a record"),
+    }
+    assert!(block.next().unwrap().is_none());
+}
+
+#[test]
+fn test_serialized_diagnostics() {
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    let diagnostics = SerializedDiagnostics::new(&data).unwrap();
+    assert_eq!(diagnostics.version, 1);
+    assert_eq!(diagnostics.files.len(), 5);
+    assert_eq!(diagnostics.diagnostics.len(), 17);
+
+    let first = &diagnostics.diagnostics[0];
+    assert_eq!(first.severity, Severity::Error);
+    assert_eq!(first.message, "expected member name following '.'");
+    assert!(first.children.is_empty());
+    assert!(diagnostics.category(first).is_none());
+    let location = diagnostics.resolve(&first.location).unwrap();
+    assert!(location
+        .file
+        .name
+        .ends_with("/ItemDetailViewController.swift"));
+    assert!(location
+        .to_string()
+        .ends_with("ItemDetailViewController.swift:53:28"));
+
+    let fix_it = &diagnostics.diagnostics[5].fix_its[0];
+    assert_eq!(fix_it.text, ",");
+    assert_eq!(fix_it.range.start.line, 21);
+    assert_eq!(fix_it.range.start.column, 69);
+
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    assert!(matches!(
+        SerializedDiagnostics::new(&data),
+        Err(Error::InvalidSignature(_))
+    ));
+}
+
+/// Fields of a string stored as its length followed by its characters
+fn string_fields(s: &str) -> Vec<u64> {
+    std::iter::once(s.len() as u64)
+        .chain(s.bytes().map(u64::from))
+        .collect()
+}
+
+#[test]
+fn test_clang_ast_file() {
+    let mut writer = BitWriter::default();
+    writer.write(u64::from(clang_ast::AST_MAGIC), 32);
+    writer.enter_block(2, 15, 3);
+    let metadata = [
+        Operand::Literal(1),
+        Operand::Fixed(16),
+        Operand::Fixed(16),
+        Operand::Fixed(16),
+        Operand::Fixed(16),
+        Operand::Fixed(1),
+        Operand::Fixed(1),
+        Operand::Fixed(1),
+        Operand::Fixed(1),
+        Operand::Blob,
+    ];
+    writer.define_abbrev(3, &metadata);
+    writer.abbreviated_record(
+        3,
+        4,
+        &metadata,
+        &[16, 0, 14, 0, 1, 1, 0, 0],
+        b"clang version 14.0.0",
+    );
+    let chars = |s: &str| s.bytes().map(u64::from).collect::<Vec<_>>();
+    writer.unabbreviated_record(3, 7, &chars("Foo"));
+    writer.unabbreviated_record(3, 9, &chars("/src/foo"));
+    let mut import = vec![1, 0, 0, 0];
+    import.extend(1..=20);
+    import.extend(string_fields("Bar"));
+    import.extend(string_fields("Bar.pcm"));
+    writer.unabbreviated_record(3, 2, &import);
+    writer.enter_block(3, 17, 3);
+    let mut target = string_fields("x86_64-unknown-linux-gnu");
+    target.extend(string_fields("x86-64"));
+    writer.unabbreviated_record(3, 2, &target);
+    writer.end_block(3);
+    writer.enter_block(3, 16, 3);
+    let mut input_file = vec![1, 100, 0, 0, 0];
+    input_file.extend(chars("foo.h"));
+    writer.unabbreviated_record(3, 1, &input_file);
+    writer.end_block(3);
+    writer.end_block(3);
+    writer.enter_block(2, 8, 3);
+    writer.unabbreviated_record(3, 1, &[0]);
+    writer.end_block(3);
+
+    let ast = AstFile::new(&writer.bytes).unwrap();
+    let ids: Vec<u64> = ast.blocks.iter().map(|block| block.id).collect();
+    assert_eq!(ids, [15, 8]);
+    assert!(ast.block(clang_ast::BlockId::Ast).is_some());
+    assert!(ast.block(clang_ast::BlockId::SourceManager).is_none());
+
+    let control = &ast.control;
+    let metadata = control.metadata.as_ref().unwrap();
+    assert_eq!(metadata.version_major, 16);
+    assert_eq!(metadata.clang_major, 14);
+    assert!(metadata.relocatable);
+    assert_eq!(metadata.clang_version, "clang version 14.0.0");
+    assert_eq!(control.module_name.as_deref(), Some("Foo"));
+    assert_eq!(control.imports.len(), 1);
+    let import = &control.imports[0];
+    assert_eq!(import.kind, ModuleKind::ExplicitModule);
+    assert_eq!(import.module_name, "Bar");
+    assert_eq!(import.signature[19], 20);
+    assert_eq!(
+        control.target_triple.as_deref(),
+        Some("x86_64-unknown-linux-gnu")
+    );
+    assert_eq!(control.input_files[0].name, "foo.h");
+    assert_eq!(control.input_files[0].size, 100);
+    let dependencies: Vec<String> = control.dependencies().collect();
+    assert_eq!(dependencies, ["/src/foo/Bar.pcm", "/src/foo/foo.h"]);
+
+    assert!(matches!(
+        AstFile::new(&fs::read("tests/fixtures/simple.bc").unwrap()),
+        Err(Error::InvalidSignature(_))
+    ));
+}
+
+#[test]
+fn test_index_store_unit_file() {
+    let mut writer = BitWriter::default();
+    writer.write(u64::from(index_store::UNIT_MAGIC), 32);
+    writer.enter_block(2, 8, 3);
+    writer.unabbreviated_record(3, 0, &[1]);
+    writer.end_block(3);
+
+    writer.enter_block(2, 9, 3);
+    let info = [
+        Operand::Literal(1),
+        Operand::Fixed(1),
+        Operand::Vbr(10),
+        Operand::Vbr(8),
+        Operand::Vbr(10),
+        Operand::Vbr(8),
+        Operand::Vbr(10),
+        Operand::Vbr(8),
+        Operand::Vbr(10),
+        Operand::Fixed(1),
+        Operand::Fixed(1),
+        Operand::Vbr(8),
+        Operand::Vbr(8),
+        Operand::Vbr(8),
+        Operand::Blob,
+    ];
+    writer.define_abbrev(3, &info);
+    writer.abbreviated_record(
+        3,
+        4,
+        &info,
+        &[0, 0, 9, 9, 5, 14, 4, 1, 1, 0, 0, 5, 4],
+        b"clang1400x86_64-apple-macosx",
+    );
+    writer.end_block(3);
+
+    writer.enter_block(2, 10, 3);
+    let dependency = [
+        Operand::Literal(2),
+        Operand::Fixed(2),
+        Operand::Fixed(1),
+        Operand::Vbr(10),
+        Operand::Vbr(10),
+        Operand::Vbr(8),
+        Operand::Vbr(8),
+        Operand::Blob,
+    ];
+    writer.define_abbrev(3, &dependency);
+    writer.abbreviated_record(3, 4, &dependency, &[1, 0, 2, 0, 0, 0], b"foo.h-ABC123");
+    writer.end_block(3);
+
+    writer.enter_block(2, 11, 3);
+    writer.unabbreviated_record(3, 3, &[1, 3, 2]);
+    writer.end_block(3);
+
+    writer.enter_block(2, 12, 3);
+    writer.unabbreviated_record(3, 4, &[1, 18, 3, 21, 6]);
+    writer.unabbreviated_record(3, 4, &[1, 27, 7, 34, 5]);
+    writer.unabbreviated_record(3, 4, &[2, 39, 11, 50, 7]);
+    let buffer = [Operand::Literal(5), Operand::Blob];
+    writer.define_abbrev(3, &buffer);
+    writer.abbreviated_record(
+        3,
+        4,
+        &buffer,
+        &[],
+        b"/work/dirout.o/sdksrcmain.cincludefoo.husr/includestdio.h",
+    );
+    writer.end_block(3);
+
+    let unit = UnitFile::new(&writer.bytes).unwrap();
+    assert_eq!(unit.version, 1);
+    assert!(unit.is_debug_compilation);
+    assert!(!unit.is_module_unit);
+    assert_eq!(unit.working_directory, "/work/dir");
+    assert_eq!(unit.output_file, "out.o");
+    assert_eq!(unit.sysroot, "/sdk");
+    assert_eq!(unit.main_file.as_deref(), Some("/work/dir/src/main.c"));
+    assert_eq!(unit.provider_identifier, "clang");
+    assert_eq!(unit.provider_version, "1400");
+    assert_eq!(unit.target, "x86_64-apple-macosx");
+    assert_eq!(unit.dependencies.len(), 1);
+    let dependency = &unit.dependencies[0];
+    assert_eq!(dependency.kind, DependencyKind::Record);
+    assert_eq!(dependency.name, "foo.h-ABC123");
+    assert_eq!(
+        dependency.file_path.as_deref(),
+        Some("/work/dir/include/foo.h")
+    );
+    assert!(dependency.module_name.is_none());
+    assert_eq!(unit.includes.len(), 1);
+    assert_eq!(unit.includes[0].line, 3);
+    assert_eq!(
+        unit.includes[0].target_path.as_deref(),
+        Some("/work/dir/include/foo.h")
+    );
+
+    assert!(matches!(
+        RecordFile::new(&writer.bytes),
+        Err(Error::InvalidSignature(_))
+    ));
+}
+
+#[test]
+fn test_index_store_record_file() {
+    let mut writer = BitWriter::default();
+    writer.write(u64::from(index_store::RECORD_MAGIC), 32);
+    writer.enter_block(2, 8, 3);
+    writer.unabbreviated_record(3, 0, &[1]);
+    writer.end_block(3);
+
+    writer.enter_block(2, 9, 3);
+    let decl = [
+        Operand::Literal(1),
+        Operand::Fixed(5),
+        Operand::Fixed(5),
+        Operand::Fixed(3),
+        Operand::Vbr(8),
+        Operand::Vbr(8),
+        Operand::Vbr(8),
+        Operand::Vbr(6),
+        Operand::Vbr(6),
+        Operand::Blob,
+    ];
+    writer.define_abbrev(3, &decl);
+    writer.abbreviated_record(3, 4, &decl, &[12, 0, 0, 0, 2, 16, 4, 9], b"mainc:@F@main");
+    writer.abbreviated_record(3, 4, &decl, &[12, 0, 0, 0, 32, 0, 3, 8], b"fooc:@F@foo_foo");
+    writer.end_block(3);
+
+    writer.enter_block(2, 11, 3);
+    writer.unabbreviated_record(3, 3, &[1, 2, 1, 5, 0]);
+    writer.unabbreviated_record(3, 3, &[2, 32, 3, 5, 1, 16, 1]);
+    writer.end_block(3);
+
+    let file = RecordFile::new(&writer.bytes).unwrap();
+    assert_eq!(file.version, 1);
+    assert_eq!(file.symbols.len(), 2);
+    assert_eq!(file.symbols[0].name, "main");
+    assert_eq!(file.symbols[0].usr, "c:@F@main");
+    assert_eq!(file.symbols[0].codegen_name, "");
+    assert_eq!(file.symbols[1].codegen_name, "_foo");
+    assert_eq!(file.occurrences.len(), 2);
+    let call = &file.occurrences[1];
+    assert_eq!(call.symbol, 1);
+    assert_eq!((call.line, call.column), (3, 5));
+    assert_eq!(call.relations.len(), 1);
+    assert_eq!(call.relations[0].symbol, 0);
+    assert_eq!(call.relations[0].roles, 16);
+}
+
+#[test]
+fn test_schema_decode() {
+    bitstream_record! {
+        struct Version = 1 {
+            version: u64,
+        }
+    }
+
+    bitstream_block! {
+        struct Meta = 8 {
+            version: Option<Version>,
+        }
+    }
+
+    bitstream_record! {
+        struct Location = 2 {
+            severity: u64,
+            file: u64,
+            line: u32,
+            column: u32,
+            offset: u32,
+            category: u64,
+            flag: u64,
+            message_size: u64,
+            message: String,
+        }
+    }
+
+    bitstream_record! {
+        struct FixIt = 7 {
+            fields: Vec<u64>,
+        }
+    }
+
+    bitstream_block! {
+        struct Diag = 9 {
+            location: Option<Location>,
+            fix_its: Vec<FixIt>,
+            notes: Vec<Diag>,
+        }
+    }
+
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    let expected = SerializedDiagnostics::new(&data).unwrap();
+
+    let (_, mut reader) = Bitcode::reader(&data).unwrap();
+    let meta: Vec<Meta> = decode::decode_all(&mut reader.iter_top_level()).unwrap();
+    assert_eq!(meta.len(), 1);
+    assert_eq!(meta[0].version.as_ref().unwrap().version, expected.version);
+
+    let (_, mut reader) = Bitcode::reader(&data).unwrap();
+    let diags: Vec<Diag> = decode::decode_all(&mut reader.iter_top_level()).unwrap();
+    assert_eq!(diags.len(), expected.diagnostics.len());
+    for (diag, expected) in diags.iter().zip(&expected.diagnostics) {
+        let location = diag.location.as_ref().unwrap();
+        assert_eq!(location.severity, u64::from(expected.severity));
+        assert_eq!(location.file, expected.location.file);
+        assert_eq!(location.line, expected.location.line);
+        assert_eq!(location.column, expected.location.column);
+        assert_eq!(location.offset, expected.location.offset);
+        assert_eq!(location.category, expected.category);
+        assert_eq!(location.flag, expected.flag);
+        assert_eq!(location.message_size as usize, expected.message.len());
+        assert_eq!(location.message, expected.message);
+        assert_eq!(diag.fix_its.len(), expected.fix_its.len());
+        assert_eq!(diag.notes.len(), expected.children.len());
+    }
+    assert_eq!(diags[5].fix_its[0].fields.len(), 10);
+}
+
+#[test]
+fn test_custom_format_helpers() {
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let (signature, stream) = Signature::parse(&data).unwrap();
+    assert!(signature.is_llvm_ir());
+    assert_eq!(signature.to_bytes(), [b'B', b'C', 0xc0, 0xde]);
+    assert!(stream.len() < data.len() - 4);
+
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    let (signature, stream) = Signature::parse(&data).unwrap();
+    assert!(!signature.is_llvm_ir());
+    assert_eq!(&signature.to_bytes(), b"DIAG");
+    assert_eq!(Signature::from_bytes(*b"DIAG"), signature);
+    assert_eq!(stream, &data[4..]);
+
+    let bitcode = Bitcode::new(&data).unwrap();
+    let diag = bitcode.find_block(diagnostics::BlockId::Diag).unwrap();
+    let codes: Vec<diagnostics::RecordId> = diag.records_by().map(|(code, _)| code).collect();
+    assert_eq!(
+        codes,
+        [diagnostics::RecordId::Filename, diagnostics::RecordId::Diag]
+    );
+
+    let names = NameTable::new()
+        .block(diagnostics::BlockId::Diag, "DIAG_BLOCK")
+        .record(
+            diagnostics::BlockId::Diag,
+            diagnostics::RecordId::Filename,
+            "FILENAME",
+        );
+    let dump = format!("{:?}", bitcode.annotated_with(&names));
+    assert!(dump.contains("name: \"DIAG_BLOCK\""));
+    assert!(dump.contains("name: \"FILENAME\""));
+    assert!(!format!("{:?}", bitcode.annotated()).contains("DIAG_BLOCK"));
+}
+
+#[test]
+fn test_detect() {
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    assert_eq!(detect(&data), FormatKind::WrappedLlvmIr);
+    let (_, stream) = Signature::parse(&data).unwrap();
+    let mut raw = b"BC\xc0\xde".to_vec();
+    raw.extend_from_slice(stream);
+    assert_eq!(detect(&raw), FormatKind::LlvmIr);
+
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    assert_eq!(detect(&data), FormatKind::Diagnostics);
+
+    // A known magic followed by blocks of another format
+    let mut writer = BitWriter::default();
+    writer.write(u64::from(index_store::RECORD_MAGIC), 32);
+    writer.enter_block(2, 8, 3);
+    writer.end_block(3);
+    assert_eq!(detect(&writer.bytes), FormatKind::IndexRecord);
+    writer.bytes[..4].copy_from_slice(b"CPCH");
+    assert_eq!(
+        detect(&writer.bytes),
+        FormatKind::UnknownBitstream(Signature::from_bytes(*b"CPCH"))
+    );
+    writer.bytes[..4].copy_from_slice(b"ABCD");
+    assert_eq!(
+        detect(&writer.bytes),
+        FormatKind::UnknownBitstream(Signature::from_bytes(*b"ABCD"))
+    );
+
+    assert_eq!(detect(b""), FormatKind::Unknown);
+    assert_eq!(detect(b"\x7fELF\x02\x01\x01\x00"), FormatKind::Unknown);
+}
+
+#[cfg(feature = "xar")]
+#[test]
+fn test_xar_bundle() {
+    use llvm_bitcode::xar::{self, FileType, XarArchive};
+
+    let data = fs::read("tests/fixtures/bundle.xar").unwrap();
+    let bundle = XarArchive::parse(&data).unwrap();
+    assert_eq!(bundle.metadata.architecture.as_deref(), Some("x86_64"));
+    assert_eq!(bundle.metadata.platform.as_deref(), Some("MacOSX"));
+    assert_eq!(
+        bundle.metadata.dylibs,
+        ["{SDKPATH}/usr/lib/libSystem.B.dylib"]
+    );
+    assert_eq!(
+        bundle.metadata.link_options,
+        ["-execute", "-macosx_version_min", "11.0.0"]
+    );
+    assert_eq!(bundle.files.len(), 2);
+    let first = &bundle.files[0];
+    assert_eq!(first.name, "1");
+    assert_eq!(first.file_type, Some(FileType::Bitcode));
+    assert_eq!(
+        first.command_line[..2],
+        ["-triple", "x86_64-apple-macosx11.0.0"]
+    );
+    assert!(matches!(first.data, Cow::Borrowed(_)));
+    // The second file is the same bitcode, compressed
+    let second = &bundle.files[1];
+    assert_eq!(second.file_type, Some(FileType::Lto));
+    assert!(matches!(second.data, Cow::Owned(_)));
+    assert_eq!(first.data, second.data);
+    assert_eq!(bundle.bitcode_files().count(), 2);
+    let bitcode = second.bitcode().unwrap();
+    assert!(bitcode.find_block(BlockId::Module).is_some());
+
+    assert_eq!(
+        XarArchive::parse(&data[..100]).unwrap_err(),
+        xar::Error::InvalidToc
+    );
+    let mut corrupt = data.clone();
+    corrupt[40] ^= 0xff;
+    assert!(XarArchive::parse(&corrupt).is_err());
+    assert_eq!(
+        XarArchive::parse(b"not a xar archive at all....").unwrap_err(),
+        xar::Error::InvalidHeader
+    );
+}
+
+/// A 64-bit little endian Mach-O object file with the given sections, all in
+/// a single unnamed segment
+fn macho_object(cpu_type: CpuType, sections: &[(&str, &str, &[u8])]) -> Vec<u8> {
+    fn name(bytes: &mut Vec<u8>, name: &str) {
+        let mut padded = [0u8; 16];
+        padded[..name.len()].copy_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&padded);
+    }
+    let command_size = 72 + 80 * sections.len();
+    let mut data_offset = 32 + command_size;
+    let mut bytes = Vec::new();
+    for word in [
+        0xfeed_facf,
+        u32::from(cpu_type),
+        3,
+        1,
+        1,
+        command_size as u32,
+        0,
+        0,
+    ] {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    for word in [0x19, command_size as u32] {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    name(&mut bytes, "");
+    bytes.extend_from_slice(&[0; 32]);
+    for word in [7u32, 7, sections.len() as u32, 0] {
+        bytes.extend_from_slice(&word.to_le_bytes());
+    }
+    for (segment, section, data) in sections {
+        name(&mut bytes, section);
+        name(&mut bytes, segment);
+        bytes.extend_from_slice(&0u64.to_le_bytes());
+        bytes.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        for word in [data_offset as u32, 0, 0, 0, 0, 0, 0, 0] {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+        data_offset += data.len();
+    }
+    for (_, _, data) in sections {
+        bytes.extend_from_slice(data);
+    }
+    bytes
+}
+
+#[test]
+fn test_macho_embedded_bitcode() {
+    let bitcode = fs::read("tests/fixtures/simple.bc").unwrap();
+    assert_eq!(embedded::wrapper_cpu_type(&bitcode), Some(CpuType::X86_64));
+    let (_, raw) = Signature::parse(&bitcode).unwrap();
+    assert_eq!(embedded::wrapper_cpu_type(raw), None);
+
+    let x86_64 = macho_object(
+        CpuType::X86_64,
+        &[
+            ("__TEXT", "__text", b"\xc3"),
+            ("__LLVM", "__bitcode", &bitcode),
+            (
+                "__LLVM",
+                "__cmdline",
+                b"-triple\0x86_64-apple-macosx\0-O2\0",
+            ),
+        ],
+    );
+    let sections = embedded::macho_sections(&x86_64, None).unwrap();
+    assert_eq!(sections.len(), 2);
+    assert_eq!(sections[0].kind, SectionKind::Bitcode);
+    assert_eq!(sections[0].cpu_type, Some(CpuType::X86_64));
+    assert!(sections[0].is_bitcode());
+    assert!(!sections[0].is_marker());
+    assert!(Bitcode::new(sections[0].data).is_ok());
+    assert_eq!(sections[1].kind, SectionKind::CommandLine);
+    assert_eq!(
+        sections[1].command_line(),
+        ["-triple", "x86_64-apple-macosx", "-O2"]
+    );
+
+    let arm64 = macho_object(CpuType::Arm64, &[("__LLVM", "__bitcode", b"\0")]);
+    let mut fat = Vec::new();
+    for word in [0xcafe_babe, 2] {
+        fat.extend_from_slice(&u32::to_be_bytes(word));
+    }
+    let mut offset = 0x1000;
+    for slice in [&x86_64, &arm64] {
+        let cpu_type = u32::from_le_bytes([slice[4], slice[5], slice[6], slice[7]]);
+        for word in [cpu_type, 0, offset as u32, slice.len() as u32, 12] {
+            fat.extend_from_slice(&word.to_be_bytes());
+        }
+        offset += slice.len();
+    }
+    fat.resize(0x1000, 0);
+    fat.extend_from_slice(&x86_64);
+    fat.extend_from_slice(&arm64);
+    assert_eq!(embedded::macho_sections(&fat, None).unwrap().len(), 3);
+    let sections = embedded::macho_sections(&fat, Some(CpuType::Arm64)).unwrap();
+    assert_eq!(sections.len(), 1);
+    assert!(sections[0].is_marker());
+    assert!(!sections[0].is_bitcode());
+    assert!(embedded::macho_sections(&x86_64, Some(CpuType::Arm64))
+        .unwrap()
+        .is_empty());
+
+    assert_eq!(
+        embedded::macho_sections(&x86_64[..100], None),
+        Err(embedded::Error::InvalidObject)
+    );
+    assert_eq!(
+        embedded::macho_sections(&bitcode, None),
+        Err(embedded::Error::UnknownFormat)
+    );
+}
+
+#[test]
+fn test_elf_embedded_bitcode() {
+    // An object file written by rustc with `-C embed-bitcode=yes`
+    let data = fs::read("tests/fixtures/embedded.o").unwrap();
+    let sections = embedded::elf_sections(&data).unwrap();
+    assert_eq!(sections.len(), 2);
+    assert_eq!(sections[0].kind, SectionKind::Bitcode);
+    assert_eq!(sections[0].section, ".llvmbc");
+    assert!(sections[0].is_bitcode());
+    let bitcode = Bitcode::new(sections[0].data).unwrap();
+    assert!(bitcode.find_block(BlockId::Module).is_some());
+    assert_eq!(sections[1].kind, SectionKind::CommandLine);
+    assert!(sections[1].command_line().is_empty());
+
+    assert_eq!(
+        embedded::elf_sections(&data[..0x50]),
+        Err(embedded::Error::InvalidObject)
+    );
+    let macho = macho_object(CpuType::X86_64, &[]);
+    assert_eq!(
+        embedded::elf_sections(&macho),
+        Err(embedded::Error::UnknownFormat)
+    );
+}
+
+/// An x86-64 COFF object file with the given sections, naming them through
+/// the string table in bigobj files
+fn coff_object(bigobj: bool, sections: &[(&str, &[u8])]) -> Vec<u8> {
+    let header_size = if bigobj { 56 } else { 20 };
+    let mut data_offset = header_size + 40 * sections.len();
+    let symbol_table = data_offset + sections.iter().map(|(_, data)| data.len()).sum::<usize>();
+    let mut bytes = Vec::new();
+    if bigobj {
+        for half in [0u16, 0xffff, 2, 0x8664] {
+            bytes.extend_from_slice(&half.to_le_bytes());
+        }
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&[
+            0xc7, 0xa1, 0xba, 0xd1, 0xee, 0xba, 0xa9, 0x4b, 0xaf, 0x20, 0xfa, 0xf6, 0x6a, 0xa4,
+            0xdc, 0xb8,
+        ]);
+        for word in [0, 0, 0, 0, sections.len() as u32, symbol_table as u32, 0] {
+            bytes.extend_from_slice(&u32::to_le_bytes(word));
+        }
+    } else {
+        for half in [0x8664u16, sections.len() as u16] {
+            bytes.extend_from_slice(&half.to_le_bytes());
+        }
+        for word in [0, symbol_table as u32, 0] {
+            bytes.extend_from_slice(&u32::to_le_bytes(word));
+        }
+        bytes.extend_from_slice(&[0; 4]);
+    }
+    let mut strings = Vec::new();
+    for (name, data) in sections {
+        let mut padded = [0u8; 8];
+        if bigobj {
+            let reference = format!("/{}", 4 + strings.len());
+            padded[..reference.len()].copy_from_slice(reference.as_bytes());
+            strings.extend_from_slice(name.as_bytes());
+            strings.push(0);
+        } else {
+            padded[..name.len()].copy_from_slice(name.as_bytes());
+        }
+        bytes.extend_from_slice(&padded);
+        for word in [0, 0, data.len() as u32, data_offset as u32, 0, 0, 0, 0] {
+            bytes.extend_from_slice(&u32::to_le_bytes(word));
+        }
+        data_offset += data.len();
+    }
+    for (_, data) in sections {
+        bytes.extend_from_slice(data);
+    }
+    bytes.extend_from_slice(&(4 + strings.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&strings);
+    bytes
+}
+
+#[test]
+fn test_coff_embedded_bitcode() {
+    let bitcode = fs::read("tests/fixtures/simple.bc").unwrap();
+    let sections: &[(&str, &[u8])] = &[
+        (".text", b"\xc3"),
+        (".llvmbc", &bitcode),
+        (".llvmcmd", b"-triple\0x86_64-pc-windows-msvc\0"),
+    ];
+    for bigobj in [false, true] {
+        let object = coff_object(bigobj, sections);
+        let found = embedded::coff_sections(&object).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].kind, SectionKind::Bitcode);
+        assert_eq!(found[0].section, ".llvmbc");
+        assert!(found[0].is_bitcode());
+        assert_eq!(found[1].kind, SectionKind::CommandLine);
+        assert_eq!(found[1].section, ".llvmcmd");
+        assert_eq!(
+            found[1].command_line(),
+            ["-triple", "x86_64-pc-windows-msvc"]
+        );
+        assert_eq!(embedded::sections(&object).unwrap(), found);
+    }
+
+    let elf = fs::read("tests/fixtures/embedded.o").unwrap();
+    assert_eq!(
+        embedded::sections(&elf).unwrap(),
+        embedded::elf_sections(&elf).unwrap()
+    );
+    assert_eq!(
+        embedded::coff_sections(&elf),
+        Err(embedded::Error::UnknownFormat)
+    );
+    assert_eq!(
+        embedded::sections(&bitcode),
+        Err(embedded::Error::UnknownFormat)
+    );
+}
+
+#[test]
+fn test_archive_members() {
+    // Written by GNU ar, with a symbol table and a long name table
+    let data = fs::read("tests/fixtures/lib.a").unwrap();
+    let archive = Archive::parse(&data).unwrap();
+    assert!(!archive.is_thin());
+    let members: Vec<_> = archive.members().collect::<Result<_, _>>().unwrap();
+    let names: Vec<_> = members.iter().map(|member| member.name).collect();
+    assert_eq!(
+        names,
+        ["a_long_bitcode_member_name.bc", "obj.o", "notes.txt"]
+    );
+    assert_eq!(members[2].data, Some(&b"hello\n"[..]));
+    assert_eq!(
+        members[1].data,
+        Some(&fs::read("tests/fixtures/embedded.o").unwrap()[..])
+    );
+    let bitcode: Vec<_> = archive.bitcode_members().collect::<Result<_, _>>().unwrap();
+    assert_eq!(bitcode.len(), 1);
+    assert_eq!(bitcode[0].size, 2328);
+    assert!(Bitcode::new(bitcode[0].data.unwrap()).is_ok());
+
+    // Written by llvm-ar in the BSD format, names stored before the contents
+    let data = fs::read("tests/fixtures/libbsd.a").unwrap();
+    let members: Vec<_> = Archive::parse(&data)
+        .unwrap()
+        .members()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(members.len(), 2);
+    assert_eq!(members[0].name, "a_long_bitcode_member_name.bc");
+    assert!(members[0].is_bitcode());
+    assert_eq!(members[0].size, 2328);
+    assert_eq!(members[1].name, "notes.txt");
+    assert_eq!(members[1].data, Some(&b"hello\n"[..]));
+
+    let data = fs::read("tests/fixtures/libthin.a").unwrap();
+    let archive = Archive::parse(&data).unwrap();
+    assert!(archive.is_thin());
+    let members: Vec<_> = archive.members().collect::<Result<_, _>>().unwrap();
+    assert_eq!(members.len(), 2);
+    assert_eq!(members[0].name, "a_long_bitcode_member_name.bc");
+    assert_eq!(members[0].data, None);
+    assert_eq!(members[0].size, 2328);
+    assert_eq!(members[1].name, "obj.o");
+    assert_eq!(members[1].size, 3624);
+
+    assert_eq!(
+        Archive::parse(b"!<arch\n").unwrap_err(),
+        archive::Error::InvalidMagic
+    );
+    let data = fs::read("tests/fixtures/lib.a").unwrap();
+    let mut members = Archive::parse(&data[..200]).unwrap().members();
+    assert!(matches!(
+        members.next(),
+        Some(Err(archive::Error::InvalidHeader(_)))
+    ));
+    assert!(members.next().is_none());
+}
+
+#[test]
+fn test_rlib_bitcode() {
+    // Built by rustc with `-C embed-bitcode=yes`
+    let data = fs::read("tests/fixtures/libtiny.rlib").unwrap();
+    let bitcode = rlib_bitcode(&data).unwrap();
+    assert_eq!(bitcode.len(), 1);
+    assert_eq!(
+        bitcode[0].member,
+        "libtiny.tiny.7378e45bed21101e-cgu.0.rcgu.o"
+    );
+    assert!(matches!(bitcode[0].data, Cow::Borrowed(_)));
+    let module = bitcode[0].bitcode().unwrap();
+    assert_eq!(module.elements[0].as_block().unwrap().id, 13);
+
+    // Versions 1 and 2 of the compressed bytecode of older rustc versions
+    let data = fs::read("tests/fixtures/liblegacy.rlib").unwrap();
+    let simple = fs::read("tests/fixtures/simple.bc").unwrap();
+    let bitcode = rlib_bitcode(&data).unwrap();
+    let members: Vec<_> = bitcode.iter().map(|bitcode| bitcode.member).collect();
+    assert_eq!(
+        members,
+        ["tiny.0.bytecode.deflate", "tiny.tiny0-cgu.0.rcgu.bc.z"]
+    );
+    for bitcode in &bitcode {
+        assert_eq!(bitcode.data, simple);
+        assert!(bitcode.bitcode().is_ok());
+    }
+
+    assert_eq!(decode_rust_object(b"RUST_OBJECT\x03\0\0\0"), None);
+    assert_eq!(decode_rust_object(&simple), None);
+    let archive = Archive::parse(&data).unwrap();
+    let member = archive.members().nth(1).unwrap().unwrap();
+    let mut corrupt = member.data.unwrap().to_vec();
+    corrupt.truncate(corrupt.len() / 2);
+    assert_eq!(decode_rust_object(&corrupt), None);
+    assert_eq!(
+        rlib_bitcode(b"!<arch").unwrap_err(),
+        rlib::Error::Archive(archive::Error::InvalidMagic)
+    );
+}
+
+#[cfg(feature = "capi")]
+#[test]
+fn test_capi() {
+    use llvm_bitcode::capi::*;
+    use std::ffi::CStr;
+    use std::os::raw::{c_int, c_void};
+    use std::ptr;
+
+    unsafe extern "C" fn count_records(
+        user_data: *mut c_void,
+        _block_id: u64,
+        _record_id: u64,
+        fields: *const u64,
+        num_fields: usize,
+    ) -> c_int {
+        assert!(num_fields == 0 || !fields.is_null());
+        *(user_data as *mut usize) += 1;
+        0
+    }
+
+    unsafe extern "C" fn stop(
+        _user_data: *mut c_void,
+        _block_id: u64,
+        _record_id: u64,
+        _fields: *const u64,
+        _num_fields: usize,
+    ) -> c_int {
+        7
+    }
+
+    fn record_count(elements: &[BitcodeElement]) -> usize {
+        elements
+            .iter()
+            .map(|element| match element {
+                BitcodeElement::Record(_) => 1,
+                BitcodeElement::Block(block) => record_count(&block.elements),
+            })
+            .sum()
+    }
+
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let expected = record_count(&Bitcode::new(&data).unwrap().elements);
+    let expected_symbols = symbols(&data).unwrap();
+    assert!(!expected_symbols.is_empty());
+    unsafe {
+        let bitcode = llvm_bitcode_parse(data.as_ptr(), data.len(), ptr::null_mut());
+        assert!(!bitcode.is_null());
+        drop(data);
+        assert_eq!(llvm_bitcode_signature(bitcode), 0xdec0_4342);
+        let triple = CStr::from_ptr(llvm_bitcode_triple(bitcode));
+        assert_eq!(triple.to_str().unwrap(), "x86_64-apple-macosx11.0.0");
+        assert!(!llvm_bitcode_producer(bitcode).is_null());
+
+        let dump = llvm_bitcode_dump(bitcode);
+        assert!(CStr::from_ptr(dump)
+            .to_str()
+            .unwrap()
+            .starts_with("Bitcode {"));
+        llvm_bitcode_string_free(dump);
+
+        let mut count = 0usize;
+        let status = llvm_bitcode_visit_records(
+            bitcode,
+            count_records,
+            &mut count as *mut usize as *mut c_void,
+        );
+        assert_eq!(status, 0);
+        assert_eq!(count, expected);
+        assert_eq!(
+            llvm_bitcode_visit_records(bitcode, stop, ptr::null_mut()),
+            7
+        );
+
+        assert_eq!(llvm_bitcode_symbol_count(bitcode), expected_symbols.len());
+        for (index, symbol) in expected_symbols.iter().enumerate() {
+            let name = CStr::from_ptr(llvm_bitcode_symbol_name(bitcode, index));
+            assert_eq!(name.to_str().unwrap(), symbol.name);
+            let defined = llvm_bitcode_symbol_defined(bitcode, index);
+            assert_eq!(defined, c_int::from(symbol.defined));
+            let kind = llvm_bitcode_symbol_kind(bitcode, index);
+            assert_eq!(kind == 0, symbol.kind == SymbolKind::Function);
+            let linkage = llvm_bitcode_symbol_linkage(bitcode, index);
+            assert_eq!(linkage == 0, symbol.linkage == Linkage::External);
+        }
+        let out_of_range = expected_symbols.len();
+        assert!(llvm_bitcode_symbol_name(bitcode, out_of_range).is_null());
+        assert_eq!(llvm_bitcode_symbol_linkage(bitcode, out_of_range), -1);
+        assert_eq!(llvm_bitcode_symbol_kind(bitcode, out_of_range), -1);
+        assert_eq!(llvm_bitcode_symbol_defined(bitcode, out_of_range), -1);
+        llvm_bitcode_free(bitcode);
+
+        let mut error = ptr::null_mut();
+        assert!(llvm_bitcode_parse(b"BC".as_ptr(), 2, &mut error).is_null());
+        assert!(!CStr::from_ptr(error).to_bytes().is_empty());
+        llvm_bitcode_string_free(error);
+    }
+}
+
+#[cfg(feature = "llvm-validation")]
+#[test]
+fn test_llvm_validation() {
+    // validation.bc was written by llvm-as 14 with debug locations, varargs,
+    // declarations and globals
+    for path in ["tests/fixtures/simple.bc", "tests/fixtures/validation.bc"] {
+        let data = fs::read(path).unwrap();
+        let mismatches = validate::validate(&data).unwrap();
+        assert!(mismatches.is_empty(), "{}: {:#?}", path, mismatches);
+    }
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    assert!(matches!(
+        validate::validate(&data),
+        Err(validate::Error::NotModule)
+    ));
+}
+
+#[test]
+fn test_json_dump() {
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    let json = to_json(&data, &JsonOptions::default()).unwrap();
+    assert!(json.starts_with(
+        "{\"signature\":1195460932,\"elements\":[{\"type\":\"block\",\"id\":8,\"name\":\"Meta\",\
+         \"elements\":[{\"type\":\"record\",\"id\":1,\"name\":\"Version\",\"fields\":[1]}]},\
+         {\"type\":\"block\",\"id\":9,\"name\":\"Diag\",\"elements\":[{\"type\":\"record\",\"id\":6,\
+         \"name\":\"FileName\",\"fields\":[1, 0, 0, 100],\"blob\":\"L1VzZXJz"
+    ));
+    assert!(json.ends_with("]}"));
+
+    let blob_operands = [Operand::Literal(4), Operand::Blob];
+    let mut writer = BitWriter::new();
+    writer.enter_block(2, 8, 3);
+    writer.define_abbrev(3, &blob_operands);
+    writer.abbreviated_record(3, 4, &blob_operands, &[], b"a\"b\n\x01");
+    writer.unabbreviated_record(3, 1, &[7, 8]);
+    writer.end_block(3);
+    let options = JsonOptions {
+        offsets: true,
+        names: Some(
+            NameTable::new()
+                .block(8u64, "MODULE")
+                .record(8u64, 4u64, "BLOB"),
+        ),
+        ..JsonOptions::default()
+    };
+    assert_eq!(
+        to_json(&writer.bytes, &options).unwrap(),
+        "{\"signature\":3737142082,\"elements\":[{\"type\":\"block\",\"id\":8,\"name\":\"MODULE\",\
+         \"offset\":32,\"elements\":[{\"type\":\"record\",\"id\":4,\"name\":\"BLOB\",\"offset\":117,\
+         \"fields\":[],\"blob\":\"YSJiCgE=\"},{\"type\":\"record\",\"id\":1,\"offset\":192,\
+         \"fields\":[7, 8]}]}]}"
+    );
+    let options = JsonOptions {
+        blobs: BlobEncoding::String,
+        ..JsonOptions::default()
+    };
+    assert!(to_json(&writer.bytes, &options)
+        .unwrap()
+        .contains("\"blob\":\"a\\\"b\\n\\u0001\""));
+    assert!(to_json(&writer.bytes[..10], &options).is_err());
+}
+
+#[test]
+fn test_symbols() {
+    let nm = |symbols: &[Symbol]| {
+        let mut listing: Vec<_> = symbols
+            .iter()
+            .filter(|symbol| !symbol.format_specific)
+            .map(|symbol| format!("{} {}", symbol.nm_type(), symbol.name))
+            .collect();
+        listing.sort_by(|a, b| a[2..].cmp(&b[2..]));
+        listing
+    };
+    let expected = [
+        "T alias_fn",
+        "D alias_var",
+        "U avail_fn",
+        "C common_var",
+        "D constant",
+        "D defined",
+        "T defined_fn",
+        "T dso_fn",
+        "D dso_var",
+        "U extern_var",
+        "w extern_weak_var",
+        "U external_fn",
+        "D hidden_var",
+        "W inline_fn",
+        "W linkonce_odr_var",
+        "d local",
+        "D protected_var",
+        "t static_fn",
+        "D tls_var",
+        "W weak_fn",
+        "w weak_import",
+        "W weak_var",
+    ];
+
+    // Mach-O names from the symbol table
+    let data = fs::read("tests/fixtures/symbols.bc").unwrap();
+    let listed = symbols(&data).unwrap();
+    let mangled: Vec<_> = expected
+        .iter()
+        .map(|line| format!("{}_{}", &line[..2], &line[2..]))
+        .collect();
+    assert_eq!(nm(&listed), mangled);
+    let find = |name: &str| listed.iter().find(|s| s.name == name).unwrap();
+    let constant = find("_constant");
+    assert!(constant.constant);
+    assert_eq!(constant.ir_name.as_deref(), Some("constant"));
+    assert_eq!(constant.kind, SymbolKind::Variable);
+    assert_eq!(find("_hidden_var").visibility, Visibility::Hidden);
+    assert!(find("_hidden_var").dso_local);
+    assert_eq!(find("_protected_var").visibility, Visibility::Protected);
+    assert!(find("_dso_fn").dso_local);
+    assert!(!find("_external_fn").dso_local);
+    assert!(find("_tls_var").thread_local);
+    assert_eq!(find("_alias_fn").kind, SymbolKind::Alias);
+    assert_eq!(find("_extern_weak_var").linkage, Linkage::ExternalWeak);
+    assert_eq!(find("_linkonce_odr_var").linkage, Linkage::LinkOnceOdr);
+    assert!(find("_common_var").is_common());
+    assert!(!find("_avail_fn").defined);
+    assert_eq!(find("_avail_fn").linkage, Linkage::AvailableExternally);
+    assert!(!find("_local").is_global());
+    assert!(listed.iter().any(|symbol| symbol.format_specific));
+
+    // Module records only, without a data layout to mangle names with
+    let data = fs::read("tests/fixtures/symbols_nosymtab.bc").unwrap();
+    let listed = symbols(&data).unwrap();
+    assert_eq!(nm(&listed), expected);
+
+    let data = fs::read("tests/fixtures/validation.bc").unwrap();
+    assert_eq!(
+        nm(&symbols(&data).unwrap()),
+        ["T add", "D counter", "T loop", "U printf", "d table"]
+    );
+
+    let data = fs::read("tests/fixtures/serialized.dia").unwrap();
+    assert!(matches!(symbols(&data), Err(Error::InvalidSignature(_))));
+}
+
+#[test]
+fn test_linkage_helpers() {
+    use Linkage::*;
+
+    let interposable = [LinkOnceAny, WeakAny, Common, ExternalWeak];
+    let weak_for_linker = [
+        LinkOnceAny,
+        LinkOnceOdr,
+        WeakAny,
+        WeakOdr,
+        ExternalWeak,
+        Common,
+    ];
+    let discardable = [
+        LinkOnceAny,
+        LinkOnceOdr,
+        Internal,
+        Private,
+        AvailableExternally,
+    ];
+    let all = [
+        External,
+        AvailableExternally,
+        LinkOnceAny,
+        LinkOnceOdr,
+        WeakAny,
+        WeakOdr,
+        Appending,
+        Internal,
+        Private,
+        ExternalWeak,
+        Common,
+    ];
+    for linkage in all {
+        assert_eq!(linkage.is_interposable(), interposable.contains(&linkage));
+        assert_eq!(
+            linkage.is_weak_for_linker(),
+            weak_for_linker.contains(&linkage)
+        );
+        assert_eq!(
+            linkage.is_discardable_if_unused(),
+            discardable.contains(&linkage)
+        );
+        // ODR definitions can't be replaced by different ones
+        assert!(!(linkage.is_odr() && linkage.is_interposable()));
+    }
+
+    // [type, isconst, initid, linkage, alignment, section, visibility,
+    //  threadlocal, unnamed_addr, externally_initialized, dllstorageclass]
+    let mut writer = BitWriter::new();
+    writer.enter_block(2, u64::from(BlockId::Module), 3);
+    writer.unabbreviated_record(3, 1, &[1]);
+    writer.unabbreviated_record(3, 7, &[0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 2]);
+    // Before the storage class field, with the obsolete dllimport linkage
+    writer.unabbreviated_record(3, 7, &[0, 0, 0, 5, 0, 0, 0, 0]);
+    writer.unabbreviated_record(3, 7, &[0, 0, 1, 3, 0, 0, 2, 0]);
+    writer.unabbreviated_record(3, 7, &[0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0]);
+    writer.end_block(3);
+
+    let listed = symbols(&writer.bytes).unwrap();
+    let classes: Vec<_> = listed
+        .iter()
+        .map(|symbol| symbol.dll_storage_class)
+        .collect();
+    assert_eq!(
+        classes,
+        [
+            DllStorageClass::DllExport,
+            DllStorageClass::DllImport,
+            DllStorageClass::Default,
+            DllStorageClass::Default,
+        ]
+    );
+    assert_eq!(listed[1].linkage, External);
+    assert!(!listed[1].defined);
+    let visibilities: Vec<_> = listed
+        .iter()
+        .map(|symbol| (symbol.visibility, symbol.effective_visibility()))
+        .collect();
+    assert_eq!(
+        visibilities,
+        [
+            (Visibility::Hidden, Visibility::Default),
+            (Visibility::Default, Visibility::Default),
+            (Visibility::Protected, Visibility::Default),
+            (Visibility::Hidden, Visibility::Hidden),
+        ]
+    );
+}
+
+#[test]
+fn test_record_operands() {
+    let record = |payload| Record {
+        id: 1,
+        fields: vec![2, 3],
+        payload,
+        abbrev: None,
+        bit_range: None,
+    };
+    assert_eq!(record(None).operands(), [2, 3]);
+    assert_eq!(
+        record(Some(Payload::Array(vec![4, 5]))).operands(),
+        [2, 3, 4, 5]
+    );
+    assert_eq!(
+        record(Some(Payload::Char6String("ab".to_string()))).operands(),
+        [2, 3, 97, 98]
+    );
+    assert_eq!(
+        record(Some(Payload::Blob(Cow::Borrowed(&[6, 7])))).operands(),
+        [2, 3, 6, 7]
+    );
+}
+
+#[test]
+fn test_semantic_diff() {
+    // The same records, abbreviated differently
+    let blob_operands = [Operand::Literal(4), Operand::Fixed(8), Operand::Blob];
+    let mut left = BitWriter::new();
+    left.enter_block(2, 8, 3);
+    left.define_abbrev(3, &blob_operands);
+    left.abbreviated_record(3, 4, &blob_operands, &[5], b"ab");
+    left.unabbreviated_record(3, 1, &[7, 8]);
+    left.end_block(3);
+    let scalar_operands = [Operand::Literal(1), Operand::Vbr(6), Operand::Fixed(4)];
+    let mut right = BitWriter::new();
+    right.enter_block(2, 8, 4);
+    right.define_abbrev(4, &scalar_operands);
+    right.unabbreviated_record(4, 4, &[5, 97, 98]);
+    right.abbreviated_record(4, 4, &scalar_operands, &[7, 8], &[]);
+    right.end_block(4);
+    let left = Bitcode::new(&left.bytes).unwrap();
+    assert!(diff(&left, &Bitcode::new(&right.bytes).unwrap()).is_empty());
+
+    let mut right = BitWriter::new();
+    right.enter_block(2, 8, 3);
+    right.unabbreviated_record(3, 2, &[1]);
+    right.unabbreviated_record(3, 4, &[5, 97, 98]);
+    right.unabbreviated_record(3, 1, &[7, 9]);
+    right.enter_block(3, 9, 3);
+    right.end_block(3);
+    right.end_block(3);
+    let right = Bitcode::new(&right.bytes).unwrap();
+    let record = |id, operands: &[u64]| DecodedRecord {
+        id,
+        operands: operands.to_vec(),
+    };
+    let differences = diff(&left, &right);
+    assert_eq!(
+        differences,
+        [
+            Difference {
+                path: vec![(8, 0)],
+                change: Change::RecordAdded {
+                    index: 0,
+                    record: record(2, &[1]),
+                },
+            },
+            Difference {
+                path: vec![(8, 0)],
+                change: Change::RecordChanged {
+                    index: 1,
+                    left: record(1, &[7, 8]),
+                    right: record(1, &[7, 9]),
+                },
+            },
+            Difference {
+                path: vec![(8, 0)],
+                change: Change::BlockAdded { id: 9, index: 0 },
+            },
+        ]
+    );
+    assert_eq!(
+        differences[1].to_string(),
+        "/8[0]: record 1 changed: 1 [7, 8] -> 1 [7, 9]"
+    );
+
+    let data = fs::read("tests/fixtures/symbols.bc").unwrap();
+    let symbols = Bitcode::new(&data).unwrap();
+    assert!(diff(&symbols, &symbols).is_empty());
+    // Without a data layout the module has no symbol table
+    let data = fs::read("tests/fixtures/symbols_nosymtab.bc").unwrap();
+    let differences = diff(&symbols, &Bitcode::new(&data).unwrap());
+    assert!(differences.contains(&Difference {
+        path: Vec::new(),
+        change: Change::BlockRemoved { id: 25, index: 0 },
+    }));
+    assert!(matches!(
+        &differences[0].change,
+        Change::RecordRemoved { index: 2, record } if record.id == 3
+    ));
+}
+
+#[test]
+fn test_size_report() {
+    let data = fs::read("tests/fixtures/validation.bc").unwrap();
+    let report = SizeReport::new(&data).unwrap();
+    assert_eq!(report.total_bytes, data.len());
+    let block = |id| report.blocks.iter().find(|block| block.id == id).unwrap();
+    assert_eq!(block(BlockId::Module).count, 1);
+    assert!(block(BlockId::Module).bits <= data.len() as u64 * 8);
+    assert_eq!(block(BlockId::Function).count, 2);
+    assert!(report
+        .blocks
+        .windows(2)
+        .all(|pair| pair[0].bits >= pair[1].bits));
+
+    // Function blocks are named from the module records and the strtab
+    let names: Vec<_> = report.functions.iter().map(|f| f.name.as_str()).collect();
+    assert_eq!(names, ["add", "loop"]);
+    let function_bits: u64 = report.functions.iter().map(|f| f.bits).sum();
+    assert_eq!(function_bits, block(BlockId::Function).bits);
+
+    let metadata = |category| {
+        report
+            .metadata
+            .iter()
+            .find(|metadata| metadata.category == category)
+            .map_or(0, |metadata| metadata.records)
+    };
+    // One `!DILocation` and two instructions reusing it
+    assert_eq!(metadata(MetadataCategory::Locations), 3);
+    assert_eq!(metadata(MetadataCategory::Types), 2);
+    assert_eq!(metadata(MetadataCategory::Variables), 2);
+    assert_eq!(metadata(MetadataCategory::Scopes), 3);
+    assert_eq!(metadata(MetadataCategory::Attachments), 1);
+    assert!(metadata(MetadataCategory::Strings) > 0);
+
+    let text = report.to_string();
+    assert!(text.starts_with(&format!("total: {} bytes\n", data.len())));
+    assert!(text.contains(" bytes add\n"));
+}
+
+#[test]
+fn test_bit_positions() {
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let bitcode = Bitcode::new(&data).unwrap();
+
+    // Items of a block are laid out back to back inside its range
+    fn check(block: &Block<'_>) {
+        let range = block.bit_range.clone().unwrap();
+        let mut end = range.start;
+        for element in &block.elements {
+            let element_range = match element {
+                BitcodeElement::Block(block) => {
+                    check(block);
+                    block.bit_range.clone().unwrap()
+                }
+                BitcodeElement::Record(record) => record.bit_range.clone().unwrap(),
+            };
+            assert!(element_range.start >= end && element_range.start < element_range.end);
+            end = element_range.end;
+        }
+        assert!(end <= range.end);
+    }
+    let mut end = 0;
+    for element in &bitcode.elements {
+        let block = element.as_block().unwrap();
+        check(block);
+        let range = block.bit_range.clone().unwrap();
+        assert!(range.start >= end);
+        end = range.end;
+    }
+    assert!(end <= (data.len() as u64 - 4) * 8);
+
+    // Seek back to a block seen earlier and read it again
+    let (_, mut reader) = Bitcode::reader(&data).unwrap();
+    let mut function = None;
+    let mut top_level = reader.iter_top_level();
+    while let Some(item) = top_level.next().unwrap() {
+        if let BlockItem::Block(mut block) = item {
+            while let Some(item) = block.next().unwrap() {
+                if let BlockItem::Block(child) = item {
+                    if child.id == u64::from(BlockId::Function) && function.is_none() {
+                        function = Some(child.context());
+                    }
+                }
+            }
+        }
+    }
+    let function = function.unwrap();
+    let expected = bitcode.find_block(BlockId::Function).unwrap();
+    assert_eq!(Some(function.bit_range()), expected.bit_range);
+    reader.seek_to(function.offset).unwrap();
+    assert_eq!(reader.bit_position(), function.offset);
+    let mut visitor = CollectingVisitor::new();
+    reader
+        .read_block(function.id, function.abbrev_width, &mut visitor)
+        .unwrap();
+    let elements = visitor.finalize_top_level_elements();
+    assert_eq!(elements.len(), expected.elements.len());
+    for (read, expected) in elements.iter().zip(&expected.elements) {
+        if let (Some(read), Some(expected)) = (read.as_record(), expected.as_record()) {
+            assert_eq!(read.fields, expected.fields);
+            assert_eq!(read.bit_range, expected.bit_range);
+        }
+    }
+    assert!(reader.seek_to(data.len() as u64 * 8).is_err());
+
+    // The streaming reader stamps the same ranges
+    let mut stream = StreamReader::new();
+    stream.feed(&data);
+    stream.finish();
+    let module = bitcode.find_block(BlockId::Module).unwrap();
+    let mut path = Vec::new();
+    let mut records = module.elements.iter().filter_map(BitcodeElement::as_record);
+    while let Some(event) = stream.next().unwrap() {
+        match event {
+            StreamEvent::EnterBlock(context) => {
+                path.push(context.id);
+                if context.id == u64::from(BlockId::Module) {
+                    assert_eq!(Some(context.bit_range()), module.bit_range);
+                }
+            }
+            StreamEvent::ExitBlock => {
+                path.pop();
+            }
+            StreamEvent::Record(record) if path == [u64::from(BlockId::Module)] => {
+                assert_eq!(record.bit_range, records.next().unwrap().bit_range);
+            }
+            StreamEvent::Record(_) => {}
+        }
+    }
+    assert!(records.next().is_none());
+}
+
+#[test]
+fn test_seekable_reader() {
+    // simple.bc has a wrapper header, serialized.dia a top level BLOCKINFO
+    for path in [
+        "tests/fixtures/simple.bc",
+        "tests/fixtures/validation.bc",
+        "tests/fixtures/serialized.dia",
+    ] {
+        let data = fs::read(path).unwrap();
+        let bitcode = Bitcode::new(&data).unwrap();
+        let mut reader = SeekableReader::new(std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(reader.signature(), bitcode.signature);
+
+        let top_level = reader.top_level_blocks().unwrap();
+        let blocks: Vec<_> = bitcode
+            .elements
+            .iter()
+            .filter_map(BitcodeElement::as_block)
+            .collect();
+        assert_eq!(top_level.len(), blocks.len());
+        for (context, block) in top_level.iter().zip(&blocks) {
+            assert_eq!(Some(context.bit_range()), block.bit_range);
+            assert_same_block(&reader.read_block(context).unwrap(), block);
+        }
+    }
+
+    // Read a function block from the middle of the module, with a cache too
+    // small to keep more than the last read
+    let data = fs::read("tests/fixtures/validation.bc").unwrap();
+    let bitcode = Bitcode::new(&data).unwrap();
+    let (_, mut stream) = Bitcode::reader(&data).unwrap();
+    let mut functions = Vec::new();
+    let mut top_level = stream.iter_top_level();
+    while let Some(item) = top_level.next().unwrap() {
+        if let BlockItem::Block(mut block) = item {
+            while let Some(item) = block.next().unwrap() {
+                if let BlockItem::Block(child) = item {
+                    if child.id == u64::from(BlockId::Function) {
+                        functions.push(child.context());
+                    }
+                }
+            }
+        }
+    }
+    let expected: Vec<_> = bitcode
+        .find_block(BlockId::Module)
+        .unwrap()
+        .elements
+        .iter()
+        .filter_map(BitcodeElement::as_block)
+        .filter(|block| block.id == u64::from(BlockId::Function))
+        .collect();
+    assert_eq!(functions.len(), expected.len());
+    let mut reader = SeekableReader::new(std::io::Cursor::new(&data)).unwrap();
+    reader.set_cache_capacity(0);
+    for (context, expected) in functions.iter().zip(&expected).rev() {
+        assert_same_block(&reader.read_block(context).unwrap(), expected);
+    }
+
+    let mut outside = functions[0];
+    outside.offset = data.len() as u64 * 8;
+    assert!(reader.read_block(&outside).is_err());
+}
+
+#[test]
+fn test_char6() {
+    let decoded: String = (0..64).map(|value| char6_decode(value).unwrap()).collect();
+    assert_eq!(
+        decoded,
+        "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789._"
+    );
+    for (value, c) in decoded.chars().enumerate() {
+        assert_eq!(char6_encode(c), Some(value as u64));
+    }
+    assert_eq!(char6_decode(64), None);
+    assert_eq!(char6_encode('-'), None);
+    assert_eq!(char6_encode('é'), None);
+}
+
+#[test]
+fn test_peek() {
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let (_, mut reader) = Bitcode::reader(&data).unwrap();
+    let start = reader.bit_position();
+
+    // An ENTER_SUBBLOCK abbreviation id followed by the identification
+    // block id, left unread
+    let mut peek = reader.peek();
+    assert_eq!(peek.fixed(2).unwrap(), 1);
+    assert_eq!(peek.vbr(8).unwrap(), u64::from(BlockId::Identification));
+    assert_eq!(peek.offset(), start + 10);
+    peek.discard();
+    assert_eq!(reader.bit_position(), start);
+
+    let mut peek = reader.peek();
+    assert!(matches!(peek.vbr(1), Err(Error::ValueOutOfRange(1))));
+    assert!(matches!(peek.fixed(65), Err(Error::ValueOutOfRange(65))));
+    assert_eq!(peek.fixed(2).unwrap(), 1);
+    assert_eq!(peek.vbr(8).unwrap(), u64::from(BlockId::Identification));
+    assert_eq!(peek.vbr(4).unwrap(), 5);
+    peek.align32().unwrap();
+    let length = peek.fixed(32).unwrap();
+    let body = peek.offset();
+    assert_eq!(reader.bit_position(), start);
+
+    // Committed fields are consumed, here a block header
+    let mut peek = reader.peek();
+    peek.fixed(2).unwrap();
+    peek.vbr(8).unwrap();
+    peek.vbr(4).unwrap();
+    peek.align32().unwrap();
+    peek.fixed(32).unwrap();
+    peek.commit();
+    assert_eq!(reader.bit_position(), body);
+    let bitcode = Bitcode::new(&data).unwrap();
+    let identification = bitcode.find_block(BlockId::Identification).unwrap();
+    assert_eq!(
+        identification.bit_range.clone().unwrap().end,
+        body + length * 32
+    );
+}
+
+#[test]
+fn test_zero_width_operands() {
+    // Zero-width fixed and VBR operands read no bits and decode as zero, so
+    // the record below takes an abbreviation id, a vbr6 and an array length
+    let operands = [
+        Operand::Literal(5),
+        Operand::Fixed(0),
+        Operand::Vbr(6),
+        Operand::Vbr(0),
+        Operand::Array(Box::new(Operand::Fixed(0))),
+    ];
+    let mut writer = BitWriter::new();
+    writer.enter_block(2, 8, 3);
+    writer.define_abbrev(3, &operands);
+    writer.write(4, 3);
+    writer.write_vbr(42, 6);
+    writer.write_vbr(3, 6);
+    writer.unabbreviated_record(3, 1, &[7]);
+    writer.end_block(3);
+
+    let bitcode = Bitcode::new(&writer.bytes).unwrap();
+    let block = bitcode.find_block(8u64).unwrap();
+    let record = block.records(5).next().unwrap();
+    assert_eq!(record.fields, [0, 42, 0]);
+    assert!(matches!(&record.payload, Some(Payload::Array(elements)) if elements == &[0, 0, 0]));
+    assert!(matches!(
+        &record.abbrev.as_ref().unwrap().abbrev.operands[..],
+        [
+            Operand::Literal(5),
+            Operand::Literal(0),
+            Operand::Vbr(6),
+            Operand::Literal(0),
+            Operand::Array(element),
+        ] if matches!(**element, Operand::Literal(0))
+    ));
+    assert_eq!(block.records(1).next().unwrap().fields, [7]);
+
+    let mut stream = StreamReader::new();
+    stream.feed(&writer.bytes);
+    stream.finish();
+    let mut records =
+        std::iter::from_fn(|| stream.next().unwrap()).filter_map(|event| match event {
+            StreamEvent::Record(record) => Some(record),
+            _ => None,
+        });
+    assert_eq!(records.next().unwrap().fields, [0, 42, 0]);
+    assert_eq!(records.next().unwrap().fields, [7]);
+
+    // A VBR chunk of one bit has no room for a value, fields are at most
+    // 32 bits wide
+    for operand in [Operand::Vbr(1), Operand::Fixed(33), Operand::Vbr(33)] {
+        let mut writer = BitWriter::new();
+        writer.enter_block(2, 8, 3);
+        writer.define_abbrev(3, &[Operand::Literal(1), operand]);
+        writer.end_block(3);
+        let err = Bitcode::new(&writer.bytes).unwrap_err();
+        assert!(matches!(err.root_cause(), Error::InvalidAbbrev));
+    }
+}
+
+#[test]
+fn test_collect_options() {
+    let data = fs::read("tests/fixtures/validation.bc").unwrap();
+    let full = Bitcode::new(&data).unwrap();
+    let expected: Vec<_> = full.query(&[BlockId::Module, BlockId::Function]).collect();
+
+    // Function bodies left unparsed, then parsed on demand
+    let options = CollectOptions {
+        unparsed_blocks: vec![BlockId::Function.into()],
+        ..Default::default()
+    };
+    let bitcode = Bitcode::new_with_collect_options(&data, options).unwrap();
+    let functions: Vec<_> = bitcode
+        .query(&[BlockId::Module, BlockId::Function])
+        .collect();
+    assert_eq!(functions.len(), expected.len());
+    for (function, expected) in functions.iter().zip(&expected) {
+        assert!(function.elements.is_empty());
+        assert_eq!(function.bit_range, expected.bit_range);
+        let context = function.unparsed.unwrap();
+        assert_same_block(&bitcode.parse_block(&data, &context).unwrap(), expected);
+    }
+    let module = bitcode.find_block(BlockId::Module).unwrap();
+    assert!(module.unparsed.is_none());
+    assert!(bitcode.find_block(BlockId::Constants).is_some());
+
+    // Only the top level blocks parsed
+    let options = CollectOptions {
+        max_depth: 1,
+        ..Default::default()
+    };
+    let bitcode = Bitcode::new_with_collect_options(&data, options).unwrap();
+    let module = bitcode.find_block(BlockId::Module).unwrap();
+    let expected_module = full.find_block(BlockId::Module).unwrap();
+    assert_eq!(module.elements.len(), expected_module.elements.len());
+    for (element, expected) in module.elements.iter().zip(&expected_module.elements) {
+        if let (Some(block), Some(expected)) = (element.as_block(), expected.as_block()) {
+            let context = block.unparsed.unwrap();
+            assert_same_block(&bitcode.parse_block(&data, &context).unwrap(), expected);
+        }
+    }
+
+    let options = CollectOptions {
+        max_records: 10,
+        ..Default::default()
+    };
+    let err = Bitcode::new_with_collect_options(&data, options).unwrap_err();
+    assert!(matches!(
+        err.root_cause(),
+        Error::LimitExceeded(Limit::CollectedRecords)
+    ));
+}
+
+#[test]
+fn test_events() {
+    for path in [
+        "tests/fixtures/simple.bc",
+        "tests/fixtures/validation.bc",
+        "tests/fixtures/serialized.dia",
+    ] {
+        let data = fs::read(path).unwrap();
+        let bitcode = Bitcode::new(&data).unwrap();
+
+        // Rebuild the tree from the events
+        let mut stack = vec![Block {
+            id: BitStreamReader::TOP_LEVEL_BLOCK_ID,
+            elements: Vec::new(),
+            bit_range: None,
+            unparsed: None,
+        }];
+        let mut events = Bitcode::events(&data).unwrap();
+        for event in &mut events {
+            match event.unwrap() {
+                Event::EnterBlock(context) => stack.push(Block {
+                    id: context.id,
+                    elements: Vec::new(),
+                    bit_range: Some(context.bit_range()),
+                    unparsed: None,
+                }),
+                Event::ExitBlock => {
+                    let block = stack.pop().unwrap();
+                    let parent = stack.last_mut().unwrap();
+                    parent.elements.push(BitcodeElement::Block(block));
+                }
+                Event::Record(record) => {
+                    if let Some(Payload::Blob(blob)) = &record.payload {
+                        assert!(matches!(blob, Cow::Borrowed(_)));
+                    }
+                    let parent = stack.last_mut().unwrap();
+                    parent.elements.push(BitcodeElement::Record(record));
+                }
+            }
+        }
+        assert_eq!(stack.len(), 1);
+        assert_eq!(events.block_info().len(), bitcode.block_info.len());
+        let top_level = stack.pop().unwrap();
+        let expected = Block {
+            id: BitStreamReader::TOP_LEVEL_BLOCK_ID,
+            elements: bitcode.elements.clone(),
+            bit_range: None,
+            unparsed: None,
+        };
+        assert_same_block(&top_level, &expected);
+    }
+
+    // Iteration stops after an error
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let (_, stream) = Signature::parse(&data).unwrap();
+    let events = Events::new(BitStreamReader::new(&stream[..stream.len() / 2]));
+    let results: Vec<_> = events.collect();
+    assert!(results.last().unwrap().is_err());
+    assert_eq!(results.iter().filter(|result| result.is_err()).count(), 1);
+}
+
+#[test]
+fn test_lazy_visitor() {
+    /// Decodes the first field of module records, and all of the records
+    /// of the identification block
+    #[derive(Default)]
+    struct FirstFields {
+        module: Vec<(u64, Option<u64>)>,
+        identification: Vec<Record<'static>>,
+        skipped: usize,
+    }
+
+    impl<'input> LazyVisitor<'input> for FirstFields {
+        type Error = Error;
+
+        fn should_enter_block_with_context(
+            &mut self,
+            context: &BlockContext,
+        ) -> Result<bool, Error> {
+            Ok(context.id != u64::from(BlockId::Function))
+        }
+
+        fn visit(
+            &mut self,
+            block_id: u64,
+            mut record: RecordIter<'_, 'input>,
+        ) -> Result<(), Error> {
+            match BlockId::from(block_id) {
+                BlockId::Module => self.module.push((record.id, record.next()?)),
+                BlockId::Identification => {
+                    self.identification.push(record.into_record()?.into_owned())
+                }
+                _ => self.skipped += 1,
+            }
+            Ok(())
+        }
+    }
+
+    let data = fs::read("tests/fixtures/validation.bc").unwrap();
+    let bitcode = Bitcode::new(&data).unwrap();
+    let mut visitor = FirstFields::default();
+    Bitcode::read_lazy(&data, &mut visitor).unwrap();
+
+    let module = bitcode.find_block(BlockId::Module).unwrap();
+    let expected: Vec<_> = module
+        .elements
+        .iter()
+        .filter_map(BitcodeElement::as_record)
+        .map(|record| (record.id, record.fields.first().copied()))
+        .collect();
+    assert_eq!(visitor.module, expected);
+    let identification = bitcode.find_block(BlockId::Identification).unwrap();
+    let expected: Vec<_> = identification
+        .elements
+        .iter()
+        .filter_map(BitcodeElement::as_record)
+        .map(DecodedRecord::from)
+        .collect();
+    let read: Vec<_> = visitor
+        .identification
+        .iter()
+        .map(DecodedRecord::from)
+        .collect();
+    assert_eq!(read, expected);
+    assert!(visitor.skipped > 0);
+}
+
+#[test]
+fn test_router() {
+    /// Records the blocks entered and the records read
+    #[derive(Default)]
+    struct Recorder {
+        blocks: Vec<u64>,
+        records: usize,
+        depth: usize,
+    }
+
+    impl BitStreamVisitor for Recorder {
+        fn should_enter_block(&mut self, id: u64) -> bool {
+            self.blocks.push(id);
+            self.depth += 1;
+            true
+        }
+
+        fn did_exit_block(&mut self) {
+            self.depth -= 1;
+        }
+
+        fn visit(&mut self, _record: Record) {
+            self.records += 1;
+        }
+    }
+
+    /// Records in `block` and its nested blocks, except blocks with the id
+    /// `except`
+    fn count_records(block: &Block<'_>, except: u64) -> usize {
+        block
+            .elements
+            .iter()
+            .map(|element| match element {
+                BitcodeElement::Block(block) if block.id == except => 0,
+                BitcodeElement::Block(block) => count_records(block, except),
+                BitcodeElement::Record(_) => 1,
+            })
+            .sum()
+    }
+
+    let data = fs::read("tests/fixtures/validation.bc").unwrap();
+    let bitcode = Bitcode::new(&data).unwrap();
+    let metadata_id = u64::from(BlockId::Metadata);
+
+    let mut functions = Recorder::default();
+    let mut metadata = Recorder::default();
+    let mut strtab = Recorder::default();
+    let mut router = Router::new()
+        .route(BlockId::Function, &mut functions)
+        .route(BlockId::Metadata, &mut metadata)
+        .route_path(&[BlockId::Strtab], &mut strtab);
+    Bitcode::read(&data, &mut router).unwrap();
+
+    // Metadata blocks nested in functions go to the metadata route
+    let function_blocks: Vec<_> = bitcode
+        .query(&[BlockId::Module, BlockId::Function])
+        .collect();
+    assert_eq!(
+        functions.records,
+        function_blocks
+            .iter()
+            .map(|block| count_records(block, metadata_id))
+            .sum::<usize>()
+    );
+    assert_eq!(
+        functions
+            .blocks
+            .iter()
+            .filter(|&&id| id == u64::from(BlockId::Function))
+            .count(),
+        function_blocks.len()
+    );
+    assert!(!functions.blocks.contains(&metadata_id));
+    assert_eq!(functions.depth, 0);
+
+    fn metadata_records(elements: &[BitcodeElement<'_>], id: u64) -> usize {
+        elements
+            .iter()
+            .filter_map(BitcodeElement::as_block)
+            .map(|block| {
+                if block.id == id {
+                    count_records(block, u64::MAX)
+                } else {
+                    metadata_records(&block.elements, id)
+                }
+            })
+            .sum()
+    }
+    assert!(metadata.records > 0);
+    assert_eq!(
+        metadata.records,
+        metadata_records(&bitcode.elements, metadata_id)
+    );
+    assert_eq!(metadata.depth, 0);
+
+    let expected = bitcode.query(&[BlockId::Strtab]).next().unwrap();
+    assert_eq!(strtab.blocks, [u64::from(BlockId::Strtab)]);
+    assert_eq!(strtab.records, count_records(expected, u64::MAX));
+
+    // A path route doesn't match the same block id elsewhere
+    let mut nested = Recorder::default();
+    let mut router = Router::new().route_path(&[BlockId::Function], &mut nested);
+    Bitcode::read(&data, &mut router).unwrap();
+    assert!(nested.blocks.is_empty());
+}
+
+#[test]
+fn test_did_define_abbrev() {
+    #[derive(Default)]
+    struct Recorder {
+        defined: Vec<(u64, AbbrevInfo, bool)>,
+        records: Vec<(u64, u64)>,
+    }
+
+    impl BitStreamVisitor for Recorder {
+        fn should_enter_block(&mut self, _id: u64) -> bool {
+            true
+        }
+
+        fn did_exit_block(&mut self) {}
+
+        fn did_define_abbrev(&mut self, block_id: u64, abbrev: &AbbrevInfo, in_block_info: bool) {
+            self.defined.push((block_id, abbrev.clone(), in_block_info));
+        }
+
+        fn visit(&mut self, record: Record) {
+            // Abbreviations are reported before the records using them
+            let abbrev_id = record.abbrev.as_ref().unwrap().id;
+            assert!(self
+                .defined
+                .iter()
+                .any(|(_, abbrev, _)| abbrev.id == abbrev_id));
+            self.records.push((record.id, abbrev_id));
+        }
+    }
+
+    let global = [Operand::Literal(1), Operand::Fixed(8)];
+    let local = [Operand::Literal(2), Operand::Vbr(6)];
+    let mut writer = BitWriter::new();
+    writer.enter_block(2, 0, 2);
+    writer.unabbreviated_record(2, 1, &[8]);
+    writer.define_abbrev(2, &global);
+    writer.end_block(2);
+    writer.enter_block(2, 8, 3);
+    writer.define_abbrev(3, &local);
+    writer.abbreviated_record(3, 4, &global, &[9], &[]);
+    writer.abbreviated_record(3, 5, &local, &[300], &[]);
+    writer.end_block(3);
+
+    let mut recorder = Recorder::default();
+    Bitcode::read(&writer.bytes, &mut recorder).unwrap();
+    assert!(matches!(
+        &recorder.defined[..],
+        [(8, global, true), (8, local, false)]
+            if global.id == 4
+                && matches!(global.abbrev.operands[..], [Operand::Literal(1), Operand::Fixed(8)])
+                && local.id == 5
+                && matches!(local.abbrev.operands[..], [Operand::Literal(2), Operand::Vbr(6)])
+    ));
+    assert_eq!(recorder.records, [(1, 4), (2, 5)]);
+}
+
+#[test]
+fn test_tee() {
+    /// Counts the records outside of the blocks with the id `skip`
+    struct Counter {
+        skip: Option<u64>,
+        records: usize,
+        depth: usize,
+    }
+
+    impl BitStreamVisitor for Counter {
+        fn should_enter_block(&mut self, id: u64) -> bool {
+            if Some(id) == self.skip {
+                return false;
+            }
+            self.depth += 1;
+            true
+        }
+
+        fn did_exit_block(&mut self) {
+            self.depth -= 1;
+        }
+
+        fn visit(&mut self, _record: Record) {
+            self.records += 1;
+        }
+    }
+
+    fn count_records(elements: &[BitcodeElement<'_>], skip: Option<u64>) -> usize {
+        elements
+            .iter()
+            .map(|element| match element {
+                BitcodeElement::Block(block) if Some(block.id) == skip => 0,
+                BitcodeElement::Block(block) => count_records(&block.elements, skip),
+                BitcodeElement::Record(_) => 1,
+            })
+            .sum()
+    }
+
+    let data = fs::read("tests/fixtures/validation.bc").unwrap();
+    let bitcode = Bitcode::new(&data).unwrap();
+    let function_id = u64::from(BlockId::Function);
+
+    let mut all = Counter {
+        skip: None,
+        records: 0,
+        depth: 0,
+    };
+    let mut no_functions = Counter {
+        skip: Some(function_id),
+        records: 0,
+        depth: 0,
+    };
+    let mut collector = CollectingVisitor::new();
+    let mut tee = Tee::new()
+        .with(&mut no_functions)
+        .with(&mut collector)
+        .with(&mut all);
+    Bitcode::read(&data, &mut tee).unwrap();
+
+    let elements = collector.finalize_top_level_elements();
+    assert_eq!(elements.len(), bitcode.elements.len());
+    for (read, expected) in elements.iter().zip(&bitcode.elements) {
+        match (read, expected) {
+            (BitcodeElement::Block(read), BitcodeElement::Block(expected)) => {
+                assert_same_block(read, expected)
+            }
+            _ => panic!("expected blocks"),
+        }
+    }
+    assert_eq!(all.records, count_records(&bitcode.elements, None));
+    assert_eq!(
+        no_functions.records,
+        count_records(&bitcode.elements, Some(function_id))
+    );
+    assert!(no_functions.records < all.records);
+    assert_eq!((all.depth, no_functions.depth), (0, 0));
+
+    // Blocks no visitor enters are skipped
+    let mut all = Counter {
+        skip: Some(function_id),
+        records: 0,
+        depth: 0,
+    };
+    let mut tee = Tee::new().with(&mut all);
+    Bitcode::read_reusing(&data, &mut tee).unwrap();
+    assert_eq!(
+        all.records,
+        count_records(&bitcode.elements, Some(function_id))
+    );
+}
+
+#[test]
+fn test_filter() {
+    fn records<'a>(
+        block: &'a Block<'a>,
+        skip: u64,
+    ) -> Box<dyn Iterator<Item = &'a Record<'a>> + 'a> {
+        Box::new(
+            block
+                .elements
+                .iter()
+                .flat_map(move |element| match element {
+                    BitcodeElement::Block(block) if block.id == skip => {
+                        Box::new(std::iter::empty())
+                    }
+                    BitcodeElement::Block(block) => records(block, skip),
+                    BitcodeElement::Record(record) => Box::new(std::iter::once(record)),
+                }),
+        )
+    }
+
+    let data = fs::read("tests/fixtures/validation.bc").unwrap();
+    let bitcode = Bitcode::new(&data).unwrap();
+    let functions: Vec<_> = bitcode
+        .query(&[BlockId::Module, BlockId::Function])
+        .collect();
+    assert!(!functions.is_empty());
+
+    // Selected blocks are passed with everything nested in them
+    let mut filter = Filter::new(CollectingVisitor::new()).blocks([BlockId::Function]);
+    Bitcode::read(&data, &mut filter).unwrap();
+    let elements = filter.into_inner().finalize_top_level_elements();
+    assert_eq!(elements.len(), functions.len());
+    for (read, expected) in elements.iter().zip(&functions) {
+        match read {
+            BitcodeElement::Block(read) => assert_same_block(read, expected),
+            BitcodeElement::Record(_) => panic!("expected a block"),
+        }
+    }
+
+    // Skipped blocks and filtered records are left out
+    let metadata_id = u64::from(BlockId::Metadata);
+    let first_id = records(functions[0], metadata_id).next().unwrap().id;
+    let mut filter = Filter::new(CollectingVisitor::new())
+        .blocks([BlockId::Function])
+        .skip_blocks([BlockId::Metadata])
+        .records(|id| id != first_id);
+    Bitcode::read_reusing(&data, &mut filter).unwrap();
+    let elements = filter.into_inner().finalize_top_level_elements();
+    assert_eq!(elements.len(), functions.len());
+    for (read, expected) in elements.iter().zip(&functions) {
+        let read = match read {
+            BitcodeElement::Block(read) => read,
+            BitcodeElement::Record(_) => panic!("expected a block"),
+        };
+        let read_ids: Vec<_> = records(read, metadata_id).map(|record| record.id).collect();
+        let expected_ids: Vec<_> = records(expected, metadata_id)
+            .map(|record| record.id)
+            .filter(|&id| id != first_id)
+            .collect();
+        assert_eq!(read_ids, expected_ids);
+        assert!(!read.elements.iter().any(
+            |element| matches!(element, BitcodeElement::Block(block) if block.id == metadata_id)
+        ));
+    }
+
+    // Without blocks, everything but what is filtered out is passed
+    let mut filter = Filter::new(CollectingVisitor::new()).skip_blocks([BlockId::Function]);
+    Bitcode::read(&data, &mut filter).unwrap();
+    let elements = filter.into_inner().finalize_top_level_elements();
+    let function_id = u64::from(BlockId::Function);
+    let count = |elements: &[BitcodeElement]| -> usize {
+        elements
+            .iter()
+            .map(|element| match element {
+                BitcodeElement::Block(block) => records(block, function_id).count(),
+                BitcodeElement::Record(_) => 1,
+            })
+            .sum()
+    };
+    assert_eq!(count(&elements), count(&bitcode.elements));
+    assert_eq!(elements.len(), bitcode.elements.len());
+}
+
+#[test]
+fn test_resume() {
+    let data = fs::read("tests/fixtures/validation.bc").unwrap();
+    let full = Bitcode::new(&data).unwrap();
+    let expected: Vec<_> = full.query(&[BlockId::Module, BlockId::Function]).collect();
+
+    // Offsets recorded by an earlier pass
+    let options = CollectOptions {
+        unparsed_blocks: vec![BlockId::Function.into()],
+        ..Default::default()
+    };
+    let bitcode = Bitcode::new_with_collect_options(&data, options).unwrap();
+    let contexts: Vec<_> = bitcode
+        .query(&[BlockId::Module, BlockId::Function])
+        .map(|block| block.unparsed.unwrap())
+        .collect();
+    assert_eq!(contexts.len(), expected.len());
+
+    // Function blocks use abbreviations defined in BLOCKINFO, in reverse
+    // order so no reader state carries over
+    for (context, expected) in contexts.iter().zip(&expected).rev() {
+        let (_, mut reader) = Bitcode::reader_at(&data, context.offset).unwrap();
+        assert_eq!(reader.bit_position(), context.offset);
+        let mut visitor = CollectingVisitor::new();
+        reader
+            .read_block(context.id, context.abbrev_width, &mut visitor)
+            .unwrap();
+        let block = Block {
+            id: context.id,
+            elements: visitor.finalize_top_level_elements(),
+            bit_range: Some(context.bit_range()),
+            unparsed: None,
+        };
+        assert_same_block(&block, expected);
+    }
+
+    // Not the start of a block body
+    let err = Bitcode::reader_at(&data, contexts[0].header_offset).unwrap_err();
+    assert!(matches!(err, Error::NoBlockAt(offset) if offset == contexts[0].header_offset));
+}
+
+#[test]
+fn test_memory_usage() {
+    fn count_records(elements: &[BitcodeElement<'_>]) -> usize {
+        elements
+            .iter()
+            .map(|element| match element {
+                BitcodeElement::Block(block) => count_records(&block.elements),
+                BitcodeElement::Record(_) => 1,
+            })
+            .sum()
+    }
+
+    let data = fs::read("tests/fixtures/validation.bc").unwrap();
+    let bitcode = Bitcode::new(&data).unwrap();
+    let usage = bitcode.memory_usage();
+    assert_eq!(
+        usage
+            .blocks
+            .iter()
+            .map(|block| block.records)
+            .sum::<usize>(),
+        count_records(&bitcode.elements)
+    );
+    let functions = usage
+        .blocks
+        .iter()
+        .find(|block| block.id == BlockId::Function)
+        .unwrap();
+    assert_eq!(
+        functions.count,
+        bitcode.query(&[BlockId::Module, BlockId::Function]).count()
+    );
+    assert!(functions.fields > 0 && functions.elements > 0);
+    assert!(usage
+        .blocks
+        .windows(2)
+        .all(|pair| pair[0].total() >= pair[1].total()));
+    assert_eq!(
+        usage.total(),
+        usage
+            .blocks
+            .iter()
+            .map(|block| block.total())
+            .sum::<usize>()
+            + usage.top_level
+            + usage.block_info
+    );
+    assert!(usage
+        .to_string()
+        .starts_with(&format!("total: {} bytes", usage.total())));
+
+    // Borrowed blobs use no memory until made owned
+    let strtab_payloads = |usage: &MemoryUsage| {
+        usage
+            .blocks
+            .iter()
+            .find(|block| block.id == BlockId::Strtab)
+            .unwrap()
+            .payloads
+    };
+    assert_eq!(strtab_payloads(&usage), 0);
+    let owned = bitcode.into_owned().memory_usage();
+    assert!(strtab_payloads(&owned) > 0);
+}
+
+#[test]
+fn test_stream_reader_invalid_wrapper() {
+    let data = fs::read("tests/fixtures/simple.bc").unwrap();
+    let word = |index: usize| {
+        u32::from_le_bytes([
+            data[index],
+            data[index + 1],
+            data[index + 2],
+            data[index + 3],
+        ])
+    };
+    assert_eq!(word(0), 0x0B17C0DE);
+    let (offset, size) = (word(8), word(12));
+
+    for (offset, size) in [
+        // Too small to hold a signature
+        (offset, 3),
+        // Overflowing the end offset
+        (u32::MAX, size),
+        // Past the end of the input
+        (offset, size + 100),
+    ] {
+        let mut data = data.clone();
+        data[8..12].copy_from_slice(&offset.to_le_bytes());
+        data[12..16].copy_from_slice(&size.to_le_bytes());
+        assert!(matches!(Bitcode::new(&data), Err(Error::InvalidWrapper)));
+
+        let mut reader = StreamReader::new();
+        reader.feed(&data);
+        reader.finish();
+        let result = std::iter::from_fn(|| reader.next().transpose()).find(Result::is_err);
+        assert!(matches!(result, Some(Err(Error::InvalidWrapper))));
+    }
+}
+
+#[test]
+fn test_stream_reader_large_record() {
+    let fields: Vec<u64> = (0..100_000).collect();
+    let mut writer = BitWriter::new();
+    writer.enter_block(2, 8, 3);
+    writer.unabbreviated_record(3, 1, &fields);
+    writer.end_block(3);
+
+    // Reading the record again for every byte fed would take quadratic time
+    let mut reader = StreamReader::new();
+    let mut events = Vec::new();
+    for byte in &writer.bytes {
+        reader.feed(std::slice::from_ref(byte));
+        loop {
+            match reader.next() {
+                Ok(Some(event)) => events.push(event),
+                Ok(None) => unreachable!(),
+                Err(Error::NeedMoreData) => break,
+                Err(err) => panic!("unexpected error {}", err),
+            }
+        }
+    }
+    reader.finish();
+    while let Some(event) = reader.next().unwrap() {
+        events.push(event);
+    }
+    assert_eq!(events.len(), 3);
+    match &events[1] {
+        StreamEvent::Record(record) => assert_eq!(record.fields, fields),
+        event => panic!("unexpected event {:?}", event),
+    }
+}
+
+#[test]
+fn test_skip_array_payloads() {
+    struct SkipArrays(Vec<Vec<u64>>);
+
+    impl BitStreamVisitor for SkipArrays {
+        fn should_enter_block(&mut self, _id: u64) -> bool {
+            true
+        }
+
+        fn should_visit_record(&mut self, _block_id: u64, record_id: u64, _abbrev_id: u64) -> bool {
+            record_id == 3
+        }
+
+        fn did_exit_block(&mut self) {}
+
+        fn visit(&mut self, record: Record) {
+            self.0.push(record.fields);
+        }
+    }
+
+    let fixed = [
+        Operand::Literal(1),
+        Operand::Array(Box::new(Operand::Fixed(7))),
+    ];
+    let char6 = [
+        Operand::Literal(2),
+        Operand::Array(Box::new(Operand::Char6)),
+    ];
+    let vbr = [
+        Operand::Literal(4),
+        Operand::Array(Box::new(Operand::Vbr(4))),
+    ];
+    let mut writer = BitWriter::new();
+    writer.enter_block(2, 8, 3);
+    writer.define_abbrev(3, &fixed);
+    writer.define_abbrev(3, &char6);
+    writer.define_abbrev(3, &vbr);
+    writer.write(4, 3);
+    writer.write_vbr(3, 6);
+    for element in [1, 100, 127] {
+        writer.write(element, 7);
+    }
+    writer.unabbreviated_record(3, 3, &[11]);
+    writer.write(5, 3);
+    writer.write_vbr(5, 6);
+    for c in "hello".chars() {
+        writer.write(char6_encode(c).unwrap(), 6);
+    }
+    writer.unabbreviated_record(3, 3, &[12]);
+    writer.write(6, 3);
+    writer.write_vbr(2, 6);
+    writer.write_vbr(1000, 4);
+    writer.write_vbr(2, 4);
+    writer.unabbreviated_record(3, 3, &[13]);
+    writer.end_block(3);
+
+    let mut visitor = SkipArrays(Vec::new());
+    Bitcode::read(&writer.bytes, &mut visitor).unwrap();
+    assert_eq!(visitor.0, [[11], [12], [13]]);
+
+    // An array longer than what is left of the block
+    let mut writer = BitWriter::new();
+    writer.enter_block(2, 8, 3);
+    writer.define_abbrev(3, &fixed);
+    writer.write(4, 3);
+    writer.write_vbr(60, 6);
+    writer.end_block(3);
+    let mut visitor = SkipArrays(Vec::new());
+    assert!(Bitcode::read(&writer.bytes, &mut visitor).is_err());
+}